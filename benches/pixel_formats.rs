@@ -0,0 +1,42 @@
+//! Frame conversion throughput for each packed pixel format.
+//!
+//! cargo bench --bench pixel_formats
+
+use std::{hint::black_box, time::Instant};
+
+use gamuboy::lcd::{self, PixelFormat};
+
+const ITERATIONS: u32 = 2_000;
+
+fn main() {
+    // every shade so the conversion can't be specialized away
+    let shades = [
+        lcd::RGB_WHITE,
+        lcd::RGB_LIGHT_GRAY,
+        lcd::RGB_DARK_GRAY,
+        lcd::RGB_BLACK,
+    ];
+    let frame: lcd::FrameBuffer = (0..lcd::PIXELS_HEIGHT)
+        .map(|y| {
+            (0..lcd::PIXELS_WIDTH)
+                .map(|x| shades[(x + y) % shades.len()])
+                .collect()
+        })
+        .collect();
+
+    let mut out = vec![];
+    for format in [
+        PixelFormat::Rgb888,
+        PixelFormat::Rgba8888,
+        PixelFormat::Rgb565Le,
+        PixelFormat::Rgb565Be,
+    ] {
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            lcd::pack_frame(black_box(&frame), format, &mut out);
+            black_box(&out);
+        }
+        let per_frame = start.elapsed() / ITERATIONS;
+        println!("{format:?}: {per_frame:?} per frame");
+    }
+}
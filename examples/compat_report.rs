@@ -0,0 +1,46 @@
+//! Compatibility report of a folder of roms, see `gamuboy::compat`. Prints Markdown, or JSON
+//! with `--json`.
+//!
+//! cargo run --release --example compat_report -- path/to/roms [frames] [--json]
+
+use std::{env, fs, path::PathBuf};
+
+use gamuboy::compat::{self, CompatReport};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let json = args.iter().any(|a| a == "--json");
+    let mut positional = args.iter().filter(|a| !a.starts_with("--"));
+    let dir = positional
+        .next()
+        .expect("usage: compat_report <rom folder> [frames] [--json]");
+    let frames = positional
+        .next()
+        .map(|f| f.parse().expect("frames must be a number"))
+        .unwrap_or(compat::DEFAULT_FRAMES);
+
+    let mut roms: Vec<PathBuf> = fs::read_dir(dir)
+        .expect("failed to read the rom folder")
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|ext| {
+                ext.eq_ignore_ascii_case("gb") || ext.eq_ignore_ascii_case("gbc")
+            })
+        })
+        .collect();
+    roms.sort();
+
+    let mut report = CompatReport::new();
+    for path in roms {
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        eprintln!("{name}...");
+        let rom = fs::read(&path).expect("failed to read rom");
+        report.entries.push(compat::check_rom(&name, rom, frames));
+    }
+
+    if json {
+        print!("{}", report.to_json());
+    } else {
+        print!("{}", report.to_markdown());
+    }
+}
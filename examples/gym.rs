@@ -0,0 +1,61 @@
+//! Minimal gym-like loop: a random agent acting on a rom and observing work ram every frame.
+//!
+//! cargo run --release --example gym -- path/to/rom.gb
+
+use std::{env, fs, sync::mpsc::channel};
+
+use gamuboy::{
+    config::Config, gameboy::GameBoy, joypad::Button, joypad_events_handler, lcd, pacing::Pacing,
+    saver, stereo,
+};
+
+const EPISODE_FRAMES: usize = 600;
+
+/// Tiny xorshift, keeps the example dependency free and reproducible
+struct Rng(u32);
+
+impl Rng {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
+
+fn main() {
+    let rom_path = env::args().nth(1).expect("usage: gym <rom>");
+    let rom = fs::read(rom_path).expect("failed to read rom");
+
+    let cfg = Config {
+        rom,
+        headless_mode: true,
+        pacing: Pacing::Uncapped,
+        ..Default::default()
+    };
+
+    let (_tx, rx) = channel::<()>();
+    let mut gb = GameBoy::new(
+        &cfg,
+        lcd::Fake,
+        stereo::Fake,
+        joypad_events_handler::Fake,
+        saver::Fake,
+        &rx,
+    );
+
+    // observe the first page of work ram and high ram
+    gb.set_observed_regions(vec![0xC000..=0xC0FF, 0xFF80..=0xFFFE]);
+
+    let mut rng = Rng(0x1234_5678);
+    let mut total_changes = 0;
+    for _ in 0..EPISODE_FRAMES {
+        let action = Button::ALL[rng.next() as usize % Button::ALL.len()];
+        let observation = gb.frame_advance(&[action]);
+
+        // a real harness would compute a reward from the observation here
+        total_changes += observation.changes.len();
+    }
+
+    println!("{EPISODE_FRAMES} frames played, {total_changes} observed bytes changed");
+}
@@ -0,0 +1,185 @@
+//! Minimal browser backend: a canvas `LCD` and a `SampleQueue` for Web Audio, exported as plain
+//! functions so no bindings generator is needed.
+//!
+//! cargo build --release --target wasm32-unknown-unknown --example wasm_canvas
+//!
+//! The page copies the rom into memory from `alloc`, calls `load_rom`, then every
+//! `requestAnimationFrame` sets the buttons and runs a frame:
+//!
+//! ```js
+//! const { instance } = await WebAssembly.instantiateStreaming(fetch("wasm_canvas.wasm"));
+//! const gb = instance.exports;
+//! const rom = new Uint8Array(await (await fetch("game.gb")).arrayBuffer());
+//! const address = gb.alloc(rom.length);
+//! new Uint8Array(gb.memory.buffer, address, rom.length).set(rom);
+//! gb.load_rom(address, rom.length);
+//!
+//! const ctx = canvas.getContext("2d");
+//! function frame() {
+//!     gb.set_buttons(held); // bit n for `Button::from(n)`
+//!     gb.run_frame();
+//!     const pixels = new Uint8ClampedArray(gb.memory.buffer, gb.frame_ptr(), 160 * 144 * 4);
+//!     ctx.putImageData(new ImageData(pixels, 160, 144), 0, 0);
+//!     requestAnimationFrame(frame);
+//! }
+//! requestAnimationFrame(frame);
+//! ```
+//!
+//! The audio callback (an `AudioWorklet` fed through a message port, or a `ScriptProcessorNode`)
+//! asks for interleaved left/right samples at 48 kHz with `pull_audio(len)` and reads them at
+//! `audio_ptr()`, silence filling what the core hasn't produced yet.
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::mpsc::{self, Receiver},
+};
+
+use gamuboy::{
+    config::Config,
+    gameboy::GameBoy,
+    joypad::Button,
+    joypad_events_handler,
+    lcd::{self, PixelFormat, LCD},
+    pacing::Pacing,
+    saver,
+    stereo::SampleQueue,
+};
+
+/// A tenth of a second of interleaved samples at 48 kHz
+const AUDIO_CAPACITY: usize = 9600;
+
+/// Keeps the last frame in RGBA for the page to copy into an `ImageData`
+struct Canvas(Rc<RefCell<Vec<u8>>>);
+
+impl LCD for Canvas {
+    fn pixel_format(&self) -> Option<PixelFormat> {
+        Some(PixelFormat::Rgba8888)
+    }
+
+    fn draw_packed(&mut self, pixels: &[u8]) {
+        self.0.borrow_mut().copy_from_slice(pixels);
+    }
+}
+
+type Core = GameBoy<'static, Canvas, (), joypad_events_handler::Fake, SampleQueue>;
+
+struct Emulator {
+    core: Core,
+    pixels: Rc<RefCell<Vec<u8>>>,
+    audio: SampleQueue,
+    samples: Vec<f32>,
+}
+
+thread_local! {
+    /// Inputs come from `set_buttons`, nothing is ever sent
+    static EVENTS: &'static Receiver<()> = Box::leak(Box::new(mpsc::channel().1));
+    static EMULATOR: RefCell<Option<Emulator>> = const { RefCell::new(None) };
+    /// Buffers handed to the page by `alloc`
+    static ALLOCATIONS: RefCell<Vec<Vec<u8>>> = const { RefCell::new(vec![]) };
+}
+
+/// `len` writable bytes, kept until the page is closed, to copy the rom in
+#[no_mangle]
+pub extern "C" fn alloc(len: usize) -> *mut u8 {
+    ALLOCATIONS.with_borrow_mut(|allocations| {
+        allocations.push(vec![0; len]);
+        allocations.last_mut().unwrap().as_mut_ptr()
+    })
+}
+
+/// Powers the console on with the rom copied at `rom`, returns false when it's refused
+///
+/// # Safety
+///
+/// `rom` must point to `len` readable bytes, e.g. from `alloc`
+#[no_mangle]
+pub unsafe extern "C" fn load_rom(rom: *const u8, len: usize) -> bool {
+    let cfg = Config {
+        rom: std::slice::from_raw_parts(rom, len).to_vec(),
+        // the page runs a frame per animation frame
+        pacing: Pacing::Uncapped,
+        ..Default::default()
+    };
+    let pixels = Rc::new(RefCell::new(vec![
+        0;
+        lcd::PIXELS_WIDTH * lcd::PIXELS_HEIGHT * 4
+    ]));
+    let audio = SampleQueue::new(AUDIO_CAPACITY);
+
+    let core = EVENTS.with(|events| {
+        GameBoy::try_new(
+            &cfg,
+            Canvas(pixels.clone()),
+            audio.clone(),
+            joypad_events_handler::Fake,
+            saver::Fake,
+            *events,
+        )
+    });
+    let Ok(core) = core else {
+        return false;
+    };
+    EMULATOR.set(Some(Emulator {
+        core,
+        pixels,
+        audio,
+        samples: vec![],
+    }));
+    true
+}
+
+/// Buttons held, bit n being `Button::from(n)`
+#[no_mangle]
+pub extern "C" fn set_buttons(buttons: u8) {
+    with_emulator(|emulator| {
+        for bit in 0..8 {
+            let pressed = buttons & 1 << bit != 0;
+            emulator.core.set_button(Button::from(bit), pressed);
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn run_frame() {
+    with_emulator(|emulator| emulator.core.step_frame());
+}
+
+/// Last frame in RGBA, rows top to bottom, null without rom
+#[no_mangle]
+pub extern "C" fn frame_ptr() -> *const u8 {
+    EMULATOR.with_borrow(|emulator| match emulator {
+        Some(emulator) => emulator.pixels.borrow().as_ptr(),
+        None => std::ptr::null(),
+    })
+}
+
+/// Moves `len` queued samples to `audio_ptr`, returns how many the core had produced
+#[no_mangle]
+pub extern "C" fn pull_audio(len: usize) -> usize {
+    EMULATOR.with_borrow_mut(|emulator| {
+        let Some(emulator) = emulator else {
+            return 0;
+        };
+        emulator.samples.resize(len, 0.);
+        emulator.audio.pull(&mut emulator.samples)
+    })
+}
+
+/// Samples of the last `pull_audio`
+#[no_mangle]
+pub extern "C" fn audio_ptr() -> *const f32 {
+    EMULATOR.with_borrow(|emulator| {
+        emulator
+            .as_ref()
+            .map_or(std::ptr::null(), |emulator| emulator.samples.as_ptr())
+    })
+}
+
+fn with_emulator(f: impl FnOnce(&mut Emulator)) {
+    EMULATOR.with_borrow_mut(|emulator| {
+        if let Some(emulator) = emulator {
+            f(emulator);
+        }
+    });
+}
@@ -0,0 +1,151 @@
+/*
+ * C bindings of the gamuboy core, generated from src/ffi.rs by cbindgen, regenerate with
+ * `cargo run --manifest-path xtask/Cargo.toml -- header` instead of editing.
+ * Built with `cargo rustc --release --features ffi --crate-type cdylib` into libgamuboy.so /
+ * gamuboy.dll / libgamuboy.dylib.
+ *
+ *     Gamuboy *gb = gamuboy_create(false);
+ *     if (gamuboy_load_rom(gb, rom, rom_len) != GAMUBOY_OK) { ... }
+ *     for (;;) {
+ *         gamuboy_set_buttons(gb, GAMUBOY_BUTTON_A | GAMUBOY_BUTTON_RIGHT);
+ *         gamuboy_run_frame(gb);
+ *         draw_rgba(gamuboy_framebuffer(gb), GAMUBOY_SCREEN_WIDTH, GAMUBOY_SCREEN_HEIGHT);
+ *     }
+ *     gamuboy_destroy(gb);
+ *
+ * A handle must only be used by one thread at a time.
+ */
+
+#ifndef GAMUBOY_H
+#define GAMUBOY_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define GAMUBOY_OK 0
+
+/**
+ * Null handle or buffer
+ */
+#define GAMUBOY_ERR_NULL -1
+
+/**
+ * No rom loaded yet
+ */
+#define GAMUBOY_ERR_NO_ROM -2
+
+#define GAMUBOY_ERR_INVALID_ROM -3
+
+#define GAMUBOY_ERR_INVALID_STATE -4
+
+/**
+ * The core panicked, the handle should only be destroyed or given a new rom
+ */
+#define GAMUBOY_ERR_PANIC -5
+
+/**
+ * `lcd::PIXELS_WIDTH`, spelled out for cbindgen
+ */
+#define GAMUBOY_SCREEN_WIDTH 160
+
+#define GAMUBOY_SCREEN_HEIGHT 144
+
+/**
+ * Bits of `gamuboy_set_buttons`, bit n being `Button::from(n)`
+ */
+#define GAMUBOY_BUTTON_A (1 << 0)
+
+#define GAMUBOY_BUTTON_B (1 << 1)
+
+#define GAMUBOY_BUTTON_SELECT (1 << 2)
+
+#define GAMUBOY_BUTTON_START (1 << 3)
+
+#define GAMUBOY_BUTTON_RIGHT (1 << 4)
+
+#define GAMUBOY_BUTTON_LEFT (1 << 5)
+
+#define GAMUBOY_BUTTON_UP (1 << 6)
+
+#define GAMUBOY_BUTTON_DOWN (1 << 7)
+
+typedef struct Gamuboy Gamuboy;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * New handle without rom, in CGB mode when `cgb` is set. Free it with `gamuboy_destroy`.
+ */
+Gamuboy *gamuboy_create(bool cgb);
+
+/**
+ * # Safety
+ *
+ * `gb` must come from `gamuboy_create` and not be used afterwards, null is ignored
+ */
+void gamuboy_destroy(Gamuboy *gb);
+
+/**
+ * Copies the rom and powers the console on, replacing any running game
+ *
+ * # Safety
+ *
+ * `gb` must be a live handle, `rom` must point to `len` readable bytes
+ */
+int32_t gamuboy_load_rom(Gamuboy *gb, const uint8_t *rom, size_t len);
+
+/**
+ * Buttons held from the next frame on, `GAMUBOY_BUTTON_*` bits
+ *
+ * # Safety
+ *
+ * `gb` must be a live handle
+ */
+int32_t gamuboy_set_buttons(Gamuboy *gb, uint8_t buttons);
+
+/**
+ * Runs one video frame with the buttons set by `gamuboy_set_buttons`
+ *
+ * # Safety
+ *
+ * `gb` must be a live handle
+ */
+int32_t gamuboy_run_frame(Gamuboy *gb);
+
+/**
+ * Last frame as `GAMUBOY_SCREEN_WIDTH * GAMUBOY_SCREEN_HEIGHT` RGBA pixels, rows top to
+ * bottom. Valid until the next call on the handle, null without rom or if the core panicked.
+ *
+ * # Safety
+ *
+ * `gb` must be a live handle
+ */
+const uint8_t *gamuboy_framebuffer(Gamuboy *gb);
+
+/**
+ * Size of the savestate, copied to `out` when `capacity` is large enough. Call it with a null
+ * `out` to get the size first. Negative on error.
+ *
+ * # Safety
+ *
+ * `gb` must be a live handle, `out` null or pointing to `capacity` writable bytes
+ */
+int64_t gamuboy_save_state(Gamuboy *gb, uint8_t *out, size_t capacity);
+
+/**
+ * Restores a savestate of `gamuboy_save_state`, the running game is kept when it's invalid
+ *
+ * # Safety
+ *
+ * `gb` must be a live handle, `state` must point to `len` readable bytes
+ */
+int32_t gamuboy_load_state(Gamuboy *gb, const uint8_t *state, size_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* GAMUBOY_H */
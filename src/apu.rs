@@ -1,6 +1,11 @@
-use crate::{memory::MemReadWriter, stereo::StereoPlayer};
+use crate::{
+    clock::{ClockDomain, Clocked},
+    memory::MemReadWriter,
+    mode::Model,
+    savestate::{ChunkReader, ChunkWriter, Snapshot},
+};
 
-const MASTER_CLOCK_FREQ: u32 = 4_194_304;
+pub(crate) const MASTER_CLOCK_FREQ: u32 = 4_194_304;
 
 const SQUARE_CHANNEL_PERIOD_FREQ: u32 = 1_048_576;
 const WAVE_CHANNEL_PERIOD_FREQ: u32 = 2_097_152;
@@ -508,12 +513,24 @@ impl SquareChannel {
         self.on = self.length_timer.tick() && self.on;
     }
 
+    /// Digital amplitude fed to the DAC
+    fn amplitude(&self) -> u8 {
+        self.wave_duty.signal(self.duty_step_counter) * self.envelope.volume
+    }
+
+    /// Digital output seen through PCM12/PCM34, 0 while the channel is off
+    fn pcm(&self) -> u8 {
+        if !self.enabled() {
+            return 0;
+        }
+        self.amplitude()
+    }
+
     fn output(&self) -> f32 {
         if !self.dac_on {
             return 0.;
         }
-        let input = self.wave_duty.signal(self.duty_step_counter) * self.envelope.volume;
-        self.dac.convert(input)
+        self.dac.convert(self.amplitude())
     }
 }
 
@@ -604,10 +621,12 @@ struct WaveChannel {
     wave_ram: WaveRam,
 
     started_sampling: bool,
+    /// DMG wave ram access and retrigger behaviours while on
+    monochrome: bool,
 }
 
 impl WaveChannel {
-    fn new() -> Self {
+    fn new(monochrome: bool) -> Self {
         Self {
             on: false,
             dac_on: false,
@@ -625,6 +644,7 @@ impl WaveChannel {
             wave_ram: WaveRam::new(),
 
             started_sampling: false,
+            monochrome,
         }
     }
 
@@ -648,9 +668,11 @@ impl WaveChannel {
     }
 
     fn read_wave_ram(&self, address: u16) -> u8 {
-        // wave read while on dmg behaviour
+        // wave read while on dmg behaviour, the cgb always reads the byte being played
         if self.enabled() {
-            if self.started_sampling && self.period.timer == self.compute_period_timer() {
+            if !self.monochrome
+                || self.started_sampling && self.period.timer == self.compute_period_timer()
+            {
                 self.wave_ram.ram[self.wave_ram.sample_index as usize / 2]
             } else {
                 0xFF
@@ -661,9 +683,11 @@ impl WaveChannel {
     }
 
     fn write_wave_ram(&mut self, address: u16, value: u8) {
-        // wave write while on dmg behaviour
+        // wave write while on dmg behaviour, the cgb always writes the byte being played
         if self.enabled() {
-            if self.started_sampling && self.period.timer == self.compute_period_timer() {
+            if !self.monochrome
+                || self.started_sampling && self.period.timer == self.compute_period_timer()
+            {
                 self.wave_ram.ram[self.wave_ram.sample_index as usize / 2] = value;
             }
         } else {
@@ -682,7 +706,8 @@ impl WaveChannel {
     fn trigger(&mut self, current_step: u8) {
         // triggering while on corrupts the first 4 bytes of wave ram on dmg
         // if next step will clock timer, then simulate corruption
-        if self.enabled()
+        if self.monochrome
+            && self.enabled()
             && self.period.timer <= (MASTER_CLOCK_FREQ / WAVE_CHANNEL_PERIOD_FREQ) as u16
         {
             let mut corrupt = |pos: usize| {
@@ -736,12 +761,22 @@ impl WaveChannel {
         self.on = self.length_timer.tick() && self.on;
     }
 
+    fn amplitude(&self) -> u8 {
+        self.wave_ram.sample_buffer >> self.output_level.shift()
+    }
+
+    fn pcm(&self) -> u8 {
+        if !self.enabled() {
+            return 0;
+        }
+        self.amplitude()
+    }
+
     fn output(&self) -> f32 {
         if !self.dac_on {
             return 0.;
         }
-        let input = self.wave_ram.sample_buffer >> self.output_level.shift();
-        self.dac.convert(input)
+        self.dac.convert(self.amplitude())
     }
 }
 
@@ -872,6 +907,13 @@ impl NoiseChannel {
         !self.lfsr as u8 & 1
     }
 
+    fn pcm(&self) -> u8 {
+        if !self.enabled() {
+            return 0;
+        }
+        self.amplitude() * self.envelope.volume
+    }
+
     fn output(&self) -> f32 {
         if !self.dac_on {
             return 0.;
@@ -881,8 +923,24 @@ impl NoiseChannel {
     }
 }
 
+/// Debug view of the APU state, see `APU::snapshot`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApuSnapshot {
+    pub on: bool,
+    /// channels 1 to 4 running with their DAC on
+    pub channels_enabled: [bool; 4],
+    /// noise channel LFSR, its output is the inverted bit 0
+    pub noise_lfsr: u16,
+    /// 7-bit LFSR mode (NR43 bit 3)
+    pub noise_short_mode: bool,
+    /// [left, right] RMS of channels 1 to 4 over the last samples buffer, for VU meters.
+    /// Taken after panning but before mixing and master volume, a full scale channel reads ~1.
+    pub channel_levels: [[f32; 2]; 4],
+}
+
+/// Holds no frontend handle: filled sample buffers are handed out by the bus, see `APU::step`
 #[derive(Clone, Debug)]
-pub struct APU<S: StereoPlayer + 'static> {
+pub struct APU {
     on: bool,
     vin_left: bool,
     vin_right: bool,
@@ -897,15 +955,30 @@ pub struct APU<S: StereoPlayer + 'static> {
     ch2: SquareChannel,
     ch3: WaveChannel,
     ch4: NoiseChannel,
+    /// DMG quirks, see `mode::Model::is_monochrome`
+    monochrome: bool,
 
     buffer: [f32; SAMPLES_BUFFER_SIZE],
     buffer_index: usize,
 
-    stereo: S,
+    /// [left, right] sums of squared channel outputs over the current buffer
+    level_sums: [[f32; 2]; 4],
+    channel_levels: [[f32; 2]; 4],
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl<S: StereoPlayer> APU<S> {
-    pub fn new(stereo: S) -> Self {
+impl APU {
+    /// DMG APU
+    pub fn new() -> Self {
+        Self::with_model(Model::DMG)
+    }
+
+    pub fn with_model(model: Model) -> Self {
         Self {
             on: false,
             vin_left: false,
@@ -918,16 +991,43 @@ impl<S: StereoPlayer> APU<S> {
 
             ch1: SquareChannel::new(true),
             ch2: SquareChannel::new(false),
-            ch3: WaveChannel::new(),
+            ch3: WaveChannel::new(model.is_monochrome()),
             ch4: NoiseChannel::new(),
+            monochrome: model.is_monochrome(),
 
             buffer: [0.; SAMPLES_BUFFER_SIZE],
             buffer_index: 0,
 
-            stereo,
+            level_sums: [[0.; 2]; 4],
+            channel_levels: [[0.; 2]; 4],
+        }
+    }
+
+    pub fn snapshot(&self) -> ApuSnapshot {
+        ApuSnapshot {
+            on: self.on,
+            channels_enabled: [
+                self.ch1.enabled(),
+                self.ch2.enabled(),
+                self.ch3.enabled(),
+                self.ch4.enabled(),
+            ],
+            noise_lfsr: self.ch4.lfsr,
+            noise_short_mode: self.ch4.short_mode,
+            channel_levels: self.channel_levels,
         }
     }
 
+    /// PCM12 (FF76, CGB only): digital outputs of channel 1 (low nibble) and 2 (high nibble)
+    pub fn pcm12(&self) -> u8 {
+        self.ch2.pcm() << 4 | self.ch1.pcm()
+    }
+
+    /// PCM34 (FF77, CGB only): digital outputs of channel 3 (low nibble) and 4 (high nibble)
+    pub fn pcm34(&self) -> u8 {
+        self.ch4.pcm() << 4 | self.ch3.pcm()
+    }
+
     fn power_on(&mut self) {
         if !self.on {
             // When powered on, the frame sequencer is reset so that the next step will be 0
@@ -946,14 +1046,17 @@ impl<S: StereoPlayer> APU<S> {
 
         self.ch1 = SquareChannel::new(true);
         self.ch2 = SquareChannel::new(false);
-        self.ch3 = WaveChannel::new();
+        self.ch3 = WaveChannel::new(self.monochrome);
         self.ch4 = NoiseChannel::new();
 
         self.ch3.wave_ram.ram = wave_ram_copy;
-        self.ch1.length_timer.timer = ch1_len_timer;
-        self.ch2.length_timer.timer = ch2_len_timer;
-        self.ch3.length_timer.timer = ch3_len_timer;
-        self.ch4.length_timer.timer = ch4_len_timer;
+        // length timers survive power off on dmg only
+        if self.monochrome {
+            self.ch1.length_timer.timer = ch1_len_timer;
+            self.ch2.length_timer.timer = ch2_len_timer;
+            self.ch3.length_timer.timer = ch3_len_timer;
+            self.ch4.length_timer.timer = ch4_len_timer;
+        }
 
         self.on = false;
         self.vin_left = false;
@@ -966,6 +1069,9 @@ impl<S: StereoPlayer> APU<S> {
 
         self.buffer = [0.; SAMPLES_BUFFER_SIZE];
         self.buffer_index = 0;
+
+        self.level_sums = [[0.; 2]; 4];
+        self.channel_levels = [[0.; 2]; 4];
     }
 
     fn read_volume(&self) -> u8 {
@@ -1045,48 +1151,54 @@ impl<S: StereoPlayer> APU<S> {
         }
     }
 
-    /// Returns (left, right) mixing output
-    fn mix(&self) -> (f32, f32) {
-        let mut left_amps = 0.;
-        if self.ch1.panning.left {
-            left_amps += self.ch1.output();
-        }
-        if self.ch2.panning.left {
-            left_amps += self.ch2.output();
-        }
-        if self.ch3.panning.left {
-            left_amps += self.ch3.output();
-        }
-        if self.ch4.panning.left {
-            left_amps += self.ch4.output();
-        }
+    /// Returns [left, right] outputs of each channel after panning
+    fn panned_outputs(&self) -> [[f32; 2]; 4] {
+        let panned = |output: f32, panning: &Panning| {
+            [
+                if panning.left { output } else { 0. },
+                if panning.right { output } else { 0. },
+            ]
+        };
+        [
+            panned(self.ch1.output(), &self.ch1.panning),
+            panned(self.ch2.output(), &self.ch2.panning),
+            panned(self.ch3.output(), &self.ch3.panning),
+            panned(self.ch4.output(), &self.ch4.panning),
+        ]
+    }
 
-        let mut right_amps = 0.;
-        if self.ch1.panning.right {
-            right_amps += self.ch1.output();
-        }
-        if self.ch2.panning.right {
-            right_amps += self.ch2.output();
-        }
-        if self.ch3.panning.right {
-            right_amps += self.ch3.output();
-        }
-        if self.ch4.panning.right {
-            right_amps += self.ch4.output();
+    /// Returns (left, right) mixing output, accumulating the channel levels
+    fn mix(&mut self) -> (f32, f32) {
+        let (mut left_amps, mut right_amps) = (0., 0.);
+        for (output, sums) in self.panned_outputs().iter().zip(&mut self.level_sums) {
+            left_amps += output[0];
+            right_amps += output[1];
+            sums[0] += output[0] * output[0];
+            sums[1] += output[1] * output[1];
         }
 
         (left_amps / 4., right_amps / 4.)
     }
 
+    /// Turns the sums of the buffer just filled into RMS levels
+    fn update_channel_levels(&mut self) {
+        let frames = (SAMPLES_BUFFER_SIZE / 2) as f32;
+        for (level, sums) in self.channel_levels.iter_mut().zip(&mut self.level_sums) {
+            *level = sums.map(|sum| (sum / frames).sqrt());
+            *sums = [0.; 2];
+        }
+    }
+
     fn get_master_volume(&self, vol: u8) -> u8 {
         // Pandocs:
         // A value of 0 is treated as a volume of 1 (very quiet), and a value of 7 is treated as a volume of 8 (no volume reduction).
         vol + (vol == 0 || vol == 7) as u8
     }
 
-    pub fn step(&mut self, cycles: u8, div_apu_event: bool) {
+    /// Returns the samples buffer when it is full and ready to be played
+    pub fn step(&mut self, cycles: u8, div_apu_event: bool) -> Option<&[f32]> {
         if !self.on {
-            return;
+            return None;
         }
 
         if div_apu_event {
@@ -1111,8 +1223,11 @@ impl<S: StereoPlayer> APU<S> {
         }
         if self.buffer_index >= SAMPLES_BUFFER_SIZE {
             self.buffer_index = 0;
-            self.stereo.play(&self.buffer);
+            self.update_channel_levels();
+            return Some(&self.buffer);
         }
+
+        None
     }
 }
 
@@ -1147,7 +1262,12 @@ const NR50: u16 = nr(5, 0);
 const NR51: u16 = nr(5, 1);
 const NR52: u16 = nr(5, 2);
 
-impl<S: StereoPlayer> MemReadWriter for APU<S> {
+/// Keeps its pace in double speed mode, DIV-APU events aside (see `Timer`)
+impl Clocked for APU {
+    const DOMAIN: ClockDomain = ClockDomain::System;
+}
+
+impl MemReadWriter for APU {
     fn read_byte(&self, address: u16) -> u8 {
         match address {
             NR10 => self.ch1.sweep.as_ref().unwrap().read(),
@@ -1181,14 +1301,11 @@ impl<S: StereoPlayer> MemReadWriter for APU<S> {
     }
 
     fn write_byte(&mut self, address: u16, value: u8) {
-        if !self.on
-            && !((0xFF30..=0xFF3F).contains(&address)
-                || [
-                    NR52, /* DMG length timer reg should be writeable when off */
-                    NR11, NR21, NR31, NR41,
-                ]
-                .contains(&address))
-        {
+        let writable_when_off = (0xFF30..=0xFF3F).contains(&address)
+            || address == NR52
+            /* DMG length timer reg should be writeable when off */
+            || self.monochrome && [NR11, NR21, NR31, NR41].contains(&address);
+        if !self.on && !writable_when_off {
             return;
         }
 
@@ -1260,10 +1377,382 @@ impl<S: StereoPlayer> MemReadWriter for APU<S> {
     }
 }
 
+impl Envelope {
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_u8(self.initial_volume);
+        w.write_u8(self.dir.clone() as u8);
+        w.write_u8(self.sweep_pace);
+        w.write_u8(self.volume);
+        w.write_u8(self.dir_shadow.clone() as u8);
+        w.write_u8(self.pace_shadow);
+        w.write_u8(self.timer);
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        self.initial_volume = r.read_u8();
+        self.dir = EnvelopeDirection::from(r.read_u8() << 3);
+        self.sweep_pace = r.read_u8();
+        self.volume = r.read_u8();
+        self.dir_shadow = EnvelopeDirection::from(r.read_u8() << 3);
+        self.pace_shadow = r.read_u8();
+        self.timer = r.read_u8();
+    }
+}
+
+impl Period {
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_u8(self.high);
+        w.write_u8(self.low);
+        w.write_u16(self.timer);
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        self.high = r.read_u8();
+        self.low = r.read_u8();
+        self.timer = r.read_u16();
+    }
+}
+
+impl LengthTimer {
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_bool(self.length_enable);
+        w.write_u8(self.init_length_timer);
+        w.write_u16(self.timer);
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        self.length_enable = r.read_bool();
+        self.init_length_timer = r.read_u8();
+        self.timer = r.read_u16();
+    }
+}
+
+impl Sweep {
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_u8(self.pace);
+        w.write_u8(self.direction.clone() as u8);
+        w.write_bool(self.has_substracted);
+        w.write_u8(self.step);
+        w.write_u8(self.timer);
+        w.write_bool(self.enabled_flag);
+        w.write_u16(self.period_shadow_register);
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        self.pace = r.read_u8();
+        self.direction = SweepDirection::from(r.read_u8() << 3);
+        self.has_substracted = r.read_bool();
+        self.step = r.read_u8();
+        self.timer = r.read_u8();
+        self.enabled_flag = r.read_bool();
+        self.period_shadow_register = r.read_u16();
+    }
+}
+
+impl Panning {
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_bool(self.left);
+        w.write_bool(self.right);
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        self.left = r.read_bool();
+        self.right = r.read_bool();
+    }
+}
+
+impl SquareChannel {
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_bool(self.on);
+        w.write_bool(self.dac_on);
+        self.panning.save(w);
+        if let Some(sweep) = &self.sweep {
+            sweep.save(w);
+        }
+        self.length_timer.save(w);
+        w.write_u8(self.wave_duty.clone() as u8);
+        w.write_u8(self.duty_step_counter);
+        self.envelope.save(w);
+        self.period.save(w);
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        self.on = r.read_bool();
+        self.dac_on = r.read_bool();
+        self.panning.load(r);
+        if let Some(sweep) = &mut self.sweep {
+            sweep.load(r);
+        }
+        self.length_timer.load(r);
+        self.wave_duty = DutyCycle::from(r.read_u8());
+        self.duty_step_counter = r.read_u8();
+        self.envelope.load(r);
+        self.period.load(r);
+    }
+}
+
+impl WaveChannel {
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_bool(self.on);
+        w.write_bool(self.dac_on);
+        self.panning.save(w);
+        self.length_timer.save(w);
+        w.write_u8(self.initial_output_level.clone() as u8);
+        w.write_u8(self.output_level.clone() as u8);
+        self.period.save(w);
+        w.write_bytes(&self.wave_ram.ram);
+        w.write_u8(self.wave_ram.sample_index);
+        w.write_u8(self.wave_ram.sample_buffer);
+        w.write_bool(self.started_sampling);
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        self.on = r.read_bool();
+        self.dac_on = r.read_bool();
+        self.panning.load(r);
+        self.length_timer.load(r);
+        self.initial_output_level = OutputLevel::from(r.read_u8());
+        self.output_level = OutputLevel::from(r.read_u8());
+        self.period.load(r);
+        r.read_into(&mut self.wave_ram.ram);
+        self.wave_ram.sample_index = r.read_u8();
+        self.wave_ram.sample_buffer = r.read_u8();
+        self.started_sampling = r.read_bool();
+    }
+}
+
+impl NoiseChannel {
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_bool(self.on);
+        w.write_bool(self.dac_on);
+        self.panning.save(w);
+        self.length_timer.save(w);
+        self.envelope.save(w);
+        w.write_u16(self.lfsr);
+        w.write_u8(self.clock_shift);
+        w.write_bool(self.short_mode);
+        w.write_u8(self.clock_divider);
+        w.write_u32(self.period_div);
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        self.on = r.read_bool();
+        self.dac_on = r.read_bool();
+        self.panning.load(r);
+        self.length_timer.load(r);
+        self.envelope.load(r);
+        self.lfsr = r.read_u16();
+        self.clock_shift = r.read_u8();
+        self.short_mode = r.read_bool();
+        self.clock_divider = r.read_u8();
+        self.period_div = r.read_u32();
+    }
+}
+
+impl Snapshot for APU {
+    const TAG: [u8; 4] = *b"APU ";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, w: &mut ChunkWriter) {
+        w.write_bool(self.on);
+        w.write_bool(self.vin_left);
+        w.write_bool(self.vin_right);
+        w.write_u8(self.left_volume);
+        w.write_u8(self.right_volume);
+        w.write_u8(self.current_step);
+        w.write_u32(self.samples_cycle_acc);
+
+        self.ch1.save(w);
+        self.ch2.save(w);
+        self.ch3.save(w);
+        self.ch4.save(w);
+
+        // samples not handed out yet, so the output stays identical across a save/load
+        w.write_u32(self.buffer_index as u32);
+        for sample in &self.buffer[..self.buffer_index] {
+            w.write_f32(*sample);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut ChunkReader, _version: u16) {
+        self.on = r.read_bool();
+        self.vin_left = r.read_bool();
+        self.vin_right = r.read_bool();
+        self.left_volume = r.read_u8();
+        self.right_volume = r.read_u8();
+        self.current_step = r.read_u8();
+        self.samples_cycle_acc = r.read_u32();
+
+        self.ch1.load(r);
+        self.ch2.load(r);
+        self.ch3.load(r);
+        self.ch4.load(r);
+
+        self.buffer_index = (r.read_u32() as usize).min(SAMPLES_BUFFER_SIZE);
+        for sample in &mut self.buffer[..self.buffer_index] {
+            *sample = r.read_f32();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_pcm_registers() {
+        let mut apu = APU::new();
+        apu.write_byte(0xFF26, 0x80);
+        assert_eq!(0x00, apu.pcm12());
+        assert_eq!(0x00, apu.pcm34());
+
+        // noise channel at full volume, its output goes high once the lfsr shifted zeros in
+        apu.write_byte(0xFF21, 0xF0);
+        apu.write_byte(0xFF23, 0x80);
+        let mut pcm34 = 0;
+        for _ in 0..1000 {
+            apu.step(4, false);
+            pcm34 |= apu.pcm34();
+        }
+        assert_eq!(0xF0, pcm34);
+        assert_eq!(0x00, apu.pcm12());
+    }
+
+    /// Output bits of `n` LFSR shifts
+    fn noise_output(ch: &mut NoiseChannel, n: usize) -> Vec<u8> {
+        (0..n)
+            .map(|_| {
+                ch.handle_lfsr_period();
+                ch.amplitude()
+            })
+            .collect()
+    }
+
+    fn period(bits: &[u8]) -> Option<usize> {
+        (1..=bits.len() / 2).find(|&p| bits.iter().zip(&bits[p..]).all(|(a, b)| a == b))
+    }
+
+    #[test]
+    fn test_noise_lfsr_periods() {
+        let mut ch = NoiseChannel::new();
+        ch.reset_lfsr();
+        assert_eq!(Some(32767), period(&noise_output(&mut ch, 32767 * 2 + 1)));
+
+        ch.reset_lfsr();
+        ch.short_mode = true;
+        let bits = noise_output(&mut ch, 127 * 4);
+        // bits 7-14 are shifted out after a few periods, leaving the 7-bit sequence
+        assert_eq!(Some(127), period(&bits[16..]));
+        // maximal length sequence: 64 ones per period in the lfsr, so 63 in the inverted output
+        assert_eq!(63, bits[16..16 + 127].iter().filter(|&&b| b == 1).count());
+    }
+
+    #[test]
+    fn test_noise_lfsr_short_mode_feedback() {
+        let mut ch = NoiseChannel::new();
+        ch.reset_lfsr();
+        ch.short_mode = true;
+        for _ in 0..200 {
+            ch.handle_lfsr_period();
+            assert_eq!(ch.lfsr >> 14 & 1, ch.lfsr >> 6 & 1, "lfsr {:#06x}", ch.lfsr);
+        }
+    }
+
+    #[test]
+    fn test_noise_lfsr_width_switch_mid_run() {
+        let mut ch = NoiseChannel::new();
+        ch.reset_lfsr();
+        noise_output(&mut ch, 1000);
+
+        // switching to 7-bit keeps the current state and converges to the short sequence
+        ch.short_mode = true;
+        let bits = noise_output(&mut ch, 127 * 4);
+        assert_eq!(Some(127), period(&bits[16..]));
+
+        // back to 15-bit, bit 6 stops being overwritten and the long sequence resumes
+        ch.short_mode = false;
+        let bits = noise_output(&mut ch, 32767 * 2 + 17);
+        assert_eq!(Some(32767), period(&bits[16..]));
+    }
+
+    #[test]
+    fn test_snapshot_exposes_lfsr() {
+        let mut apu = APU::new();
+        apu.write_byte(0xFF26, 0x80);
+        apu.write_byte(0xFF21, 0xF0);
+        // 7-bit mode, shortest period: one shift every 8 cycles
+        apu.write_byte(0xFF22, 0x08);
+        apu.write_byte(0xFF23, 0x80);
+
+        let snapshot = apu.snapshot();
+        assert!(snapshot.on);
+        assert_eq!([false, false, false, true], snapshot.channels_enabled);
+        assert_eq!(0x7FFF, snapshot.noise_lfsr);
+        assert!(snapshot.noise_short_mode);
+
+        apu.step(80, false);
+        let lfsr = apu.snapshot().noise_lfsr;
+        assert_ne!(0x7FFF, lfsr);
+        assert_eq!(lfsr >> 14 & 1, lfsr >> 6 & 1);
+
+        apu.write_byte(0xFF22, 0x00);
+        assert!(!apu.snapshot().noise_short_mode);
+    }
+
+    #[test]
+    fn test_model_quirks() {
+        for (model, dmg) in [(Model::DMG, true), (Model::CGB, false)] {
+            let mut apu = APU::with_model(model);
+            for (i, address) in (0xFF30..=0xFF3F).enumerate() {
+                apu.write_byte(address, i as u8 * 0x11);
+            }
+            // length timer writes while off
+            apu.write_byte(0xFF11, 0x3F);
+            assert_eq!(dmg, apu.ch1.length_timer.timer != 0, "{model:?}");
+
+            apu.write_byte(0xFF26, 0x80);
+            apu.ch1.length_timer.timer = 10;
+            apu.write_byte(0xFF26, 0x00);
+            assert_eq!(dmg, apu.ch1.length_timer.timer == 10, "{model:?}");
+
+            // wave ram reads while playing: only at the exact time on dmg
+            apu.write_byte(0xFF26, 0x80);
+            apu.write_byte(0xFF1A, 0x80);
+            apu.write_byte(0xFF1E, 0x87);
+            let expected = match dmg {
+                true => 0xFF,
+                false => apu.ch3.wave_ram.ram[apu.ch3.wave_ram.sample_index as usize / 2],
+            };
+            assert_eq!(expected, apu.read_byte(0xFF30), "{model:?}");
+        }
+    }
+
+    #[test]
+    fn test_channel_levels() {
+        let mut apu = APU::new();
+        apu.write_byte(0xFF26, 0x80);
+        // channel 2 on the left only, 50% duty at full volume
+        apu.write_byte(0xFF25, 0x20);
+        apu.write_byte(0xFF16, 0x80);
+        apu.write_byte(0xFF17, 0xF0);
+        apu.write_byte(0xFF19, 0x87);
+
+        let mut buffers = 0;
+        while buffers < 2 {
+            buffers += apu.step(4, false).is_some() as u32;
+        }
+
+        let levels = apu.snapshot().channel_levels;
+        assert!(levels[1][0] > 0.5, "{levels:?}");
+        assert_eq!(0., levels[1][1]);
+        for channel in [0, 2, 3] {
+            assert_eq!([0., 0.], levels[channel]);
+        }
+
+        apu.write_byte(0xFF26, 0x00);
+        assert_eq!([[0.; 2]; 4], apu.snapshot().channel_levels);
+    }
+
     #[test]
     fn test_waveram_handle_period() {
         let mut wave_ram = WaveRam::new();
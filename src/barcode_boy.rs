@@ -0,0 +1,181 @@
+//! Barcode Boy card scanner, see https://gbdev.io/pandocs/Barcode_Boy.html
+//!
+//! The game first polls the scanner with a handshake clocked by the Game Boy, then waits on
+//! external clock transfers: each swiped card is sent as `0x02`, the 13 ASCII digits of its
+//! JAN-13 barcode and `0x03`, the whole packet being sent twice.
+
+use std::{collections::VecDeque, str::FromStr, sync::mpsc::Receiver};
+
+use crate::link::LinkDevice;
+
+const HANDSHAKE: [u8; 4] = [0x10, 0x07, 0x10, 0x07];
+const HANDSHAKE_REPLY: [u8; 4] = [0xFF, 0xFF, 0x10, 0x07];
+
+const PACKET_START: u8 = 0x02;
+const PACKET_END: u8 = 0x03;
+/// Cycles between two bytes of a scan, leaving the game time to rearm the next transfer
+const BYTE_GAP_CYCLES: u32 = 4096;
+
+/// 13 digits JAN/EAN barcode
+#[derive(Clone, Debug, PartialEq)]
+pub struct Barcode([u8; 13]);
+
+impl FromStr for Barcode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits: [u8; 13] = s.as_bytes().try_into().map_err(|_| ())?;
+        match digits.iter().all(u8::is_ascii_digit) {
+            true => Ok(Self(digits)),
+            false => Err(()),
+        }
+    }
+}
+
+pub struct BarcodeBoy {
+    /// cards swiped by the frontend or a script
+    scans: Receiver<Barcode>,
+    handshake: usize,
+    packet: VecDeque<u8>,
+    gap: u32,
+}
+
+impl BarcodeBoy {
+    pub fn new(scans: Receiver<Barcode>) -> Self {
+        Self {
+            scans,
+            handshake: 0,
+            packet: VecDeque::new(),
+            gap: 0,
+        }
+    }
+}
+
+impl LinkDevice for BarcodeBoy {
+    fn transfer(&mut self, sent: u8) -> u8 {
+        if sent != HANDSHAKE[self.handshake] {
+            // restarts the sequence, the scanner only answers the expected bytes
+            self.handshake = 0;
+            if sent != HANDSHAKE[0] {
+                return 0xFF;
+            }
+        }
+
+        let reply = HANDSHAKE_REPLY[self.handshake];
+        self.handshake = (self.handshake + 1) % HANDSHAKE.len();
+        reply
+    }
+
+    fn clock(&mut self, cycles: u8, _sent: u8) -> Option<u8> {
+        if self.packet.is_empty() {
+            let Barcode(digits) = self.scans.try_recv().ok()?;
+            for _ in 0..2 {
+                self.packet.push_back(PACKET_START);
+                self.packet.extend(digits);
+                self.packet.push_back(PACKET_END);
+            }
+            self.gap = 0;
+        }
+
+        self.gap += cycles as u32;
+        if self.gap < BYTE_GAP_CYCLES {
+            return None;
+        }
+        self.gap = 0;
+        self.packet.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use crate::{
+        config::Config,
+        gameboy::GameBoy,
+        joypad_events_handler, lcd,
+        pacing::Pacing,
+        saver,
+        testing::{Asm, Cond, RomBuilder, R8},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_barcode_parsing() {
+        assert!("4902370501234".parse::<Barcode>().is_ok());
+        assert!("490237050123".parse::<Barcode>().is_err());
+        assert!("49023705012a4".parse::<Barcode>().is_err());
+    }
+
+    #[test]
+    fn test_handshake() {
+        let (_, rx) = mpsc::channel();
+        let mut bcb = BarcodeBoy::new(rx);
+
+        let replies: Vec<u8> = [0x10, 0x07, 0x10, 0x07, 0x10, 0x42, 0x10]
+            .into_iter()
+            .map(|b| bcb.transfer(b))
+            .collect();
+        assert_eq!(vec![0xFF, 0xFF, 0x10, 0x07, 0xFF, 0xFF, 0xFF], replies);
+    }
+
+    #[test]
+    fn test_scan_packet() {
+        let (tx, rx) = mpsc::channel();
+        let mut bcb = BarcodeBoy::new(rx);
+        assert_eq!(None, bcb.clock(4, 0));
+
+        tx.send("4902370501234".parse().unwrap()).unwrap();
+        let mut received = vec![];
+        for _ in 0..200_000 {
+            received.extend(bcb.clock(4, 0));
+        }
+
+        let mut packet = vec![PACKET_START];
+        packet.extend(b"4902370501234");
+        packet.push(PACKET_END);
+        assert_eq!([packet.clone(), packet].concat(), received);
+    }
+
+    #[test]
+    fn test_handshake_through_link_port() {
+        let mut asm = Asm::new();
+        asm.ld_rr_nn(crate::testing::R16::HL, 0xC000);
+        for byte in HANDSHAKE {
+            asm.ld_r_n(R8::A, byte)
+                .ldh_n_a(0x01)
+                .ld_r_n(R8::A, 0x81)
+                .ldh_n_a(0x02);
+            let wait = asm.here();
+            asm.ldh_a_n(0x02)
+                .and_n(0x80)
+                .jr(Some(Cond::NZ), wait)
+                .ldh_a_n(0x01)
+                .ld_hli_a();
+        }
+        asm.spin();
+
+        let cfg = Config {
+            rom: RomBuilder::new().code(&asm).build(),
+            headless_mode: true,
+            pacing: Pacing::Uncapped,
+            ..Default::default()
+        };
+        let (_, rx) = mpsc::channel::<()>();
+        let mut gb = GameBoy::new(
+            &cfg,
+            lcd::Fake,
+            crate::stereo::Fake,
+            joypad_events_handler::Fake,
+            saver::Fake,
+            &rx,
+        );
+        let (_, scans) = mpsc::channel();
+        gb.attach_link_device(BarcodeBoy::new(scans));
+        gb.step_frame();
+
+        let received: Vec<u8> = (0xC000..0xC004).map(|a| gb.read_memory(a)).collect();
+        assert_eq!(HANDSHAKE_REPLY.to_vec(), received);
+    }
+}
@@ -1,31 +1,79 @@
-use std::sync::mpsc::Receiver;
+use std::{
+    cell::{Cell, RefCell},
+    mem,
+    ops::RangeInclusive,
+    sync::mpsc::Receiver,
+};
 
 use crate::{
-    apu::APU,
-    cartridge::Cartridge,
+    apu::{self, ApuSnapshot, APU},
+    cartridge::{Cartridge, CartridgeCapabilities, HotSwapError},
+    cheats::{Cheats, RamBank},
+    clock::{Clock, Clocked},
+    cycle_observer::{BusAccess, BusActivity, CycleObserver},
+    debug::{self, BankUsage, MemoryHeatmap, RomCoverage},
+    debugger::{WatchAction, WatchHit, WatchKind, WatchpointId, Watchpoints},
+    expr::{Context, Expr},
+    input_display,
     interrupts::InterruptRegisters,
     joypad::Joypad,
     joypad_events_handler,
-    lcd::LCD,
+    lcd::{self, DmgPalette, Frame, LCD},
+    link::LinkDevice,
     memory::MemReadWriter,
     ppu::{self, DMARequest, PPU},
     ram::RAM,
+    registers::Registers,
+    savestate::{ChunkWriter, SaveStateError, Snapshot, StateReader, StateWriter},
     serial::Serial,
-    stereo::StereoPlayer,
+    stats::AudioMonitor,
+    stereo::{
+        self, AudioFilter, FastForward, FastForwardAudio, HighPass, Normalizer, StereoPlayer,
+    },
     timer::Timer,
+    trace,
+    undocumented::UndocumentedRegisters,
 };
 
+/// Value read from addresses nothing answers to
+const OPEN_BUS: u8 = 0xFF;
+
+/// Tag of the chunk holding the bus own state (clock, speed switch)
+const BUS_TAG: [u8; 4] = *b"BUS ";
+
+/// Hands `frame` to the LCD in the format it asks for, `packed` holds the conversion output
+fn draw_frame<L: LCD>(lcd: &mut L, frame: &Frame, packed: &mut Vec<u8>) {
+    match lcd.pixel_format() {
+        Some(format) => {
+            lcd::pack_frame(frame, format, packed);
+            lcd.draw_packed(packed);
+        }
+        None => lcd.draw_buffer(frame),
+    }
+}
+
 /// Bus acts as an interface between the cpu and other system components
 pub trait Bus {
     fn read_byte(&self, address: u16) -> u8;
+    /// Reads without recording the access nor checking the watchpoints, for the debugger side
+    /// channels
+    fn peek(&self, address: u16) -> u8;
     fn write_byte(&mut self, address: u16, value: u8);
 
     fn check_interrupts(&mut self, reset_flag: bool) -> Option<u16>;
 
-    fn switch_speed(&mut self);
+    /// Switches the cpu speed if a switch was armed through KEY1, returns whether it happened
+    fn switch_speed(&mut self) -> bool;
+
+    /// Whether a pressed button pulls a P1 input line low
+    fn is_joypad_line_low(&self) -> bool;
 
     fn step_peripherals(&mut self, cycles: u8, cpu_halted: bool);
     fn is_frame_buffer_ready(&mut self) -> bool;
+
+    /// `INC rr`/`DEC rr` put the register on the address bus, corrupting OAM on monochrome
+    /// models when it points there during the OAM scan
+    fn idu_access(&mut self, _address: u16) {}
 }
 
 pub struct SystemBus<
@@ -35,21 +83,58 @@ pub struct SystemBus<
     H: joypad_events_handler::EventsHandler<E>,
     S: StereoPlayer + 'static,
 > {
-    dummy_mem: Vec<u8>,
-
     cartridge: Cartridge,
-    apu: APU<S>,
-    ppu: PPU<L>,
+    apu: APU,
+    ppu: PPU,
     int_reg: InterruptRegisters,
     joypad: Joypad,
     timer: Timer,
     serial: Serial,
     ram: RAM,
+    undocumented: UndocumentedRegisters,
     joypad_events_handler: H,
     event_rx: &'a Receiver<E>,
+    cheats: Cheats,
 
-    double_speed_mode: bool,
+    lcd: L,
+    stereo: S,
+    headless_mode: bool,
+    /// draws the joypad state over the displayed frames, see `input_display`
+    input_overlay: bool,
+    /// output of the pixel format conversion, kept to reuse its allocation
+    packed_frame: Vec<u8>,
+    /// frames not drawn after each drawn one, see `set_frameskip`
+    frameskip: u32,
+    frames_to_skip: u32,
+    /// master gain applied to the mixed samples before they're played
+    volume: f32,
+    muted: bool,
+    scaled_samples: [f32; apu::SAMPLES_BUFFER_SIZE],
+    fast_forward: FastForward,
+    high_pass: Option<HighPass>,
+    normalizer: Option<Normalizer>,
+    audio_monitor: AudioMonitor,
+
+    heatmap: Option<MemoryHeatmap>,
+    cycle_observer: Option<Box<dyn CycleObserver>>,
+    /// last cpu access, reported to the cycle observer with the step it triggers
+    pending_access: Cell<Option<BusAccess>>,
+    /// checked on cpu accesses, reads take the bus by shared reference
+    watchpoints: RefCell<Watchpoints>,
+    /// addresses watched by scripts and the writes to them not dispatched yet
+    script_writes: Vec<RangeInclusive<u16>>,
+    hooked_writes: Vec<(u16, u8)>,
+
+    clock: Clock,
     switch_armed: bool,
+
+    /// extra cpu cycles granted at the start of each scanline, see `set_overclock`
+    overclock: u32,
+    /// extra cycles left on the current line
+    overclock_budget: u32,
+    overclock_ly: u8,
+    /// LY reads `trace::DOCTOR_LY`
+    stub_ly: bool,
 }
 
 impl<
@@ -62,19 +147,21 @@ impl<
 {
     pub fn new(
         cartridge: Cartridge,
-        apu: APU<S>,
-        ppu: PPU<L>,
+        apu: APU,
+        ppu: PPU,
         int_reg: InterruptRegisters,
         joypad: Joypad,
         timer: Timer,
         serial: Serial,
         ram: RAM,
+        undocumented: UndocumentedRegisters,
         joypad_events_handler: H,
         event_rx: &'a Receiver<E>,
+        lcd: L,
+        stereo: S,
+        headless_mode: bool,
     ) -> Self {
         Self {
-            dummy_mem: vec![0xFF; 0xA0000],
-
             cartridge,
             apu,
             ppu,
@@ -83,46 +170,386 @@ impl<
             timer,
             serial,
             ram,
+            undocumented,
             joypad_events_handler,
             event_rx,
+            cheats: Cheats::new(),
+
+            lcd,
+            stereo,
+            headless_mode,
+            input_overlay: false,
+            packed_frame: vec![],
+            frameskip: 0,
+            frames_to_skip: 0,
+            volume: 1.,
+            muted: false,
+            scaled_samples: [0.; apu::SAMPLES_BUFFER_SIZE],
+            fast_forward: FastForward::new(),
+            high_pass: None,
+            normalizer: None,
+            audio_monitor: AudioMonitor::default(),
 
-            double_speed_mode: false,
+            heatmap: None,
+            cycle_observer: None,
+            pending_access: Cell::new(None),
+            watchpoints: RefCell::new(Watchpoints::default()),
+            script_writes: vec![],
+            hooked_writes: vec![],
+
+            clock: Clock::new(),
             switch_armed: false,
+
+            overclock: 0,
+            overclock_budget: 0,
+            overclock_ly: 0,
+            stub_ly: false,
         }
     }
 
-    fn oam_dma_transfer(&mut self, value: u8) {
-        let src = value as u16 * 0x100;
-        for (i, addr) in (0xFE00..=0xFE9F).enumerate() {
-            let val = self.read_byte(src + i as u16);
-            self.ppu.write_oam(addr, val);
+    pub fn set_input_overlay(&mut self, enabled: bool) {
+        self.input_overlay = enabled;
+    }
+
+    pub fn joypad(&self) -> &Joypad {
+        &self.joypad
+    }
+
+    pub fn frame_buffer(&self) -> &Frame {
+        self.ppu.frame_buffer()
+    }
+
+    pub fn joypad_mut(&mut self) -> &mut Joypad {
+        &mut self.joypad
+    }
+
+    pub fn audio_queued_samples(&self) -> usize {
+        self.stereo.queued_samples()
+    }
+
+    pub fn audio_monitor(&self) -> &AudioMonitor {
+        &self.audio_monitor
+    }
+
+    pub fn audio_monitor_mut(&mut self) -> &mut AudioMonitor {
+        &mut self.audio_monitor
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.max(0.);
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// Drops the starvations the frontend reported while the emulation was paused
+    pub fn resync_audio(&mut self) {
+        self.stereo.take_underruns();
+        self.audio_monitor.resync();
+    }
+
+    pub fn set_fast_forward(&mut self, factor: u32) {
+        self.fast_forward.set_factor(factor);
+    }
+
+    pub fn set_fast_forward_audio(&mut self, policy: FastForwardAudio) {
+        self.fast_forward.set_policy(policy);
+    }
+
+    pub fn set_stub_ly(&mut self, enabled: bool) {
+        self.stub_ly = enabled;
+    }
+
+    /// Lets the cpu run `cycles` (cpu clock) at the start of each scanline with every other
+    /// component frozen, 0 turning it off
+    pub fn set_overclock(&mut self, cycles: u32) {
+        self.overclock = cycles;
+        self.overclock_budget = 0;
+    }
+
+    pub fn set_normalizer(&mut self, target: Option<f32>) {
+        self.normalizer = target.map(Normalizer::new);
+    }
+
+    pub fn set_audio_filter(&mut self, filter: AudioFilter) {
+        self.high_pass = HighPass::new(filter);
+    }
+
+    /// Draws one frame out of `frames + 1`, the skipped ones are still emulated. The next frame
+    /// is drawn.
+    pub fn set_frameskip(&mut self, frames: u32) {
+        self.frameskip = frames;
+        self.frames_to_skip = 0;
+    }
+
+    pub fn set_palette(&mut self, colors: DmgPalette) {
+        self.ppu.set_palette(colors);
+    }
+
+    pub fn set_vblank_oam_stat(&mut self, enabled: bool) {
+        self.ppu.set_vblank_oam_stat(enabled);
+    }
+
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.ppu.set_sprite_limit(enabled);
+    }
+
+    /// Swaps the LCD frontend handle, returning the previous one
+    pub fn replace_lcd(&mut self, lcd: L) -> L {
+        std::mem::replace(&mut self.lcd, lcd)
+    }
+
+    /// Swaps the audio frontend handle, returning the previous one
+    pub fn replace_audio(&mut self, stereo: S) -> S {
+        std::mem::replace(&mut self.stereo, stereo)
+    }
+
+    pub fn hot_swap_rom(&mut self, rom: Vec<u8>) -> Result<(), HotSwapError> {
+        self.cartridge.hot_swap_rom(rom)
+    }
+
+    pub fn apu_snapshot(&self) -> ApuSnapshot {
+        self.apu.snapshot()
+    }
+
+    pub fn cartridge_capabilities(&self) -> CartridgeCapabilities {
+        self.cartridge.capabilities()
+    }
+
+    /// Plugs `device` into the link port, returning the previous one
+    pub fn set_link_device(
+        &mut self,
+        device: Option<Box<dyn LinkDevice>>,
+    ) -> Option<Box<dyn LinkDevice>> {
+        self.serial.set_device(device)
+    }
+
+    pub fn serial(&self) -> &Serial {
+        &self.serial
+    }
+
+    pub fn serial_mut(&mut self) -> &mut Serial {
+        &mut self.serial
+    }
+
+    /// Cartridge bank mapped at `address`, 0 outside of rom and cartridge ram
+    pub fn cartridge_bank(&self, address: u16) -> usize {
+        match address {
+            ..=0x7FFF => self.cartridge.rom_bank(address),
+            0xA000..=0xBFFF => self.cartridge.ram_bank().unwrap_or(0),
+            _ => 0,
         }
     }
 
-    fn vram_dma_transfer(&mut self, src: u16, dst: u16, len: u16) {
-        let src = src & 0xFFF0;
-        let dst = 0x8000 | (dst & 0x1FF0);
-        for (i, addr) in (dst..dst + len).enumerate() {
-            let val = self.read_byte(src + i as u16);
-            self.ppu.write_vram(addr, val);
+    pub fn take_rejected_save(&self) -> Option<(usize, usize)> {
+        self.cartridge.take_rejected_save()
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.cartridge.set_tilt(x, y);
+    }
+
+    pub fn rom_crc32(&self) -> u32 {
+        self.cartridge.crc32()
+    }
+
+    /// Writes a chunk per subsystem, frontend state (volume, cheats, link device...) isn't saved
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.write(&self.cartridge);
+        w.write(&self.apu);
+        w.write(&self.ppu);
+        w.write(&self.int_reg);
+        w.write(&self.joypad);
+        w.write(&self.timer);
+        w.write(&self.serial);
+        w.write(&self.ram);
+        w.write(&self.undocumented);
+
+        let mut chunk = ChunkWriter::default();
+        self.clock.save(&mut chunk);
+        chunk.write_bool(self.switch_armed);
+        w.write_chunk(BUS_TAG, 1, chunk);
+    }
+
+    /// Fails without touching anything when a chunk is missing or the state comes from another rom
+    pub fn load_state(&mut self, r: &StateReader) -> Result<(), SaveStateError> {
+        r.require(&[
+            Cartridge::TAG,
+            APU::TAG,
+            PPU::TAG,
+            InterruptRegisters::TAG,
+            Joypad::TAG,
+            Timer::TAG,
+            Serial::TAG,
+            RAM::TAG,
+            UndocumentedRegisters::TAG,
+            BUS_TAG,
+        ])?;
+        self.cartridge.check_state(r)?;
+        self.ppu.check_state(r)?;
+
+        r.read(&mut self.cartridge)?;
+        r.read(&mut self.apu)?;
+        r.read(&mut self.ppu)?;
+        r.read(&mut self.int_reg)?;
+        r.read(&mut self.joypad)?;
+        r.read(&mut self.timer)?;
+        r.read(&mut self.serial)?;
+        r.read(&mut self.ram)?;
+        r.read(&mut self.undocumented)?;
+
+        let (_, mut chunk) = r
+            .chunk(BUS_TAG)
+            .ok_or(SaveStateError::MissingChunk(BUS_TAG))?;
+        self.clock.load(&mut chunk);
+        self.switch_armed = chunk.read_bool();
+        self.pending_access.set(None);
+        chunk.check(BUS_TAG)
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    /// Writes the enabled ram cheats, the banked ones only while their bank is mapped. Called on
+    /// entering VBlank, like a GameShark hooking the VBlank interrupt.
+    fn apply_ram_cheats(&mut self) {
+        // not cpu accesses: no watchpoint, script hook or cycle observer sees them
+        for (address, value) in self.cheats.ram_writes() {
+            self.poke(address, value);
+        }
+        for (bank, address, value) in self.cheats.banked_ram_writes() {
+            let mapped = match bank {
+                RamBank::Cartridge(bank) => self.cartridge.ram_bank() == Some(bank as usize),
+                // bank 0 selects bank 1, like SVBK
+                RamBank::Work(bank) => self.ram.wram_bank() == bank.max(1),
+            };
+            if mapped {
+                self.poke(address, value);
+            }
         }
     }
-}
 
-impl<
-        'a,
-        L: LCD,
-        E: Send + 'static,
-        H: joypad_events_handler::EventsHandler<E>,
-        S: StereoPlayer,
-    > Bus for SystemBus<'a, L, E, H, S>
-{
-    fn read_byte(&self, address: u16) -> u8 {
+    /// Enables or disables bus access counting, disabling drops the collected counters
+    pub fn set_memory_heatmap(&mut self, enabled: bool) {
+        self.heatmap = match enabled {
+            true => self.heatmap.take().or_else(|| Some(MemoryHeatmap::new())),
+            false => None,
+        };
+    }
+
+    pub fn memory_heatmap(&self) -> Option<&MemoryHeatmap> {
+        self.heatmap.as_ref()
+    }
+
+    pub fn set_bank_usage(&mut self, enabled: bool) {
+        self.cartridge.set_bank_usage(enabled);
+    }
+
+    pub fn bank_usage(&self) -> Option<&BankUsage> {
+        self.cartridge.bank_usage()
+    }
+
+    pub fn set_rom_coverage(&mut self, enabled: bool) {
+        self.cartridge.set_rom_coverage(enabled);
+    }
+
+    pub fn rom_coverage(&self) -> Option<&RomCoverage> {
+        self.cartridge.rom_coverage()
+    }
+
+    /// Flags the instruction at `pc` as executed in the rom coverage
+    pub fn record_execution(&self, pc: u16) {
+        if self.cartridge.rom_coverage().is_some() && pc <= 0x7FFF {
+            let opcode = self.peek(pc);
+            self.cartridge
+                .record_execution(pc, debug::instruction_length(opcode));
+        }
+    }
+
+    pub fn set_cycle_observer(&mut self, observer: Option<Box<dyn CycleObserver>>) {
+        self.cycle_observer = observer;
+        self.pending_access.set(None);
+    }
+
+    pub fn add_watchpoint(
+        &mut self,
+        range: RangeInclusive<u16>,
+        kind: WatchKind,
+        action: WatchAction,
+        condition: Option<Expr>,
+    ) -> WatchpointId {
+        self.watchpoints
+            .get_mut()
+            .add(range, kind, action, condition)
+    }
+
+    pub fn remove_watchpoint(&mut self, id: WatchpointId) {
+        self.watchpoints.get_mut().remove(id);
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.get_mut().clear();
+    }
+
+    /// First breaking watchpoint hit since the last call
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watchpoints.get_mut().take_hit()
+    }
+
+    /// Evaluates the conditions of the watchpoints hit by the instruction that just completed
+    pub fn resolve_watch_conditions(&self, registers: &Registers, sp: u16, pc: u16) {
+        let mut watchpoints = self.watchpoints.borrow_mut();
+        if watchpoints.has_pending() {
+            let read = |address| self.peek(address);
+            watchpoints.resolve_pending(&mut Context {
+                registers,
+                sp,
+                pc,
+                read: &read,
+                access: None,
+            });
+        }
+    }
+
+    /// Writes to `ranges` are kept for `take_hooked_writes`, see `scripting`
+    pub fn set_script_writes(&mut self, ranges: Vec<RangeInclusive<u16>>) {
+        self.script_writes = ranges;
+        self.hooked_writes.clear();
+    }
+
+    /// (address, value) of the writes to the script ranges since the last call
+    pub fn take_hooked_writes(&mut self) -> Vec<(u16, u8)> {
+        mem::take(&mut self.hooked_writes)
+    }
+
+    fn check_watchpoints(&self, address: u16, value: u8, write: bool) {
+        let mut watchpoints = self.watchpoints.borrow_mut();
+        if !watchpoints.is_empty() {
+            watchpoints.check(address, value, write);
+        }
+    }
+
+    fn record_cartridge_access(&self, address: u16, write: bool) {
+        if let 0x0000..=0x7FFF | 0xA000..=0xBFFF = address {
+            self.cartridge.record_access(address, write);
+        }
+    }
+
+    /// Reads the memory map without recording the access
+    pub fn peek(&self, address: u16) -> u8 {
         match address {
-            0x0000..=0x7FFF | 0xA000..=0xBFFF | 0xFF50..=0xFF50 => {
-                self.cartridge.read_byte(address)
-            }
+            0x0000..=0x7FFF => self
+                .cheats
+                .patch_rom(address, self.cartridge.read_byte(address)),
+            0xA000..=0xBFFF | 0xFF50..=0xFF50 => self.cartridge.read_byte(address),
             0xFF10..=0xFF3F => self.apu.read_byte(address),
+            0xFF44 if self.stub_ly => trace::DOCTOR_LY,
             0x8000..=0x9FFF
             | 0xFE00..=0xFE9F
             | 0xFF40..=0xFF4B
@@ -135,17 +562,23 @@ impl<
             0xFF04..=0xFF07 => self.timer.read_byte(address),
             0xFF01..=0xFF02 => self.serial.read_byte(address),
             0xC000..=0xFDFF | 0xFF70 | 0xFF80..=0xFFFE => self.ram.read_byte(address),
+            0xFF76 if self.undocumented.is_mapped() => self.apu.pcm12(),
+            0xFF77 if self.undocumented.is_mapped() => self.apu.pcm34(),
+            0xFF72..=0xFF77 => self.undocumented.read_byte(address),
 
             0xFF4D => {
-                let spd = (self.double_speed_mode as u8) << 7 | self.switch_armed as u8;
+                let spd = (self.clock.is_double_speed() as u8) << 7 | self.switch_armed as u8;
                 spd
             }
 
-            _ => self.dummy_mem[address as usize],
+            // unmapped: FEA0-FEFF and unused IO registers
+            _ => OPEN_BUS,
         }
     }
 
-    fn write_byte(&mut self, address: u16, value: u8) {
+    /// Writes the memory map without recording the access nor checking the watchpoints, freeze
+    /// cheats and script hooks don't see it either
+    pub fn poke(&mut self, address: u16, value: u8) {
         match address {
             0x0000..=0x7FFF | 0xA000..=0xBFFF | 0xFF50..=0xFF50 => {
                 self.cartridge.write_byte(address, value)
@@ -163,36 +596,91 @@ impl<
             0xFF04..=0xFF07 => self.timer.write_byte(address, value),
             0xFF01..=0xFF02 => self.serial.write_byte(address, value),
             0xC000..=0xFDFF | 0xFF70 | 0xFF80..=0xFFFE => self.ram.write_byte(address, value),
+            0xFF72..=0xFF77 => self.undocumented.write_byte(address, value),
 
             0xFF4D => self.switch_armed = value & 1 == 1,
 
-            _ => self.dummy_mem[address as usize] = value,
+            _ => {}
         };
     }
 
-    fn check_interrupts(&mut self, reset_flag: bool) -> Option<u16> {
-        self.int_reg.check(reset_flag)
+    fn read_dma_byte(&self, src: u16, dst: u16) -> u8 {
+        if let Some(heatmap) = &self.heatmap {
+            heatmap.record_dma(src);
+            heatmap.record_dma(dst);
+        }
+        self.record_cartridge_access(src, false);
+        self.peek(src)
     }
 
-    fn switch_speed(&mut self) {
-        if self.switch_armed {
-            self.double_speed_mode = !self.double_speed_mode;
-            self.switch_armed = false;
+    fn oam_dma_transfer(&mut self, value: u8) {
+        let src = value as u16 * 0x100;
+        for (i, addr) in (0xFE00..=0xFE9F).enumerate() {
+            let val = self.read_dma_byte(src + i as u16, addr);
+            self.ppu.write_oam(addr, val);
         }
     }
 
-    fn step_peripherals(&mut self, cycles: u8, cpu_halted: bool) {
-        let normal_speed_cycles = if self.double_speed_mode {
-            cycles / 2
+    /// https://gbdev.io/pandocs/CGB_Registers.html#ff51ff52--hdma1-hdma2-cgb-mode-only-vram-dma-source-high-low
+    /// Sources are only valid in ROM, cartridge RAM and WRAM, VRAM and echo RAM onwards read open bus.
+    /// The destination is confined to VRAM, the copy stops once it passes 0x9FFF.
+    fn vram_dma_transfer(&mut self, src: u16, dst: u16, len: u16) {
+        let src = src & 0xFFF0;
+        let dst = 0x8000 | (dst & 0x1FF0);
+        for i in 0..len {
+            let addr = dst as u32 + i as u32;
+            if addr > 0x9FFF {
+                break;
+            }
+            let addr = addr as u16;
+
+            let val = match src.wrapping_add(i) {
+                0x8000..=0x9FFF | 0xE000..=0xFFFF => 0xFF,
+                src => self.read_dma_byte(src, addr),
+            };
+            self.ppu.write_vram(addr, val);
+        }
+    }
+
+    /// Draws `frame` instead of the emulated one, nothing is drawn in headless mode
+    pub fn show_frame(&mut self, frame: &Frame) {
+        if !self.headless_mode {
+            draw_frame(&mut self.lcd, frame, &mut self.packed_frame);
+        }
+    }
+
+    fn output_frame(&mut self) {
+        let overlay_frame;
+        let frame = if self.input_overlay {
+            // drawn on a copy, the emulated frame stays untouched
+            let mut frame = self.ppu.frame_buffer().clone();
+            input_display::draw_input_overlay(&mut frame.pixels, &self.joypad.pressed_buttons());
+            overlay_frame = frame;
+            &overlay_frame
         } else {
-            cycles
+            self.ppu.frame_buffer()
         };
 
-        self.joypad_events_handler
-            .handle_events(self.event_rx, &mut self.joypad);
+        draw_frame(&mut self.lcd, frame, &mut self.packed_frame);
+    }
+
+    /// Steps every clocked component, returning the cpu cycles stalled by a VRAM DMA transfer
+    fn step_components(&mut self, cycles: u8, cpu_halted: bool) -> u32 {
+        let ticks = self.clock.tick(cycles);
 
-        self.ppu.step(&mut self.int_reg, normal_speed_cycles);
+        if self.ppu.step(&mut self.int_reg, ticks.get(PPU::DOMAIN)) && !self.headless_mode {
+            if self.frames_to_skip == 0 {
+                self.output_frame();
+                self.frames_to_skip = self.frameskip;
+            } else {
+                self.frames_to_skip -= 1;
+            }
+        }
+        if self.ppu.take_vblank_started() && !self.cheats.is_empty() {
+            self.apply_ram_cheats();
+        }
 
+        let mut stall_cycles = 0;
         if let Some(req) = self.ppu.check_dma_request() {
             // TODO: handle with cycle accuracy
             match req {
@@ -208,23 +696,420 @@ impl<
                 } => {
                     if !is_hdma || !cpu_halted {
                         self.vram_dma_transfer(src, dst, len);
+                        stall_cycles = req.stall_cycles(self.clock.is_double_speed());
                         self.ppu.dma_transfer_done(req);
                     }
                 }
             }
         }
 
-        self.timer
-            .step(&mut self.int_reg, cycles, self.double_speed_mode);
+        self.timer.step(
+            &mut self.int_reg,
+            ticks.get(Timer::DOMAIN),
+            self.clock.is_double_speed(),
+        );
+
+        self.serial
+            .step(&mut self.int_reg, ticks.get(Serial::DOMAIN));
 
         let div_apu_event = self.timer.check_apu_div();
 
-        self.apu.step(normal_speed_cycles, div_apu_event);
+        let samples = self.apu.step(ticks.get(APU::DOMAIN), div_apu_event);
+        if let Some(samples) = samples.and_then(|s| self.fast_forward.process(s)) {
+            let samples = match &mut self.high_pass {
+                Some(high_pass) => high_pass.process(samples),
+                None => samples,
+            };
+            let samples = match &mut self.normalizer {
+                Some(normalizer) => normalizer.process(samples),
+                None => samples,
+            };
+            self.audio_monitor
+                .before_play(self.stereo.queued_samples(), self.stereo.take_underruns());
+
+            if !self.muted && self.volume == 1. {
+                self.stereo.play(samples);
+            } else {
+                let gain = if self.muted { 0. } else { self.volume };
+                stereo::apply_gain(samples, gain, &mut self.scaled_samples);
+                self.stereo.play(&self.scaled_samples);
+            }
+
+            self.audio_monitor.after_play(self.stereo.queued_samples());
+        }
+
+        stall_cycles
+    }
+}
+
+impl<
+        'a,
+        L: LCD,
+        E: Send + 'static,
+        H: joypad_events_handler::EventsHandler<E>,
+        S: StereoPlayer,
+    > Bus for SystemBus<'a, L, E, H, S>
+{
+    fn read_byte(&self, address: u16) -> u8 {
+        if let Some(heatmap) = &self.heatmap {
+            heatmap.record_read(address);
+        }
+        self.record_cartridge_access(address, false);
+        let value = self.peek(address);
+        self.check_watchpoints(address, value, false);
+        if self.cycle_observer.is_some() {
+            self.pending_access
+                .set(Some(BusAccess::Read { address, value }));
+        }
+        value
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        SystemBus::peek(self, address)
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) {
+        let value = self.cheats.patch_write(address, value);
+        if let Some(heatmap) = &self.heatmap {
+            heatmap.record_write(address);
+        }
+        self.record_cartridge_access(address, true);
+        self.check_watchpoints(address, value, true);
+        if self.script_writes.iter().any(|r| r.contains(&address)) {
+            self.hooked_writes.push((address, value));
+        }
+        if self.cycle_observer.is_some() {
+            self.pending_access
+                .set(Some(BusAccess::Write { address, value }));
+        }
+        self.poke(address, value);
+    }
+
+    fn check_interrupts(&mut self, reset_flag: bool) -> Option<u16> {
+        self.int_reg.check(reset_flag)
+    }
+
+    fn switch_speed(&mut self) -> bool {
+        if !self.switch_armed {
+            return false;
+        }
+        self.clock.toggle_speed();
+        self.switch_armed = false;
+        true
+    }
+
+    fn is_joypad_line_low(&self) -> bool {
+        self.joypad.is_line_low()
+    }
+
+    fn step_peripherals(&mut self, cycles: u8, cpu_halted: bool) {
+        // a halted cpu has nothing to do with extra cycles, the line goes on
+        if self.overclock_budget > 0 && !cpu_halted {
+            self.overclock_budget = self.overclock_budget.saturating_sub(cycles as u32);
+            self.pending_access.set(None);
+            return;
+        }
+
+        self.joypad_events_handler
+            .handle_events(self.event_rx, &mut self.joypad);
+
+        let mut stall_cycles = self.step_components(cycles, cpu_halted);
+
+        // the cpu doesn't execute while a VRAM DMA runs but the rest of the system keeps going
+        let mut dma_stall_cycles = 0;
+        while stall_cycles > 0 {
+            let chunk = stall_cycles.min(4);
+            stall_cycles -= chunk;
+            dma_stall_cycles += chunk;
+            stall_cycles += self.step_components(chunk as u8, cpu_halted);
+        }
 
         self.joypad.check(&mut self.int_reg);
+
+        // granted on the line start, in OAM scan: writes done by LY=LYC or mode 2 STAT handlers
+        // still land before the line is drawn
+        if self.overclock > 0 && self.ppu.ly() != self.overclock_ly {
+            self.overclock_ly = self.ppu.ly();
+            self.overclock_budget = self.overclock;
+        }
+
+        if let Some(observer) = &mut self.cycle_observer {
+            observer.on_step(&BusActivity {
+                cycles,
+                dma_stall_cycles,
+                double_speed: self.clock.is_double_speed(),
+                cpu_halted,
+                access: self.pending_access.take(),
+                interrupt_flags: self.int_reg.read_byte(0xFF0F),
+            });
+        }
     }
 
     fn is_frame_buffer_ready(&mut self) -> bool {
         self.ppu.is_frame_buffer_ready()
     }
+
+    fn idu_access(&mut self, address: u16) {
+        if let 0xFE00..=0xFEFF = address {
+            self.ppu.trigger_oam_bug();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc, sync::mpsc};
+
+    use crate::{
+        config::Config, joypad_events_handler, lcd, mode::Mode, oam::OAM, saver, serial::Serial,
+        stereo, testing, timer::Timer, vram::VRAM,
+    };
+
+    use super::*;
+
+    fn with_cgb_bus(
+        test: impl FnOnce(&mut SystemBus<lcd::Fake, (), joypad_events_handler::Fake, stereo::Fake>),
+    ) {
+        let cfg = Config {
+            mode: Mode::CGB,
+            rom: testing::idle_rom(),
+            headless_mode: true,
+            ..Default::default()
+        };
+        let (_, rx) = mpsc::channel::<()>();
+        let mut bus = SystemBus::new(
+            Cartridge::new(&cfg, saver::Fake).unwrap(),
+            APU::new(),
+            PPU::new(&cfg, VRAM::new(Mode::CGB), OAM::new()),
+            InterruptRegisters::new(),
+            Joypad::new(),
+            Timer::new(),
+            Serial::new(Mode::CGB),
+            RAM::new(Mode::CGB),
+            UndocumentedRegisters::new(Mode::CGB),
+            joypad_events_handler::Fake,
+            &rx,
+            lcd::Fake,
+            stereo::Fake,
+            true,
+        );
+        test(&mut bus);
+    }
+
+    fn start_gdma<B: Bus>(bus: &mut B, src: u16, dst: u16, blocks: u8) {
+        bus.write_byte(0xFF51, (src >> 8) as u8);
+        bus.write_byte(0xFF52, src as u8);
+        bus.write_byte(0xFF53, (dst >> 8) as u8);
+        bus.write_byte(0xFF54, dst as u8);
+        bus.write_byte(0xFF55, blocks - 1);
+        bus.step_peripherals(4, false);
+    }
+
+    #[test]
+    fn test_vram_dma_masks_addresses() {
+        with_cgb_bus(|bus| {
+            for i in 0..0x20 {
+                bus.write_byte(0xC000 + i, i as u8 + 1);
+            }
+
+            // low source nibble ignored, destination forced into VRAM
+            start_gdma(bus, 0xC00F, 0x2105, 1);
+            for i in 0..0x10 {
+                assert_eq!(i as u8 + 1, bus.peek(0x8100 + i));
+            }
+        });
+    }
+
+    #[test]
+    fn test_vram_dma_invalid_source_reads_ff() {
+        with_cgb_bus(|bus| {
+            bus.write_byte(0xC000, 0x42);
+
+            // echo ram isn't mirrored for DMA
+            start_gdma(bus, 0xE000, 0x8000, 1);
+            assert_eq!(0xFF, bus.peek(0x8000));
+
+            bus.write_byte(0x9000, 0x24);
+            start_gdma(bus, 0x9000, 0x8010, 1);
+            assert_eq!(0xFF, bus.peek(0x8010));
+        });
+    }
+
+    #[test]
+    fn test_vram_dma_stops_at_vram_end() {
+        with_cgb_bus(|bus| {
+            for i in 0..0x20 {
+                bus.write_byte(0xC000 + i, 0x11);
+            }
+
+            // the second block would land past VRAM
+            start_gdma(bus, 0xC000, 0x9FF0, 2);
+            assert_eq!(0x11, bus.peek(0x9FF0));
+            assert_eq!(0x11, bus.peek(0x9FFF));
+        });
+    }
+
+    /// Cpu cycles stalled by each VRAM DMA transfer
+    #[derive(Clone, Default)]
+    struct DmaStalls(Rc<RefCell<Vec<u32>>>);
+
+    impl CycleObserver for DmaStalls {
+        fn on_step(&mut self, activity: &BusActivity) {
+            if activity.dma_stall_cycles > 0 {
+                self.0.borrow_mut().push(activity.dma_stall_cycles);
+            }
+        }
+    }
+
+    fn run_lines<B: Bus>(bus: &mut B, lines: usize, cpu_halted: bool) {
+        for _ in 0..lines {
+            let ly = bus.read_byte(0xFF44);
+            while bus.read_byte(0xFF44) == ly {
+                bus.step_peripherals(4, cpu_halted);
+            }
+        }
+    }
+
+    #[test]
+    fn test_vram_dma_stalls_cpu() {
+        for double_speed in [false, true] {
+            with_cgb_bus(|bus| {
+                if double_speed {
+                    bus.write_byte(0xFF4D, 1);
+                    bus.switch_speed();
+                }
+                let stalls = DmaStalls::default();
+                bus.set_cycle_observer(Some(Box::new(stalls.clone())));
+                // 8 M-cycles per 0x10 bytes block, in cpu clock
+                let block = if double_speed { 64 } else { 32 };
+
+                // general purpose: every block at once
+                start_gdma(bus, 0xC000, 0x8000, 4);
+                assert_eq!(vec![4 * block], stalls.0.take(), "{double_speed}");
+
+                // HBlank: one block per line, held while the cpu is halted
+                run_lines(bus, 1, false);
+                start_gdma(bus, 0xC000, 0x8000, 0x82);
+                run_lines(bus, 2, true);
+                assert!(stalls.0.borrow().is_empty(), "{double_speed}");
+                run_lines(bus, 3, false);
+                assert_eq!(vec![block, block], stalls.0.take(), "{double_speed}");
+                assert_eq!(0xFF, bus.read_byte(0xFF55));
+            });
+        }
+    }
+
+    #[test]
+    fn test_double_speed_clock_domains() {
+        with_cgb_bus(|bus| {
+            bus.write_byte(0xFF4D, 1);
+            bus.switch_speed();
+            assert_eq!(0x80, bus.read_byte(0xFF4D) & 0x80);
+
+            // fast serial clock: 128 cpu cycles per byte whatever the speed
+            bus.write_byte(0xFF02, 0x83);
+            let ly = bus.read_byte(0xFF44);
+            for _ in 0..128 / 4 {
+                bus.step_peripherals(4, false);
+            }
+            assert_eq!(0, bus.read_byte(0xFF02) & 0x80);
+
+            // a line is 456 dots, twice as many cpu cycles
+            for _ in 0..(456 * 2 - 128) / 4 - 1 {
+                bus.step_peripherals(4, false);
+            }
+            assert_eq!(ly, bus.read_byte(0xFF44));
+            bus.step_peripherals(4, false);
+            assert_eq!(ly + 1, bus.read_byte(0xFF44));
+        });
+    }
+
+    #[test]
+    fn test_overclock_freezes_peripherals_on_line_start() {
+        with_cgb_bus(|bus| {
+            fn next_line<B: Bus>(bus: &mut B, cpu_halted: bool) -> usize {
+                let ly = bus.read_byte(0xFF44);
+                let mut steps = 0;
+                while bus.read_byte(0xFF44) == ly {
+                    bus.step_peripherals(4, cpu_halted);
+                    steps += 1;
+                }
+                steps
+            }
+
+            next_line(bus, false);
+            assert_eq!(456 / 4, next_line(bus, false));
+
+            bus.set_overclock(40);
+            next_line(bus, false);
+            let div = bus.read_byte(0xFF04);
+            for _ in 0..40 / 4 {
+                bus.step_peripherals(4, false);
+            }
+            assert_eq!(div, bus.read_byte(0xFF04));
+            assert_eq!(456 / 4, next_line(bus, false));
+            assert_eq!((456 + 40) / 4, next_line(bus, false));
+
+            // not spent while halted
+            assert_eq!(456 / 4, next_line(bus, true));
+
+            bus.set_overclock(0);
+            next_line(bus, false);
+            assert_eq!(456 / 4, next_line(bus, false));
+        });
+    }
+
+    #[test]
+    fn test_unmapped_addresses_are_open_bus() {
+        with_cgb_bus(|bus| {
+            for address in [
+                0xFEA0, 0xFEFF, 0xFF03, 0xFF08, 0xFF4C, 0xFF56, 0xFF71, 0xFF7F,
+            ] {
+                bus.write_byte(address, 0x12);
+                assert_eq!(OPEN_BUS, bus.read_byte(address), "{address:#06x}");
+            }
+        });
+    }
+
+    #[test]
+    fn test_cycle_observer() {
+        struct Recorder(Rc<RefCell<Vec<BusActivity>>>);
+
+        impl CycleObserver for Recorder {
+            fn on_step(&mut self, activity: &BusActivity) {
+                self.0.borrow_mut().push(*activity);
+            }
+        }
+
+        with_cgb_bus(|bus| {
+            let steps = Rc::new(RefCell::new(vec![]));
+            bus.set_cycle_observer(Some(Box::new(Recorder(steps.clone()))));
+
+            bus.write_byte(0xC000, 0x42);
+            bus.step_peripherals(4, false);
+            bus.read_byte(0xC000);
+            bus.step_peripherals(4, false);
+            bus.step_peripherals(8, true);
+
+            let steps = steps.borrow();
+            assert_eq!(3, steps.len());
+            assert_eq!(
+                Some(BusAccess::Write {
+                    address: 0xC000,
+                    value: 0x42
+                }),
+                steps[0].access
+            );
+            assert_eq!(
+                Some(BusAccess::Read {
+                    address: 0xC000,
+                    value: 0x42
+                }),
+                steps[1].access
+            );
+            assert_eq!(None, steps[2].access);
+            assert_eq!(8, steps[2].cycles);
+            assert!(steps[2].cpu_halted);
+        });
+    }
 }
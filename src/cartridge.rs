@@ -1,14 +1,33 @@
 use std::{
+    fmt,
     io::{self, Write},
     str::Utf8Error,
 };
 
-use crc::{Crc, CRC_32_ISO_HDLC};
-
-use crate::{config::Config, mbc, memory::MemReadWriter, mode::Mode, saver::GameSave};
+use crate::{
+    config::Config,
+    debug::{BankUsage, RomCoverage},
+    mbc,
+    memory::MemReadWriter,
+    mode::Mode,
+    romdb,
+    saver::GameSave,
+    savestate::{ChunkReader, ChunkWriter, SaveStateError, Snapshot, StateReader},
+};
 
 const ROM_CHECKSUM_ADDRESS: usize = 0x014D;
 const CARTRIDGE_TYPE_ADDRESS: usize = 0x0147;
+const CGB_FLAG_ADDRESS: usize = 0x0143;
+const SGB_FLAG_ADDRESS: usize = 0x0146;
+const OLD_LICENSEE_CODE_ADDRESS: usize = 0x014B;
+const LOGO_ADDRESS: usize = 0x0104;
+
+/// https://gbdev.io/pandocs/The_Cartridge_Header.html#0104-0133--nintendo-logo
+pub(crate) const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
 
 fn compute_rom_checksum(rom: &Vec<u8>) -> u8 {
     let mut checksum: u8 = 0;
@@ -22,17 +41,71 @@ fn validate_rom_checksum(rom: &Vec<u8>, checksum: u8) -> bool {
     rom[ROM_CHECKSUM_ADDRESS] == checksum
 }
 
-fn bytes_to_string(bytes: &[u8]) -> Result<String, Utf8Error> {
-    Ok((std::str::from_utf8(bytes)?).to_string())
+/// Header checks done by the boot rom, which locks up when one fails
+#[derive(Debug, PartialEq)]
+pub enum HeaderError {
+    /// The Nintendo logo doesn't match, only its top half is checked in CGB mode
+    InvalidLogo,
+    ChecksumMismatch {
+        computed: u8,
+        expected: u8,
+    },
+    /// `Config::mapper` forces a cartridge type no mapper is emulated for
+    UnsupportedMapperOverride(u8),
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidLogo => write!(f, "invalid Nintendo logo"),
+            Self::ChecksumMismatch { computed, expected } => write!(
+                f,
+                "header checksum mismatch: computed {computed:#04x}, expected {expected:#04x}"
+            ),
+            Self::UnsupportedMapperOverride(code) => {
+                write!(
+                    f,
+                    "no mapper emulated for the forced cartridge type {code:#04x}"
+                )
+            }
+        }
+    }
 }
 
-fn checksum_identifier(rom: &[u8]) -> u32 {
-    const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
-    CRC32.checksum(rom)
+impl std::error::Error for HeaderError {}
+
+/// Failed header checks, in the order the boot rom runs them
+fn check_header(rom: &Vec<u8>, mode: &Mode) -> Vec<HeaderError> {
+    let mut errors = vec![];
+
+    let logo_len = match mode {
+        Mode::DMG => NINTENDO_LOGO.len(),
+        Mode::CGB => NINTENDO_LOGO.len() / 2,
+    };
+    if rom[LOGO_ADDRESS..LOGO_ADDRESS + logo_len] != NINTENDO_LOGO[..logo_len] {
+        errors.push(HeaderError::InvalidLogo);
+    }
+
+    let rom_checksum = compute_rom_checksum(rom);
+    if !validate_rom_checksum(rom, rom_checksum) {
+        errors.push(HeaderError::ChecksumMismatch {
+            computed: rom_checksum,
+            expected: rom[ROM_CHECKSUM_ADDRESS],
+        });
+    }
+
+    errors
+}
+
+fn bytes_to_string(bytes: &[u8]) -> Result<String, Utf8Error> {
+    Ok((std::str::from_utf8(bytes)?).to_string())
 }
 
 #[allow(dead_code)]
 struct Header {
+    cgb_flag: u8,
+    sgb_flag: u8,
+    cartridge_type: u8,
     title: String,
     new_licensee_code: String,
     old_licensee_code: u8,
@@ -42,44 +115,174 @@ struct Header {
     rom_version: u8,
 }
 
+impl Header {
+    fn new(rom: &[u8]) -> Self {
+        Self {
+            cgb_flag: rom[CGB_FLAG_ADDRESS],
+            sgb_flag: rom[SGB_FLAG_ADDRESS],
+            cartridge_type: rom[CARTRIDGE_TYPE_ADDRESS],
+            title: bytes_to_string(&rom[0x0134..=0x0143]).unwrap_or("ERROR PARSING TITLE".into()),
+            new_licensee_code: bytes_to_string(&rom[0x0144..=0x0145])
+                .unwrap_or("ERROR PARSING NEW LICENSEE CODE".into()),
+            old_licensee_code: rom[OLD_LICENSEE_CODE_ADDRESS],
+            rom_size: rom[0x0148],
+            ram_size: rom[0x0149],
+            destination_code: rom[0x014A],
+            rom_version: rom[0x014C],
+        }
+    }
+}
+
+/// https://gbdev.io/pandocs/The_Cartridge_Header.html#0143--cgb-flag
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CgbSupport {
+    None,
+    /// Also runs on DMG
+    Enhanced,
+    Exclusive,
+}
+
+/// Features a game declares in its header, for frontends to show badges or warn about
+/// hardware the emulator doesn't support
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CartridgeCapabilities {
+    pub sgb: bool,
+    pub cgb: CgbSupport,
+    pub battery: bool,
+    pub rumble: bool,
+    pub rtc: bool,
+    /// MBC7 tilt sensor, fed with `GameBoy::set_tilt`
+    pub accelerometer: bool,
+    cartridge_type: u8,
+}
+
+impl CartridgeCapabilities {
+    /// Reads the capabilities from a rom header, None when the rom is too small to hold one
+    pub fn from_rom(rom: &[u8]) -> Option<Self> {
+        if rom.len() <= ROM_CHECKSUM_ADDRESS {
+            return None;
+        }
+        Some(Self::from_header(&Header::new(rom)))
+    }
+
+    fn from_header(header: &Header) -> Self {
+        // https://gbdev.io/pandocs/The_Cartridge_Header.html#0147--cartridge-type
+        let cartridge_type = header.cartridge_type;
+        Self {
+            // the sgb flag is ignored unless the old licensee code defers to the new one
+            sgb: header.sgb_flag == 0x03 && header.old_licensee_code == 0x33,
+            cgb: match header.cgb_flag {
+                0x80 => CgbSupport::Enhanced,
+                0xC0 => CgbSupport::Exclusive,
+                _ => CgbSupport::None,
+            },
+            battery: matches!(
+                cartridge_type,
+                0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFE | 0xFF
+            ),
+            rumble: matches!(cartridge_type, 0x1C..=0x1E),
+            rtc: matches!(cartridge_type, 0x0F | 0x10 | 0xFE),
+            accelerometer: cartridge_type == 0x22,
+            cartridge_type,
+        }
+    }
+
+    /// Declared features the emulator doesn't emulate yet
+    pub fn unsupported(&self) -> Vec<&'static str> {
+        let mut features = vec![];
+        if self.sgb {
+            features.push("SGB");
+        }
+        if self.rumble {
+            features.push("rumble");
+        }
+        // only the HuC3 clock is emulated, not the MBC3 one
+        if self.rtc && self.cartridge_type != 0xFE {
+            features.push("RTC");
+        }
+        features
+    }
+}
+
+/// Smallest rom size, two 16 KiB banks
+const MIN_ROM_SIZE: usize = 0x8000;
+
+/// Cartridge controller forced over the header detection, see `Config::mapper`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MapperOverride {
+    /// Cartridge type code (0x0147) to use, e.g. 0x19 for an MBC5
+    CartridgeType(u8),
+    /// Wisdom Tree: the low byte of the address written in 0000-3FFF selects a 32 KiB bank
+    WisdomTree,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HotSwapError {
+    /// The new rom is too small to hold two banks
+    InvalidRom,
+    /// Cartridge type byte (0x0147) differs, the mapper can't be kept
+    MapperMismatch { current: u8, new: u8 },
+    /// Ram size byte (0x0149) differs, the cartridge ram can't be kept
+    RamSizeMismatch { current: u8, new: u8 },
+}
+
+impl fmt::Display for HotSwapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidRom => write!(f, "rom is smaller than {MIN_ROM_SIZE:#x} bytes"),
+            Self::MapperMismatch { current, new } => write!(
+                f,
+                "cartridge type mismatch: current {current:#04x}, new {new:#04x}"
+            ),
+            Self::RamSizeMismatch { current, new } => {
+                write!(
+                    f,
+                    "ram size mismatch: current {current:#04x}, new {new:#04x}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for HotSwapError {}
+
 pub struct Cartridge {
     mode: Mode,
     bootrom_enabled: bool,
     bootrom: Option<Vec<u8>>,
-    #[allow(dead_code)]
     header: Header,
+    crc32: u32,
     mbc: mbc::MBC,
 }
 
 impl Cartridge {
-    pub fn new<S: GameSave + 'static>(cfg: &Config, mut saver: S) -> Self {
+    /// Fails on the first bad header check with `Config::strict_header`, only warns otherwise.
+    /// Always fails on a `Config::mapper` override without emulated mapper.
+    pub fn new<S: GameSave + 'static>(cfg: &Config, mut saver: S) -> Result<Self, HeaderError> {
         let rom = &cfg.rom;
+        if let Some(MapperOverride::CartridgeType(code)) = cfg.mapper {
+            if !mbc::is_supported_cartridge_type(code) {
+                return Err(HeaderError::UnsupportedMapperOverride(code));
+            }
+        }
 
-        let rom_checksum = compute_rom_checksum(rom);
-        if !validate_rom_checksum(rom, rom_checksum) {
-            _ = io::stderr().write(
-                format!(
-                    "WARNING: game rom checksum mismatch! computed checksum: {}; rom checksum: {}\n",
-                    rom_checksum & 0xFF,
-                    rom[ROM_CHECKSUM_ADDRESS],
-                )
-                .as_bytes(),
-            );
+        let mut header_errors = check_header(rom, &cfg.mode);
+        if cfg.strict_header && !header_errors.is_empty() {
+            return Err(header_errors.remove(0));
+        }
+        for error in header_errors {
+            _ = io::stderr().write(format!("WARNING: game rom {error}\n").as_bytes());
         }
 
-        let header = Header {
-            title: bytes_to_string(&rom[0x0134..=0x0143]).unwrap_or("ERROR PARSING TITLE".into()),
-            new_licensee_code: bytes_to_string(&rom[0x0144..=0x0145])
-                .unwrap_or("ERROR PARSING NEW LICENSEE CODE".into()),
-            old_licensee_code: rom[0x014B],
-            rom_size: rom[0x0148],
-            ram_size: rom[0x0149],
-            destination_code: rom[0x014A],
-            rom_version: rom[0x014C],
-        };
+        let header = Header::new(rom);
+        let crc32 = romdb::rom_crc32(rom);
 
-        let title = header.title.clone().trim_matches('\0').to_owned();
-        saver.set_title(format!("{title}-{:08x}", checksum_identifier(rom)));
+        // the crc suffix keeps saves apart when titles collide
+        let title = match &cfg.rom_info {
+            Some(info) => info.file_name(),
+            None => header.title.clone().trim_matches('\0').to_owned(),
+        };
+        saver.set_title(format!("{title}-{crc32:08x}"));
 
         let ram_size = match header.ram_size {
             0x00 | 0x01 => 0,
@@ -90,16 +293,147 @@ impl Cartridge {
             _ => unreachable!(),
         };
 
-        Self {
+        Ok(Self {
             mode: cfg.mode.clone(),
             bootrom_enabled: cfg.bootrom.is_some(),
             bootrom: cfg.bootrom.clone(),
-            mbc: mbc::MBC::new(rom[CARTRIDGE_TYPE_ADDRESS], rom.clone(), ram_size, saver),
+            mbc: mbc::MBC::new(
+                rom[CARTRIDGE_TYPE_ADDRESS],
+                cfg.mapper,
+                rom.clone(),
+                ram_size,
+                saver,
+            ),
             header,
+            crc32,
+        })
+    }
+
+    /// Swaps the rom contents in place, keeping the mapper state and the cartridge ram.
+    /// The new rom must use the same mapper and ram size as the current one.
+    pub fn hot_swap_rom(&mut self, rom: Vec<u8>) -> Result<(), HotSwapError> {
+        if rom.len() < MIN_ROM_SIZE {
+            return Err(HotSwapError::InvalidRom);
+        }
+
+        let header = Header::new(&rom);
+        if header.cartridge_type != self.header.cartridge_type {
+            return Err(HotSwapError::MapperMismatch {
+                current: self.header.cartridge_type,
+                new: header.cartridge_type,
+            });
+        }
+        if header.ram_size != self.header.ram_size {
+            return Err(HotSwapError::RamSizeMismatch {
+                current: self.header.ram_size,
+                new: header.ram_size,
+            });
+        }
+
+        self.crc32 = romdb::rom_crc32(&rom);
+        self.mbc.replace_rom(rom);
+        self.header = header;
+
+        Ok(())
+    }
+
+    pub fn capabilities(&self) -> CartridgeCapabilities {
+        CartridgeCapabilities::from_header(&self.header)
+    }
+
+    pub fn set_bank_usage(&mut self, enabled: bool) {
+        self.mbc.set_bank_usage(enabled);
+    }
+
+    pub fn bank_usage(&self) -> Option<&BankUsage> {
+        self.mbc.bank_usage()
+    }
+
+    pub fn set_rom_coverage(&mut self, enabled: bool) {
+        self.mbc.set_rom_coverage(enabled);
+    }
+
+    pub fn rom_coverage(&self) -> Option<&RomCoverage> {
+        self.mbc.rom_coverage()
+    }
+
+    /// (expected, found) sizes of the save rejected when loading, once
+    pub fn take_rejected_save(&self) -> Option<(usize, usize)> {
+        self.mbc.take_rejected_save()
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mbc.set_tilt(x, y);
+    }
+
+    /// Rom bank mapped at `address`, in 0000-7FFF
+    pub fn rom_bank(&self, address: u16) -> usize {
+        self.mbc.rom_bank(address)
+    }
+
+    /// Ram bank mapped at A000-BFFF, None when the ram is disabled or absent
+    pub fn ram_bank(&self) -> Option<usize> {
+        self.mbc.ram_bank()
+    }
+
+    /// Counts an access in the bank usage and the rom coverage, the bootrom overlay aside
+    pub fn record_access(&self, address: u16, write: bool) {
+        if !self.bootrom_overlays(address) {
+            self.mbc.record_access(address, write);
+        }
+    }
+
+    /// Flags the instruction at `address` in the rom coverage, the bootrom overlay aside
+    pub fn record_execution(&self, address: u16, len: u16) {
+        if !self.bootrom_overlays(address) {
+            self.mbc.record_execution(address, len);
+        }
+    }
+
+    fn bootrom_overlays(&self, address: u16) -> bool {
+        if !self.bootrom_enabled || self.bootrom.is_none() {
+            return false;
+        }
+        match self.mode {
+            Mode::DMG => address <= 0x00FF,
+            Mode::CGB => address <= 0x00FF || (0x0200..=0x08FF).contains(&address),
+        }
+    }
+
+    /// CRC32 of the loaded rom, see `romdb::rom_crc32`
+    pub fn crc32(&self) -> u32 {
+        self.crc32
+    }
+
+    /// Fails when `state` was made with another rom
+    pub fn check_state(&self, state: &StateReader) -> Result<(), SaveStateError> {
+        let (_, mut r) = state
+            .chunk(Self::TAG)
+            .ok_or(SaveStateError::MissingChunk(Self::TAG))?;
+        match r.read_u32() {
+            crc32 if crc32 == self.crc32 => Ok(()),
+            crc32 => Err(SaveStateError::RomMismatch(crc32, self.crc32)),
         }
     }
 }
 
+impl Snapshot for Cartridge {
+    const TAG: [u8; 4] = *b"CART";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, w: &mut ChunkWriter) {
+        w.write_u32(self.crc32);
+        w.write_bool(self.bootrom_enabled);
+        self.mbc.save(w);
+    }
+
+    fn load_state(&mut self, r: &mut ChunkReader, _version: u16) {
+        _ = r.read_u32();
+        self.bootrom_enabled = r.read_bool();
+        self.mbc.load(r);
+    }
+}
+
 impl MemReadWriter for Cartridge {
     fn read_byte(&self, address: u16) -> u8 {
         match self.mode {
@@ -156,3 +490,165 @@ impl MemReadWriter for Cartridge {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{saver, testing};
+
+    use super::*;
+
+    fn make_rom(cartridge_type: u8, ram_size: u8, fill: u8) -> Vec<u8> {
+        let mut rom = vec![fill; MIN_ROM_SIZE];
+        rom[CARTRIDGE_TYPE_ADDRESS] = cartridge_type;
+        rom[0x0148] = 0;
+        rom[0x0149] = ram_size;
+        rom
+    }
+
+    fn make_cartridge(rom: Vec<u8>) -> Cartridge {
+        Cartridge::new(
+            &Config {
+                rom,
+                ..Default::default()
+            },
+            saver::Fake,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_hot_swap_rom() {
+        let mut cartridge = make_cartridge(make_rom(0x01, 0x02, 0x11));
+        cartridge.write_byte(0x0000, 0x0A); // enable ram
+        cartridge.write_byte(0xA000, 0x42);
+
+        assert_eq!(Ok(()), cartridge.hot_swap_rom(make_rom(0x01, 0x02, 0x22)));
+        assert_eq!(0x22, cartridge.read_byte(0x4000));
+        assert_eq!(0x42, cartridge.read_byte(0xA000));
+    }
+
+    #[test]
+    fn test_hot_swap_rom_header_mismatch() {
+        let mut cartridge = make_cartridge(make_rom(0x01, 0x02, 0x11));
+
+        assert_eq!(
+            Err(HotSwapError::MapperMismatch {
+                current: 0x01,
+                new: 0x19
+            }),
+            cartridge.hot_swap_rom(make_rom(0x19, 0x02, 0x22))
+        );
+        assert_eq!(
+            Err(HotSwapError::RamSizeMismatch {
+                current: 0x02,
+                new: 0x03
+            }),
+            cartridge.hot_swap_rom(make_rom(0x01, 0x03, 0x22))
+        );
+        assert_eq!(
+            Err(HotSwapError::InvalidRom),
+            cartridge.hot_swap_rom(vec![])
+        );
+        assert_eq!(0x11, cartridge.read_byte(0x4000));
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let mut rom = make_rom(0x1E, 0x03, 0x00);
+        rom[CGB_FLAG_ADDRESS] = 0xC0;
+        rom[SGB_FLAG_ADDRESS] = 0x03;
+        rom[OLD_LICENSEE_CODE_ADDRESS] = 0x33;
+
+        assert_eq!(
+            Some(CartridgeCapabilities {
+                sgb: true,
+                cgb: CgbSupport::Exclusive,
+                battery: true,
+                rumble: true,
+                rtc: false,
+                accelerometer: false,
+                cartridge_type: 0x1E,
+            }),
+            CartridgeCapabilities::from_rom(&rom)
+        );
+        assert_eq!(
+            vec!["SGB", "rumble"],
+            make_cartridge(rom).capabilities().unsupported()
+        );
+
+        let mut rom = make_rom(0x10, 0x03, 0x00);
+        rom[CGB_FLAG_ADDRESS] = 0x80;
+        rom[SGB_FLAG_ADDRESS] = 0x03;
+        let capabilities = CartridgeCapabilities::from_rom(&rom).unwrap();
+        assert!(!capabilities.sgb);
+        assert_eq!(CgbSupport::Enhanced, capabilities.cgb);
+        assert!(capabilities.battery && capabilities.rtc);
+
+        let capabilities = CartridgeCapabilities::from_rom(&make_rom(0x22, 0x00, 0x00)).unwrap();
+        assert!(capabilities.battery && capabilities.accelerometer);
+        assert!(capabilities.unsupported().is_empty());
+
+        let capabilities = CartridgeCapabilities::from_rom(&make_rom(0xFE, 0x03, 0x00)).unwrap();
+        assert!(capabilities.battery && capabilities.rtc);
+        assert!(capabilities.unsupported().is_empty());
+
+        assert_eq!(None, CartridgeCapabilities::from_rom(&[0; 0x100]));
+    }
+
+    #[test]
+    fn test_strict_header() {
+        let cfg = |rom: Vec<u8>, mode: Mode, strict_header: bool| Config {
+            rom,
+            mode,
+            strict_header,
+            ..Default::default()
+        };
+        let new = |cfg: Config| Cartridge::new(&cfg, saver::Fake).map(|_| ());
+
+        let rom = testing::idle_rom();
+        assert_eq!(Ok(()), new(cfg(rom.clone(), Mode::DMG, true)));
+
+        // tolerant by default
+        let mut bad_checksum = rom.clone();
+        bad_checksum[ROM_CHECKSUM_ADDRESS] = bad_checksum[ROM_CHECKSUM_ADDRESS].wrapping_add(1);
+        assert_eq!(Ok(()), new(cfg(bad_checksum.clone(), Mode::DMG, false)));
+        assert_eq!(
+            Err(HeaderError::ChecksumMismatch {
+                computed: rom[ROM_CHECKSUM_ADDRESS],
+                expected: bad_checksum[ROM_CHECKSUM_ADDRESS],
+            }),
+            new(cfg(bad_checksum.clone(), Mode::DMG, true))
+        );
+
+        // the logo is checked first
+        let mut bad_logo = bad_checksum;
+        bad_logo[LOGO_ADDRESS] = 0x00;
+        assert_eq!(
+            Err(HeaderError::InvalidLogo),
+            new(cfg(bad_logo, Mode::DMG, true))
+        );
+
+        // the CGB only checks the top half of the logo
+        let mut bad_bottom_logo = rom;
+        bad_bottom_logo[LOGO_ADDRESS + 0x2F] = 0x00;
+        assert_eq!(Ok(()), new(cfg(bad_bottom_logo.clone(), Mode::CGB, true)));
+        assert_eq!(
+            Err(HeaderError::InvalidLogo),
+            new(cfg(bad_bottom_logo, Mode::DMG, true))
+        );
+    }
+
+    #[test]
+    fn test_unsupported_mapper_override() {
+        let new = |code: u8| {
+            let cfg = Config {
+                rom: testing::idle_rom(),
+                mapper: Some(MapperOverride::CartridgeType(code)),
+                ..Default::default()
+            };
+            Cartridge::new(&cfg, saver::Fake).map(|_| ())
+        };
+        assert_eq!(Ok(()), new(0x19));
+        assert_eq!(Err(HeaderError::UnsupportedMapperOverride(0xFD)), new(0xFD));
+    }
+}
@@ -0,0 +1,124 @@
+//! Ram scanner for cheat finders: snapshot the ram, let the game run, then keep the addresses
+//! whose value matches (e.g. decreased by 1 after losing a life) until a few candidates remain.
+//! A candidate is then locked with `cheats::Cheat::Freeze`.
+
+use std::ops::RangeInclusive;
+
+/// Cartridge ram, work ram and high ram, as currently mapped
+pub const RAM_REGIONS: [RangeInclusive<u16>; 3] =
+    [0xA000..=0xBFFF, 0xC000..=0xDFFF, 0xFF80..=0xFFFE];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchFilter {
+    Equal(u8),
+    NotEqual(u8),
+    Greater(u8),
+    Less(u8),
+    /// Relative to the value in the previous snapshot
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    IncreasedBy(u8),
+    DecreasedBy(u8),
+}
+
+impl SearchFilter {
+    fn matches(self, previous: u8, value: u8) -> bool {
+        match self {
+            SearchFilter::Equal(n) => value == n,
+            SearchFilter::NotEqual(n) => value != n,
+            SearchFilter::Greater(n) => value > n,
+            SearchFilter::Less(n) => value < n,
+            SearchFilter::Changed => value != previous,
+            SearchFilter::Unchanged => value == previous,
+            SearchFilter::Increased => value > previous,
+            SearchFilter::Decreased => value < previous,
+            SearchFilter::IncreasedBy(n) => value.wrapping_sub(previous) == n,
+            SearchFilter::DecreasedBy(n) => previous.wrapping_sub(value) == n,
+        }
+    }
+}
+
+/// Addresses still matching every filter applied, with their value in the last snapshot
+#[derive(Clone, Debug, Default)]
+pub struct CheatSearch {
+    candidates: Vec<(u16, u8)>,
+}
+
+impl CheatSearch {
+    /// Snapshots every address of `regions` with the given memory reader
+    pub fn new<M: Fn(u16) -> u8>(regions: &[RangeInclusive<u16>], read: M) -> Self {
+        Self {
+            candidates: regions
+                .iter()
+                .flat_map(|r| r.clone())
+                .map(|address| (address, read(address)))
+                .collect(),
+        }
+    }
+
+    /// Takes a new snapshot of the candidates, keeping the ones matching `filter`. Returns how
+    /// many remain.
+    pub fn refine<M: Fn(u16) -> u8>(&mut self, filter: SearchFilter, read: M) -> usize {
+        self.candidates.retain_mut(|(address, previous)| {
+            let value = read(*address);
+            let keep = filter.matches(*previous, value);
+            *previous = value;
+            keep
+        });
+        self.candidates.len()
+    }
+
+    /// Remaining (address, value) pairs
+    pub fn candidates(&self) -> &[(u16, u8)] {
+        &self.candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    #[test]
+    fn test_search_lives_counter() {
+        let memory = RefCell::new([0u8; 0x10000]);
+        let read = |address: u16| memory.borrow()[address as usize];
+        memory.borrow_mut()[0xC010] = 3;
+        memory.borrow_mut()[0xC020] = 3;
+        memory.borrow_mut()[0xC030] = 7;
+
+        let mut search = CheatSearch::new(&[0xC000..=0xC0FF], read);
+        assert_eq!(256, search.candidates().len());
+        assert_eq!(2, search.refine(SearchFilter::Equal(3), read));
+
+        // a life is lost, the other byte at 3 goes up
+        memory.borrow_mut()[0xC010] = 2;
+        memory.borrow_mut()[0xC020] = 4;
+        assert_eq!(1, search.refine(SearchFilter::DecreasedBy(1), read));
+        assert_eq!(&[(0xC010, 2)], search.candidates());
+
+        assert_eq!(1, search.refine(SearchFilter::Unchanged, read));
+        assert_eq!(0, search.refine(SearchFilter::Changed, read));
+    }
+
+    #[test]
+    fn test_filters() {
+        let cases = [
+            (SearchFilter::Equal(5), 0, 5, true),
+            (SearchFilter::NotEqual(5), 0, 5, false),
+            (SearchFilter::Greater(5), 0, 6, true),
+            (SearchFilter::Less(5), 0, 5, false),
+            (SearchFilter::Increased, 4, 5, true),
+            (SearchFilter::Decreased, 4, 5, false),
+            (SearchFilter::IncreasedBy(2), 0xFF, 0x01, true),
+            (SearchFilter::DecreasedBy(2), 0x01, 0xFF, true),
+            (SearchFilter::DecreasedBy(2), 0x05, 0x04, false),
+        ];
+        for (filter, previous, value, expected) in cases {
+            assert_eq!(expected, filter.matches(previous, value), "{filter:?}");
+        }
+    }
+}
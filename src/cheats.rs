@@ -0,0 +1,717 @@
+//! Rom (e.g. decoded Game Genie codes) and ram cheats, plus per game patch sets built on them
+//! such as "skip intro" patches.
+
+use std::{collections::HashMap, fmt};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cheat {
+    /// Replaces a rom byte as it is read, Game Genie style.
+    /// With `compare` set, only applies while the original byte matches (i.e. in the right bank).
+    Rom {
+        address: u16,
+        value: u8,
+        compare: Option<u8>,
+    },
+    /// Written to memory once per frame on entering VBlank, GameShark style. Ignored outside
+    /// the external ram, work ram and HRAM, see `is_ram_address`.
+    Ram { address: u16, value: u8 },
+    /// `Ram` cheat only written while `bank` is mapped, for addresses in switchable ram
+    BankedRam {
+        bank: RamBank,
+        address: u16,
+        value: u8,
+    },
+    /// Locks `address` to `value`: written once per frame like `Ram`, and every write to it
+    /// through the bus is replaced by `value`, for "infinite health" style cheats. Ignored outside
+    /// the ram like `Ram`.
+    Freeze { address: u16, value: u8 },
+}
+
+impl Cheat {
+    /// Decodes a Game Genie code, `ABC-DEF-GHI` or `ABC-DEF` without compare byte, dashes
+    /// optional. AB is the value, FCDE the address with F complemented, GI the compare byte
+    /// rotated left by 2 and XORed with 0xBA, H is unused.
+    pub fn from_game_genie(code: &str) -> Result<Self, CodeError> {
+        let digits = code
+            .chars()
+            .filter(|&c| c != '-')
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or(CodeError::InvalidFormat)?;
+        if digits.len() != 6 && digits.len() != 9 {
+            return Err(CodeError::InvalidFormat);
+        }
+
+        let value = digits[0] << 4 | digits[1];
+        let address = ((digits[5] ^ 0xF) as u16) << 12
+            | (digits[2] as u16) << 8
+            | (digits[3] as u16) << 4
+            | digits[4] as u16;
+        if address > 0x7FFF {
+            return Err(CodeError::NotRomAddress(address));
+        }
+        let compare =
+            (digits.len() == 9).then(|| (digits[6] << 4 | digits[8]).rotate_right(2) ^ 0xBA);
+
+        Ok(Cheat::Rom {
+            address,
+            value,
+            compare,
+        })
+    }
+
+    /// Decodes a GameShark code `ttvvaaaa`: vv is the value, aaaa the little endian address and
+    /// tt the type, 00 or 01 writing whatever bank is mapped, 8x only while cartridge ram bank x
+    /// is mapped and 9x while CGB work ram bank x is. The address must be in the ram the type
+    /// writes to.
+    pub fn from_gameshark(code: &str) -> Result<Self, CodeError> {
+        if code.len() != 8 || !code.is_ascii() {
+            return Err(CodeError::InvalidFormat);
+        }
+        let byte = |i: usize| {
+            u8::from_str_radix(&code[i..i + 2], 16).map_err(|_| CodeError::InvalidFormat)
+        };
+        let (code_type, value) = (byte(0)?, byte(2)?);
+        let address = u16::from_le_bytes([byte(4)?, byte(6)?]);
+
+        let (bank, in_ram) = match code_type {
+            0x00 | 0x01 => (None, is_ram_address(address)),
+            0x80..=0x8F => (
+                Some(RamBank::Cartridge(code_type & 0x0F)),
+                (0xA000..=0xBFFF).contains(&address),
+            ),
+            0x90..=0x97 => (
+                Some(RamBank::Work(code_type & 0x07)),
+                (0xD000..=0xDFFF).contains(&address),
+            ),
+            _ => return Err(CodeError::InvalidFormat),
+        };
+        if !in_ram {
+            return Err(CodeError::NotRamAddress(address));
+        }
+        let Some(bank) = bank else {
+            return Ok(Cheat::Ram { address, value });
+        };
+        Ok(Cheat::BankedRam {
+            bank,
+            address,
+            value,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CodeError {
+    InvalidFormat,
+    /// Game Genie codes can only patch the rom, mapped at 0x0000-0x7FFF
+    NotRomAddress(u16),
+    /// GameShark codes can only write ram, see `is_ram_address`
+    NotRamAddress(u16),
+}
+
+impl fmt::Display for CodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodeError::InvalidFormat => write!(f, "invalid cheat code"),
+            CodeError::NotRomAddress(address) => {
+                write!(f, "cheat code address {address:#06x} is outside the rom")
+            }
+            CodeError::NotRamAddress(address) => {
+                write!(f, "cheat code address {address:#06x} is outside the ram")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CodeError {}
+
+/// External ram (A000-BFFF), work ram (C000-DFFF) or HRAM (FF80-FFFE), the only addresses ram
+/// cheats write: echo ram, OAM and IO registers are left alone
+pub fn is_ram_address(address: u16) -> bool {
+    matches!(address, 0xA000..=0xDFFF | 0xFF80..=0xFFFE)
+}
+
+/// Switchable ram bank a `Cheat::BankedRam` targets
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RamBank {
+    /// Cartridge ram bank mapped at A000-BFFF
+    Cartridge(u8),
+    /// CGB work ram bank mapped at D000-DFFF, see SVBK
+    Work(u8),
+}
+
+pub type CheatId = usize;
+
+#[derive(Clone, Debug)]
+struct Entry {
+    id: CheatId,
+    cheat: Cheat,
+    enabled: bool,
+}
+
+/// Active cheats, rom ones are applied by the bus on reads, ram ones on entering VBlank and
+/// frozen addresses on writes too
+#[derive(Clone, Debug, Default)]
+pub struct Cheats {
+    entries: Vec<Entry>,
+    next_id: CheatId,
+    /// skips the rom lookup on every read when no rom cheat is enabled
+    rom_cheats: bool,
+    /// enabled freezes, looked up on every write
+    frozen: HashMap<u16, u8>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, cheat: Cheat) -> CheatId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push(Entry {
+            id,
+            cheat,
+            enabled: true,
+        });
+        self.update_lookups();
+        id
+    }
+
+    pub fn remove(&mut self, id: CheatId) {
+        self.entries.retain(|e| e.id != id);
+        self.update_lookups();
+    }
+
+    pub fn set_enabled(&mut self, id: CheatId, enabled: bool) {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.id == id) {
+            entry.enabled = enabled;
+        }
+        self.update_lookups();
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.update_lookups();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Every cheat added, in order, with whether it's enabled
+    pub fn list(&self) -> Vec<(CheatId, Cheat, bool)> {
+        self.entries
+            .iter()
+            .map(|e| (e.id, e.cheat, e.enabled))
+            .collect()
+    }
+
+    fn update_lookups(&mut self) {
+        let rom_cheats = self
+            .enabled()
+            .any(|cheat| matches!(cheat, Cheat::Rom { .. }));
+        let frozen = self
+            .enabled()
+            .filter_map(|cheat| match *cheat {
+                Cheat::Freeze { address, value } if is_ram_address(address) => {
+                    Some((address, value))
+                }
+                _ => None,
+            })
+            .collect();
+        self.rom_cheats = rom_cheats;
+        self.frozen = frozen;
+    }
+
+    fn enabled(&self) -> impl Iterator<Item = &Cheat> {
+        self.entries.iter().filter(|e| e.enabled).map(|e| &e.cheat)
+    }
+
+    /// Value the cpu sees when reading `original` at rom `address`
+    pub fn patch_rom(&self, address: u16, original: u8) -> u8 {
+        if !self.rom_cheats {
+            return original;
+        }
+
+        for cheat in self.enabled() {
+            if let Cheat::Rom {
+                address: a,
+                value,
+                compare,
+            } = *cheat
+            {
+                if a == address && compare.is_none_or(|c| c == original) {
+                    return value;
+                }
+            }
+        }
+        original
+    }
+
+    /// Value actually written when the cpu writes `value` at `address`
+    pub fn patch_write(&self, address: u16, value: u8) -> u8 {
+        if self.frozen.is_empty() {
+            return value;
+        }
+        self.frozen.get(&address).copied().unwrap_or(value)
+    }
+
+    /// Enabled ram writes and freezes as (address, value)
+    pub fn ram_writes(&self) -> Vec<(u16, u8)> {
+        self.enabled()
+            .filter_map(|cheat| match *cheat {
+                Cheat::Ram { address, value } | Cheat::Freeze { address, value } => {
+                    Some((address, value))
+                }
+                _ => None,
+            })
+            .filter(|&(address, _)| is_ram_address(address))
+            .collect()
+    }
+
+    /// Enabled banked ram writes as (bank, address, value)
+    pub fn banked_ram_writes(&self) -> Vec<(RamBank, u16, u8)> {
+        self.enabled()
+            .filter_map(|cheat| match *cheat {
+                Cheat::BankedRam {
+                    bank,
+                    address,
+                    value,
+                } => Some((bank, address, value)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid patch at line {}", self.line)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Per game patches keyed by rom CRC32, e.g. to skip lengthy intros while iterating.
+/// Each line holds `crc32 rom address value [compare]`, `crc32 ram address value` or
+/// `crc32 freeze address value`, in hex, turned into the matching `Cheat`.
+/// Empty lines and lines starting with `#` are skipped.
+///
+/// The CRC32 is the one of the whole rom (`romdb::rom_crc32`, the No-Intro DAT checksum), so a
+/// patch never applies to another game or revision. Verify each patch against the exact rom dump,
+/// typical skip intro patches turn the `call` to the intro routine into `nop`s or force the game
+/// state byte the title screen waits on, e.g. `1234abcd rom 0150 c9 f5` returning from a routine
+/// at 0x0150 starting with `push af`.
+#[derive(Clone, Debug, Default)]
+pub struct PatchSet {
+    games: HashMap<u32, Vec<Cheat>>,
+}
+
+impl PatchSet {
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut set = Self::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let err = || ParseError { line: i + 1 };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let hex = |field: &str| {
+                u32::from_str_radix(field.trim_start_matches("0x"), 16).map_err(|_| err())
+            };
+            if fields.len() < 4 {
+                return Err(err());
+            }
+
+            let crc = hex(fields[0])?;
+            let address = u16::try_from(hex(fields[2])?).map_err(|_| err())?;
+            let value = u8::try_from(hex(fields[3])?).map_err(|_| err())?;
+            let cheat = match (fields[1], fields.get(4)) {
+                ("rom", compare) => Cheat::Rom {
+                    address,
+                    value,
+                    compare: match compare {
+                        Some(c) => Some(u8::try_from(hex(c)?).map_err(|_| err())?),
+                        None => None,
+                    },
+                },
+                ("ram", None) if is_ram_address(address) => Cheat::Ram { address, value },
+                ("freeze", None) if is_ram_address(address) => Cheat::Freeze { address, value },
+                _ => return Err(err()),
+            };
+            set.games.entry(crc).or_default().push(cheat);
+        }
+
+        Ok(set)
+    }
+
+    pub fn for_rom(&self, crc32: u32) -> &[Cheat] {
+        self.games.get(&crc32).map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use crate::{
+        config::Config,
+        gameboy::GameBoy,
+        joypad_events_handler, lcd,
+        mode::Mode,
+        pacing::Pacing,
+        romdb::rom_crc32,
+        saver,
+        testing::{Asm, RomBuilder, R8},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_rom_cheats() {
+        let mut cheats = Cheats::new();
+        assert_eq!(0x12, cheats.patch_rom(0x4000, 0x12));
+
+        let id = cheats.add(Cheat::Rom {
+            address: 0x4000,
+            value: 0x00,
+            compare: Some(0x12),
+        });
+        cheats.add(Cheat::Rom {
+            address: 0x0150,
+            value: 0xC9,
+            compare: None,
+        });
+
+        assert_eq!(0x00, cheats.patch_rom(0x4000, 0x12));
+        // another bank mapped at the same address
+        assert_eq!(0x34, cheats.patch_rom(0x4000, 0x34));
+        assert_eq!(0xC9, cheats.patch_rom(0x0150, 0x00));
+
+        cheats.set_enabled(id, false);
+        assert_eq!(0x12, cheats.patch_rom(0x4000, 0x12));
+        cheats.clear();
+        assert_eq!(0x00, cheats.patch_rom(0x0150, 0x00));
+    }
+
+    #[test]
+    fn test_game_genie_codes() {
+        assert_eq!(
+            Ok(Cheat::Rom {
+                address: 0x4A17,
+                value: 0x00,
+                compare: Some(0xC8),
+            }),
+            Cheat::from_game_genie("00A-17B-C49")
+        );
+        assert_eq!(
+            Cheat::from_game_genie("00A-17B-C49"),
+            Cheat::from_game_genie("00a17bc49")
+        );
+        assert_eq!(
+            Ok(Cheat::Rom {
+                address: 0x0150,
+                value: 0xC9,
+                compare: None,
+            }),
+            Cheat::from_game_genie("C91-50F")
+        );
+
+        assert_eq!(
+            Err(CodeError::NotRomAddress(0x8150)),
+            Cheat::from_game_genie("C91-507")
+        );
+        assert_eq!(
+            Err(CodeError::InvalidFormat),
+            Cheat::from_game_genie("00A-17B-C4")
+        );
+        assert_eq!(
+            Err(CodeError::InvalidFormat),
+            Cheat::from_game_genie("00A-17G-C49")
+        );
+    }
+
+    #[test]
+    fn test_gameshark_codes() {
+        assert_eq!(
+            Ok(Cheat::Ram {
+                address: 0xC0D2,
+                value: 0x63,
+            }),
+            Cheat::from_gameshark("0163D2C0")
+        );
+        assert_eq!(
+            Ok(Cheat::BankedRam {
+                bank: RamBank::Cartridge(2),
+                address: 0xA010,
+                value: 0xFF,
+            }),
+            Cheat::from_gameshark("82ff10a0")
+        );
+        assert_eq!(
+            Ok(Cheat::BankedRam {
+                bank: RamBank::Work(3),
+                address: 0xD000,
+                value: 0x01,
+            }),
+            Cheat::from_gameshark("930100D0")
+        );
+
+        assert_eq!(
+            Err(CodeError::InvalidFormat),
+            Cheat::from_gameshark("0163D2C")
+        );
+        assert_eq!(
+            Err(CodeError::InvalidFormat),
+            Cheat::from_gameshark("0163D2CX")
+        );
+        assert_eq!(
+            Err(CodeError::InvalidFormat),
+            Cheat::from_gameshark("A163D2C0")
+        );
+
+        // IO registers, echo ram and banks mapped elsewhere
+        for code in ["010140FF", "010100E0", "010100FE", "820110C0", "930100C0"] {
+            assert!(
+                matches!(
+                    Cheat::from_gameshark(code),
+                    Err(CodeError::NotRamAddress(_))
+                ),
+                "{code}"
+            );
+        }
+        assert!(Cheat::from_gameshark("010180FF").is_ok());
+    }
+
+    #[test]
+    fn test_cheats_list() {
+        let mut cheats = Cheats::new();
+        let first = cheats.add(Cheat::from_gameshark("0163D2C0").unwrap());
+        let second = cheats.add(Cheat::from_gameshark("930100D0").unwrap());
+        cheats.set_enabled(first, false);
+
+        assert_eq!(
+            vec![
+                (
+                    first,
+                    Cheat::Ram {
+                        address: 0xC0D2,
+                        value: 0x63
+                    },
+                    false
+                ),
+                (
+                    second,
+                    Cheat::BankedRam {
+                        bank: RamBank::Work(3),
+                        address: 0xD000,
+                        value: 0x01
+                    },
+                    true
+                ),
+            ],
+            cheats.list()
+        );
+        assert!(cheats.ram_writes().is_empty());
+        assert_eq!(
+            vec![(RamBank::Work(3), 0xD000, 0x01)],
+            cheats.banked_ram_writes()
+        );
+    }
+
+    #[test]
+    fn test_ram_cheats() {
+        let mut cheats = Cheats::new();
+        let id = cheats.add(Cheat::Ram {
+            address: 0xC000,
+            value: 99,
+        });
+        assert_eq!(vec![(0xC000, 99)], cheats.ram_writes());
+
+        cheats.remove(id);
+        assert!(cheats.is_empty());
+        assert!(cheats.ram_writes().is_empty());
+
+        // never written to the IO registers
+        cheats.add(Cheat::Ram {
+            address: 0xFF40,
+            value: 0,
+        });
+        assert!(cheats.ram_writes().is_empty());
+    }
+
+    #[test]
+    fn test_freeze_cheats() {
+        let mut cheats = Cheats::new();
+        assert_eq!(0x05, cheats.patch_write(0xC000, 0x05));
+
+        let id = cheats.add(Cheat::Freeze {
+            address: 0xC000,
+            value: 99,
+        });
+        assert_eq!(99, cheats.patch_write(0xC000, 0x05));
+        assert_eq!(0x05, cheats.patch_write(0xC001, 0x05));
+        assert_eq!(vec![(0xC000, 99)], cheats.ram_writes());
+
+        cheats.set_enabled(id, false);
+        assert_eq!(0x05, cheats.patch_write(0xC000, 0x05));
+        cheats.set_enabled(id, true);
+        cheats.clear();
+        assert_eq!(0x05, cheats.patch_write(0xC000, 0x05));
+
+        // the IO registers keep working
+        cheats.add(Cheat::Freeze {
+            address: 0xFF40,
+            value: 0,
+        });
+        assert_eq!(0x91, cheats.patch_write(0xFF40, 0x91));
+        assert!(cheats.ram_writes().is_empty());
+    }
+
+    #[test]
+    fn test_patch_set() {
+        let set = PatchSet::parse(
+            "# skip intro\n\
+             \n\
+             1234abcd rom 0x0150 c9\n\
+             1234ABCD rom 4000 00 12\n\
+             deadbeef ram C0A0 01\n\
+             deadbeef freeze C0A1 02\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            &[
+                Cheat::Rom {
+                    address: 0x0150,
+                    value: 0xC9,
+                    compare: None
+                },
+                Cheat::Rom {
+                    address: 0x4000,
+                    value: 0x00,
+                    compare: Some(0x12)
+                },
+            ],
+            set.for_rom(0x1234ABCD)
+        );
+        assert_eq!(
+            &[
+                Cheat::Ram {
+                    address: 0xC0A0,
+                    value: 0x01
+                },
+                Cheat::Freeze {
+                    address: 0xC0A1,
+                    value: 0x02
+                }
+            ],
+            set.for_rom(0xDEADBEEF)
+        );
+        assert!(set.for_rom(0).is_empty());
+
+        assert_eq!(
+            Some(ParseError { line: 2 }),
+            PatchSet::parse("\n1234abcd ram 10000 00").err()
+        );
+        assert_eq!(
+            Some(ParseError { line: 1 }),
+            PatchSet::parse("1234abcd ram c000 00 01").err()
+        );
+        assert_eq!(
+            Some(ParseError { line: 1 }),
+            PatchSet::parse("1234abcd wram c000 00").err()
+        );
+        assert_eq!(
+            Some(ParseError { line: 1 }),
+            PatchSet::parse("1234abcd ram ff40 00").err()
+        );
+        assert_eq!(
+            Some(ParseError { line: 1 }),
+            PatchSet::parse("1234abcd freeze 8000 00").err()
+        );
+    }
+
+    #[test]
+    fn test_apply_patches() {
+        let mut asm = Asm::new();
+        // the freeze holds even though the game keeps clearing c003
+        asm.ld_a_nn(0x0200).ld_nn_a(0xC000);
+        let clear = asm.here();
+        asm.ld_r_n(R8::A, 0x00).ld_nn_a(0xC003).jr(None, clear);
+        let rom = RomBuilder::new().code(&asm).patch(0x0200, &[0x11]).build();
+
+        let patches = PatchSet::parse(&format!(
+            "{crc:08x} rom 0200 22 11\n\
+             {crc:08x} ram c001 33\n\
+             {crc:08x} freeze c003 55\n\
+             00000000 ram c002 44\n",
+            crc = rom_crc32(&rom)
+        ))
+        .unwrap();
+
+        let cfg = Config {
+            rom,
+            headless_mode: true,
+            pacing: Pacing::Uncapped,
+            ..Default::default()
+        };
+        let (_, rx) = mpsc::channel::<()>();
+        let mut gb = GameBoy::new(
+            &cfg,
+            lcd::Fake,
+            crate::stereo::Fake,
+            joypad_events_handler::Fake,
+            saver::Fake,
+            &rx,
+        );
+        assert_eq!(3, gb.apply_patches(&patches));
+        gb.step_frame();
+
+        assert_eq!(0x22, gb.read_memory(0xC000));
+        assert_eq!(0x33, gb.read_memory(0xC001));
+        assert_ne!(0x44, gb.read_memory(0xC002));
+        assert_eq!(0x55, gb.read_memory(0xC003));
+    }
+
+    #[test]
+    fn test_banked_ram_cheats() {
+        let mut asm = Asm::new();
+        asm.ld_r_n(R8::A, 3).ldh_n_a(0x70).spin();
+
+        let cfg = Config {
+            mode: Mode::CGB,
+            rom: RomBuilder::new().code(&asm).build(),
+            headless_mode: true,
+            pacing: Pacing::Uncapped,
+            ..Default::default()
+        };
+        let (_, rx) = mpsc::channel::<()>();
+        let mut gb = GameBoy::new(
+            &cfg,
+            lcd::Fake,
+            crate::stereo::Fake,
+            joypad_events_handler::Fake,
+            saver::Fake,
+            &rx,
+        );
+        gb.add_cheat(Cheat::from_gameshark("931100D0").unwrap());
+        gb.add_cheat(Cheat::from_gameshark("922201D0").unwrap());
+        // no cartridge ram
+        gb.add_cheat(Cheat::from_gameshark("803300A0").unwrap());
+        gb.step_frame();
+
+        assert_eq!(0x11, gb.read_memory(0xD000));
+        assert_ne!(0x22, gb.read_memory(0xD001));
+        assert_ne!(0x33, gb.read_memory(0xA000));
+        assert_eq!(3, gb.cheats().len());
+    }
+}
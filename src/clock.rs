@@ -0,0 +1,109 @@
+//! Clock domains of the CGB double speed mode, see https://gbdev.io/pandocs/CGB_Registers.html#ff4d--key1-cgb-mode-only-prepare-speed-switch
+//!
+//! The cpu and the peripherals it clocks run twice as fast in double speed mode, while the ones
+//! tied to the 4 MiHz system clock keep their pace.
+
+use crate::savestate::{ChunkReader, ChunkWriter};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockDomain {
+    /// Follows the cpu speed
+    Cpu,
+    /// Fixed 4 MiHz dot clock
+    System,
+}
+
+/// Peripheral stepped by the bus, in cycles of its clock domain
+pub trait Clocked {
+    const DOMAIN: ClockDomain;
+}
+
+/// Cycles elapsed in each domain during a step
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ticks {
+    cpu: u8,
+    system: u8,
+}
+
+impl Ticks {
+    pub fn get(&self, domain: ClockDomain) -> u8 {
+        match domain {
+            ClockDomain::Cpu => self.cpu,
+            ClockDomain::System => self.system,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Clock {
+    double_speed: bool,
+    /// half a system cycle left by an odd number of cpu cycles in double speed mode
+    remainder: u8,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    pub fn toggle_speed(&mut self) {
+        self.double_speed = !self.double_speed;
+        self.remainder = 0;
+    }
+
+    /// Stored in the bus savestate chunk
+    pub(crate) fn save(&self, w: &mut ChunkWriter) {
+        w.write_bool(self.double_speed);
+        w.write_u8(self.remainder);
+    }
+
+    pub(crate) fn load(&mut self, r: &mut ChunkReader) {
+        self.double_speed = r.read_bool();
+        self.remainder = r.read_u8();
+    }
+
+    /// Converts `cpu_cycles` elapsed cpu cycles into cycles of each domain
+    pub fn tick(&mut self, cpu_cycles: u8) -> Ticks {
+        let system = match self.double_speed {
+            true => {
+                let halves = cpu_cycles + self.remainder;
+                self.remainder = halves % 2;
+                halves / 2
+            }
+            false => cpu_cycles,
+        };
+
+        Ticks {
+            cpu: cpu_cycles,
+            system,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ticks() {
+        let mut clock = Clock::new();
+        let ticks = clock.tick(4);
+        assert_eq!(4, ticks.get(ClockDomain::Cpu));
+        assert_eq!(4, ticks.get(ClockDomain::System));
+
+        clock.toggle_speed();
+        assert!(clock.is_double_speed());
+        assert_eq!(2, clock.tick(4).get(ClockDomain::System));
+        // odd cpu cycles carry over to the next tick
+        let system: Vec<u8> = [3, 3, 1, 1]
+            .into_iter()
+            .map(|cycles| clock.tick(cycles).get(ClockDomain::System))
+            .collect();
+        assert_eq!(vec![1, 2, 0, 1], system);
+        assert_eq!(8, clock.tick(8).get(ClockDomain::Cpu));
+    }
+}
@@ -0,0 +1,327 @@
+//! Compatibility reports: each rom runs headless for a number of frames, recording whether it
+//! shows something, runs into an illegal opcode or crashes the core. The Markdown output is meant
+//! to be pasted in issues, the JSON one to be aggregated across users. See
+//! `examples/compat_report.rs`.
+
+use std::{
+    cell::RefCell,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    rc::Rc,
+    sync::mpsc::channel,
+};
+
+use crate::{
+    cartridge::{CartridgeCapabilities, CgbSupport},
+    config::Config,
+    events::Event,
+    gameboy::GameBoy,
+    joypad_events_handler, lcd,
+    mode::Mode,
+    pacing::Pacing,
+    saver, stereo,
+};
+
+/// Frames each rom runs by default, 30 seconds of emulated time
+pub const DEFAULT_FRAMES: u64 = 1800;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompatStatus {
+    /// Something other than a single color was shown, without illegal opcode nor crash
+    Boots,
+    BlankScreen,
+    IllegalOpcode,
+    Crashed,
+    /// Not a rom the core accepts, e.g. too small for a header
+    Refused,
+}
+
+impl fmt::Display for CompatStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            Self::Boots => "boots",
+            Self::BlankScreen => "blank screen",
+            Self::IllegalOpcode => "illegal opcode",
+            Self::Crashed => "crashed",
+            Self::Refused => "refused",
+        };
+        write!(f, "{status}")
+    }
+}
+
+/// Outcome of running one rom
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompatEntry {
+    /// Usually the rom file name
+    pub name: String,
+    pub cgb: bool,
+    /// Frames run, the crashing one included
+    pub frames: u64,
+    /// First frame showing more than a single color
+    pub first_visible_frame: Option<u64>,
+    /// First illegal opcode executed, (pc, opcode)
+    pub illegal_opcode: Option<(u16, u8)>,
+    /// Panic message of a core crash, or why the rom was refused
+    pub error: Option<String>,
+    pub refused: bool,
+}
+
+impl CompatEntry {
+    pub fn status(&self) -> CompatStatus {
+        if self.refused {
+            CompatStatus::Refused
+        } else if self.error.is_some() {
+            CompatStatus::Crashed
+        } else if self.illegal_opcode.is_some() {
+            CompatStatus::IllegalOpcode
+        } else if self.first_visible_frame.is_none() {
+            CompatStatus::BlankScreen
+        } else {
+            CompatStatus::Boots
+        }
+    }
+
+    fn details(&self) -> String {
+        match (self.status(), self.illegal_opcode) {
+            (CompatStatus::Refused | CompatStatus::Crashed, _) => {
+                self.error.clone().unwrap_or_default()
+            }
+            (CompatStatus::IllegalOpcode, Some((pc, opcode))) => {
+                format!("{opcode:#04x} at {pc:#06x}")
+            }
+            (_, _) => match self.first_visible_frame {
+                Some(frame) => format!("visible from frame {frame}"),
+                None => String::new(),
+            },
+        }
+    }
+}
+
+/// Runs `rom` headless for `frames` frames, in CGB mode when its header supports it
+pub fn check_rom(name: &str, rom: Vec<u8>, frames: u64) -> CompatEntry {
+    let cgb = CartridgeCapabilities::from_rom(&rom).is_some_and(|c| c.cgb != CgbSupport::None);
+    let mut entry = CompatEntry {
+        name: name.to_string(),
+        cgb,
+        frames,
+        first_visible_frame: None,
+        illegal_opcode: None,
+        error: None,
+        refused: false,
+    };
+
+    let cfg = Config {
+        mode: if cgb { Mode::CGB } else { Mode::DMG },
+        rom,
+        headless_mode: true,
+        pacing: Pacing::Uncapped,
+        ..Default::default()
+    };
+    let (_tx, rx) = channel::<()>();
+    let built = panic::catch_unwind(AssertUnwindSafe(|| {
+        GameBoy::try_new(
+            &cfg,
+            lcd::Fake,
+            stereo::Fake,
+            joypad_events_handler::Fake,
+            saver::Fake,
+            &rx,
+        )
+    }));
+    let mut gb = match built {
+        Ok(Ok(gb)) => gb,
+        Ok(Err(e)) => {
+            entry.refused = true;
+            entry.error = Some(e.to_string());
+            return entry;
+        }
+        Err(_) => {
+            entry.refused = true;
+            entry.error = Some("not a valid rom".to_string());
+            return entry;
+        }
+    };
+
+    let crash = Rc::new(RefCell::new(None));
+    let report = crash.clone();
+    gb.set_crash_handler(move |r| *report.borrow_mut() = Some(r.message.clone()));
+
+    for frame in 1..=frames {
+        if panic::catch_unwind(AssertUnwindSafe(|| gb.step_frame())).is_err() {
+            entry.error = Some(crash.take().unwrap_or_else(|| "unknown panic".to_string()));
+            entry.frames = frame;
+            break;
+        }
+        for event in gb.drain_events() {
+            if let Event::IllegalOpcode { pc, opcode, .. } = event {
+                entry.illegal_opcode.get_or_insert((pc, opcode));
+            }
+        }
+        let pixels = gb.frame_buffer();
+        let first = pixels[0][0];
+        if entry.first_visible_frame.is_none() && pixels.iter().flatten().any(|&p| p != first) {
+            entry.first_visible_frame = Some(frame);
+        }
+    }
+
+    entry
+}
+
+/// Results of a batch of roms
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompatReport {
+    pub entries: Vec<CompatEntry>,
+}
+
+impl CompatReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn count(&self, status: CompatStatus) -> usize {
+        self.entries.iter().filter(|e| e.status() == status).count()
+    }
+
+    /// Summary line and one table row per rom
+    pub fn to_markdown(&self) -> String {
+        let mut md = format!(
+            "{} roms: {} boot, {} blank screen, {} illegal opcode, {} crashed, {} refused\n\n",
+            self.entries.len(),
+            self.count(CompatStatus::Boots),
+            self.count(CompatStatus::BlankScreen),
+            self.count(CompatStatus::IllegalOpcode),
+            self.count(CompatStatus::Crashed),
+            self.count(CompatStatus::Refused),
+        );
+        md.push_str("| Rom | Mode | Status | Details |\n|---|---|---|---|\n");
+        for entry in &self.entries {
+            md.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                markdown_cell(&entry.name),
+                if entry.cgb { "CGB" } else { "DMG" },
+                entry.status(),
+                markdown_cell(&entry.details()),
+            ));
+        }
+        md
+    }
+
+    /// Array of objects, absent values are `null`
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self
+            .entries
+            .iter()
+            .map(|e| {
+                let (pc, opcode) = match e.illegal_opcode {
+                    Some((pc, opcode)) => (pc.to_string(), opcode.to_string()),
+                    None => ("null".to_string(), "null".to_string()),
+                };
+                format!(
+                    "{{\"name\":{},\"mode\":\"{}\",\"status\":\"{}\",\"frames\":{},\"first_visible_frame\":{},\"illegal_opcode\":{opcode},\"illegal_opcode_pc\":{pc},\"error\":{}}}",
+                    json_string(&e.name),
+                    if e.cgb { "CGB" } else { "DMG" },
+                    e.status(),
+                    e.frames,
+                    e.first_visible_frame
+                        .map_or("null".to_string(), |f| f.to_string()),
+                    e.error.as_deref().map_or("null".to_string(), json_string),
+                )
+            })
+            .collect();
+        format!("[{}]\n", entries.join(",\n"))
+    }
+}
+
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, Asm, Cond, RomBuilder, R8};
+
+    use super::*;
+
+    #[test]
+    fn test_check_rom() {
+        let mut asm = Asm::new();
+        // lcd off on vblank, tile 0 row 0 black, lcd on
+        let wait = asm.here();
+        asm.ldh_a_n(0x44).cp_n(144).jr(Some(Cond::NZ), wait);
+        asm.ld_r_n(R8::A, 0x00)
+            .ldh_n_a(0x40)
+            .ld_r_n(R8::A, 0xFF)
+            .ld_nn_a(0x8000)
+            .ld_nn_a(0x8001)
+            .ld_r_n(R8::A, 0x91)
+            .ldh_n_a(0x40)
+            .spin();
+        let entry = check_rom("stripes.gb", RomBuilder::new().code(&asm).build(), 5);
+        assert_eq!(CompatStatus::Boots, entry.status());
+        assert!(!entry.cgb);
+
+        let entry = check_rom("idle.gb", testing::idle_rom(), 5);
+        assert_eq!(CompatStatus::BlankScreen, entry.status());
+
+        let mut asm = Asm::new();
+        asm.db(&[0xDD]).spin();
+        let entry = check_rom("illegal.gb", RomBuilder::new().code(&asm).build(), 5);
+        assert_eq!(CompatStatus::IllegalOpcode, entry.status());
+        assert_eq!(Some((testing::CODE_START, 0xDD)), entry.illegal_opcode);
+
+        let entry = check_rom("empty.gb", vec![0; 0x100], 5);
+        assert_eq!(CompatStatus::Refused, entry.status());
+    }
+
+    #[test]
+    fn test_report_output() {
+        let report = CompatReport {
+            entries: vec![
+                CompatEntry {
+                    name: "a|b.gb".to_string(),
+                    cgb: true,
+                    frames: 60,
+                    first_visible_frame: Some(12),
+                    illegal_opcode: None,
+                    error: None,
+                    refused: false,
+                },
+                CompatEntry {
+                    name: "crash.gb".to_string(),
+                    cgb: false,
+                    frames: 60,
+                    first_visible_frame: None,
+                    illegal_opcode: Some((0x0150, 0xDD)),
+                    error: Some("index \"out\" of bounds".to_string()),
+                    refused: false,
+                },
+            ],
+        };
+
+        let md = report.to_markdown();
+        assert!(md.starts_with("2 roms: 1 boot, 0 blank screen, 0 illegal opcode, 1 crashed"));
+        assert!(md.contains("| a\\|b.gb | CGB | boots | visible from frame 12 |"));
+        assert!(md.contains("| crash.gb | DMG | crashed | index \"out\" of bounds |"));
+
+        let json = report.to_json();
+        assert!(json.contains(
+            "{\"name\":\"a|b.gb\",\"mode\":\"CGB\",\"status\":\"boots\",\"frames\":60,\"first_visible_frame\":12,\"illegal_opcode\":null,\"illegal_opcode_pc\":null,\"error\":null}"
+        ));
+        assert!(json.contains("\"illegal_opcode\":221,\"illegal_opcode_pc\":336,\"error\":\"index \\\"out\\\" of bounds\"}"));
+    }
+}
@@ -1,10 +1,88 @@
-use crate::mode::Mode;
+use crate::{
+    cartridge::MapperOverride,
+    joypad::SimultaneousPressPolicy,
+    lcd::DmgPalette,
+    mode::{Mode, Model},
+    pacing::{BackgroundPolicy, Pacing},
+    romdb::RomInfo,
+    stereo::AudioFilter,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Config {
     pub mode: Mode,
+    /// Hardware revision within `mode`, None being the usual one (DMG or CGB). Ignored when it
+    /// belongs to the other mode, see `effective_model`.
+    pub model: Option<Model>,
     pub rom: Vec<u8>,
     pub headless_mode: bool,
     pub bootrom: Option<Vec<u8>>,
     pub log_file_path: Option<String>,
+    pub pacing: Pacing,
+    /// Emulation speed multiplier, 0 and 1 being real time. See `GameBoy::set_fast_forward`,
+    /// `Pacing::Uncapped` runs as fast as possible.
+    pub fast_forward: u32,
+    pub simultaneous_press_policy: SimultaneousPressPolicy,
+    /// Captures homebrew debug messages (BGB/Emulicious `ld d,d` convention), see `events::Event::DebugMessage`
+    pub debug_messages: bool,
+    /// Shows `splash::LogoSplash` before the rom starts when no bootrom is provided
+    pub boot_splash: bool,
+    /// Turns off the OAM STAT interrupt also requested when entering VBlank (line 144), for
+    /// debugging games misbehaving with it. Some games rely on it, see `ppu::PPU`.
+    pub disable_vblank_oam_stat: bool,
+    /// Refuses roms with a bad Nintendo logo or header checksum like the boot rom does, see
+    /// `GameBoy::try_new`. Only a warning is printed otherwise.
+    pub strict_header: bool,
+    /// Mapper used instead of the header one, for unlicensed roms the header heuristics miss.
+    /// Frontends usually keep it per game, keyed by `romdb::rom_crc32`.
+    pub mapper: Option<MapperOverride>,
+    /// What happens while the frontend is in the background, see `GameBoy::set_background`
+    pub background_policy: BackgroundPolicy,
+    /// Canonical identity of the rom, see `romdb::RomDatabase`. Saves are named after its title
+    /// instead of the header one when set.
+    pub rom_info: Option<RomInfo>,
+    /// Experimental: extra cpu cycles per scanline, reducing slowdown in games running out of
+    /// cpu time. See `GameBoy::set_overclock`.
+    pub overclock: u32,
+    /// Traces every executed instruction in the Gameboy Doctor format to `log_file_path`, or
+    /// stdout when it's None. See `trace` and `GameBoy::set_cpu_trace`.
+    pub cpu_trace: bool,
+    /// LY always reads 0x90, as in the Gameboy Doctor reference logs
+    pub stub_ly: bool,
+    /// Draws every object of a line instead of the first 10 in OAM, removing the flicker of
+    /// crowded games. See `GameBoy::set_sprite_limit`.
+    pub disable_sprite_limit: bool,
+    /// Executed instructions kept for crash reports and `GameBoy::pc_history`, None keeping
+    /// `cpu::OPCODE_HISTORY_LEN` and 0 turning the history off
+    pub pc_history: Option<usize>,
+    /// Seconds of frames kept for `GameBoy::export_replay_gif`, 0 turning the replay off. See
+    /// `replay`.
+    pub replay_seconds: u32,
+    /// Colors of the DMG shades, see `GameBoy::set_palette`
+    pub palette: DmgPalette,
+    /// Frames emulated without being drawn after each drawn one, 0 drawing them all. See
+    /// `GameBoy::set_frameskip`.
+    pub frameskip: u32,
+    /// Models the high-pass filter of the console audio output, see `GameBoy::set_audio_filter`
+    pub audio_filter: AudioFilter,
+}
+
+impl Config {
+    /// `model`, or the usual revision of `mode` when unset or mismatched
+    pub fn effective_model(&self) -> Model {
+        match self.model {
+            Some(model) if model.mode() as u8 == self.mode.clone() as u8 => model,
+            _ => Model::of(&self.mode),
+        }
+    }
+
+    /// Turns off the enhancements deviating from the hardware, `overclock` and
+    /// `disable_sprite_limit`, as accuracy test runs expect
+    pub fn without_enhancements(self) -> Self {
+        Self {
+            overclock: 0,
+            disable_sprite_limit: false,
+            ..self
+        }
+    }
 }
@@ -1,7 +1,17 @@
-use crate::{bus::Bus, config::Config, instr::OP_STOP, mode::Mode, registers};
+use crate::{
+    bus::Bus,
+    config::Config,
+    instr::OP_STOP,
+    mode::Mode,
+    registers,
+    savestate::{ChunkReader, ChunkWriter, Snapshot},
+};
 
 const INSTRUCTION_PREFIX: u8 = 0xCB;
 
+/// Instructions kept in the history by default, see `CPU::opcode_history`
+pub const OPCODE_HISTORY_LEN: usize = 32;
+
 pub struct CPU<B: Bus> {
     mode: Mode,
 
@@ -16,6 +26,18 @@ pub struct CPU<B: Bus> {
     bus: B,
 
     cycles_synced: u8,
+
+    debug_messages_enabled: bool,
+    debug_messages: Vec<String>,
+    /// first illegal opcode executed since the last take, (pc, opcode)
+    illegal_opcode: Option<(u16, u8)>,
+    /// history when the illegal opcode was executed, itself included
+    illegal_opcode_history: Vec<(u16, u8)>,
+
+    /// (pc, opcode) of the last executed instructions, a ring buffer of `history.len()` entries
+    history: Vec<(u16, u8)>,
+    history_next: usize,
+    history_len: usize,
 }
 
 impl<B: Bus> CPU<B> {
@@ -31,7 +53,7 @@ impl<B: Bus> CPU<B> {
             ime_delayed: false,
 
             registers: if skip_boot {
-                registers::Registers::new_post_boot(cfg.mode.clone())
+                registers::Registers::new_post_boot(cfg.effective_model())
             } else {
                 registers::Registers::new()
             },
@@ -41,9 +63,108 @@ impl<B: Bus> CPU<B> {
             bus,
 
             cycles_synced: 0,
+
+            debug_messages_enabled: cfg.debug_messages,
+            debug_messages: vec![],
+            illegal_opcode: None,
+            illegal_opcode_history: vec![],
+
+            history: vec![(0, 0); cfg.pc_history.unwrap_or(OPCODE_HISTORY_LEN)],
+            history_next: 0,
+            history_len: 0,
         }
     }
 
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    pub fn registers(&self) -> &registers::Registers {
+        &self.registers
+    }
+
+    /// (pc, opcode) of the last executed instructions, oldest first.
+    /// Prefixed instructions show up with the 0xCB prefix.
+    pub fn opcode_history(&self) -> Vec<(u16, u8)> {
+        let (newest, oldest) = self.history.split_at(self.history_next);
+        oldest
+            .iter()
+            .chain(newest)
+            .skip(self.history.len() - self.history_len)
+            .copied()
+            .collect()
+    }
+
+    /// Keeps the last `len` instructions in the history, 0 turns it off. Clears the history.
+    pub fn set_history_len(&mut self, len: usize) {
+        self.history = vec![(0, 0); len];
+        self.history_next = 0;
+        self.history_len = 0;
+    }
+
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.is_halted
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.is_stopped
+    }
+
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    /// Returns and clears the homebrew debug messages captured since the last call
+    pub fn drain_debug_messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.debug_messages)
+    }
+
+    /// First illegal opcode (0xD3, 0xDB...) executed since the last call, as (pc, opcode). They
+    /// lock up the hardware, they're executed as NOPs here.
+    pub fn take_illegal_opcode(&mut self) -> Option<(u16, u8)> {
+        self.illegal_opcode.take()
+    }
+
+    /// Opcode history up to the illegal opcode last returned by `take_illegal_opcode`
+    pub fn take_illegal_opcode_history(&mut self) -> Vec<(u16, u8)> {
+        std::mem::take(&mut self.illegal_opcode_history)
+    }
+
+    /// Debug message convention shared by BGB and Emulicious:
+    /// ld d,d / jr .end / dw $6464 / dw $0000 / db "message" / .end
+    /// The message is read without any timing side effect, the jr skips it at execution.
+    fn capture_debug_message(&mut self) {
+        let at = |offset: u16| self.bus.peek(self.pc.wrapping_add(offset));
+
+        if at(1) != 0x18 || at(3) != 0x64 || at(4) != 0x64 || at(5) != 0x00 || at(6) != 0x00 {
+            return;
+        }
+
+        let jump_len = at(2);
+        if jump_len < 4 {
+            return;
+        }
+
+        let message: String = (7..3 + jump_len as u16)
+            .map(at)
+            .take_while(|&c| c != 0)
+            .map(|c| c as char)
+            .collect();
+        self.debug_messages.push(message);
+    }
+
     fn read_byte(&mut self, address: u16) -> u8 {
         let v = self.bus.read_byte(address);
         self.bus.step_peripherals(4, false);
@@ -76,11 +197,9 @@ impl<B: Bus> CPU<B> {
         match instruction_byte {
             0x00 => Some((self.pc.wrapping_add(1), 4)),
             OP_STOP => {
-                self.is_stopped = true;
-                match self.mode {
-                    Mode::CGB => self.bus.switch_speed(),
-                    _ => {}
-                }
+                let speed_switched = matches!(self.mode, Mode::CGB) && self.bus.switch_speed();
+                // a button already held keeps the cpu from stopping
+                self.is_stopped = !speed_switched && !self.bus.is_joypad_line_low();
                 Some((self.pc.wrapping_add(1), 4))
             }
             0x03 => {
@@ -748,7 +867,10 @@ impl<B: Bus> CPU<B> {
                 Some((self.pc.wrapping_add(1), 4))
             }
             0x52 => {
-                self.registers.d = self.registers.d;
+                // ld d,d is a no-op, also used as a debug message marker
+                if self.debug_messages_enabled {
+                    self.capture_debug_message();
+                }
                 Some((self.pc.wrapping_add(1), 4))
             }
             0x53 => {
@@ -1013,10 +1135,6 @@ impl<B: Bus> CPU<B> {
             0xF1 => {
                 let val = self.pop();
                 self.registers.set_af(val);
-                self.registers.f.zero = ((val & 0xFF) >> 7) & 1 == 1;
-                self.registers.f.subtract = ((val & 0xFF) >> 6) & 1 == 1;
-                self.registers.f.half_carry = ((val & 0xFF) >> 5) & 1 == 1;
-                self.registers.f.carry = ((val & 0xFF) >> 4) & 1 == 1;
                 Some((self.pc.wrapping_add(1), 12))
             }
 
@@ -2253,13 +2371,28 @@ impl<B: Bus> CPU<B> {
             self.ime_delayed = false;
         }
 
+        // the peripherals keep running while stopped so frames are still produced, only a
+        // joypad line going low, interrupts enabled or not, wakes the cpu up
+        if self.is_stopped {
+            self.bus.step_peripherals(4, true);
+            self.is_stopped = !self.bus.is_joypad_line_low();
+            return 4;
+        }
+
         if self.is_halted {
             let cycles = 4 + self.check_interrupts();
             self.bus.step_peripherals(cycles, true);
             return cycles;
         }
 
-        let (next_pc, cycles) = match self.read_byte(self.pc) {
+        let opcode = self.read_byte(self.pc);
+        if !self.history.is_empty() {
+            self.history[self.history_next] = (self.pc, opcode);
+            self.history_next = (self.history_next + 1) % self.history.len();
+            self.history_len = (self.history_len + 1).min(self.history.len());
+        }
+
+        let (next_pc, cycles) = match opcode {
             INSTRUCTION_PREFIX => {
                 let byte = self.read_byte(self.pc + 1);
                 match self.execute_prefixed(byte) {
@@ -2269,7 +2402,13 @@ impl<B: Bus> CPU<B> {
             }
             byte => match self.execute(byte) {
                 Some((next_pc, cycles)) => (next_pc, cycles),
-                None => (self.pc.wrapping_add(1), 4),
+                None => {
+                    if self.illegal_opcode.is_none() {
+                        self.illegal_opcode = Some((self.pc, byte));
+                        self.illegal_opcode_history = self.opcode_history();
+                    }
+                    (self.pc.wrapping_add(1), 4)
+                }
             },
         };
         self.pc = next_pc;
@@ -2337,36 +2476,34 @@ impl<B: Bus> CPU<B> {
         (pc, cycles)
     }
 
+    /// Decimal adjusts A after a BCD addition or subtraction. After an addition the digits are
+    /// corrected when they overflowed (H, C) or went past 9, after a subtraction only the H and
+    /// C flags tell which digits borrowed, A itself isn't looked at. N is kept, H cleared, C set
+    /// when the tens overflowed and never cleared after a subtraction.
     fn daa(&mut self) {
-        let (has_carry, has_half_carry) = (self.registers.f.carry, self.registers.f.half_carry);
+        let a = self.registers.a;
+        let f = &self.registers.f;
 
-        self.registers.f.carry = false;
+        let low_adjust = f.half_carry || (!f.subtract && a & 0x0F > 0x09);
+        let high_adjust = f.carry || (!f.subtract && a > 0x99);
+        let correction = if low_adjust { 0x06 } else { 0 } | if high_adjust { 0x60 } else { 0 };
 
-        let mut offset: u8 = 0;
-        if (!self.registers.f.subtract && self.registers.a & 0xF > 0x09) || has_half_carry {
-            offset |= 0x06;
-        }
-        if (!self.registers.f.subtract && self.registers.a > 0x99) || has_carry {
-            offset |= 0x60;
-            self.registers.f.carry = true;
-        }
-
-        self.registers.a = if self.registers.f.subtract {
-            self.registers.a.wrapping_sub(offset)
+        self.registers.a = if f.subtract {
+            a.wrapping_sub(correction)
         } else {
-            self.registers.a.wrapping_add(offset)
+            a.wrapping_add(correction)
         };
-
-        self.registers.f.half_carry = false;
         self.registers.f.zero = self.registers.a == 0;
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = high_adjust;
     }
 
     fn add(&mut self, value: u8) {
-        let (new_value, did_overflow) = self.registers.a.overflowing_add(value);
+        let (new_value, half_carry, carry) = add_with_carry_8bits(self.registers.a, value, false);
         self.registers.f.zero = new_value == 0;
         self.registers.f.subtract = false;
-        self.registers.f.carry = did_overflow;
-        self.registers.f.half_carry = half_carry_add_8bits(self.registers.a, value);
+        self.registers.f.half_carry = half_carry;
+        self.registers.f.carry = carry;
         self.registers.a = new_value;
     }
 
@@ -2380,37 +2517,32 @@ impl<B: Bus> CPU<B> {
     }
 
     fn adc(&mut self, value: u8) {
-        let new_value =
-            (self.registers.a as u16) + (value as u16) + (self.registers.f.carry as u16);
-        self.registers.f.zero = new_value as u8 == 0;
+        let (new_value, half_carry, carry) =
+            add_with_carry_8bits(self.registers.a, value, self.registers.f.carry);
+        self.registers.f.zero = new_value == 0;
         self.registers.f.subtract = false;
-        self.registers.f.half_carry = half_carry_add_8bits(self.registers.a, value)
-            || half_carry_add_8bits(
-                (self.registers.a & 0xF) + (value & 0xF),
-                self.registers.f.carry as u8,
-            );
-        self.registers.f.carry = new_value > 0xFF;
-        self.registers.a = new_value as u8;
+        self.registers.f.half_carry = half_carry;
+        self.registers.f.carry = carry;
+        self.registers.a = new_value;
     }
 
     fn sub(&mut self, value: u8) {
-        let (new_value, did_overflow) = self.registers.a.overflowing_sub(value);
+        let (new_value, half_carry, carry) = sub_with_carry_8bits(self.registers.a, value, false);
         self.registers.f.zero = new_value == 0;
         self.registers.f.subtract = true;
-        self.registers.f.carry = did_overflow;
-        self.registers.f.half_carry = half_carry_sub_8bits(self.registers.a, value);
+        self.registers.f.half_carry = half_carry;
+        self.registers.f.carry = carry;
         self.registers.a = new_value;
     }
 
     fn sbc(&mut self, value: u8) {
-        let new_value =
-            (self.registers.a as i16) - (value as i16) - (self.registers.f.carry as i16);
-        self.registers.f.zero = new_value as u8 == 0;
+        let (new_value, half_carry, carry) =
+            sub_with_carry_8bits(self.registers.a, value, self.registers.f.carry);
+        self.registers.f.zero = new_value == 0;
         self.registers.f.subtract = true;
-        self.registers.f.half_carry =
-            half_carry_sub_with_carry_8bits(self.registers.a, value, self.registers.f.carry);
-        self.registers.f.carry = new_value < 0;
-        self.registers.a = new_value as u8;
+        self.registers.f.half_carry = half_carry;
+        self.registers.f.carry = carry;
+        self.registers.a = new_value;
     }
 
     fn and(&mut self, value: u8) {
@@ -2440,11 +2572,11 @@ impl<B: Bus> CPU<B> {
     }
 
     fn cp(&mut self, value: u8) {
-        let (new_value, did_overflow) = self.registers.a.overflowing_sub(value);
+        let (new_value, half_carry, carry) = sub_with_carry_8bits(self.registers.a, value, false);
         self.registers.f.zero = new_value == 0;
         self.registers.f.subtract = true;
-        self.registers.f.carry = did_overflow;
-        self.registers.f.half_carry = half_carry_sub_8bits(self.registers.a, value);
+        self.registers.f.half_carry = half_carry;
+        self.registers.f.carry = carry;
     }
 
     fn inc(&mut self, value: u8) -> u8 {
@@ -2456,6 +2588,7 @@ impl<B: Bus> CPU<B> {
     }
 
     fn inc_16bits(&mut self, value: u16) -> u16 {
+        self.bus.idu_access(value);
         let (new_value, _) = value.overflowing_add(1);
         new_value
     }
@@ -2469,6 +2602,7 @@ impl<B: Bus> CPU<B> {
     }
 
     fn dec_16bits(&mut self, value: u16) -> u16 {
+        self.bus.idu_access(value);
         let (new_value, _) = value.overflowing_sub(1);
         new_value
     }
@@ -2623,8 +2757,18 @@ fn half_carry_sub_8bits(x: u8, y: u8) -> bool {
     (x & 0xF).overflowing_sub(y & 0xF).1
 }
 
-fn half_carry_sub_with_carry_8bits(x: u8, y: u8, carry: bool) -> bool {
-    ((x & 0xF) as i16) - ((y & 0xF) as i16) - (carry as i16) < 0
+/// x + y + carry as (result, carry out of bit 3, carry out of bit 7)
+fn add_with_carry_8bits(x: u8, y: u8, carry: bool) -> (u8, bool, bool) {
+    let sum = x as u16 + y as u16 + carry as u16;
+    let half_carry = (x & 0xF) + (y & 0xF) + carry as u8 > 0xF;
+    (sum as u8, half_carry, sum > 0xFF)
+}
+
+/// x - y - carry as (result, borrow from bit 4, borrow from bit 8)
+fn sub_with_carry_8bits(x: u8, y: u8, carry: bool) -> (u8, bool, bool) {
+    let difference = x as i16 - y as i16 - carry as i16;
+    let half_carry = ((x & 0xF) as i16) - ((y & 0xF) as i16) - (carry as i16) < 0;
+    (difference as u8, half_carry, difference < 0)
 }
 
 fn right_rotate_through_carry(value: u8, carry: bool) -> (u8, bool) {
@@ -2639,17 +2783,57 @@ fn add_u16_i8(x: u16, y: i8) -> u16 {
     ((x as i16).wrapping_add(y as i16)) as u16
 }
 
+/// Registers and execution state only, the bus stores its own chunks
+impl<B: Bus> Snapshot for CPU<B> {
+    const TAG: [u8; 4] = *b"CPU ";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, w: &mut ChunkWriter) {
+        w.write_u16(self.registers.get_af());
+        w.write_u16(self.registers.get_bc());
+        w.write_u16(self.registers.get_de());
+        w.write_u16(self.registers.get_hl());
+        w.write_u16(self.pc);
+        w.write_u16(self.sp);
+        w.write_bool(self.ime);
+        w.write_bool(self.ime_delayed);
+        w.write_bool(self.is_halted);
+        w.write_bool(self.is_stopped);
+        w.write_u8(self.cycles_synced);
+    }
+
+    fn load_state(&mut self, r: &mut ChunkReader, _version: u16) {
+        self.registers.set_af(r.read_u16());
+        self.registers.set_bc(r.read_u16());
+        self.registers.set_de(r.read_u16());
+        self.registers.set_hl(r.read_u16());
+        self.pc = r.read_u16();
+        self.sp = r.read_u16();
+        self.ime = r.read_bool();
+        self.ime_delayed = r.read_bool();
+        self.is_halted = r.read_bool();
+        self.is_stopped = r.read_bool();
+        self.cycles_synced = r.read_u8();
+        // the history describes the timeline that got replaced
+        self.history_len = 0;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     pub struct FakeBus {
         mem: [u8; 0x10000],
+        joypad_line_low: bool,
     }
 
     impl FakeBus {
         pub fn new() -> Self {
-            Self { mem: [0; 0x10000] }
+            Self {
+                mem: [0; 0x10000],
+                joypad_line_low: false,
+            }
         }
     }
 
@@ -2658,6 +2842,10 @@ mod tests {
             self.mem[address as usize]
         }
 
+        fn peek(&self, address: u16) -> u8 {
+            self.mem[address as usize]
+        }
+
         fn write_byte(&mut self, address: u16, value: u8) {
             self.mem[address as usize] = value;
         }
@@ -2666,7 +2854,13 @@ mod tests {
             None
         }
 
-        fn switch_speed(&mut self) {}
+        fn switch_speed(&mut self) -> bool {
+            false
+        }
+
+        fn is_joypad_line_low(&self) -> bool {
+            self.joypad_line_low
+        }
 
         fn step_peripherals(&mut self, _cycles: u8, _is_halted: bool) {}
 
@@ -2683,11 +2877,44 @@ mod tests {
                 headless_mode: false,
                 bootrom: Some(vec![]),
                 log_file_path: None,
+                ..Default::default()
             },
             FakeBus::new(),
         )
     }
 
+    #[test]
+    fn test_cpu_stop_until_joypad_line_low() {
+        let mut cpu = make_test_cpu();
+        cpu.bus.mem[0] = OP_STOP;
+        cpu.bus.mem[1] = 0x3C; // INC A
+
+        cpu.step();
+        assert!(cpu.is_stopped());
+        for _ in 0..10 {
+            cpu.step();
+        }
+        assert_eq!(1, cpu.pc);
+        assert_eq!(0, cpu.registers.a);
+
+        cpu.bus.joypad_line_low = true;
+        cpu.step();
+        assert!(!cpu.is_stopped());
+        cpu.step();
+        assert_eq!(1, cpu.registers.a);
+    }
+
+    #[test]
+    fn test_cpu_stop_with_button_held() {
+        let mut cpu = make_test_cpu();
+        cpu.bus.mem[0] = OP_STOP;
+        cpu.bus.joypad_line_low = true;
+
+        cpu.step();
+        assert!(!cpu.is_stopped());
+        assert_eq!(1, cpu.pc);
+    }
+
     #[test]
     fn test_cpu_add_nominal() {
         let mut cpu = make_test_cpu();
@@ -3725,6 +3952,168 @@ mod tests {
         assert_eq!(true, cpu.registers.f.carry);
     }
 
+    /// DAA as the Z80 correction table after an addition (digit ranges and flags pick the
+    /// correction and the carry), the SM83 only looks at H and C after a subtraction.
+    /// Returns A and F.
+    fn reference_daa(a: u8, subtract: bool, half_carry: bool, carry: bool) -> (u8, u8) {
+        let (high, low) = (a >> 4, a & 0x0F);
+        let (correction, carry_out) = if subtract {
+            ((half_carry as u8) * 0x06 + (carry as u8) * 0x60, carry)
+        } else {
+            match (carry, high, half_carry, low) {
+                (false, 0x0..=0x9, false, 0x0..=0x9) => (0x00, false),
+                (false, 0x0..=0x9, true, 0x0..=0x9) => (0x06, false),
+                (false, 0x0..=0x8, _, 0xA..=0xF) => (0x06, false),
+                (false, 0xA..=0xF, false, 0x0..=0x9) => (0x60, true),
+                (true, _, false, 0x0..=0x9) => (0x60, true),
+                (true, _, true, 0x0..=0x9) => (0x66, true),
+                (true, _, _, 0xA..=0xF) => (0x66, true),
+                (false, 0x9..=0xF, _, 0xA..=0xF) => (0x66, true),
+                (false, 0xA..=0xF, true, 0x0..=0x9) => (0x66, true),
+                _ => unreachable!(),
+            }
+        };
+        let result = if subtract {
+            a.wrapping_sub(correction)
+        } else {
+            a.wrapping_add(correction)
+        };
+        let f = ((result == 0) as u8 * 0x80) | (subtract as u8 * 0x40) | (carry_out as u8 * 0x10);
+        (result, f)
+    }
+
+    #[test]
+    fn test_cpu_daa_truth_table() {
+        let mut cpu = make_test_cpu();
+        for a in 0..=0xFF {
+            for flags in 0..8u8 {
+                let (subtract, half_carry, carry) =
+                    (flags & 4 != 0, flags & 2 != 0, flags & 1 != 0);
+                cpu.registers.a = a;
+                cpu.registers.f = registers::FlagsRegister {
+                    zero: a & 1 == 0, // ignored
+                    subtract,
+                    half_carry,
+                    carry,
+                };
+
+                cpu.daa();
+
+                assert_eq!(
+                    reference_daa(a, subtract, half_carry, carry),
+                    (cpu.registers.a, u8::from(cpu.registers.f.clone())),
+                    "A={a:#04x} N={subtract} H={half_carry} C={carry}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_cpu_daa_edge_cases() {
+        // (A, N, H, C) -> (A, F)
+        let cases = [
+            // 0x99 is the largest valid BCD value, 0x9A wraps to 0 with carry
+            ((0x99, false, false, false), (0x99, 0x00)),
+            ((0x9A, false, false, false), (0x00, 0x90)),
+            // tens already past 9 with a valid unit
+            ((0xA0, false, false, false), (0x00, 0x90)),
+            // the unit correction carries into the tens, 0x8A + 6 = 0x90
+            ((0x8A, false, false, false), (0x90, 0x00)),
+            // 0x99 + 0x99 = 0x32 with H and C: 198
+            ((0x32, false, true, true), (0x98, 0x10)),
+            // after a subtraction the digits don't matter, only H and C
+            ((0x0F, true, false, false), (0x0F, 0x40)),
+            ((0x00, true, true, false), (0xFA, 0x40)),
+            ((0x00, true, false, true), (0xA0, 0x50)),
+            ((0x66, true, true, true), (0x00, 0xD0)),
+        ];
+
+        let mut cpu = make_test_cpu();
+        for ((a, subtract, half_carry, carry), expected) in cases {
+            cpu.registers.a = a;
+            cpu.registers.f = registers::FlagsRegister {
+                zero: false,
+                subtract,
+                half_carry,
+                carry,
+            };
+
+            cpu.daa();
+
+            assert_eq!(
+                expected,
+                (cpu.registers.a, u8::from(cpu.registers.f.clone())),
+                "A={a:#04x} N={subtract} H={half_carry} C={carry}"
+            );
+        }
+    }
+
+    /// 8-bit addition or subtraction computed 16 bits wide: bit n of x ^ y ^ result is the carry
+    /// (or borrow) into bit n, so bit 4 gives H and bit 8 gives C. Returns A and F.
+    fn reference_alu(x: u8, y: u8, carry: bool, subtract: bool) -> (u8, u8) {
+        let (x, y, carry) = (x as u16, y as u16, carry as u16);
+        let result = if subtract {
+            x.wrapping_sub(y).wrapping_sub(carry)
+        } else {
+            x + y + carry
+        };
+        let carries = x ^ y ^ result;
+        let f = ((result as u8 == 0) as u8) << 7
+            | (subtract as u8) << 6
+            | ((carries >> 4 & 1) as u8) << 5
+            | ((carries >> 8 & 1) as u8) << 4;
+        (result as u8, f)
+    }
+
+    #[test]
+    fn test_cpu_alu_against_wide_reference() {
+        let mut cpu = make_test_cpu();
+        let ops: [(fn(&mut CPU<FakeBus>, u8), bool, bool); 4] = [
+            (CPU::add, false, false),
+            (CPU::adc, false, true),
+            (CPU::sub, true, false),
+            (CPU::sbc, true, true),
+        ];
+
+        for (op, subtract, uses_carry) in ops {
+            for x in 0..=0xFF {
+                for y in 0..=0xFF {
+                    for carry in [false, true] {
+                        cpu.registers.a = x;
+                        cpu.registers.f = registers::FlagsRegister {
+                            zero: false,
+                            subtract: !subtract,
+                            half_carry: false,
+                            carry,
+                        };
+
+                        op(&mut cpu, y);
+
+                        assert_eq!(
+                            reference_alu(x, y, carry && uses_carry, subtract),
+                            (cpu.registers.a, u8::from(cpu.registers.f.clone())),
+                            "{x:#04x} {} {y:#04x} with carry {carry}",
+                            if subtract { '-' } else { '+' }
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_cpu_cp_leaves_a() {
+        let mut cpu = make_test_cpu();
+        for x in 0..=0xFF {
+            for y in 0..=0xFF {
+                cpu.registers.a = x;
+                cpu.cp(y);
+                let (_, f) = reference_alu(x, y, false, true);
+                assert_eq!((x, f), (cpu.registers.a, u8::from(cpu.registers.f.clone())));
+            }
+        }
+    }
+
     #[test]
     fn test_cpu_push_nominal() {
         let mut cpu = make_test_cpu();
@@ -3749,4 +4138,100 @@ mod tests {
         assert_eq!(128, cpu.sp);
         assert_eq!(0xEEAA, val);
     }
+
+    #[test]
+    fn test_cpu_debug_message() {
+        let mut cpu = CPU::new(
+            &Config {
+                mode: Mode::DMG,
+                rom: vec![],
+                headless_mode: false,
+                bootrom: Some(vec![]),
+                log_file_path: None,
+                debug_messages: true,
+                ..Default::default()
+            },
+            FakeBus::new(),
+        );
+
+        let message = b"hello";
+        let program = [
+            &[0x52, 0x18, 4 + message.len() as u8, 0x64, 0x64, 0x00, 0x00][..],
+            message,
+            &[0x00],
+        ]
+        .concat();
+        for (i, &b) in program.iter().enumerate() {
+            cpu.bus.write_byte(i as u16, b);
+        }
+
+        cpu.step();
+        cpu.step();
+
+        assert_eq!(program.len() as u16 - 1, cpu.pc);
+        assert_eq!(vec!["hello".to_owned()], cpu.drain_debug_messages());
+        assert!(cpu.drain_debug_messages().is_empty());
+
+        // a plain ld d,d isn't a message
+        cpu.pc = 0x100;
+        cpu.step();
+        assert!(cpu.drain_debug_messages().is_empty());
+    }
+
+    #[test]
+    fn test_cpu_illegal_opcode() {
+        let mut cpu = CPU::new(
+            &Config {
+                bootrom: Some(vec![]),
+                ..Default::default()
+            },
+            FakeBus::new(),
+        );
+        // nop; illegal 0xD3; illegal 0xFD
+        for (i, b) in [0x00, 0xD3, 0xFD].into_iter().enumerate() {
+            cpu.bus.write_byte(i as u16, b);
+        }
+
+        cpu.step();
+        assert_eq!(None, cpu.take_illegal_opcode());
+        cpu.step();
+        cpu.step();
+        assert_eq!(3, cpu.pc);
+        assert_eq!(Some((0x0001, 0xD3)), cpu.take_illegal_opcode());
+        assert_eq!(
+            vec![(0x0000, 0x00), (0x0001, 0xD3)],
+            cpu.take_illegal_opcode_history()
+        );
+        assert_eq!(None, cpu.take_illegal_opcode());
+        assert!(cpu.take_illegal_opcode_history().is_empty());
+    }
+
+    #[test]
+    fn test_cpu_history_len() {
+        let mut cpu = CPU::new(
+            &Config {
+                bootrom: Some(vec![]),
+                pc_history: Some(3),
+                ..Default::default()
+            },
+            FakeBus::new(),
+        );
+        // nops, then inc a
+        cpu.bus.write_byte(0x0004, 0x3C);
+        for _ in 0..5 {
+            cpu.step();
+        }
+        assert_eq!(
+            vec![(0x0002, 0x00), (0x0003, 0x00), (0x0004, 0x3C)],
+            cpu.opcode_history()
+        );
+
+        cpu.set_history_len(0);
+        cpu.step();
+        assert!(cpu.opcode_history().is_empty());
+
+        cpu.set_history_len(OPCODE_HISTORY_LEN);
+        cpu.step();
+        assert_eq!(vec![(0x0006, 0x00)], cpu.opcode_history());
+    }
 }
@@ -0,0 +1,180 @@
+//! Crash reports with the emulation context, so user reports of internal panics carry enough
+//! to reproduce them. See `GameBoy::set_crash_handler`.
+
+use std::{
+    cell::RefCell,
+    fmt, fs,
+    panic::{self, PanicHookInfo},
+    path::PathBuf,
+    sync::Once,
+};
+
+use crate::registers::Registers;
+
+/// IO registers summarized in reports
+pub const IO_SUMMARY: [(&str, u16); 16] = [
+    ("P1", 0xFF00),
+    ("DIV", 0xFF04),
+    ("TIMA", 0xFF05),
+    ("TMA", 0xFF06),
+    ("TAC", 0xFF07),
+    ("IF", 0xFF0F),
+    ("NR52", 0xFF26),
+    ("LCDC", 0xFF40),
+    ("STAT", 0xFF41),
+    ("SCY", 0xFF42),
+    ("SCX", 0xFF43),
+    ("LY", 0xFF44),
+    ("LYC", 0xFF45),
+    ("KEY1", 0xFF4D),
+    ("SVBK", 0xFF70),
+    ("IE", 0xFFFF),
+];
+
+thread_local! {
+    /// message and location of the last panic on this thread, set by the hook
+    static LAST_PANIC: RefCell<Option<(String, Option<String>)>> = const { RefCell::new(None) };
+}
+
+static INSTALL_HOOK: Once = Once::new();
+
+/// Records the panic message and location for crash reports, the previously installed hook
+/// keeps running. Installing it more than once has no effect.
+pub fn install_panic_hook() {
+    INSTALL_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info: &PanicHookInfo| {
+            let message = match info.payload().downcast_ref::<&str>() {
+                Some(s) => s.to_string(),
+                None => info
+                    .payload()
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .unwrap_or_else(|| "unknown panic".to_string()),
+            };
+            let location = info.location().map(|l| l.to_string());
+            LAST_PANIC.with(|last| *last.borrow_mut() = Some((message, location)));
+            previous(info);
+        }));
+    });
+}
+
+pub(crate) fn take_last_panic() -> (String, Option<String>) {
+    LAST_PANIC
+        .with(|last| last.borrow_mut().take())
+        .unwrap_or_else(|| ("unknown panic".to_string(), None))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CrashReport {
+    pub message: String,
+    /// Source location of the panic
+    pub location: Option<String>,
+    pub frame: u64,
+    pub pc: u16,
+    pub sp: u16,
+    pub registers: Registers,
+    /// (pc, opcode) of the last executed instructions, oldest first
+    pub opcode_history: Vec<(u16, u8)>,
+    /// (name, address, value), see `IO_SUMMARY`
+    pub io: Vec<(&'static str, u16, u8)>,
+    /// See `features()`
+    pub build: String,
+}
+
+impl fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "panic: {}", self.message)?;
+        if let Some(location) = &self.location {
+            writeln!(f, "at: {location}")?;
+        }
+        writeln!(f, "build: {}", self.build)?;
+        writeln!(f, "frame: {}", self.frame)?;
+
+        writeln!(
+            f,
+            "PC={:04X} SP={:04X} {}",
+            self.pc, self.sp, self.registers
+        )?;
+
+        write!(f, "last opcodes:")?;
+        for (pc, opcode) in &self.opcode_history {
+            write!(f, " {pc:04X}:{opcode:02X}")?;
+        }
+        writeln!(f)?;
+
+        write!(f, "io:")?;
+        for (name, _, value) in &self.io {
+            write!(f, " {name}={value:02X}")?;
+        }
+        writeln!(f)
+    }
+}
+
+pub type CrashHandler = Box<dyn FnMut(&CrashReport)>;
+
+/// Crash handler writing the report to `path`, overwriting the previous one
+pub fn write_to_file(path: impl Into<PathBuf>) -> impl FnMut(&CrashReport) {
+    let path = path.into();
+    move |report| {
+        if let Err(e) = fs::write(&path, report.to_string()) {
+            warn!("Cannot write the crash report to {}: {e}", path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{panic::AssertUnwindSafe, rc::Rc, sync::mpsc};
+
+    use crate::{
+        config::Config, gameboy::GameBoy, joypad_events_handler, lcd, pacing::Pacing, saver,
+        testing::RomBuilder,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_crash_report() {
+        // writing to the bootrom area while it's mapped trips an internal assertion
+        // ld a, 0x42; ld (0x0010), a
+        let mut bootrom = vec![0x3E, 0x42, 0xEA, 0x10, 0x00];
+        bootrom.resize(0x100, 0);
+
+        let cfg = Config {
+            rom: RomBuilder::new().build(),
+            bootrom: Some(bootrom),
+            headless_mode: true,
+            pacing: Pacing::Uncapped,
+            ..Default::default()
+        };
+        let (_, rx) = mpsc::channel::<()>();
+        let mut gb = GameBoy::new(
+            &cfg,
+            lcd::Fake,
+            crate::stereo::Fake,
+            joypad_events_handler::Fake,
+            saver::Fake,
+            &rx,
+        );
+
+        let report = Rc::new(RefCell::new(None));
+        let sink = report.clone();
+        gb.set_crash_handler(move |r: &CrashReport| *sink.borrow_mut() = Some(r.clone()));
+
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| gb.step_frame())).is_err());
+
+        let report = report.borrow_mut().take().expect("crash handler called");
+        assert!(report.message.contains("writing to bootrom"));
+        assert!(report.location.as_deref().unwrap().contains("cartridge.rs"));
+        assert_eq!(0, report.frame);
+        assert_eq!(0x0002, report.pc);
+        assert_eq!(vec![(0x0000, 0x3E), (0x0002, 0xEA)], report.opcode_history);
+        assert_eq!(0x42, report.registers.a());
+        assert!(report.io.contains(&("LY", 0xFF44, 0)));
+
+        let text = report.to_string();
+        assert!(text.contains("last opcodes: 0000:3E 0002:EA\n"));
+        assert!(text.contains(" LCDC="));
+    }
+}
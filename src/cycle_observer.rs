@@ -0,0 +1,31 @@
+//! Hook into every peripheral step, for co-simulation against other hardware models or
+//! cycle-exact visualizations.
+
+/// CPU memory access that triggered a peripheral step
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BusAccess {
+    Read { address: u16, value: u8 },
+    Write { address: u16, value: u8 },
+}
+
+/// Summary of the bus activity during one peripheral step
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BusActivity {
+    /// Cycles the peripherals were stepped by, in cpu clock (doubled in double speed mode)
+    pub cycles: u8,
+    /// Extra cycles the cpu was stalled by a VRAM DMA transfer during the step
+    pub dma_stall_cycles: u32,
+    pub double_speed: bool,
+    pub cpu_halted: bool,
+    /// None on internal cpu cycles (and while halted)
+    pub access: Option<BusAccess>,
+    /// IF (0xFF0F) after the step
+    pub interrupt_flags: u8,
+}
+
+/// Called after every peripheral step, see `GameBoy::set_cycle_observer`.
+/// The cpu steps the peripherals once per memory access and internal cycle, so each call covers
+/// at most one access.
+pub trait CycleObserver {
+    fn on_step(&mut self, activity: &BusActivity);
+}
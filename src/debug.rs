@@ -0,0 +1,898 @@
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fmt::{self, Write},
+};
+
+use crate::{lcd::FrameBuffer, symbols::SymbolTable, testing::frame_hash};
+
+/// Bytes covered by each heatmap bucket, keeps the counters bounded to 256 buckets
+pub const HEATMAP_BUCKET_SIZE: usize = 0x100;
+pub const HEATMAP_BUCKETS: usize = 0x10000 / HEATMAP_BUCKET_SIZE;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BucketAccesses {
+    pub reads: u32,
+    pub writes: u32,
+    /// Bytes moved by OAM and VRAM DMA, counted on both the source and the destination
+    pub dma: u32,
+}
+
+/// Coarse per-address bus access counters.
+/// Reads happen through `&self` on the bus, hence the cells.
+#[derive(Debug)]
+pub struct MemoryHeatmap {
+    reads: [Cell<u32>; HEATMAP_BUCKETS],
+    writes: [Cell<u32>; HEATMAP_BUCKETS],
+    dma: [Cell<u32>; HEATMAP_BUCKETS],
+}
+
+impl Default for MemoryHeatmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryHeatmap {
+    pub fn new() -> Self {
+        Self {
+            reads: std::array::from_fn(|_| Cell::new(0)),
+            writes: std::array::from_fn(|_| Cell::new(0)),
+            dma: std::array::from_fn(|_| Cell::new(0)),
+        }
+    }
+
+    fn inc(counters: &[Cell<u32>; HEATMAP_BUCKETS], address: u16) {
+        let counter = &counters[address as usize / HEATMAP_BUCKET_SIZE];
+        counter.set(counter.get().saturating_add(1));
+    }
+
+    pub fn record_read(&self, address: u16) {
+        Self::inc(&self.reads, address);
+    }
+
+    pub fn record_write(&self, address: u16) {
+        Self::inc(&self.writes, address);
+    }
+
+    pub fn record_dma(&self, address: u16) {
+        Self::inc(&self.dma, address);
+    }
+
+    /// Accesses of the bucket containing `address`
+    pub fn bucket(&self, address: u16) -> BucketAccesses {
+        let i = address as usize / HEATMAP_BUCKET_SIZE;
+        BucketAccesses {
+            reads: self.reads[i].get(),
+            writes: self.writes[i].get(),
+            dma: self.dma[i].get(),
+        }
+    }
+
+    /// Every bucket in address order, bucket `i` covers `i * HEATMAP_BUCKET_SIZE..(i + 1) * HEATMAP_BUCKET_SIZE`
+    pub fn buckets(&self) -> Vec<BucketAccesses> {
+        (0..HEATMAP_BUCKETS)
+            .map(|i| self.bucket((i * HEATMAP_BUCKET_SIZE) as u16))
+            .collect()
+    }
+
+    pub fn reset(&self) {
+        for counters in [&self.reads, &self.writes, &self.dma] {
+            for c in counters.iter() {
+                c.set(0);
+            }
+        }
+    }
+}
+
+/// Per-bank cartridge access counters, filled by the mapper, see `GameBoy::set_bank_usage`.
+/// Useful to trim padded roms or check which banks a multicart menu maps.
+#[derive(Debug)]
+pub struct BankUsage {
+    rom_reads: Vec<Cell<u32>>,
+    ram_reads: Vec<Cell<u32>>,
+    ram_writes: Vec<Cell<u32>>,
+    /// writes to the mapper registers (rom area)
+    register_writes: Cell<u32>,
+}
+
+impl BankUsage {
+    pub fn new(rom_banks: usize, ram_banks: usize) -> Self {
+        Self {
+            rom_reads: (0..rom_banks).map(|_| Cell::new(0)).collect(),
+            ram_reads: (0..ram_banks).map(|_| Cell::new(0)).collect(),
+            ram_writes: (0..ram_banks).map(|_| Cell::new(0)).collect(),
+            register_writes: Cell::new(0),
+        }
+    }
+
+    fn inc(counter: Option<&Cell<u32>>) {
+        if let Some(c) = counter {
+            c.set(c.get().saturating_add(1));
+        }
+    }
+
+    pub(crate) fn record_rom_read(&self, bank: usize) {
+        Self::inc(self.rom_reads.get(bank));
+    }
+
+    pub(crate) fn record_ram_read(&self, bank: usize) {
+        Self::inc(self.ram_reads.get(bank));
+    }
+
+    pub(crate) fn record_ram_write(&self, bank: usize) {
+        Self::inc(self.ram_writes.get(bank));
+    }
+
+    pub(crate) fn record_register_write(&self) {
+        Self::inc(Some(&self.register_writes));
+    }
+
+    pub fn rom_banks(&self) -> usize {
+        self.rom_reads.len()
+    }
+
+    pub fn ram_banks(&self) -> usize {
+        self.ram_reads.len()
+    }
+
+    pub fn rom_reads(&self, bank: usize) -> u32 {
+        self.rom_reads.get(bank).map_or(0, Cell::get)
+    }
+
+    pub fn ram_reads(&self, bank: usize) -> u32 {
+        self.ram_reads.get(bank).map_or(0, Cell::get)
+    }
+
+    pub fn ram_writes(&self, bank: usize) -> u32 {
+        self.ram_writes.get(bank).map_or(0, Cell::get)
+    }
+
+    pub fn register_writes(&self) -> u32 {
+        self.register_writes.get()
+    }
+
+    pub fn used_rom_banks(&self) -> Vec<usize> {
+        (0..self.rom_banks())
+            .filter(|&b| self.rom_reads(b) > 0)
+            .collect()
+    }
+
+    pub fn used_ram_banks(&self) -> Vec<usize> {
+        (0..self.ram_banks())
+            .filter(|&b| self.ram_reads(b) > 0 || self.ram_writes(b) > 0)
+            .collect()
+    }
+
+    /// Smallest valid rom size (a power of two, 2 banks at least) holding every bank read so far
+    pub fn trimmed_rom_size(&self) -> usize {
+        let banks = self.used_rom_banks().last().map_or(0, |&b| b + 1);
+        banks.next_power_of_two().max(2) * 0x4000
+    }
+
+    pub fn reset(&self) {
+        for counters in [&self.rom_reads, &self.ram_reads, &self.ram_writes] {
+            for c in counters.iter() {
+                c.set(0);
+            }
+        }
+        self.register_writes.set(0);
+    }
+}
+
+impl fmt::Display for BankUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |banks: Vec<usize>| {
+            banks
+                .iter()
+                .map(|b| b.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        writeln!(
+            f,
+            "rom banks used: {}/{} [{}]",
+            self.used_rom_banks().len(),
+            self.rom_banks(),
+            list(self.used_rom_banks())
+        )?;
+        writeln!(
+            f,
+            "ram banks used: {}/{} [{}]",
+            self.used_ram_banks().len(),
+            self.ram_banks(),
+            list(self.used_ram_banks())
+        )?;
+        writeln!(f, "mapper register writes: {}", self.register_writes())?;
+        write!(f, "trimmed rom size: {:#x}", self.trimmed_rom_size())
+    }
+}
+
+/// Size in bytes of the instruction starting with `opcode`, operands included
+pub fn instruction_length(opcode: u8) -> u16 {
+    match opcode {
+        0x01 | 0x08 | 0x11 | 0x21 | 0x31 | 0xC2 | 0xC3 | 0xC4 | 0xCA | 0xCC | 0xCD | 0xD2
+        | 0xD4 | 0xDA | 0xDC | 0xEA | 0xFA => 3,
+        0x06 | 0x0E | 0x10 | 0x16 | 0x18 | 0x1E | 0x20 | 0x26 | 0x28 | 0x2E | 0x30 | 0x36
+        | 0x38 | 0x3E | 0xC6 | 0xCB | 0xCE | 0xD6 | 0xDE | 0xE0 | 0xE6 | 0xE8 | 0xEE | 0xF0
+        | 0xF6 | 0xF8 | 0xFE => 2,
+        _ => 1,
+    }
+}
+
+/// Per rom byte access flags, filled by the mapper, see `GameBoy::set_rom_coverage`.
+/// Bytes are indexed by their offset in the rom file, whatever bank maps them.
+#[derive(Debug)]
+pub struct RomCoverage {
+    flags: Vec<Cell<u8>>,
+}
+
+impl RomCoverage {
+    /// Opcode or operand of an executed instruction
+    pub const EXECUTED: u8 = 1 << 0;
+    /// Read by the cpu or a DMA, instruction fetches included
+    pub const READ: u8 = 1 << 1;
+    /// Written to, i.e. a mapper register write at that address
+    pub const WRITTEN: u8 = 1 << 2;
+
+    pub fn new(rom_size: usize) -> Self {
+        Self {
+            flags: (0..rom_size).map(|_| Cell::new(0)).collect(),
+        }
+    }
+
+    pub(crate) fn record(&self, offset: usize, flag: u8) {
+        if let Some(flags) = self.flags.get(offset) {
+            flags.set(flags.get() | flag);
+        }
+    }
+
+    pub fn rom_size(&self) -> usize {
+        self.flags.len()
+    }
+
+    /// Flags of the rom byte at `offset`, 0 when never accessed
+    pub fn flags(&self, offset: usize) -> u8 {
+        self.flags.get(offset).map_or(0, Cell::get)
+    }
+
+    /// Rom bytes with every bit of `flag` set
+    pub fn count(&self, flag: u8) -> usize {
+        self.flags.iter().filter(|f| f.get() & flag == flag).count()
+    }
+
+    /// One flags byte per rom byte, in rom order
+    pub fn export(&self) -> Vec<u8> {
+        self.flags.iter().map(Cell::get).collect()
+    }
+
+    /// One bit per rom byte with every bit of `flag` set, least significant bit first
+    pub fn bitmap(&self, flag: u8) -> Vec<u8> {
+        self.flags
+            .chunks(8)
+            .map(|bytes| {
+                bytes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, f)| f.get() & flag == flag)
+                    .fold(0, |bits, (i, _)| bits | 1 << i)
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for RomCoverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = |count: usize| count as f64 * 100.0 / self.rom_size().max(1) as f64;
+        for (name, flag) in [
+            ("executed", Self::EXECUTED),
+            ("read", Self::READ),
+            ("written", Self::WRITTEN),
+        ] {
+            let count = self.count(flag);
+            writeln!(
+                f,
+                "{name}: {count:#x}/{:#x} bytes ({:.1}%)",
+                self.rom_size(),
+                percent(count)
+            )?;
+        }
+        let untouched = self.flags.iter().filter(|f| f.get() == 0).count();
+        write!(
+            f,
+            "never accessed: {untouched:#x} bytes ({:.1}%)",
+            percent(untouched)
+        )
+    }
+}
+
+/// Cycles spent in a function, or on a single instruction without symbols, see
+/// `Profiler::functions`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionProfile {
+    /// Label, or `BB:PPPP` like in symbol files
+    pub name: String,
+    pub bank: u16,
+    pub address: u16,
+    pub cycles: u64,
+    /// Instructions executed
+    pub instructions: u64,
+}
+
+/// Cpu cycles executed per instruction address and rom bank, see `GameBoy::set_profiler`.
+/// Interrupt dispatch counts towards the instruction it follows.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    /// (bank, pc) -> (cycles, instructions)
+    pcs: HashMap<(u16, u16), (u64, u64)>,
+    halted_cycles: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record(&mut self, bank: u16, pc: u16, cycles: u8) {
+        let (total, instructions) = self.pcs.entry((bank, pc)).or_default();
+        *total += cycles as u64;
+        *instructions += 1;
+    }
+
+    /// Cycles spent halted or stopped
+    pub(crate) fn record_halted(&mut self, cycles: u8) {
+        self.halted_cycles += cycles as u64;
+    }
+
+    /// Halted cycles included
+    pub fn total_cycles(&self) -> u64 {
+        self.halted_cycles + self.pcs.values().map(|(cycles, _)| cycles).sum::<u64>()
+    }
+
+    pub fn halted_cycles(&self) -> u64 {
+        self.halted_cycles
+    }
+
+    /// Cycles grouped by the function containing each instruction, the closest label before it,
+    /// hottest first
+    pub fn functions(&self, symbols: Option<&SymbolTable>) -> Vec<FunctionProfile> {
+        let mut functions: HashMap<(u16, u16), FunctionProfile> = HashMap::new();
+        for (&(bank, pc), &(cycles, instructions)) in &self.pcs {
+            let (address, name) = match symbols.and_then(|s| s.nearest(bank, pc)) {
+                Some((address, name)) => (address, name.to_string()),
+                None => (pc, format!("{bank:02X}:{pc:04X}")),
+            };
+            let function = functions
+                .entry((bank, address))
+                .or_insert_with(|| FunctionProfile {
+                    name,
+                    bank,
+                    address,
+                    cycles: 0,
+                    instructions: 0,
+                });
+            function.cycles += cycles;
+            function.instructions += instructions;
+        }
+        let mut functions: Vec<_> = functions.into_values().collect();
+        functions.sort_by(|a, b| {
+            b.cycles
+                .cmp(&a.cycles)
+                .then((a.bank, a.address).cmp(&(b.bank, b.address)))
+        });
+        functions
+    }
+
+    /// The `count` hottest functions with their share of the total cycles, then the time spent
+    /// halted
+    pub fn report(&self, count: usize, symbols: Option<&SymbolTable>) -> String {
+        let rows = self
+            .functions(symbols)
+            .into_iter()
+            .take(count)
+            .map(|function| {
+                let columns = format!("{:>12}  {}", function.instructions, function.name);
+                (function.cycles, columns)
+            });
+        cycles_report(
+            &format!("{:>12}  function", "instructions"),
+            rows,
+            (self.halted_cycles, format!("{:>12}  (halted)", "")),
+            self.total_cycles(),
+        )
+    }
+
+    pub fn reset(&mut self) {
+        self.pcs.clear();
+        self.halted_cycles = 0;
+    }
+}
+
+/// Table of the `Profiler` and `HotSpots` reports: `rows` of cycles and the columns after their
+/// share of `total`, then the `last` row
+fn cycles_report(
+    header: &str,
+    rows: impl Iterator<Item = (u64, String)>,
+    last: (u64, String),
+    total: u64,
+) -> String {
+    let share = |cycles: u64| cycles as f64 * 100. / total.max(1) as f64;
+    let mut report = format!("{:>12} {:>6} {header}\n", "cycles", "%");
+    for (cycles, columns) in rows {
+        _ = writeln!(report, "{cycles:>12} {:>5.1}% {columns}", share(cycles));
+    }
+    let (cycles, columns) = last;
+    _ = write!(report, "{cycles:>12} {:>5.1}% {columns}", share(cycles));
+    report
+}
+
+/// Rom bytes covered by each `HotSpots` bucket
+pub const HOT_SPOT_BUCKET_SIZE: usize = 0x100;
+
+/// Cycles spent in a `HOT_SPOT_BUCKET_SIZE` range of the rom, see `HotSpots::hottest`
+#[derive(Clone, Debug, PartialEq)]
+pub struct HotSpot {
+    /// Offset in the rom file of the first byte of the range
+    pub rom_offset: usize,
+    pub cycles: u64,
+}
+
+impl HotSpot {
+    pub fn bank(&self) -> usize {
+        self.rom_offset / 0x4000
+    }
+
+    /// Address the range is mapped at when its bank is selected
+    pub fn address(&self) -> u16 {
+        match self.bank() {
+            0 => self.rom_offset as u16,
+            _ => 0x4000 | (self.rom_offset & 0x3FFF) as u16,
+        }
+    }
+}
+
+/// Cpu cycles executed per 256 bytes of rom, see `GameBoy::set_hot_spots`. Coarser than the
+/// `Profiler`: each instruction still looks its bank up, but then adds to a flat array instead of
+/// hashing its address, cheap enough to leave on while playing.
+#[derive(Debug, Default)]
+pub struct HotSpots {
+    /// rom offset / HOT_SPOT_BUCKET_SIZE -> cycles, grown to the highest bucket executed
+    buckets: Vec<u64>,
+    /// halted, stopped or running from ram
+    outside_cycles: u64,
+}
+
+impl HotSpots {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `rom_offset` of the executed instruction, None outside of the rom
+    pub(crate) fn record(&mut self, rom_offset: Option<usize>, cycles: u8) {
+        let Some(offset) = rom_offset else {
+            self.outside_cycles += cycles as u64;
+            return;
+        };
+        let bucket = offset / HOT_SPOT_BUCKET_SIZE;
+        if bucket >= self.buckets.len() {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += cycles as u64;
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.outside_cycles + self.buckets.iter().sum::<u64>()
+    }
+
+    /// Cycles spent halted, stopped or running code copied to ram
+    pub fn outside_cycles(&self) -> u64 {
+        self.outside_cycles
+    }
+
+    /// Cycles of the bucket containing `rom_offset`
+    pub fn cycles(&self, rom_offset: usize) -> u64 {
+        self.buckets
+            .get(rom_offset / HOT_SPOT_BUCKET_SIZE)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// The `count` buckets with the most cycles, hottest first
+    pub fn hottest(&self, count: usize) -> Vec<HotSpot> {
+        let mut spots: Vec<_> = self
+            .buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, &cycles)| cycles > 0)
+            .map(|(bucket, &cycles)| HotSpot {
+                rom_offset: bucket * HOT_SPOT_BUCKET_SIZE,
+                cycles,
+            })
+            .collect();
+        spots.sort_by(|a, b| {
+            b.cycles
+                .cmp(&a.cycles)
+                .then(a.rom_offset.cmp(&b.rom_offset))
+        });
+        spots.truncate(count);
+        spots
+    }
+
+    /// The `count` hottest rom ranges with their share of the total cycles, then the time spent
+    /// outside of the rom
+    pub fn report(&self, count: usize) -> String {
+        let rows = self.hottest(count).into_iter().map(|spot| {
+            let address = spot.address();
+            let columns = format!(
+                " {:02X}:{:04X}-{:04X} ({:06X})",
+                spot.bank(),
+                address,
+                address as usize + HOT_SPOT_BUCKET_SIZE - 1,
+                spot.rom_offset
+            );
+            (spot.cycles, columns)
+        });
+        cycles_report(
+            " rom range",
+            rows,
+            (self.outside_cycles, " (halted or ram)".to_string()),
+            self.total_cycles(),
+        )
+    }
+
+    pub fn reset(&mut self) {
+        self.buckets.clear();
+        self.outside_cycles = 0;
+    }
+}
+
+/// Frames the blank screen diagnostic looks back, one second of emulated time
+pub const BLANK_SCREEN_WINDOW: u64 = 60;
+
+/// Remembers when the frame buffer content last changed
+#[derive(Debug, Default)]
+pub(crate) struct FrameChangeTracker {
+    hash: Option<u64>,
+    last_change: Option<u64>,
+}
+
+impl FrameChangeTracker {
+    pub(crate) fn update(&mut self, frame: u64, buffer: &FrameBuffer) {
+        let hash = frame_hash(buffer);
+        if self.hash.is_some_and(|h| h != hash) {
+            self.last_change = Some(frame);
+        }
+        self.hash = Some(hash);
+    }
+
+    /// None when the content never changed
+    pub(crate) fn frames_since_change(&self, frame: u64) -> Option<u64> {
+        self.last_change.map(|f| frame.saturating_sub(f))
+    }
+}
+
+/// State usually behind "the screen stays blank" reports, see `GameBoy::diagnose_blank_screen`.
+/// Its `Display` is meant to be pasted as is in bug reports.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlankScreenReport {
+    pub cgb: bool,
+    pub frame: u64,
+    /// LCDC bit 7
+    pub lcd_enabled: bool,
+    /// LCDC bit 0, background and window display on DMG
+    pub bg_enabled: bool,
+    pub bgp: u8,
+    pub interrupt_enable: u8,
+    pub ime: bool,
+    pub halted: bool,
+    /// Every pixel of the last frame has the same color
+    pub single_color_frame: bool,
+    /// None when the frame buffer never changed
+    pub frames_since_change: Option<u64>,
+}
+
+impl BlankScreenReport {
+    pub fn vblank_interrupt_enabled(&self) -> bool {
+        self.interrupt_enable & 1 != 0
+    }
+
+    /// Likely causes of a blank screen, empty when nothing looks off
+    pub fn findings(&self) -> Vec<String> {
+        let mut findings = vec![];
+
+        if !self.lcd_enabled {
+            findings.push("LCD is off (LCDC bit 7 clear)".to_string());
+        }
+        // on CGB these only affect priorities and the palettes live in CRAM
+        if !self.cgb {
+            if !self.bg_enabled {
+                findings.push("background and window are disabled (LCDC bit 0 clear)".to_string());
+            }
+            let shades = [0, 2, 4, 6].map(|shift| (self.bgp >> shift) & 0b11);
+            if shades.iter().all(|&s| s == shades[0]) {
+                findings.push(format!(
+                    "BGP {:#04x} maps every color to the same shade",
+                    self.bgp
+                ));
+            }
+        }
+        if !self.vblank_interrupt_enabled() {
+            findings.push("VBlank interrupt is disabled in IE".to_string());
+        }
+        if self.halted && self.interrupt_enable & 0x1F == 0 {
+            findings.push("CPU is halted with every interrupt disabled".to_string());
+        }
+        match self.frames_since_change {
+            None => findings.push(format!(
+                "frame buffer never changed in {} frames",
+                self.frame
+            )),
+            Some(frames) if frames >= BLANK_SCREEN_WINDOW => {
+                findings.push(format!("frame buffer unchanged for {frames} frames"))
+            }
+            _ => {}
+        }
+
+        findings
+    }
+}
+
+impl fmt::Display for BlankScreenReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "mode: {}, frame: {}",
+            if self.cgb { "CGB" } else { "DMG" },
+            self.frame
+        )?;
+        writeln!(
+            f,
+            "LCD enabled: {}, BG enabled: {}, BGP: {:#04x}",
+            self.lcd_enabled, self.bg_enabled, self.bgp
+        )?;
+        writeln!(
+            f,
+            "IE: {:#04x}, IME: {}, halted: {}",
+            self.interrupt_enable, self.ime, self.halted
+        )?;
+        match self.frames_since_change {
+            Some(frames) => writeln!(f, "frame buffer last changed {frames} frames ago")?,
+            None => writeln!(f, "frame buffer never changed")?,
+        }
+        writeln!(f, "single color frame: {}", self.single_color_frame)?;
+
+        let findings = self.findings();
+        if findings.is_empty() {
+            write!(f, "no obvious cause found")
+        } else {
+            write!(f, "findings:")?;
+            for finding in findings {
+                write!(f, "\n- {finding}")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heatmap_buckets() {
+        let heatmap = MemoryHeatmap::new();
+        heatmap.record_read(0xC000);
+        heatmap.record_read(0xC0FF);
+        heatmap.record_write(0xC100);
+        heatmap.record_dma(0xFE00);
+
+        assert_eq!(
+            BucketAccesses {
+                reads: 2,
+                writes: 0,
+                dma: 0
+            },
+            heatmap.bucket(0xC042)
+        );
+        assert_eq!(1, heatmap.bucket(0xC1FF).writes);
+
+        let buckets = heatmap.buckets();
+        assert_eq!(HEATMAP_BUCKETS, buckets.len());
+        assert_eq!(1, buckets[0xFE].dma);
+
+        heatmap.reset();
+        assert!(heatmap
+            .buckets()
+            .iter()
+            .all(|b| *b == BucketAccesses::default()));
+    }
+
+    #[test]
+    fn test_profiler() {
+        let mut profiler = Profiler::new();
+        for _ in 0..10 {
+            profiler.record(0, 0x0150, 4);
+            profiler.record(0, 0x0151, 8);
+        }
+        profiler.record(1, 0x4000, 16);
+        profiler.record(2, 0x4000, 12);
+        profiler.record_halted(4);
+        assert_eq!(152, profiler.total_cycles());
+
+        let names = |functions: Vec<FunctionProfile>| -> Vec<(String, u64, u64)> {
+            functions
+                .into_iter()
+                .map(|f| (f.name, f.cycles, f.instructions))
+                .collect()
+        };
+        assert_eq!(
+            vec![
+                ("00:0151".to_string(), 80, 10),
+                ("00:0150".to_string(), 40, 10),
+                ("01:4000".to_string(), 16, 1),
+                ("02:4000".to_string(), 12, 1),
+            ],
+            names(profiler.functions(None))
+        );
+
+        let symbols = SymbolTable::parse("00:0150 Main\n01:4000 Bank1").unwrap();
+        assert_eq!(
+            vec![
+                ("Main".to_string(), 120, 20),
+                ("Bank1".to_string(), 16, 1),
+                ("02:4000".to_string(), 12, 1),
+            ],
+            names(profiler.functions(Some(&symbols)))
+        );
+        assert_eq!(
+            "      cycles      % instructions  function\n\
+             \x20        120  78.9%           20  Main\n\
+             \x20         16  10.5%            1  Bank1\n\
+             \x20          4   2.6%               (halted)",
+            profiler.report(2, Some(&symbols))
+        );
+
+        profiler.reset();
+        assert_eq!(0, profiler.total_cycles());
+        assert!(profiler.functions(None).is_empty());
+    }
+
+    #[test]
+    fn test_hot_spots() {
+        let mut hot_spots = HotSpots::new();
+        for _ in 0..10 {
+            hot_spots.record(Some(0x0150), 4);
+            hot_spots.record(Some(0x01FF), 8);
+        }
+        hot_spots.record(Some(0x4A10), 16);
+        hot_spots.record(Some(0x8000), 12);
+        hot_spots.record(None, 20);
+        assert_eq!(168, hot_spots.total_cycles());
+        assert_eq!(20, hot_spots.outside_cycles());
+        assert_eq!(120, hot_spots.cycles(0x0100));
+        assert_eq!(0, hot_spots.cycles(0x0200));
+        assert_eq!(0, hot_spots.cycles(0x100000));
+
+        let spots = hot_spots.hottest(10);
+        assert_eq!(
+            vec![(0x0100, 120), (0x4A00, 16), (0x8000, 12)],
+            spots
+                .iter()
+                .map(|s| (s.rom_offset, s.cycles))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!((1, 0x4A00), (spots[1].bank(), spots[1].address()));
+        assert_eq!((2, 0x4000), (spots[2].bank(), spots[2].address()));
+        assert_eq!(
+            "      cycles      %  rom range\n\
+             \x20        120  71.4%  00:0100-01FF (000100)\n\
+             \x20         16   9.5%  01:4A00-4AFF (004A00)\n\
+             \x20         20  11.9%  (halted or ram)",
+            hot_spots.report(2)
+        );
+
+        hot_spots.reset();
+        assert_eq!(0, hot_spots.total_cycles());
+        assert!(hot_spots.hottest(1).is_empty());
+    }
+
+    #[test]
+    fn test_bank_usage_report() {
+        let usage = BankUsage::new(32, 1);
+        assert_eq!(0x8000, usage.trimmed_rom_size());
+
+        for bank in [0, 1, 2, 5, 40] {
+            usage.record_rom_read(bank);
+        }
+        usage.record_ram_write(0);
+        assert_eq!(vec![0, 1, 2, 5], usage.used_rom_banks());
+        assert_eq!(8 * 0x4000, usage.trimmed_rom_size());
+        assert_eq!(
+            "rom banks used: 4/32 [0,1,2,5]\n\
+             ram banks used: 1/1 [0]\n\
+             mapper register writes: 0\n\
+             trimmed rom size: 0x20000",
+            usage.to_string()
+        );
+
+        usage.reset();
+        assert!(usage.used_rom_banks().is_empty());
+    }
+
+    #[test]
+    fn test_rom_coverage() {
+        let coverage = RomCoverage::new(16);
+        coverage.record(0, RomCoverage::EXECUTED | RomCoverage::READ);
+        coverage.record(1, RomCoverage::EXECUTED);
+        coverage.record(9, RomCoverage::READ);
+        coverage.record(9, RomCoverage::WRITTEN);
+        coverage.record(16, RomCoverage::READ);
+
+        assert_eq!(2, coverage.count(RomCoverage::EXECUTED));
+        assert_eq!(1, coverage.count(RomCoverage::READ | RomCoverage::EXECUTED));
+        assert_eq!(RomCoverage::READ | RomCoverage::WRITTEN, coverage.flags(9));
+        assert_eq!(0, coverage.flags(16));
+        assert_eq!(vec![3, 1, 0, 0, 0, 0, 0, 0, 0, 6], coverage.export()[..10]);
+        assert_eq!(vec![0b11, 0], coverage.bitmap(RomCoverage::EXECUTED));
+        assert_eq!(vec![0b1, 0b10], coverage.bitmap(RomCoverage::READ));
+        assert_eq!(
+            "executed: 0x2/0x10 bytes (12.5%)\n\
+             read: 0x2/0x10 bytes (12.5%)\n\
+             written: 0x1/0x10 bytes (6.2%)\n\
+             never accessed: 0xd bytes (81.2%)",
+            coverage.to_string()
+        );
+
+        assert_eq!(3, instruction_length(0xCD));
+        assert_eq!(2, instruction_length(0xCB));
+        assert_eq!(1, instruction_length(0x00));
+    }
+
+    #[test]
+    fn test_frame_change_tracker() {
+        let mut tracker = FrameChangeTracker::default();
+        let mut buffer = vec![vec![crate::lcd::RGB_WHITE; 2]; 2];
+
+        tracker.update(1, &buffer);
+        tracker.update(2, &buffer);
+        assert_eq!(None, tracker.frames_since_change(2));
+
+        buffer[1][1] = crate::lcd::RGB_BLACK;
+        tracker.update(3, &buffer);
+        tracker.update(4, &buffer);
+        assert_eq!(Some(7), tracker.frames_since_change(10));
+    }
+
+    #[test]
+    fn test_blank_screen_findings() {
+        let healthy = BlankScreenReport {
+            cgb: false,
+            frame: 120,
+            lcd_enabled: true,
+            bg_enabled: true,
+            bgp: 0xE4,
+            interrupt_enable: 0x01,
+            ime: true,
+            halted: true,
+            single_color_frame: false,
+            frames_since_change: Some(0),
+        };
+        assert!(healthy.findings().is_empty());
+        assert!(healthy.to_string().ends_with("no obvious cause found"));
+
+        let blank = BlankScreenReport {
+            lcd_enabled: false,
+            bg_enabled: false,
+            bgp: 0x00,
+            interrupt_enable: 0x00,
+            single_color_frame: true,
+            frames_since_change: None,
+            ..healthy.clone()
+        };
+        assert_eq!(6, blank.findings().len());
+
+        // DMG only checks don't apply to CGB
+        let cgb = BlankScreenReport { cgb: true, ..blank };
+        assert_eq!(4, cgb.findings().len());
+    }
+}
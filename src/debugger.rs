@@ -0,0 +1,452 @@
+//! Execution control for debugger UIs: pc breakpoints, memory watchpoints, step-into/step-over
+//! and a run loop returning why it stopped, see `GameBoy::step_into`, `GameBoy::step_over`,
+//! `GameBoy::run_until_break` and `GameBoy::add_watchpoint`. Both can be conditional, see `expr`.
+
+use std::{collections::BTreeMap, fmt, ops::RangeInclusive};
+
+use crate::expr::{Context, Expr};
+
+/// Frames a step over runs before giving up on the subroutine returning
+pub const STEP_OVER_MAX_FRAMES: u64 = 600;
+
+/// Why a debugger run loop returned
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    /// About to execute the instruction at a breakpoint
+    Breakpoint { pc: u16 },
+    /// The requested step completed
+    Step,
+    /// The frame budget ran out without anything stopping the run
+    FrameLimit,
+    /// A breaking watchpoint was hit by the instruction that just executed
+    Watchpoint(WatchHit),
+}
+
+/// Breakpoints, owned by `GameBoy`
+#[derive(Clone, Debug, Default)]
+pub struct Debugger {
+    breakpoints: BTreeMap<u16, Option<Expr>>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Breaks before executing the instruction at `pc`, in whatever bank is mapped
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.insert(pc, None);
+    }
+
+    /// Breaks before executing the instruction at `pc` when `condition` holds, e.g.
+    /// `A == 0x3F && [HL] > 10`. Replaces any breakpoint at `pc`.
+    pub fn add_conditional_breakpoint(&mut self, pc: u16, condition: Expr) {
+        self.breakpoints.insert(pc, Some(condition));
+    }
+
+    pub fn breakpoint_condition(&self, pc: u16) -> Option<&Expr> {
+        self.breakpoints.get(&pc).and_then(Option::as_ref)
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.remove(&pc);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Breakpoints in address order
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.keys().copied()
+    }
+
+    /// Why the cpu should stop before executing the instruction at `ctx.pc`, if it should
+    pub(crate) fn check(&self, ctx: &Context) -> Option<StopReason> {
+        let condition = self.breakpoints.get(&ctx.pc)?;
+        condition
+            .as_ref()
+            .is_none_or(|c| c.is_true(ctx))
+            .then_some(StopReason::Breakpoint { pc: ctx.pc })
+    }
+}
+
+/// Accesses a watchpoint triggers on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// Cpu access that triggered a watchpoint. Writes carry the value after cheats patched it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WatchHit {
+    pub id: WatchpointId,
+    pub address: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
+pub enum WatchAction {
+    /// Pauses the emulation after the instruction doing the access, see `GameBoy::take_watch_break`
+    Break,
+    /// Called on the access, the emulation goes on
+    Callback(Box<dyn FnMut(&WatchHit)>),
+}
+
+impl fmt::Debug for WatchAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Break => write!(f, "Break"),
+            Self::Callback(_) => write!(f, "Callback"),
+        }
+    }
+}
+
+pub type WatchpointId = usize;
+
+#[derive(Debug)]
+struct Watchpoint {
+    id: WatchpointId,
+    range: RangeInclusive<u16>,
+    kind: WatchKind,
+    action: WatchAction,
+    condition: Option<Expr>,
+}
+
+impl Watchpoint {
+    fn trigger(&mut self, hit: &WatchHit, first_break: &mut Option<WatchHit>) {
+        match &mut self.action {
+            WatchAction::Break => _ = first_break.get_or_insert(*hit),
+            WatchAction::Callback(callback) => callback(hit),
+        }
+    }
+
+    fn matches(&self, address: u16, write: bool) -> bool {
+        let kind = match self.kind {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::ReadWrite => true,
+        };
+        kind && self.range.contains(&address)
+    }
+}
+
+/// Watchpoints, checked by the bus on every cpu access once one is set
+#[derive(Debug, Default)]
+pub(crate) struct Watchpoints {
+    entries: Vec<Watchpoint>,
+    next_id: WatchpointId,
+    /// first breaking hit not yet taken
+    hit: Option<WatchHit>,
+    /// hits of conditional watchpoints, evaluated once the accessing instruction completed
+    pending: Vec<WatchHit>,
+}
+
+impl Watchpoints {
+    pub(crate) fn add(
+        &mut self,
+        range: RangeInclusive<u16>,
+        kind: WatchKind,
+        action: WatchAction,
+        condition: Option<Expr>,
+    ) -> WatchpointId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push(Watchpoint {
+            id,
+            range,
+            kind,
+            action,
+            condition,
+        });
+        id
+    }
+
+    pub(crate) fn remove(&mut self, id: WatchpointId) {
+        self.entries.retain(|w| w.id != id);
+        self.pending.retain(|hit| hit.id != id);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.hit = None;
+        self.pending.clear();
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub(crate) fn check(&mut self, address: u16, value: u8, write: bool) {
+        for watchpoint in self.entries.iter_mut() {
+            if !watchpoint.matches(address, write) {
+                continue;
+            }
+            let hit = WatchHit {
+                id: watchpoint.id,
+                address,
+                value,
+                write,
+            };
+            if watchpoint.condition.is_some() {
+                self.pending.push(hit);
+            } else {
+                watchpoint.trigger(&hit, &mut self.hit);
+            }
+        }
+    }
+
+    pub(crate) fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Triggers the pending conditional hits whose condition holds, `ctx` being the state after
+    /// the accessing instruction. `ADDR` and `VALUE` are the ones of each access.
+    pub(crate) fn resolve_pending(&mut self, ctx: &mut Context) {
+        for hit in std::mem::take(&mut self.pending) {
+            let Some(watchpoint) = self.entries.iter_mut().find(|w| w.id == hit.id) else {
+                continue;
+            };
+            ctx.access = Some((hit.address, hit.value));
+            if watchpoint
+                .condition
+                .as_ref()
+                .is_some_and(|c| c.is_true(ctx))
+            {
+                watchpoint.trigger(&hit, &mut self.hit);
+            }
+        }
+    }
+
+    pub(crate) fn take_hit(&mut self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+}
+
+/// One `PC: opcode` line per instruction of a `GameBoy::pc_history`, oldest first
+pub fn format_history(history: &[(u16, u8)]) -> String {
+    format_history_with(history, |_| None)
+}
+
+/// `format_history` followed by the label `describe` gives each pc, see
+/// `GameBoy::format_pc_history`
+pub fn format_history_with<F: Fn(u16) -> Option<String>>(
+    history: &[(u16, u8)],
+    describe: F,
+) -> String {
+    history
+        .iter()
+        .map(|&(pc, opcode)| match describe(pc) {
+            Some(label) => format!("{pc:04X}: {opcode:02X} {label}\n"),
+            None => format!("{pc:04X}: {opcode:02X}\n"),
+        })
+        .collect()
+}
+
+/// Address a `CALL` or `RST` at `pc` returns to, None for other opcodes. Stepping over them
+/// runs until the subroutine returns there.
+pub(crate) fn return_address(opcode: u8, pc: u16) -> Option<u16> {
+    match opcode {
+        // CALL nn, CALL cc,nn
+        0xCD | 0xC4 | 0xCC | 0xD4 | 0xDC => Some(pc.wrapping_add(3)),
+        // RST n
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => Some(pc.wrapping_add(1)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::registers::Registers;
+
+    use super::*;
+
+    /// Checks `debugger` at `pc` with A set to `a` and memory reading as its address low byte
+    fn check_at(debugger: &Debugger, pc: u16, a: u8) -> Option<StopReason> {
+        let mut registers = Registers::new();
+        registers.a = a;
+        let read = |address: u16| address as u8;
+        debugger.check(&Context {
+            registers: &registers,
+            sp: 0xFFFE,
+            pc,
+            read: &read,
+            access: None,
+        })
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x0150);
+        debugger.add_breakpoint(0x0100);
+        debugger.add_breakpoint(0x0150);
+
+        assert_eq!(
+            vec![0x0100, 0x0150],
+            debugger.breakpoints().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(StopReason::Breakpoint { pc: 0x0150 }),
+            check_at(&debugger, 0x0150, 0)
+        );
+        assert_eq!(None, check_at(&debugger, 0x0151, 0));
+
+        debugger.remove_breakpoint(0x0150);
+        assert_eq!(None, check_at(&debugger, 0x0150, 0));
+        debugger.clear_breakpoints();
+        assert_eq!(0, debugger.breakpoints().count());
+    }
+
+    #[test]
+    fn test_conditional_breakpoints() {
+        let mut debugger = Debugger::new();
+        let condition = Expr::parse("A == 0x3F && [0x0A] > 9").unwrap();
+        debugger.add_conditional_breakpoint(0x0150, condition.clone());
+
+        assert_eq!(Some(&condition), debugger.breakpoint_condition(0x0150));
+        assert_eq!(None, check_at(&debugger, 0x0150, 0x3E));
+        assert_eq!(
+            Some(StopReason::Breakpoint { pc: 0x0150 }),
+            check_at(&debugger, 0x0150, 0x3F)
+        );
+
+        // an unconditional breakpoint replaces the condition
+        debugger.add_breakpoint(0x0150);
+        assert_eq!(None, debugger.breakpoint_condition(0x0150));
+        assert!(check_at(&debugger, 0x0150, 0x3E).is_some());
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let calls = Rc::new(RefCell::new(vec![]));
+        let mut watchpoints = Watchpoints::default();
+        let log = calls.clone();
+        let callback = watchpoints.add(
+            0xC000..=0xC0FF,
+            WatchKind::ReadWrite,
+            WatchAction::Callback(Box::new(move |hit| log.borrow_mut().push(*hit))),
+            None,
+        );
+        let write = watchpoints.add(0xFF40..=0xFF40, WatchKind::Write, WatchAction::Break, None);
+
+        watchpoints.check(0xC010, 0x12, false);
+        watchpoints.check(0xC100, 0x34, true);
+        assert_eq!(
+            vec![WatchHit {
+                id: callback,
+                address: 0xC010,
+                value: 0x12,
+                write: false
+            }],
+            *calls.borrow()
+        );
+
+        watchpoints.check(0xFF40, 0x91, false);
+        assert_eq!(None, watchpoints.take_hit());
+        watchpoints.check(0xFF40, 0x91, true);
+        watchpoints.check(0xFF40, 0x11, true);
+        // the first hit is kept until taken
+        assert_eq!(
+            Some(WatchHit {
+                id: write,
+                address: 0xFF40,
+                value: 0x91,
+                write: true
+            }),
+            watchpoints.take_hit()
+        );
+        assert_eq!(None, watchpoints.take_hit());
+
+        watchpoints.remove(callback);
+        watchpoints.check(0xC010, 0x12, true);
+        assert_eq!(1, calls.borrow().len());
+        watchpoints.clear();
+        assert!(watchpoints.is_empty());
+    }
+
+    #[test]
+    fn test_conditional_watchpoints() {
+        let calls = Rc::new(RefCell::new(vec![]));
+        let mut watchpoints = Watchpoints::default();
+        let log = calls.clone();
+        let callback = watchpoints.add(
+            0xC000..=0xC0FF,
+            WatchKind::Write,
+            WatchAction::Callback(Box::new(move |hit| log.borrow_mut().push(hit.value))),
+            Some(Expr::parse("VALUE > 0x10 && A == 1").unwrap()),
+        );
+        let lcdc = watchpoints.add(
+            0xFF40..=0xFF40,
+            WatchKind::Write,
+            WatchAction::Break,
+            Some(Expr::parse("VALUE & 0x80 == 0").unwrap()),
+        );
+
+        let mut registers = Registers::new();
+        registers.a = 1;
+        let read = |_| 0;
+        let mut ctx = Context {
+            registers: &registers,
+            sp: 0xFFFE,
+            pc: 0x0150,
+            read: &read,
+            access: None,
+        };
+
+        watchpoints.check(0xC000, 0x05, true);
+        watchpoints.check(0xC001, 0x20, true);
+        watchpoints.check(0xFF40, 0x91, true);
+        // nothing triggers before the instruction completed
+        assert!(watchpoints.has_pending());
+        assert!(calls.borrow().is_empty());
+        watchpoints.resolve_pending(&mut ctx);
+        assert!(!watchpoints.has_pending());
+        assert_eq!(vec![0x20], *calls.borrow());
+        assert_eq!(None, watchpoints.take_hit());
+
+        watchpoints.check(0xFF40, 0x11, true);
+        watchpoints.resolve_pending(&mut ctx);
+        assert_eq!(
+            Some(WatchHit {
+                id: lcdc,
+                address: 0xFF40,
+                value: 0x11,
+                write: true
+            }),
+            watchpoints.take_hit()
+        );
+
+        watchpoints.check(0xC002, 0x30, true);
+        watchpoints.remove(callback);
+        assert!(!watchpoints.has_pending());
+    }
+
+    #[test]
+    fn test_format_history() {
+        assert_eq!("", format_history(&[]));
+        assert_eq!(
+            "0150: 3E\n0152: CB\n",
+            format_history(&[(0x0150, 0x3E), (0x0152, 0xCB)])
+        );
+        assert_eq!(
+            "0150: 3E Main\n0152: CB\n",
+            format_history_with(&[(0x0150, 0x3E), (0x0152, 0xCB)], |pc| {
+                (pc == 0x0150).then(|| "Main".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn test_return_address() {
+        assert_eq!(Some(0x0203), return_address(0xCD, 0x0200));
+        assert_eq!(Some(0x0203), return_address(0xDC, 0x0200));
+        assert_eq!(Some(0x0201), return_address(0xFF, 0x0200));
+        assert_eq!(None, return_address(0xC3, 0x0200));
+        assert_eq!(None, return_address(0xC9, 0x0200));
+    }
+}
@@ -0,0 +1,326 @@
+//! DMG-07 4-player adapter, see https://gbdev.io/pandocs/Four_Player_Adapter.html
+//!
+//! The adapter clocks every transfer (the Game Boys use the external clock) and goes through two phases:
+//! - ping: each player repeatedly receives `0xFE` followed by 3 status bytes (connected players in
+//!   the high nibble, player number in the low one) and answers `0x88, 0x88, RATE, SIZE`. Player 1
+//!   answering `0xAA` to a whole ping starts the game, every player then receives `0xCC` 4 times.
+//! - transmission: in each round every connected player receives the `SIZE` bytes each player sent
+//!   during the previous round, player 1 first, while sending its own bytes for the next round in
+//!   the first `SIZE` transfers. A round where player 1 only sends `0xFF` goes back to ping.
+//!
+//! Each `GameBoy` gets one of the adapter ports, the adapter holds a player back until the others
+//! caught up with the current round so instances only have to be stepped roughly in lockstep.
+//! Bytes are clocked at a fixed rate, RATE is recorded but not used for pacing.
+
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+use crate::link::LinkDevice;
+
+pub const PLAYERS: usize = 4;
+
+const PING: u8 = 0xFE;
+const ACK: u8 = 0x88;
+const START: u8 = 0xAA;
+const STARTED: u8 = 0xCC;
+const RESTART: u8 = 0xFF;
+const PACKET_LEN: usize = 4;
+const MAX_SIZE: usize = 4;
+
+/// Cycles between two transfers on a port
+const BYTE_GAP_CYCLES: u32 = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    Ping,
+    Transmission,
+}
+
+#[derive(Debug)]
+struct Hub {
+    phase: Phase,
+    /// bumped on every phase change so ports resync at their next packet
+    epoch: u64,
+    /// bit per player
+    connected: u8,
+    rate: u8,
+    size: usize,
+    /// rounds completed by each player
+    rounds: [u64; PLAYERS],
+    /// bytes sent by each player, double buffered by round parity
+    data: [[Vec<u8>; PLAYERS]; 2],
+}
+
+impl Hub {
+    fn set_phase(&mut self, phase: Phase) {
+        self.phase = phase;
+        self.epoch += 1;
+        self.rounds = [0; PLAYERS];
+        self.data = Default::default();
+    }
+
+    fn is_connected(&self, player: usize) -> bool {
+        self.connected & 1 << player != 0
+    }
+
+    /// Bytes sent to every player in `round`, `None` until all players completed the previous one
+    fn payload(&self, round: u64) -> Option<Vec<u8>> {
+        if round == 0 {
+            return Some(vec![0; self.size * PLAYERS]);
+        }
+        let ready = (0..PLAYERS).all(|p| !self.is_connected(p) || self.rounds[p] >= round);
+        if !ready {
+            return None;
+        }
+
+        let data = &self.data[((round - 1) % 2) as usize];
+        Some(
+            data.iter()
+                .flat_map(|bytes| {
+                    let mut bytes = bytes.clone();
+                    bytes.resize(self.size, 0);
+                    bytes
+                })
+                .collect(),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Packet {
+    Ping,
+    Started,
+    Round(u64),
+}
+
+/// The adapter, hand its ports to the `GameBoy` instances with `GameBoy::attach_link_device`
+pub struct Dmg07 {
+    hub: Rc<RefCell<Hub>>,
+}
+
+impl Dmg07 {
+    pub fn new() -> Self {
+        Self {
+            hub: Rc::new(RefCell::new(Hub {
+                phase: Phase::Ping,
+                epoch: 0,
+                connected: 0,
+                rate: 0,
+                size: 1,
+                rounds: [0; PLAYERS],
+                data: Default::default(),
+            })),
+        }
+    }
+
+    /// Port of `player`, from 0 (player 1) to 3
+    pub fn port(&self, player: usize) -> Dmg07Port {
+        assert!(player < PLAYERS, "DMG-07 has {PLAYERS} ports");
+        Dmg07Port {
+            hub: self.hub.clone(),
+            player,
+            epoch: 0,
+            packet: None,
+            outgoing: VecDeque::new(),
+            received: vec![],
+            gap: 0,
+        }
+    }
+
+    /// Bit per player that answered the last ping
+    pub fn connected_players(&self) -> u8 {
+        self.hub.borrow().connected
+    }
+
+    pub fn is_transmitting(&self) -> bool {
+        self.hub.borrow().phase == Phase::Transmission
+    }
+
+    /// Transfer speed requested by player 1
+    pub fn rate(&self) -> u8 {
+        self.hub.borrow().rate
+    }
+}
+
+impl Default for Dmg07 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Dmg07Port {
+    hub: Rc<RefCell<Hub>>,
+    player: usize,
+    epoch: u64,
+    packet: Option<Packet>,
+    outgoing: VecDeque<u8>,
+    received: Vec<u8>,
+    gap: u32,
+}
+
+impl Dmg07Port {
+    /// Prepares the next packet, returns false while the player has to wait for the others
+    fn start_packet(&mut self) -> bool {
+        let hub = self.hub.borrow();
+        let resync = self.epoch != hub.epoch;
+        self.epoch = hub.epoch;
+
+        let packet = match (hub.phase, self.packet) {
+            (Phase::Ping, _) => Packet::Ping,
+            _ if !hub.is_connected(self.player) => return false,
+            (Phase::Transmission, _) if resync => Packet::Started,
+            (Phase::Transmission, Some(Packet::Started)) => Packet::Round(0),
+            (Phase::Transmission, Some(Packet::Round(round))) => Packet::Round(round + 1),
+            (Phase::Transmission, _) => Packet::Started,
+        };
+
+        self.outgoing = match packet {
+            Packet::Ping => {
+                let status = hub.connected << 4 | (self.player as u8 + 1);
+                [PING, status, status, status].into()
+            }
+            Packet::Started => [STARTED; PACKET_LEN].into(),
+            Packet::Round(round) => match hub.payload(round) {
+                Some(payload) => payload.into(),
+                None => return false,
+            },
+        };
+        self.packet = Some(packet);
+        self.received.clear();
+        true
+    }
+
+    fn finish_packet(&mut self) {
+        let mut hub = self.hub.borrow_mut();
+        let received = &self.received;
+        match self.packet {
+            Some(Packet::Ping) => {
+                if received[..2] == [ACK, ACK] {
+                    hub.connected |= 1 << self.player;
+                    if self.player == 0 {
+                        hub.rate = received[2];
+                        hub.size = (received[3] as usize).clamp(1, MAX_SIZE);
+                    }
+                } else if self.player == 0 && received.iter().all(|&b| b == START) {
+                    hub.connected |= 1;
+                    hub.set_phase(Phase::Transmission);
+                } else {
+                    hub.connected &= !(1 << self.player);
+                }
+            }
+            Some(Packet::Round(round)) => {
+                if self.player == 0 && received.iter().all(|&b| b == RESTART) {
+                    hub.set_phase(Phase::Ping);
+                    return;
+                }
+                let size = hub.size;
+                hub.data[(round % 2) as usize][self.player] = received[..size].to_vec();
+                hub.rounds[self.player] = round + 1;
+            }
+            Some(Packet::Started) | None => {}
+        }
+    }
+}
+
+impl LinkDevice for Dmg07Port {
+    fn clock(&mut self, cycles: u8, sent: u8) -> Option<u8> {
+        self.gap += cycles as u32;
+        if self.gap < BYTE_GAP_CYCLES {
+            return None;
+        }
+        if self.outgoing.is_empty() && !self.start_packet() {
+            return None;
+        }
+
+        self.gap = 0;
+        let byte = self.outgoing.pop_front();
+        self.received.push(sent);
+        if self.outgoing.is_empty() {
+            self.finish_packet();
+        }
+        byte
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers with `replies`, returns the bytes received or `None` when the port held the player back
+    fn exchange(port: &mut Dmg07Port, replies: &[u8]) -> Option<Vec<u8>> {
+        let mut received = vec![];
+        for &reply in replies {
+            let mut byte = None;
+            for _ in 0..=BYTE_GAP_CYCLES / 4 {
+                byte = port.clock(4, reply);
+                if byte.is_some() {
+                    break;
+                }
+            }
+            received.push(byte?);
+        }
+        Some(received)
+    }
+
+    #[test]
+    fn test_ping_phase() {
+        let adapter = Dmg07::new();
+        let mut p1 = adapter.port(0);
+        let mut p3 = adapter.port(2);
+
+        assert_eq!(
+            Some(vec![PING, 0x01, 0x01, 0x01]),
+            exchange(&mut p1, &[ACK, ACK, 0x10, 0x02])
+        );
+        assert_eq!(
+            Some(vec![PING, 0x13, 0x13, 0x13]),
+            exchange(&mut p3, &[ACK, ACK, 0x00, 0x00])
+        );
+        assert_eq!(
+            Some(vec![PING, 0x51, 0x51, 0x51]),
+            exchange(&mut p1, &[ACK, ACK, 0x10, 0x02])
+        );
+        assert_eq!(0b0101, adapter.connected_players());
+        assert_eq!(0x10, adapter.rate());
+
+        // player 3 unplugged
+        exchange(&mut p3, &[0xFF; 4]);
+        assert_eq!(0b0001, adapter.connected_players());
+    }
+
+    #[test]
+    fn test_transmission_rounds() {
+        let adapter = Dmg07::new();
+        let mut p1 = adapter.port(0);
+        let mut p2 = adapter.port(1);
+
+        exchange(&mut p1, &[ACK, ACK, 0x00, 0x02]);
+        exchange(&mut p2, &[ACK, ACK, 0x00, 0x02]);
+        exchange(&mut p1, &[START; 4]);
+        assert!(adapter.is_transmitting());
+
+        assert_eq!(Some(vec![STARTED; 4]), exchange(&mut p1, &[0; 4]));
+        assert_eq!(Some(vec![STARTED; 4]), exchange(&mut p2, &[0; 4]));
+
+        // round 0, nothing collected yet
+        assert_eq!(
+            Some(vec![0; 8]),
+            exchange(&mut p1, &[0x11, 0x12, 0, 0, 0, 0, 0, 0])
+        );
+        // player 1 waits for player 2 to complete the round
+        assert_eq!(None, exchange(&mut p1, &[0; 8]));
+        assert_eq!(
+            Some(vec![0; 8]),
+            exchange(&mut p2, &[0x21, 0x22, 0, 0, 0, 0, 0, 0])
+        );
+
+        let round1 = vec![0x11, 0x12, 0x21, 0x22, 0, 0, 0, 0];
+        assert_eq!(Some(round1.clone()), exchange(&mut p1, &[0; 8]));
+        assert_eq!(Some(round1), exchange(&mut p2, &[0; 8]));
+
+        // player 1 asks for a restart
+        exchange(&mut p2, &[0; 8]);
+        exchange(&mut p1, &[RESTART; 8]);
+        assert!(!adapter.is_transmitting());
+        assert_eq!(Some(PING), exchange(&mut p2, &[0; 4]).map(|r| r[0]));
+    }
+}
@@ -0,0 +1,23 @@
+use crate::probes::ProbeEvent;
+
+/// Emulator events surfaced to the frontend, see `GameBoy::drain_events`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A registered memory probe triggered
+    Probe(ProbeEvent),
+    /// A homebrew debug message was printed, only captured when `Config::debug_messages` is set
+    DebugMessage(String),
+    /// The frontend audio queue ran dry `count` times since the last drain, see `stats::PerfStats`
+    AudioUnderrun { count: u64 },
+    /// The cpu ran into an illegal opcode, which would have locked up the hardware. Only the first
+    /// one since the last drain is reported, what follows is usually garbage. `history` holds the
+    /// (pc, opcode) of the instructions leading to it, see `debugger::format_history`.
+    IllegalOpcode {
+        pc: u16,
+        opcode: u8,
+        history: Vec<(u16, u8)>,
+    },
+    /// The battery save didn't match the `expected` cartridge ram size and was replaced by blank
+    /// ram, which the player may want to know before the game overwrites the save
+    SaveRejected { expected: usize, found: usize },
+}
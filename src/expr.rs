@@ -0,0 +1,488 @@
+//! Conditions for breakpoints and watchpoints, e.g. `A == 0x3F && [HL] > 10`.
+//!
+//! Operands are numbers (`42`, `0x2A`, `$2A`), registers (`A`...`L`, `AF`, `BC`, `DE`, `HL`, `SP`,
+//! `PC`), memory reads (`[HL+1]`, one byte) and, for watchpoints, the access address and value
+//! (`ADDR`, `VALUE`). Operators, loosest first, as in Rust: `||`, `&&`, comparisons, `|`, `^`,
+//! `&`, `+ -`, then unary `! - ~` and parentheses. Comparisons and logic operators give 0 or 1,
+//! values are unsigned 32 bits with wrapping arithmetic and anything non-zero is true.
+
+use std::{error, fmt, str::FromStr};
+
+use crate::registers::Registers;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExprError {
+    UnexpectedChar { position: usize, found: char },
+    UnexpectedEnd,
+    UnknownIdentifier(String),
+    InvalidNumber(String),
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedChar { position, found } => {
+                write!(f, "unexpected '{found}' at position {position}")
+            }
+            Self::UnexpectedEnd => write!(f, "unexpected end of expression"),
+            Self::UnknownIdentifier(name) => write!(f, "unknown identifier {name}"),
+            Self::InvalidNumber(number) => write!(f, "invalid number {number}"),
+        }
+    }
+}
+
+impl error::Error for ExprError {}
+
+/// State an expression is evaluated against
+pub struct Context<'a> {
+    pub registers: &'a Registers,
+    pub sp: u16,
+    pub pc: u16,
+    pub read: &'a dyn Fn(u16) -> u8,
+    /// (address, value) of the access being checked, for watchpoint conditions. `ADDR` and
+    /// `VALUE` read 0 without one.
+    pub access: Option<(u16, u8)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operand {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+    Addr,
+    Value,
+}
+
+impl Operand {
+    fn parse(name: &str) -> Option<Self> {
+        let operand = match name.to_ascii_uppercase().as_str() {
+            "A" => Self::A,
+            "F" => Self::F,
+            "B" => Self::B,
+            "C" => Self::C,
+            "D" => Self::D,
+            "E" => Self::E,
+            "H" => Self::H,
+            "L" => Self::L,
+            "AF" => Self::AF,
+            "BC" => Self::BC,
+            "DE" => Self::DE,
+            "HL" => Self::HL,
+            "SP" => Self::SP,
+            "PC" => Self::PC,
+            "ADDR" => Self::Addr,
+            "VALUE" => Self::Value,
+            _ => return None,
+        };
+        Some(operand)
+    }
+
+    fn eval(self, ctx: &Context) -> u32 {
+        let r = ctx.registers;
+        let value = match self {
+            Self::A => r.a() as u16,
+            Self::F => r.f() as u16,
+            Self::B => r.b() as u16,
+            Self::C => r.c() as u16,
+            Self::D => r.d() as u16,
+            Self::E => r.e() as u16,
+            Self::H => r.h() as u16,
+            Self::L => r.l() as u16,
+            Self::AF => r.get_af(),
+            Self::BC => r.get_bc(),
+            Self::DE => r.get_de(),
+            Self::HL => r.get_hl(),
+            Self::SP => ctx.sp,
+            Self::PC => ctx.pc,
+            Self::Addr => ctx.access.map_or(0, |(address, _)| address),
+            Self::Value => ctx.access.map_or(0, |(_, value)| value as u16),
+        };
+        value as u32
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Sub,
+}
+
+impl BinaryOp {
+    fn apply(self, lhs: u32, rhs: u32) -> u32 {
+        match self {
+            Self::Or => (lhs != 0 || rhs != 0) as u32,
+            Self::And => (lhs != 0 && rhs != 0) as u32,
+            Self::Equal => (lhs == rhs) as u32,
+            Self::NotEqual => (lhs != rhs) as u32,
+            Self::Less => (lhs < rhs) as u32,
+            Self::LessOrEqual => (lhs <= rhs) as u32,
+            Self::Greater => (lhs > rhs) as u32,
+            Self::GreaterOrEqual => (lhs >= rhs) as u32,
+            Self::BitOr => lhs | rhs,
+            Self::BitXor => lhs ^ rhs,
+            Self::BitAnd => lhs & rhs,
+            Self::Add => lhs.wrapping_add(rhs),
+            Self::Sub => lhs.wrapping_sub(rhs),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum UnaryOp {
+    Not,
+    Neg,
+    BitNot,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Number(u32),
+    Operand(Operand),
+    /// byte at the address
+    Memory(Box<Node>),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn eval(&self, ctx: &Context) -> u32 {
+        match self {
+            Self::Number(n) => *n,
+            Self::Operand(operand) => operand.eval(ctx),
+            Self::Memory(address) => (ctx.read)(address.eval(ctx) as u16) as u32,
+            Self::Unary(op, node) => {
+                let value = node.eval(ctx);
+                match op {
+                    UnaryOp::Not => (value == 0) as u32,
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::BitNot => !value,
+                }
+            }
+            Self::Binary(BinaryOp::Or, lhs, rhs) => {
+                (lhs.eval(ctx) != 0 || rhs.eval(ctx) != 0) as u32
+            }
+            Self::Binary(BinaryOp::And, lhs, rhs) => {
+                (lhs.eval(ctx) != 0 && rhs.eval(ctx) != 0) as u32
+            }
+            Self::Binary(op, lhs, rhs) => op.apply(lhs.eval(ctx), rhs.eval(ctx)),
+        }
+    }
+}
+
+/// Parsed condition, see the module documentation for the syntax
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr {
+    source: String,
+    root: Node,
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Self, ExprError> {
+        let mut parser = Parser {
+            chars: source.char_indices().collect(),
+            next: 0,
+        };
+        let root = parser.or()?;
+        parser.skip_spaces();
+        if let Some(&(position, found)) = parser.chars.get(parser.next) {
+            return Err(ExprError::UnexpectedChar { position, found });
+        }
+        Ok(Self {
+            source: source.to_string(),
+            root,
+        })
+    }
+
+    pub fn eval(&self, ctx: &Context) -> u32 {
+        self.root.eval(ctx)
+    }
+
+    pub fn is_true(&self, ctx: &Context) -> bool {
+        self.eval(ctx) != 0
+    }
+}
+
+/// Number literal as written in expressions: `42`, `0x2A` or `$2A`
+pub fn parse_number(word: &str) -> Option<u32> {
+    let parsed = match word
+        .strip_prefix('$')
+        .or_else(|| word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => word.parse(),
+    };
+    parsed.ok()
+}
+
+impl FromStr for Expr {
+    type Err = ExprError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Recursive descent, one function per precedence level
+struct Parser {
+    chars: Vec<(usize, char)>,
+    next: usize,
+}
+
+impl Parser {
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.next += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.next).map(|&(_, c)| c)
+    }
+
+    /// Consumes `token` if it comes next
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_spaces();
+        let matches = token
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.next + i).is_some_and(|&(_, n)| n == c));
+        if matches {
+            self.next += token.len();
+        }
+        matches
+    }
+
+    fn unexpected(&self) -> ExprError {
+        match self.chars.get(self.next) {
+            Some(&(position, found)) => ExprError::UnexpectedChar { position, found },
+            None => ExprError::UnexpectedEnd,
+        }
+    }
+
+    fn binary(
+        &mut self,
+        operand: fn(&mut Self) -> Result<Node, ExprError>,
+        ops: &[(&str, BinaryOp)],
+    ) -> Result<Node, ExprError> {
+        let mut node = operand(self)?;
+        'outer: loop {
+            for &(token, op) in ops {
+                if self.eat(token) {
+                    node = Node::Binary(op, Box::new(node), Box::new(operand(self)?));
+                    continue 'outer;
+                }
+            }
+            return Ok(node);
+        }
+    }
+
+    fn or(&mut self) -> Result<Node, ExprError> {
+        self.binary(Self::and, &[("||", BinaryOp::Or)])
+    }
+
+    fn and(&mut self) -> Result<Node, ExprError> {
+        self.binary(Self::comparison, &[("&&", BinaryOp::And)])
+    }
+
+    fn comparison(&mut self) -> Result<Node, ExprError> {
+        // two char operators first so `<=` isn't read as `<`
+        self.binary(
+            Self::bit_or,
+            &[
+                ("==", BinaryOp::Equal),
+                ("!=", BinaryOp::NotEqual),
+                ("<=", BinaryOp::LessOrEqual),
+                (">=", BinaryOp::GreaterOrEqual),
+                ("<", BinaryOp::Less),
+                (">", BinaryOp::Greater),
+            ],
+        )
+    }
+
+    fn bit_or(&mut self) -> Result<Node, ExprError> {
+        let mut node = self.bit_xor()?;
+        while self.eat_single('|') {
+            node = Node::Binary(BinaryOp::BitOr, Box::new(node), Box::new(self.bit_xor()?));
+        }
+        Ok(node)
+    }
+
+    fn bit_xor(&mut self) -> Result<Node, ExprError> {
+        self.binary(Self::bit_and, &[("^", BinaryOp::BitXor)])
+    }
+
+    fn bit_and(&mut self) -> Result<Node, ExprError> {
+        let mut node = self.additive()?;
+        while self.eat_single('&') {
+            node = Node::Binary(BinaryOp::BitAnd, Box::new(node), Box::new(self.additive()?));
+        }
+        Ok(node)
+    }
+
+    /// Consumes a bitwise `c`, leaving its doubled logic operator alone
+    fn eat_single(&mut self, c: char) -> bool {
+        self.skip_spaces();
+        let single =
+            self.peek() == Some(c) && self.chars.get(self.next + 1).map(|&(_, n)| n) != Some(c);
+        if single {
+            self.next += 1;
+        }
+        single
+    }
+
+    fn additive(&mut self) -> Result<Node, ExprError> {
+        self.binary(Self::unary, &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)])
+    }
+
+    fn unary(&mut self) -> Result<Node, ExprError> {
+        for (token, op) in [
+            ("!", UnaryOp::Not),
+            ("-", UnaryOp::Neg),
+            ("~", UnaryOp::BitNot),
+        ] {
+            // `!=` is a comparison, it can't start an operand anyway
+            if self.eat(token) {
+                return Ok(Node::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Node, ExprError> {
+        if self.eat("(") {
+            let node = self.or()?;
+            return if self.eat(")") {
+                Ok(node)
+            } else {
+                Err(self.unexpected())
+            };
+        }
+        if self.eat("[") {
+            let node = self.or()?;
+            return if self.eat("]") {
+                Ok(Node::Memory(Box::new(node)))
+            } else {
+                Err(self.unexpected())
+            };
+        }
+
+        self.skip_spaces();
+        let start = self.next;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '$' || c == '_')
+        {
+            self.next += 1;
+        }
+        if start == self.next {
+            return Err(self.unexpected());
+        }
+        let word: String = self.chars[start..self.next]
+            .iter()
+            .map(|&(_, c)| c)
+            .collect();
+
+        if word.starts_with(|c: char| c.is_ascii_digit() || c == '$') {
+            return parse_number(&word)
+                .map(Node::Number)
+                .ok_or(ExprError::InvalidNumber(word));
+        }
+        Operand::parse(&word)
+            .map(Node::Operand)
+            .ok_or(ExprError::UnknownIdentifier(word))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str) -> u32 {
+        let mut registers = Registers::new();
+        registers.a = 0x3F;
+        registers.h = 0xC0;
+        registers.l = 0x10;
+        let read = |address: u16| match address {
+            0xC010 => 11,
+            0xC011 => 0xF0,
+            _ => 0,
+        };
+        let ctx = Context {
+            registers: &registers,
+            sp: 0xDFF0,
+            pc: 0x0150,
+            read: &read,
+            access: Some((0xFF40, 0x91)),
+        };
+        Expr::parse(source).unwrap().eval(&ctx)
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(1, eval("A == 0x3F && [HL] > 10"));
+        assert_eq!(0, eval("A == 0x3F && [HL] > 11"));
+        assert_eq!(1, eval("a == $3f || 0"));
+        assert_eq!(0xF0, eval("[HL + 1]"));
+        assert_eq!(1, eval("[hl+1] & 0x80 == 0x80"));
+        assert_eq!(1, eval("HL == 0xC010 && SP >= 0xDFF0 && PC < 0x200"));
+        assert_eq!(1, eval("ADDR == 0xFF40 && VALUE & 0x80 != 0"));
+        assert_eq!(9, eval("1 + 2 - 0 + 6"));
+        assert_eq!(1, eval("A & 1 || 0 && 1"));
+        assert_eq!(1, eval("!(A != 0x3F)"));
+        assert_eq!(0xFFFF_FFFF, eval("0 - 1"));
+        assert_eq!(0x3E, eval("A ^ 1"));
+        assert_eq!(0x3F, eval("A | 0x30"));
+        assert_eq!(0xFFFF_FFC0, eval("~A"));
+        assert_eq!(1, eval("1 < 2 == 1"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Err(ExprError::UnknownIdentifier("Q".to_string())),
+            Expr::parse("Q == 1")
+        );
+        assert_eq!(Err(ExprError::UnexpectedEnd), Expr::parse("A =="));
+        assert_eq!(Err(ExprError::UnexpectedEnd), Expr::parse("[HL"));
+        assert_eq!(
+            Err(ExprError::UnexpectedChar {
+                position: 2,
+                found: '='
+            }),
+            Expr::parse("A = 1")
+        );
+        assert_eq!(
+            Err(ExprError::InvalidNumber("0xZZ".to_string())),
+            Expr::parse("A == 0xZZ")
+        );
+        assert_eq!("A == 1", Expr::parse("A == 1").unwrap().to_string());
+    }
+}
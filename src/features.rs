@@ -0,0 +1,116 @@
+//! Build configuration surfaced at runtime, so frontends can adapt to the optional subsystems
+//! compiled in and bug reports can include the exact build.
+//!
+//! Video, audio and input backends aren't part of the core: they're provided by the frontend
+//! through the `LCD`, `StereoPlayer` and `EventsHandler` traits.
+
+use std::fmt;
+
+use crate::{
+    config::Config, joypad::SimultaneousPressPolicy, mode::Mode, pacing::Pacing, savestate,
+};
+
+/// Emulation behaviour used when the `Config` doesn't override it
+#[derive(Clone, Debug)]
+pub struct AccuracyDefaults {
+    pub mode: Mode,
+    pub pacing: Pacing,
+    pub simultaneous_press_policy: SimultaneousPressPolicy,
+    /// The boot sequence is skipped unless a bootrom is provided
+    pub bootrom: bool,
+    /// See `Config::disable_vblank_oam_stat`
+    pub vblank_oam_stat: bool,
+    /// See `Config::disable_sprite_limit`
+    pub sprite_limit: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Features {
+    /// Crate version
+    pub version: &'static str,
+    /// Python bindings, `pyo3` cargo feature
+    pub python: bool,
+    /// Savestate container version, see `savestate::FORMAT_VERSION`
+    pub savestate_format: u16,
+    /// Instant replay GIF export, `replay-gif` cargo feature
+    pub replay_gif: bool,
+    /// C bindings, `ffi` cargo feature
+    pub c_ffi: bool,
+    /// Rhai scripts, `rhai` cargo feature. See `scripting`.
+    pub scripting: bool,
+    pub defaults: AccuracyDefaults,
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |enabled: bool| if enabled { "+" } else { "-" };
+        write!(
+            f,
+            "gamuboy {} ({}python, savestate v{}, {}replay gif, {}c ffi, {}scripting; defaults: {:?}, {:?} pacing, {:?} simultaneous presses, {}, {}vblank OAM STAT, {}sprite limit)",
+            self.version,
+            flag(self.python),
+            self.savestate_format,
+            flag(self.replay_gif),
+            flag(self.c_ffi),
+            flag(self.scripting),
+            self.defaults.mode,
+            self.defaults.pacing,
+            self.defaults.simultaneous_press_policy,
+            match self.defaults.bootrom {
+                true => "bootrom",
+                false => "no bootrom",
+            },
+            flag(self.defaults.vblank_oam_stat),
+            flag(self.defaults.sprite_limit),
+        )
+    }
+}
+
+/// Optional subsystems and defaults of this build
+pub fn features() -> Features {
+    let cfg = Config::default();
+    Features {
+        version: env!("CARGO_PKG_VERSION"),
+        python: cfg!(feature = "pyo3"),
+        savestate_format: savestate::FORMAT_VERSION,
+        replay_gif: cfg!(feature = "replay-gif"),
+        c_ffi: cfg!(feature = "ffi"),
+        scripting: cfg!(feature = "rhai"),
+        defaults: AccuracyDefaults {
+            mode: cfg.mode,
+            pacing: cfg.pacing,
+            simultaneous_press_policy: cfg.simultaneous_press_policy,
+            bootrom: cfg.bootrom.is_some(),
+            vblank_oam_stat: !cfg.disable_vblank_oam_stat,
+            sprite_limit: !cfg.disable_sprite_limit,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_features() {
+        let features = features();
+        assert_eq!(cfg!(feature = "pyo3"), features.python);
+        assert_eq!(cfg!(feature = "ffi"), features.c_ffi);
+        assert_eq!(cfg!(feature = "rhai"), features.scripting);
+        assert_eq!(savestate::FORMAT_VERSION, features.savestate_format);
+        assert!(!features.defaults.bootrom);
+        assert!(features.defaults.vblank_oam_stat);
+        assert!(features.defaults.sprite_limit);
+
+        let report = features.to_string();
+        assert!(report.starts_with(&format!("gamuboy {} (", env!("CARGO_PKG_VERSION"))));
+        assert!(report.contains("python, savestate v"));
+        assert!(report.contains(&format!(
+            "c ffi, {}scripting; defaults",
+            if features.scripting { "+" } else { "-" }
+        )));
+        assert!(report.contains(
+            "DMG, Uncapped pacing, Allow simultaneous presses, no bootrom, +vblank OAM STAT, +sprite limit)"
+        ));
+    }
+}
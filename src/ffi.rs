@@ -0,0 +1,369 @@
+//! C bindings, `ffi` cargo feature, declared in `include/gamuboy.h` generated from this file by
+//! cbindgen (`cargo run --manifest-path xtask/Cargo.toml -- header`): an opaque `Gamuboy` handle
+//! running a headless core frame by frame. Panics are caught at the boundary and returned as
+//! `GAMUBOY_ERR_PANIC`. A handle must only be used by one thread at a time.
+
+use std::{
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+    sync::mpsc,
+};
+
+use crate::{
+    config::Config,
+    gameboy::HeadlessGameBoy,
+    joypad::Button,
+    lcd::{self, PixelFormat},
+    mode::Mode,
+    pacing::Pacing,
+};
+
+pub const GAMUBOY_OK: i32 = 0;
+/// Null handle or buffer
+pub const GAMUBOY_ERR_NULL: i32 = -1;
+/// No rom loaded yet
+pub const GAMUBOY_ERR_NO_ROM: i32 = -2;
+pub const GAMUBOY_ERR_INVALID_ROM: i32 = -3;
+pub const GAMUBOY_ERR_INVALID_STATE: i32 = -4;
+/// The core panicked, the handle should only be destroyed or given a new rom
+pub const GAMUBOY_ERR_PANIC: i32 = -5;
+
+/// `lcd::PIXELS_WIDTH`, spelled out for cbindgen
+pub const GAMUBOY_SCREEN_WIDTH: u32 = 160;
+pub const GAMUBOY_SCREEN_HEIGHT: u32 = 144;
+
+/// Bits of `gamuboy_set_buttons`, bit n being `Button::from(n)`
+pub const GAMUBOY_BUTTON_A: u8 = 1 << 0;
+pub const GAMUBOY_BUTTON_B: u8 = 1 << 1;
+pub const GAMUBOY_BUTTON_SELECT: u8 = 1 << 2;
+pub const GAMUBOY_BUTTON_START: u8 = 1 << 3;
+pub const GAMUBOY_BUTTON_RIGHT: u8 = 1 << 4;
+pub const GAMUBOY_BUTTON_LEFT: u8 = 1 << 5;
+pub const GAMUBOY_BUTTON_UP: u8 = 1 << 6;
+pub const GAMUBOY_BUTTON_DOWN: u8 = 1 << 7;
+
+type Core = HeadlessGameBoy<'static>;
+
+pub struct Gamuboy {
+    cgb: bool,
+    core: Option<Box<Core>>,
+    /// bit n held for `Button::from(n)`
+    buttons: u8,
+    /// last frame in RGBA, returned by `gamuboy_framebuffer`
+    pixels: Vec<u8>,
+    /// events receiver borrowed by the core, nobody sends to it as inputs only come from
+    /// `gamuboy_set_buttons`. Boxed so it doesn't move, declared after the core to be dropped last.
+    rx: Box<mpsc::Receiver<()>>,
+}
+
+/// # Safety
+///
+/// The core borrows `rx`, which must outlive it
+unsafe fn new_core(rom: Vec<u8>, cgb: bool, rx: &mpsc::Receiver<()>) -> Box<Core> {
+    let cfg = Config {
+        mode: if cgb { Mode::CGB } else { Mode::DMG },
+        rom,
+        headless_mode: true,
+        pacing: Pacing::Uncapped,
+        ..Default::default()
+    };
+
+    let rx: &'static mpsc::Receiver<()> = &*(rx as *const _);
+    Box::new(HeadlessGameBoy::headless(&cfg, rx))
+}
+
+/// Runs `f` on the handle core, mapping null pointers, missing rom and panics to error codes
+unsafe fn with_core(gb: *mut Gamuboy, f: impl FnOnce(&mut Gamuboy) -> i32) -> i32 {
+    let Some(gb) = gb.as_mut() else {
+        return GAMUBOY_ERR_NULL;
+    };
+    if gb.core.is_none() {
+        return GAMUBOY_ERR_NO_ROM;
+    }
+    panic::catch_unwind(AssertUnwindSafe(|| f(gb))).unwrap_or(GAMUBOY_ERR_PANIC)
+}
+
+/// New handle without rom, in CGB mode when `cgb` is set. Free it with `gamuboy_destroy`.
+#[no_mangle]
+pub extern "C" fn gamuboy_create(cgb: bool) -> *mut Gamuboy {
+    Box::into_raw(Box::new(Gamuboy {
+        cgb,
+        core: None,
+        buttons: 0,
+        pixels: vec![],
+        rx: Box::new(mpsc::channel().1),
+    }))
+}
+
+/// # Safety
+///
+/// `gb` must come from `gamuboy_create` and not be used afterwards, null is ignored
+#[no_mangle]
+pub unsafe extern "C" fn gamuboy_destroy(gb: *mut Gamuboy) {
+    if !gb.is_null() {
+        drop(Box::from_raw(gb));
+    }
+}
+
+/// Copies the rom and powers the console on, replacing any running game
+///
+/// # Safety
+///
+/// `gb` must be a live handle, `rom` must point to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn gamuboy_load_rom(gb: *mut Gamuboy, rom: *const u8, len: usize) -> i32 {
+    let Some(gb) = gb.as_mut() else {
+        return GAMUBOY_ERR_NULL;
+    };
+    if rom.is_null() {
+        return GAMUBOY_ERR_NULL;
+    }
+    let rom = slice::from_raw_parts(rom, len).to_vec();
+    if rom.len() < 0x8000 {
+        return GAMUBOY_ERR_INVALID_ROM;
+    }
+
+    let cgb = gb.cgb;
+    // SAFETY: the handle owns the receiver and drops it after the core
+    match panic::catch_unwind(AssertUnwindSafe(|| new_core(rom, cgb, &gb.rx))) {
+        Ok(core) => {
+            gb.core = Some(core);
+            GAMUBOY_OK
+        }
+        Err(_) => GAMUBOY_ERR_INVALID_ROM,
+    }
+}
+
+/// Buttons held from the next frame on, `GAMUBOY_BUTTON_*` bits
+///
+/// # Safety
+///
+/// `gb` must be a live handle
+#[no_mangle]
+pub unsafe extern "C" fn gamuboy_set_buttons(gb: *mut Gamuboy, buttons: u8) -> i32 {
+    let Some(gb) = gb.as_mut() else {
+        return GAMUBOY_ERR_NULL;
+    };
+    gb.buttons = buttons;
+    GAMUBOY_OK
+}
+
+/// Runs one video frame with the buttons set by `gamuboy_set_buttons`
+///
+/// # Safety
+///
+/// `gb` must be a live handle
+#[no_mangle]
+pub unsafe extern "C" fn gamuboy_run_frame(gb: *mut Gamuboy) -> i32 {
+    with_core(gb, |gb| {
+        let buttons: Vec<_> = (0..8)
+            .filter(|bit| gb.buttons & 1 << bit != 0)
+            .map(Button::from)
+            .collect();
+        if let Some(core) = &mut gb.core {
+            core.frame_advance(&buttons);
+        }
+        GAMUBOY_OK
+    })
+}
+
+/// Last frame as `GAMUBOY_SCREEN_WIDTH * GAMUBOY_SCREEN_HEIGHT` RGBA pixels, rows top to
+/// bottom. Valid until the next call on the handle, null without rom or if the core panicked.
+///
+/// # Safety
+///
+/// `gb` must be a live handle
+#[no_mangle]
+pub unsafe extern "C" fn gamuboy_framebuffer(gb: *mut Gamuboy) -> *const u8 {
+    let Some(gb) = gb.as_mut() else {
+        return ptr::null();
+    };
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let core = gb.core.as_ref()?;
+        lcd::pack_frame(core.frame_buffer(), PixelFormat::Rgba8888, &mut gb.pixels);
+        Some(gb.pixels.as_ptr())
+    }))
+    .ok()
+    .flatten()
+    .unwrap_or(ptr::null())
+}
+
+/// Size of the savestate, copied to `out` when `capacity` is large enough. Call it with a null
+/// `out` to get the size first. Negative on error.
+///
+/// # Safety
+///
+/// `gb` must be a live handle, `out` null or pointing to `capacity` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn gamuboy_save_state(
+    gb: *mut Gamuboy,
+    out: *mut u8,
+    capacity: usize,
+) -> i64 {
+    let mut state = vec![];
+    let status = with_core(gb, |gb| {
+        if let Some(core) = &gb.core {
+            state = core.save_state();
+        }
+        GAMUBOY_OK
+    });
+    if status != GAMUBOY_OK {
+        return status as i64;
+    }
+
+    if !out.is_null() && capacity >= state.len() {
+        ptr::copy_nonoverlapping(state.as_ptr(), out, state.len());
+    }
+    state.len() as i64
+}
+
+/// Restores a savestate of `gamuboy_save_state`, the running game is kept when it's invalid
+///
+/// # Safety
+///
+/// `gb` must be a live handle, `state` must point to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn gamuboy_load_state(gb: *mut Gamuboy, state: *const u8, len: usize) -> i32 {
+    if state.is_null() {
+        return GAMUBOY_ERR_NULL;
+    }
+    let state = slice::from_raw_parts(state, len);
+    with_core(gb, |gb| {
+        match gb.core.as_mut().map(|core| core.load_state(state)) {
+            Some(Ok(())) => GAMUBOY_OK,
+            _ => GAMUBOY_ERR_INVALID_STATE,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{Asm, RomBuilder, R8};
+
+    use super::*;
+
+    const HEADER: &str = include_str!("../include/gamuboy.h");
+
+    /// Drift check of the generated header, for builds without the xtask
+    #[test]
+    fn test_header_declares_the_api() {
+        let source = include_str!("ffi.rs");
+        let functions: Vec<_> = source
+            .split("extern \"C\" fn ")
+            .skip(1)
+            .map(|rest| &rest[..rest.find('(').unwrap()])
+            .collect();
+        assert_eq!(8, functions.len());
+        for function in functions {
+            let declared = HEADER.lines().any(|line| {
+                !line.starts_with(' ')
+                    && line.contains(&format!("{function}("))
+                    && line.ends_with(");")
+            });
+            assert!(declared, "{function}");
+        }
+
+        for (name, value) in [
+            ("GAMUBOY_OK", GAMUBOY_OK),
+            ("GAMUBOY_ERR_NULL", GAMUBOY_ERR_NULL),
+            ("GAMUBOY_ERR_NO_ROM", GAMUBOY_ERR_NO_ROM),
+            ("GAMUBOY_ERR_INVALID_ROM", GAMUBOY_ERR_INVALID_ROM),
+            ("GAMUBOY_ERR_INVALID_STATE", GAMUBOY_ERR_INVALID_STATE),
+            ("GAMUBOY_ERR_PANIC", GAMUBOY_ERR_PANIC),
+        ] {
+            assert!(
+                HEADER.contains(&format!("#define {name} {value}")),
+                "{name}"
+            );
+        }
+        assert_eq!(lcd::PIXELS_WIDTH, GAMUBOY_SCREEN_WIDTH as usize);
+        assert_eq!(lcd::PIXELS_HEIGHT, GAMUBOY_SCREEN_HEIGHT as usize);
+        assert!(HEADER.contains(&format!(
+            "#define GAMUBOY_SCREEN_WIDTH {GAMUBOY_SCREEN_WIDTH}"
+        )));
+        assert!(HEADER.contains(&format!(
+            "#define GAMUBOY_SCREEN_HEIGHT {GAMUBOY_SCREEN_HEIGHT}"
+        )));
+
+        for (bit, (name, value)) in [
+            ("A", GAMUBOY_BUTTON_A),
+            ("B", GAMUBOY_BUTTON_B),
+            ("SELECT", GAMUBOY_BUTTON_SELECT),
+            ("START", GAMUBOY_BUTTON_START),
+            ("RIGHT", GAMUBOY_BUTTON_RIGHT),
+            ("LEFT", GAMUBOY_BUTTON_LEFT),
+            ("UP", GAMUBOY_BUTTON_UP),
+            ("DOWN", GAMUBOY_BUTTON_DOWN),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            assert_eq!(1 << bit, value, "{name}");
+            assert_eq!(
+                name,
+                format!("{:?}", Button::from(bit as u8)).to_uppercase()
+            );
+            assert!(
+                HEADER.contains(&format!("#define GAMUBOY_BUTTON_{name} (1 << {bit})")),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_ffi() {
+        // loop: ldh a,(0x00); ld (0xC000),a; jr loop, with the direction keys selected
+        let mut asm = Asm::new();
+        asm.ld_r_n(R8::A, 0x20).ldh_n_a(0x00);
+        let main_loop = asm.here();
+        asm.ldh_a_n(0x00).ld_nn_a(0xC000).jr(None, main_loop);
+        let rom = RomBuilder::new().code(&asm).build();
+
+        unsafe {
+            let gb = gamuboy_create(false);
+            assert_eq!(GAMUBOY_ERR_NO_ROM, gamuboy_run_frame(gb));
+            assert!(gamuboy_framebuffer(gb).is_null());
+            assert_eq!(
+                GAMUBOY_ERR_INVALID_ROM,
+                gamuboy_load_rom(gb, rom.as_ptr(), 0x100)
+            );
+            assert_eq!(GAMUBOY_OK, gamuboy_load_rom(gb, rom.as_ptr(), rom.len()));
+
+            // right held
+            assert_eq!(GAMUBOY_OK, gamuboy_set_buttons(gb, 1 << 4));
+            assert_eq!(GAMUBOY_OK, gamuboy_run_frame(gb));
+            let read = |gb: *mut Gamuboy| (*gb).core.as_ref().unwrap().read_memory(0xC000);
+            assert_eq!(0x0E, read(gb) & 0x0F);
+            let pixels = gamuboy_framebuffer(gb);
+            assert!(!pixels.is_null());
+            assert_eq!(
+                lcd::PIXELS_WIDTH * lcd::PIXELS_HEIGHT * 4,
+                (*gb).pixels.len()
+            );
+
+            let size = gamuboy_save_state(gb, ptr::null_mut(), 0);
+            assert!(size > 0);
+            let mut state = vec![0; size as usize];
+            assert_eq!(
+                size,
+                gamuboy_save_state(gb, state.as_mut_ptr(), state.len())
+            );
+
+            gamuboy_set_buttons(gb, 0);
+            gamuboy_run_frame(gb);
+            assert_eq!(0x0F, read(gb) & 0x0F);
+            assert_eq!(
+                GAMUBOY_OK,
+                gamuboy_load_state(gb, state.as_ptr(), state.len())
+            );
+            assert_eq!(0x0E, read(gb) & 0x0F);
+            assert_eq!(
+                GAMUBOY_ERR_INVALID_STATE,
+                gamuboy_load_state(gb, state.as_ptr(), 4)
+            );
+
+            assert_eq!(GAMUBOY_ERR_NULL, gamuboy_run_frame(ptr::null_mut()));
+            gamuboy_destroy(gb);
+        }
+    }
+}
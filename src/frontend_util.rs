@@ -0,0 +1,269 @@
+//! Common emulator UX conventions, so every frontend built on gamuboy behaves the same:
+//! numbered savestate slots cycled with hotkeys, debounced quick save/load, screenshots and pause.
+//!
+//! `Hotkeys` only tracks the state, the frontend maps its keys to `Hotkey`s and performs the
+//! returned `Action`s with the core APIs.
+
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local};
+
+use crate::lcd::{pack_frame, Frame, PixelFormat};
+
+pub const DEFAULT_SLOTS: u8 = 10;
+/// Long enough to swallow key auto-repeat, short enough not to get in the way
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hotkey {
+    QuickSave,
+    QuickLoad,
+    NextSlot,
+    PreviousSlot,
+    Screenshot,
+    TogglePause,
+}
+
+impl Hotkey {
+    const ALL: [Hotkey; 6] = [
+        Hotkey::QuickSave,
+        Hotkey::QuickLoad,
+        Hotkey::NextSlot,
+        Hotkey::PreviousSlot,
+        Hotkey::Screenshot,
+        Hotkey::TogglePause,
+    ];
+
+    /// Conventional default bindings, for frontends' help screens
+    pub fn default_key(&self) -> &'static str {
+        match self {
+            Self::QuickSave => "F5",
+            Self::QuickLoad => "F7",
+            Self::NextSlot => "F6",
+            Self::PreviousSlot => "Shift+F6",
+            Self::Screenshot => "F12",
+            Self::TogglePause => "P",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    SaveState { slot: u8 },
+    LoadState { slot: u8 },
+    SlotSelected { slot: u8 },
+    Screenshot,
+    Paused(bool),
+}
+
+pub struct Hotkeys {
+    slots: u8,
+    slot: u8,
+    paused: bool,
+    debounce: Duration,
+    /// last accepted press of each hotkey, indexed like `Hotkey::ALL`
+    last_press: [Option<Instant>; Hotkey::ALL.len()],
+}
+
+impl Hotkeys {
+    /// `slots` savestate slots, numbered from 0
+    ///
+    /// # Panics
+    ///
+    /// When `slots` is 0
+    pub fn new(slots: u8, debounce: Duration) -> Self {
+        assert!(slots > 0, "at least one savestate slot is needed");
+        Self {
+            slots,
+            slot: 0,
+            paused: false,
+            debounce,
+            last_press: [None; Hotkey::ALL.len()],
+        }
+    }
+
+    pub fn slot(&self) -> u8 {
+        self.slot
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Handles a hotkey press at `now`, returning what the frontend has to do.
+    /// Presses repeated within the debounce delay are ignored.
+    pub fn press(&mut self, hotkey: Hotkey, now: Instant) -> Option<Action> {
+        let i = Hotkey::ALL.iter().position(|&h| h == hotkey)?;
+        if let Some(last) = self.last_press[i] {
+            if now.saturating_duration_since(last) < self.debounce {
+                return None;
+            }
+        }
+        self.last_press[i] = Some(now);
+
+        Some(match hotkey {
+            Hotkey::QuickSave => Action::SaveState { slot: self.slot },
+            Hotkey::QuickLoad => Action::LoadState { slot: self.slot },
+            Hotkey::NextSlot => {
+                self.slot = (self.slot + 1) % self.slots;
+                Action::SlotSelected { slot: self.slot }
+            }
+            Hotkey::PreviousSlot => {
+                self.slot = match self.slot {
+                    0 => self.slots - 1,
+                    slot => slot - 1,
+                };
+                Action::SlotSelected { slot: self.slot }
+            }
+            Hotkey::Screenshot => Action::Screenshot,
+            Hotkey::TogglePause => {
+                self.paused = !self.paused;
+                Action::Paused(self.paused)
+            }
+        })
+    }
+}
+
+impl Default for Hotkeys {
+    fn default() -> Self {
+        Self::new(DEFAULT_SLOTS, DEFAULT_DEBOUNCE)
+    }
+}
+
+/// Savestate file name of `slot`, e.g. `Tetris.ss0`
+pub fn slot_file_name(title: &str, slot: u8) -> String {
+    format!("{title}.ss{slot}")
+}
+
+/// Screenshot file name taken at `time`, e.g. `Tetris-2024-05-01_13-37-00.ppm`
+pub fn screenshot_file_name(title: &str, time: DateTime<Local>) -> String {
+    format!("{title}-{}.ppm", time.format("%Y-%m-%d_%H-%M-%S"))
+}
+
+/// Writes the frame as a binary PPM image, readable by most image tools without any dependency
+pub fn write_screenshot<W: Write>(frame: &Frame, mut w: W) -> io::Result<()> {
+    let mut pixels = vec![];
+    pack_frame(frame, PixelFormat::Rgb888, &mut pixels);
+    write!(w, "P6\n{} {}\n255\n", frame.width(), frame.height())?;
+    w.write_all(&pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use crate::lcd::{PIXELS_HEIGHT, PIXELS_WIDTH, RGB_BLACK, RGB_WHITE};
+
+    use super::*;
+
+    #[test]
+    fn test_slot_cycling() {
+        let mut hotkeys = Hotkeys::new(3, Duration::ZERO);
+        let now = Instant::now();
+
+        assert_eq!(
+            Some(Action::SlotSelected { slot: 2 }),
+            hotkeys.press(Hotkey::PreviousSlot, now)
+        );
+        assert_eq!(
+            Some(Action::SlotSelected { slot: 0 }),
+            hotkeys.press(Hotkey::NextSlot, now)
+        );
+        hotkeys.press(Hotkey::NextSlot, now);
+        assert_eq!(
+            Some(Action::SaveState { slot: 1 }),
+            hotkeys.press(Hotkey::QuickSave, now)
+        );
+        assert_eq!(
+            Some(Action::LoadState { slot: 1 }),
+            hotkeys.press(Hotkey::QuickLoad, now)
+        );
+    }
+
+    #[test]
+    fn test_many_slots() {
+        let mut hotkeys = Hotkeys::new(200, Duration::ZERO);
+        let now = Instant::now();
+        assert_eq!(
+            Some(Action::SlotSelected { slot: 199 }),
+            hotkeys.press(Hotkey::PreviousSlot, now)
+        );
+        assert_eq!(
+            Some(Action::SlotSelected { slot: 198 }),
+            hotkeys.press(Hotkey::PreviousSlot, now)
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_no_slot() {
+        Hotkeys::new(0, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_debounce() {
+        let mut hotkeys = Hotkeys::default();
+        let now = Instant::now();
+
+        assert_eq!(
+            Some(Action::SaveState { slot: 0 }),
+            hotkeys.press(Hotkey::QuickSave, now)
+        );
+        assert_eq!(
+            None,
+            hotkeys.press(Hotkey::QuickSave, now + Duration::from_millis(100))
+        );
+        // other hotkeys aren't affected
+        assert_eq!(
+            Some(Action::LoadState { slot: 0 }),
+            hotkeys.press(Hotkey::QuickLoad, now + Duration::from_millis(100))
+        );
+        assert_eq!(
+            Some(Action::SaveState { slot: 0 }),
+            hotkeys.press(Hotkey::QuickSave, now + DEFAULT_DEBOUNCE)
+        );
+    }
+
+    #[test]
+    fn test_pause_toggle() {
+        let mut hotkeys = Hotkeys::default();
+        let now = Instant::now();
+
+        assert_eq!(
+            Some(Action::Paused(true)),
+            hotkeys.press(Hotkey::TogglePause, now)
+        );
+        assert!(hotkeys.is_paused());
+        assert_eq!(
+            Some(Action::Paused(false)),
+            hotkeys.press(Hotkey::TogglePause, now + DEFAULT_DEBOUNCE)
+        );
+    }
+
+    #[test]
+    fn test_file_names() {
+        assert_eq!("Tetris.ss3", slot_file_name("Tetris", 3));
+        let time = Local.with_ymd_and_hms(2024, 5, 1, 13, 37, 0).unwrap();
+        assert_eq!(
+            "Tetris-2024-05-01_13-37-00.ppm",
+            screenshot_file_name("Tetris", time)
+        );
+    }
+
+    #[test]
+    fn test_write_screenshot() {
+        let mut frame = Frame::new(vec![vec![RGB_WHITE; PIXELS_WIDTH]; PIXELS_HEIGHT]);
+        frame.pixels[0][0] = RGB_BLACK;
+
+        let mut ppm = vec![];
+        write_screenshot(&frame, &mut ppm).unwrap();
+
+        let header = b"P6\n160 144\n255\n";
+        assert_eq!(header, &ppm[..header.len()]);
+        assert_eq!(header.len() + PIXELS_WIDTH * PIXELS_HEIGHT * 3, ppm.len());
+        assert_eq!([0, 0, 0, 255], ppm[header.len()..header.len() + 4]);
+    }
+}
@@ -1,25 +1,61 @@
-use std::{marker::Send, sync::mpsc::Receiver};
+use std::{
+    fs::File,
+    io::{self, Write},
+    marker::Send,
+    ops::RangeInclusive,
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc::Receiver,
+};
 
 use crate::{
-    apu::APU,
-    bus::SystemBus,
-    cartridge::Cartridge,
+    apu::{ApuSnapshot, APU},
+    bus::{Bus, SystemBus},
+    cartridge::{Cartridge, CartridgeCapabilities, HeaderError, HotSwapError},
+    cheat_search::{CheatSearch, SearchFilter},
+    cheats::{Cheat, CheatId, PatchSet},
     config::Config,
     cpu::{self, CPU},
+    crash::{self, CrashHandler, CrashReport, IO_SUMMARY},
+    cycle_observer::CycleObserver,
+    debug::{
+        BankUsage, BlankScreenReport, FrameChangeTracker, HotSpots, MemoryHeatmap, Profiler,
+        RomCoverage,
+    },
+    debugger::{self, Debugger, StopReason, WatchAction, WatchHit, WatchKind, WatchpointId},
+    events::Event,
+    expr::{Context, Expr},
+    input_display::InputLog,
     interrupts::InterruptRegisters,
-    joypad::Joypad,
-    joypad_events_handler::EventsHandler,
-    lcd::LCD,
+    joypad::{Button, Joypad, SimultaneousPressPolicy},
+    joypad_events_handler::{self, EventsHandler},
+    lcd::{self, DmgPalette, Frame, LCD},
+    link::LinkDevice,
+    mode::{Mode, Model},
     oam::OAM,
+    observation::{MemoryObserver, Observation},
+    pacing::{self, BackgroundPolicy, FrameLimiter, FramePacer, Pacing},
     ppu::PPU,
+    probes::{Condition, MemoryProbes, ProbeId},
     ram::RAM,
-    saver::GameSave,
-    serial::Serial,
-    stereo::StereoPlayer,
+    registers::Registers,
+    replay::ReplayBuffer,
+    saver::{self, GameSave},
+    savestate::{ChunkWriter, SaveStateError, Snapshot, StateReader, StateWriter},
+    scripting::{Script, ScriptApi, ScriptId, Scripts},
+    serial::{Serial, TransferCallback},
+    splash::{BootAnimation, LogoSplash, Playback},
+    stats::PerfStats,
+    stereo::{self, AudioFilter, FastForwardAudio, StereoPlayer},
+    symbols::SymbolTable,
     timer::Timer,
+    trace::CpuTrace,
+    undocumented::UndocumentedRegisters,
     vram::VRAM,
 };
 
+/// Tag of the chunk holding the frame counter
+const GAMEBOY_TAG: [u8; 4] = *b"GB  ";
+
 pub struct GameBoy<
     'a,
     L: LCD + 'static,
@@ -28,9 +64,38 @@ pub struct GameBoy<
     S: StereoPlayer + 'static,
 > {
     cpu: cpu::CPU<SystemBus<'a, L, E, H, S>>,
+    frame_limiter: FrameLimiter,
+    frame_ready: bool,
+    probes: MemoryProbes,
+    observer: MemoryObserver,
+    frames: u64,
+    frame_changes: FrameChangeTracker,
+    mode: Mode,
+    model: Model,
+    input_log: Option<InputLog>,
+    crash_handler: Option<CrashHandler>,
+    cpu_trace: Option<CpuTrace>,
+    /// writes the label lines in the cpu trace, see `set_cpu_trace_labels`
+    trace_labels: bool,
+    symbols: Option<SymbolTable>,
+    replay: Option<ReplayBuffer>,
+    scripts: Scripts,
+    profiler: Option<Profiler>,
+    hot_spots: Option<HotSpots>,
+    boot_animation: Option<Playback>,
+    paused: bool,
+    /// frontend out of focus, see `set_background`
+    background: bool,
+    background_policy: BackgroundPolicy,
+    /// muted by `set_muted`, the background may mute on top of it
+    muted: bool,
+    debugger: Debugger,
+    /// breaking watchpoint hit not taken yet
+    watch_hit: Option<WatchHit>,
 }
 
 impl<'a, L: LCD, E: Send + 'static, H: EventsHandler<E>, S: StereoPlayer> GameBoy<'a, L, E, H, S> {
+    /// Panics on a bad rom header with `Config::strict_header`, see `try_new`
     pub fn new<GS: GameSave + 'static>(
         cfg: &Config,
         lcd: L,
@@ -39,36 +104,1034 @@ impl<'a, L: LCD, E: Send + 'static, H: EventsHandler<E>, S: StereoPlayer> GameBo
         saver: GS,
         event_rx: &'a Receiver<E>,
     ) -> Self {
-        Self {
+        Self::try_new(cfg, lcd, stereo, joypad_events_handler, saver, event_rx)
+            .unwrap_or_else(|e| panic!("refusing rom: {e}"))
+    }
+
+    /// Fails on a bad rom header with `Config::strict_header`
+    pub fn try_new<GS: GameSave + 'static>(
+        cfg: &Config,
+        lcd: L,
+        stereo: S,
+        joypad_events_handler: H,
+        saver: GS,
+        event_rx: &'a Receiver<E>,
+    ) -> Result<Self, HeaderError> {
+        if let Some(model) = cfg.model.filter(|&model| model != cfg.effective_model()) {
+            warn!(
+                "{model:?} isn't a {:?} model, running as {:?}",
+                cfg.mode,
+                cfg.effective_model()
+            );
+        }
+        let mut joypad = Joypad::new();
+        joypad.set_simultaneous_press_policy(cfg.simultaneous_press_policy.clone());
+
+        let mut gb = Self {
             cpu: CPU::new(
                 cfg,
                 SystemBus::new(
-                    Cartridge::new(cfg, saver),
-                    APU::new(stereo),
-                    PPU::new(cfg, VRAM::new(cfg.mode.clone()), OAM::new(), lcd),
+                    Cartridge::new(cfg, saver)?,
+                    APU::with_model(cfg.effective_model()),
+                    PPU::new(cfg, VRAM::new(cfg.mode.clone()), OAM::new()),
                     InterruptRegisters::new(),
-                    Joypad::new(),
+                    joypad,
                     Timer::new(),
-                    Serial::new(),
+                    Serial::new(cfg.mode.clone()),
                     RAM::new(cfg.mode.clone()),
+                    UndocumentedRegisters::new(cfg.mode.clone()),
                     joypad_events_handler,
                     event_rx,
+                    lcd,
+                    stereo,
+                    cfg.headless_mode,
                 ),
             ),
+            frame_limiter: FrameLimiter::new(cfg.pacing.clone()),
+            frame_ready: false,
+            probes: MemoryProbes::new(),
+            observer: MemoryObserver::default(),
+            frames: 0,
+            frame_changes: FrameChangeTracker::default(),
+            mode: cfg.mode.clone(),
+            model: cfg.effective_model(),
+            input_log: None,
+            crash_handler: None,
+            cpu_trace: None,
+            trace_labels: false,
+            symbols: None,
+            replay: (cfg.replay_seconds > 0).then(|| ReplayBuffer::new(cfg.replay_seconds)),
+            scripts: Scripts::new(),
+            profiler: None,
+            hot_spots: None,
+            boot_animation: (cfg.boot_splash && cfg.bootrom.is_none())
+                .then(|| Playback::new(Box::new(LogoSplash::new()))),
+            paused: false,
+            background: false,
+            background_policy: cfg.background_policy,
+            muted: false,
+            debugger: Debugger::new(),
+            watch_hit: None,
+        };
+        gb.set_fast_forward(cfg.fast_forward);
+        gb.set_overclock(cfg.overclock);
+        gb.set_palette(cfg.palette);
+        gb.set_frameskip(cfg.frameskip);
+        gb.set_audio_filter(cfg.audio_filter);
+        gb.cpu.bus_mut().set_stub_ly(cfg.stub_ly);
+        if cfg.cpu_trace {
+            let out: Option<Box<dyn Write>> = match &cfg.log_file_path {
+                Some(path) => match File::create(path) {
+                    Ok(file) => Some(Box::new(file)),
+                    Err(e) => {
+                        warn!("Cannot create the cpu trace file {path}, not tracing: {e}");
+                        None
+                    }
+                },
+                None => Some(Box::new(io::stdout())),
+            };
+            gb.set_cpu_trace(out);
+        }
+
+        Ok(gb)
+    }
+
+    /// Plays `animation` before the rom starts instead of the one set by `Config::boot_splash`,
+    /// call it before the first step. Each animation frame takes a `step_frame`.
+    pub fn set_boot_animation<A: BootAnimation + 'static>(&mut self, animation: A) {
+        self.boot_animation = Some(Playback::new(Box::new(animation)));
+    }
+
+    /// Hardware revision emulated, see `Config::model`
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Current joypad state as seen by the game
+    pub fn joypad(&self) -> &Joypad {
+        self.cpu.bus().joypad()
+    }
+
+    /// CPU registers, PC and SP aside
+    pub fn registers(&self) -> &Registers {
+        self.cpu.registers()
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.cpu.pc()
+    }
+
+    pub fn sp(&self) -> u16 {
+        self.cpu.sp()
+    }
+
+    /// Master gain applied to the mixed audio before `StereoPlayer::play`, 1.0 leaves samples untouched.
+    /// Scaled samples are clamped to [-1.0, 1.0].
+    pub fn set_volume(&mut self, volume: f32) {
+        self.cpu.bus_mut().set_volume(volume);
+    }
+
+    /// Muted audio still reaches `StereoPlayer::play` as silence, keeping audio pacing running
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        self.apply_muted();
+    }
+
+    fn apply_muted(&mut self) {
+        let background_muted = self.background && self.background_policy != BackgroundPolicy::Run;
+        self.cpu.bus_mut().set_muted(self.muted || background_muted);
+    }
+
+    /// Called by the frontend when its window loses (true) or regains (false) focus, applies
+    /// the `Config::background_policy`. A background pause is separate from `pause`: regaining
+    /// focus doesn't resume an emulation the player paused.
+    pub fn set_background(&mut self, background: bool) {
+        if background == self.background {
+            return;
+        }
+        let was_suspended = self.is_suspended();
+        self.background = background;
+        self.apply_muted();
+        if was_suspended && !self.is_suspended() {
+            self.resync_after_pause();
+        }
+    }
+
+    pub fn is_background(&self) -> bool {
+        self.background
+    }
+
+    /// Overrides `Config::background_policy`, applied right away when in the background
+    pub fn set_background_policy(&mut self, policy: BackgroundPolicy) {
+        let was_suspended = self.is_suspended();
+        self.background_policy = policy;
+        self.apply_muted();
+        if was_suspended && !self.is_suspended() {
+            self.resync_after_pause();
+        }
+    }
+
+    /// Runs emulation `factor` times faster than real time, 1 going back to normal speed.
+    /// Video pacing shortens frames, audio pacing follows the audio kept by the fast-forward policy.
+    pub fn set_fast_forward(&mut self, factor: u32) {
+        self.frame_limiter.set_speed(factor);
+        self.cpu.bus_mut().set_fast_forward(factor);
+    }
+
+    /// Experimental: grants the cpu `cycles` extra cpu clock cycles at the start of each
+    /// scanline while the ppu, apu, timer and serial are frozen, so their timing stays nominal.
+    /// Reduces slowdown and sprite flicker in games running out of cpu time (Gradius, Kirby's
+    /// Dream Land 2...), but raster effects timed by counting instruction cycles may break.
+    /// 0 turns it off.
+    pub fn set_overclock(&mut self, cycles: u32) {
+        self.cpu.bus_mut().set_overclock(cycles);
+    }
+
+    /// What is heard while fast-forwarding, can be changed at any time
+    pub fn set_fast_forward_audio(&mut self, policy: FastForwardAudio) {
+        self.cpu.bus_mut().set_fast_forward_audio(policy);
+    }
+
+    /// Normalizes the mixed audio peaks to `target` before the master gain, see
+    /// `stereo::Normalizer`. None turns it off.
+    pub fn set_loudness_normalizer(&mut self, target: Option<f32>) {
+        self.cpu.bus_mut().set_normalizer(target);
+    }
+
+    /// Overrides `Config::pacing`, taking effect on the next frame
+    pub fn set_pacing(&mut self, pacing: Pacing) {
+        self.frame_limiter.set_pacing(pacing);
+    }
+
+    /// Hands frame pacing over to the frontend, switching to `Pacing::Delegated`
+    pub fn set_frame_pacer<P: FramePacer + 'static>(&mut self, pacer: P) {
+        self.frame_limiter.set_pacer(Box::new(pacer));
+    }
+
+    /// Overrides `Config::simultaneous_press_policy`
+    pub fn set_simultaneous_press_policy(&mut self, policy: SimultaneousPressPolicy) {
+        self.cpu
+            .bus_mut()
+            .joypad_mut()
+            .set_simultaneous_press_policy(policy);
+    }
+
+    /// Overrides `Config::disable_vblank_oam_stat`
+    pub fn set_vblank_oam_stat(&mut self, enabled: bool) {
+        self.cpu.bus_mut().set_vblank_oam_stat(enabled);
+    }
+
+    /// Overrides `Config::disable_sprite_limit`, from the next line
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.cpu.bus_mut().set_sprite_limit(enabled);
+    }
+
+    /// Overrides `Config::palette`, from the next pixel. Only DMG games are affected.
+    pub fn set_palette(&mut self, colors: DmgPalette) {
+        self.cpu.bus_mut().set_palette(colors);
+    }
+
+    /// Overrides `Config::frameskip`, the next frame being drawn. Saves the frontend drawing
+    /// time on slow hosts, emulation and audio are untouched.
+    pub fn set_frameskip(&mut self, frames: u32) {
+        self.cpu.bus_mut().set_frameskip(frames);
+    }
+
+    /// Overrides `Config::audio_filter`, starting from a discharged capacitor
+    pub fn set_audio_filter(&mut self, filter: AudioFilter) {
+        self.cpu.bus_mut().set_audio_filter(filter);
+    }
+
+    /// Turns off the enhancements deviating from the hardware, like
+    /// `Config::without_enhancements` does on a config, as accuracy test runs expect
+    pub fn disable_enhancements(&mut self) {
+        self.set_overclock(0);
+        self.set_sprite_limit(true);
+    }
+
+    /// Swaps the LCD implementation without touching emulation state, returning the previous one
+    pub fn replace_lcd(&mut self, lcd: L) -> L {
+        self.cpu.bus_mut().replace_lcd(lcd)
+    }
+
+    /// Swaps the StereoPlayer implementation without touching emulation state, returning the previous one
+    pub fn replace_audio(&mut self, stereo: S) -> S {
+        self.cpu.bus_mut().replace_audio(stereo)
+    }
+
+    /// Draws the joypad state over the frames sent to the LCD, the emulated frame buffer is left as is
+    pub fn set_input_overlay(&mut self, enabled: bool) {
+        self.cpu.bus_mut().set_input_overlay(enabled);
+    }
+
+    /// Starts recording the buttons held during each frame, disabling drops the recorded log
+    pub fn set_input_log(&mut self, enabled: bool) {
+        self.input_log = match enabled {
+            true => self.input_log.take().or_else(|| Some(InputLog::new())),
+            false => None,
+        };
+    }
+
+    pub fn input_log(&self) -> Option<&InputLog> {
+        self.input_log.as_ref()
+    }
+
+    /// Registers a memory probe evaluated once per frame, see `probes::MemoryProbes`
+    pub fn register_probe(&mut self, conditions: Vec<Condition>) -> ProbeId {
+        self.probes.register(conditions)
+    }
+
+    pub fn unregister_probe(&mut self, id: ProbeId) {
+        self.probes.unregister(id);
+    }
+
+    /// Returns and clears the events emitted since the last call
+    pub fn drain_events(&mut self) -> Vec<Event> {
+        let mut events: Vec<Event> = self
+            .cpu
+            .drain_debug_messages()
+            .into_iter()
+            .map(Event::DebugMessage)
+            .collect();
+        events.extend(self.probes.drain_events().into_iter().map(Event::Probe));
+        if let Some((pc, opcode)) = self.cpu.take_illegal_opcode() {
+            let history = self.cpu.take_illegal_opcode_history();
+            events.push(Event::IllegalOpcode {
+                pc,
+                opcode,
+                history,
+            });
         }
+        if let Some((expected, found)) = self.cpu.bus().take_rejected_save() {
+            events.push(Event::SaveRejected { expected, found });
+        }
+
+        let underruns = self
+            .cpu
+            .bus_mut()
+            .audio_monitor_mut()
+            .take_pending_underruns();
+        if underruns > 0 {
+            events.push(Event::AudioUnderrun { count: underruns });
+        }
+
+        events
+    }
+
+    pub fn perf_stats(&self) -> PerfStats {
+        let audio = self.cpu.bus().audio_monitor();
+        PerfStats {
+            frames: self.frames,
+            audio_buffers: audio.buffers(),
+            audio_underruns: audio.underruns(),
+        }
+    }
+
+    /// Swaps the game rom without resetting the emulator nor the cartridge ram, for fast homebrew
+    /// edit-compile-swap loops. Fails when the new header doesn't use the same mapper and ram size,
+    /// the emulator has to be recreated in that case.
+    pub fn hot_swap_rom(&mut self, rom: Vec<u8>) -> Result<(), HotSwapError> {
+        self.cpu.bus_mut().hot_swap_rom(rom)
+    }
+
+    /// Features declared by the loaded game header, see `CartridgeCapabilities::unsupported`
+    /// to warn about the ones that aren't emulated
+    pub fn cartridge_capabilities(&self) -> CartridgeCapabilities {
+        self.cpu.bus().cartridge_capabilities()
     }
 
+    /// Plugs `device` into the link port, replacing the previous one
+    pub fn attach_link_device<D: LinkDevice + 'static>(&mut self, device: D) {
+        self.cpu.bus_mut().set_link_device(Some(Box::new(device)));
+    }
+
+    /// Unplugs the link port device, returning it
+    pub fn detach_link_device(&mut self) -> Option<Box<dyn LinkDevice>> {
+        self.cpu.bus_mut().set_link_device(None)
+    }
+
+    /// Acts as the remote device clocking the link port, see `Serial::inject_byte`
+    pub fn inject_serial_byte(&mut self, value: u8) {
+        self.cpu.bus_mut().serial_mut().inject_byte(value);
+    }
+
+    /// Injected bytes the game hasn't received yet
+    pub fn pending_serial_bytes(&self) -> usize {
+        self.cpu.bus().serial().pending_injected()
+    }
+
+    pub fn clear_injected_serial_bytes(&mut self) {
+        self.cpu.bus_mut().serial_mut().clear_injected();
+    }
+
+    /// Called with the bytes exchanged by every completed link transfer, e.g. to forward what
+    /// the game sends to a real link cable
+    pub fn set_serial_transfer_callback(&mut self, callback: Option<TransferCallback>) {
+        self.cpu
+            .bus_mut()
+            .serial_mut()
+            .set_transfer_callback(callback);
+    }
+
+    /// Rom cheats apply on every read, ram cheats are written on entering VBlank and freezes on
+    /// every write too
+    pub fn add_cheat(&mut self, cheat: Cheat) -> CheatId {
+        self.cpu.bus_mut().cheats_mut().add(cheat)
+    }
+
+    pub fn remove_cheat(&mut self, id: CheatId) {
+        self.cpu.bus_mut().cheats_mut().remove(id);
+    }
+
+    pub fn set_cheat_enabled(&mut self, id: CheatId, enabled: bool) {
+        self.cpu.bus_mut().cheats_mut().set_enabled(id, enabled);
+    }
+
+    pub fn clear_cheats(&mut self) {
+        self.cpu.bus_mut().cheats_mut().clear();
+    }
+
+    /// Runs `script` after every frame and on writes to its watched addresses, see `scripting`
+    pub fn add_script(&mut self, script: Box<dyn Script>) -> ScriptId {
+        let id = self.scripts.add(script);
+        self.cpu
+            .bus_mut()
+            .set_script_writes(self.scripts.watched_writes());
+        id
+    }
+
+    pub fn remove_script(&mut self, id: ScriptId) -> Option<Box<dyn Script>> {
+        let script = self.scripts.remove(id);
+        self.cpu
+            .bus_mut()
+            .set_script_writes(self.scripts.watched_writes());
+        script
+    }
+
+    pub fn clear_scripts(&mut self) {
+        self.scripts.clear();
+        self.cpu.bus_mut().set_script_writes(vec![]);
+    }
+
+    /// Starts a cheat search over `regions`, e.g. `cheat_search::RAM_REGIONS`
+    pub fn start_cheat_search(&self, regions: &[RangeInclusive<u16>]) -> CheatSearch {
+        let bus = self.cpu.bus();
+        CheatSearch::new(regions, |address| bus.peek(address))
+    }
+
+    /// Keeps the candidates of `search` matching `filter` in the current memory, returns how many
+    /// remain
+    pub fn refine_cheat_search(&self, search: &mut CheatSearch, filter: SearchFilter) -> usize {
+        let bus = self.cpu.bus();
+        search.refine(filter, |address| bus.peek(address))
+    }
+
+    /// Every cheat added, in order, with whether it's enabled
+    pub fn cheats(&self) -> Vec<(CheatId, Cheat, bool)> {
+        self.cpu.bus().cheats().list()
+    }
+
+    /// Adds the patches listed for the loaded rom as cheats, returning how many were applied,
+    /// e.g. "skip intro" patches to speed up development. See `PatchSet` for the format.
+    pub fn apply_patches(&mut self, patches: &PatchSet) -> usize {
+        let crc32 = self.cpu.bus().rom_crc32();
+        let cheats = patches.for_rom(crc32);
+        for &cheat in cheats {
+            self.add_cheat(cheat);
+        }
+        cheats.len()
+    }
+
+    /// Enables or disables bus access counting, see `debug::MemoryHeatmap`
+    pub fn set_memory_heatmap(&mut self, enabled: bool) {
+        self.cpu.bus_mut().set_memory_heatmap(enabled);
+    }
+
+    pub fn memory_heatmap(&self) -> Option<&MemoryHeatmap> {
+        self.cpu.bus().memory_heatmap()
+    }
+
+    /// Enables or disables cycle counting per executed instruction, see `debug::Profiler`.
+    /// Enabling it again starts over.
+    pub fn set_profiler(&mut self, enabled: bool) {
+        self.profiler = enabled.then(Profiler::new);
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// `Profiler::report` of the `count` hottest functions, grouped by the labels of
+    /// `set_symbols`
+    pub fn profile_report(&self, count: usize) -> Option<String> {
+        Some(self.profiler.as_ref()?.report(count, self.symbols.as_ref()))
+    }
+
+    /// Enables or disables cycle counting per 256 bytes of rom, see `debug::HotSpots`.
+    /// Enabling it again starts over.
+    pub fn set_hot_spots(&mut self, enabled: bool) {
+        self.hot_spots = enabled.then(HotSpots::new);
+    }
+
+    pub fn hot_spots(&self) -> Option<&HotSpots> {
+        self.hot_spots.as_ref()
+    }
+
+    /// `HotSpots::report` of the `count` hottest rom ranges
+    pub fn hot_spot_report(&self, count: usize) -> Option<String> {
+        Some(self.hot_spots.as_ref()?.report(count))
+    }
+
+    /// Enables or disables per-bank cartridge access counting, see `debug::BankUsage`
+    pub fn set_bank_usage(&mut self, enabled: bool) {
+        self.cpu.bus_mut().set_bank_usage(enabled);
+    }
+
+    pub fn bank_usage(&self) -> Option<&BankUsage> {
+        self.cpu.bus().bank_usage()
+    }
+
+    /// Enables or disables per rom byte executed/read/written flags, see `debug::RomCoverage`.
+    /// Disabling it drops the collected flags.
+    pub fn set_rom_coverage(&mut self, enabled: bool) {
+        self.cpu.bus_mut().set_rom_coverage(enabled);
+    }
+
+    pub fn rom_coverage(&self) -> Option<&RomCoverage> {
+        self.cpu.bus().rom_coverage()
+    }
+
+    /// Calls `observer` after every peripheral step, replacing the previous one.
+    /// Observing slows emulation down, `clear_cycle_observer` removes it.
+    pub fn set_cycle_observer<O: CycleObserver + 'static>(&mut self, observer: O) {
+        self.cpu
+            .bus_mut()
+            .set_cycle_observer(Some(Box::new(observer)));
+    }
+
+    pub fn clear_cycle_observer(&mut self) {
+        self.cpu.bus_mut().set_cycle_observer(None);
+    }
+
+    /// APU internals for debugging and VU meters, e.g. the noise channel LFSR or the channel levels
+    pub fn apu_snapshot(&self) -> ApuSnapshot {
+        self.cpu.bus().apu_snapshot()
+    }
+
+    /// Collects the state usually behind a blank screen, to turn "black screen" bug reports
+    /// into actionable data
+    pub fn diagnose_blank_screen(&self) -> BlankScreenReport {
+        let bus = self.cpu.bus();
+        let lcdc = bus.peek(0xFF40);
+        let frame = bus.frame_buffer();
+        let first = frame[0][0];
+
+        BlankScreenReport {
+            cgb: matches!(self.mode, Mode::CGB),
+            frame: self.frames,
+            lcd_enabled: lcdc & 0x80 != 0,
+            bg_enabled: lcdc & 0x01 != 0,
+            bgp: bus.peek(0xFF47),
+            interrupt_enable: bus.peek(0xFFFF),
+            ime: self.cpu.ime(),
+            halted: self.cpu.is_halted(),
+            single_color_frame: frame.iter().flatten().all(|&pixel| pixel == first),
+            frames_since_change: self.frame_changes.frames_since_change(self.frames),
+        }
+    }
+
+    /// Last completed frame, also available in headless mode
+    pub fn frame_buffer(&self) -> &Frame {
+        self.cpu.bus().frame_buffer()
+    }
+
+    /// Reads the memory map as the cpu would, without any timing side effect
+    pub fn read_memory(&self, address: u16) -> u8 {
+        self.cpu.bus().peek(address)
+    }
+
+    /// Writes to the memory map as the cpu would, without any timing side effect. Watchpoints,
+    /// freeze cheats and script hooks don't see it.
+    pub fn write_memory(&mut self, address: u16, value: u8) {
+        self.cpu.bus_mut().poke(address, value);
+    }
+
+    /// Holds or releases a button, on top of the inputs coming from the events handler
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.cpu.bus_mut().joypad_mut().update(button, pressed);
+    }
+
+    /// Serializes the whole machine (cpu, memories, mapper, ppu, apu, timer...), see `savestate`
+    /// for the format. Frontend settings, cheats and the link device aren't part of it.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.write(&self.cpu);
+        self.cpu.bus().save_state(&mut w);
+
+        let mut chunk = ChunkWriter::default();
+        chunk.write_u64(self.frames);
+        w.write_chunk(GAMEBOY_TAG, 1, chunk);
+
+        w.finish()
+    }
+
+    /// Restores a state made by `save_state` with the same rom. The machine is left untouched
+    /// on error.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let r = StateReader::new(data)?;
+        r.require(&[cpu::CPU::<SystemBus<L, E, H, S>>::TAG, GAMEBOY_TAG])?;
+
+        // a field not fitting its subsystem is only found once the subsystem is loaded
+        let backup = self.save_state();
+        if let Err(e) = self.read_state(&r) {
+            let backup = StateReader::new(&backup).expect("the backup state is valid");
+            self.read_state(&backup).expect("the backup state loads");
+            return Err(e);
+        }
+
+        self.frame_ready = false;
+        self.boot_animation = None;
+        // the changes seen were made after the state's frame
+        self.frame_changes = FrameChangeTracker::default();
+        if let Some(replay) = &mut self.replay {
+            replay.clear();
+        }
+        Ok(())
+    }
+
+    fn read_state(&mut self, r: &StateReader) -> Result<(), SaveStateError> {
+        self.cpu.bus_mut().load_state(r)?;
+        r.read(&mut self.cpu)?;
+
+        if let Some((_, mut chunk)) = r.chunk(GAMEBOY_TAG) {
+            self.frames = chunk.read_u64();
+        }
+        Ok(())
+    }
+
+    /// Tilt of the console in g, positive to the right and towards the bottom, for cartridges with
+    /// an accelerometer (MBC7). Frontends feed it every frame, e.g. from a gamepad stick.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.cpu.bus_mut().set_tilt(x, y);
+    }
+
+    /// Memory regions returned by `frame_advance`
+    pub fn set_observed_regions(&mut self, regions: Vec<RangeInclusive<u16>>) {
+        self.observer = MemoryObserver::new(regions);
+    }
+
+    /// Gym-like act loop: holds exactly `buttons` for one frame, then snapshots the observed regions.
+    /// Meant to be used with a no-op events handler so inputs only come from here.
+    pub fn frame_advance(&mut self, buttons: &[Button]) -> Observation {
+        self.cpu.bus_mut().joypad_mut().set_pressed(buttons);
+        self.step_frame();
+
+        let bus = self.cpu.bus();
+        self.observer.observe(|address| bus.peek(address))
+    }
+
+    /// Stops `step` and `run` from advancing the emulation, `step_frame` still advances it one
+    /// frame at a time
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        let was_suspended = self.is_suspended();
+        self.paused = false;
+        if was_suspended && !self.is_suspended() {
+            self.resync_after_pause();
+        }
+    }
+
+    /// Paused by `pause` or by the background policy
+    fn is_suspended(&self) -> bool {
+        self.paused || self.background && self.background_policy == BackgroundPolicy::Pause
+    }
+
+    /// The frontend audio queue drained during the pause: pacing starts over and the underruns
+    /// it reported meanwhile are dropped
+    fn resync_after_pause(&mut self) {
+        self.frame_limiter.resync();
+        self.cpu.bus_mut().resync_audio();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Runs exactly one video frame (70224 dots, LCD on or off) and returns, paused or not
     pub fn step_frame(&mut self) {
+        self.frame_ready = false;
         loop {
-            self.step();
-            if self.cpu.is_frame_buffer_ready() {
+            self.advance();
+            if self.frame_ready {
+                self.frame_ready = false;
                 return;
             }
         }
     }
 
+    /// `step_frame` then the frame it completed, nothing has to be drawn by the `LCD`
+    pub fn run_frame(&mut self) -> &Frame {
+        self.step_frame();
+        self.frame_buffer()
+    }
+
+    /// Breakpoints used by `step_over` and `run_until_break`
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// Watches cpu accesses to `range`. A `WatchAction::Break` hit pauses the emulation after
+    /// the instruction doing the access and stops the debugger run loops.
+    pub fn add_watchpoint(
+        &mut self,
+        range: RangeInclusive<u16>,
+        kind: WatchKind,
+        action: WatchAction,
+    ) -> WatchpointId {
+        self.cpu.bus_mut().add_watchpoint(range, kind, action, None)
+    }
+
+    /// Watchpoint only triggering when `condition` holds, e.g. `VALUE == 0 && [HL] > 10`, see
+    /// `expr`. The condition is evaluated once the instruction doing the access completed, with
+    /// `ADDR` and `VALUE` the ones of the access, so callbacks run late by that instruction.
+    pub fn add_conditional_watchpoint(
+        &mut self,
+        range: RangeInclusive<u16>,
+        kind: WatchKind,
+        action: WatchAction,
+        condition: Expr,
+    ) -> WatchpointId {
+        self.cpu
+            .bus_mut()
+            .add_watchpoint(range, kind, action, Some(condition))
+    }
+
+    pub fn remove_watchpoint(&mut self, id: WatchpointId) {
+        self.cpu.bus_mut().remove_watchpoint(id);
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.cpu.bus_mut().clear_watchpoints();
+        self.watch_hit = None;
+    }
+
+    /// (pc, opcode) of the last executed instructions, oldest first, see `Config::pc_history`
+    /// and `debugger::format_history`
+    pub fn pc_history(&self) -> Vec<(u16, u8)> {
+        self.cpu.opcode_history()
+    }
+
+    /// Overrides `Config::pc_history`, clearing the history
+    pub fn set_pc_history_len(&mut self, len: usize) {
+        self.cpu.set_history_len(len);
+    }
+
+    /// Overrides `Config::replay_seconds`, clearing the replay
+    pub fn set_replay_seconds(&mut self, seconds: u32) {
+        self.replay = (seconds > 0).then(|| ReplayBuffer::new(seconds));
+    }
+
+    /// Recent frames, None with the replay off
+    pub fn replay(&self) -> Option<&ReplayBuffer> {
+        self.replay.as_ref()
+    }
+
+    /// Looping GIF of the last `duration` of gameplay, at most `Config::replay_seconds`. None
+    /// with the replay off or before the first frame.
+    #[cfg(feature = "replay-gif")]
+    pub fn export_replay_gif(&self, duration: std::time::Duration) -> Option<Vec<u8>> {
+        let frames = self.replay.as_ref()?.last(duration);
+        (!frames.is_empty()).then(|| crate::replay::encode_gif(&frames))
+    }
+
+    /// Breaking watchpoint hit that paused the emulation, if any since the last call
+    pub fn take_watch_break(&mut self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    /// Executes one instruction, entering subroutines and interrupt handlers. A halted cpu only
+    /// lets 4 cycles pass.
+    pub fn step_into(&mut self) -> StopReason {
+        self.advance();
+        self.watch_hit
+            .take()
+            .map_or(StopReason::Step, StopReason::Watchpoint)
+    }
+
+    /// Executes one instruction, running a `CALL` or `RST` until the subroutine returns. Stops
+    /// early on breakpoints, gives up after `debugger::STEP_OVER_MAX_FRAMES`.
+    pub fn step_over(&mut self) -> StopReason {
+        let pc = self.cpu.pc();
+        let halted = self.cpu.is_halted() || self.cpu.is_stopped();
+        match debugger::return_address(self.read_memory(pc), pc) {
+            Some(return_pc) if !halted => {
+                // recursive calls return to the same address deeper in the stack
+                let sp = self.cpu.sp();
+                self.run_debugged(debugger::STEP_OVER_MAX_FRAMES, |gb| {
+                    gb.cpu.pc() == return_pc && gb.cpu.sp() >= sp
+                })
+            }
+            _ => self.step_into(),
+        }
+    }
+
+    /// Runs until a breakpoint or a breaking watchpoint is reached or `max_frames` frames went by.
+    /// The instruction at the current pc always executes, so a run can resume from a breakpoint.
+    pub fn run_until_break(&mut self, max_frames: u64) -> StopReason {
+        self.run_debugged(max_frames, |_| false)
+    }
+
+    fn run_debugged(&mut self, max_frames: u64, done: impl Fn(&Self) -> bool) -> StopReason {
+        self.frame_ready = false;
+        let mut frames = 0;
+        let mut first = true;
+        loop {
+            // no instruction is about to run while halted
+            let halted = self.cpu.is_halted() || self.cpu.is_stopped();
+            if !first && !halted {
+                let bus = self.cpu.bus();
+                let read = |address| bus.peek(address);
+                let ctx = Context {
+                    registers: self.cpu.registers(),
+                    sp: self.cpu.sp(),
+                    pc: self.cpu.pc(),
+                    read: &read,
+                    access: None,
+                };
+                if let Some(reason) = self.debugger.check(&ctx) {
+                    return reason;
+                }
+            }
+            first = false;
+
+            self.advance();
+            if let Some(hit) = self.watch_hit.take() {
+                return StopReason::Watchpoint(hit);
+            }
+            if done(self) {
+                return StopReason::Step;
+            }
+            if self.frame_ready {
+                self.frame_ready = false;
+                frames += 1;
+                if frames >= max_frames {
+                    return StopReason::FrameLimit;
+                }
+            }
+        }
+    }
+
+    /// Calls `handler` with a report of the emulation state when an internal invariant trips,
+    /// before resuming the panic. Installs `crash::install_panic_hook`.
+    pub fn set_crash_handler<F: FnMut(&CrashReport) + 'static>(&mut self, handler: F) {
+        crash::install_panic_hook();
+        self.crash_handler = Some(Box::new(handler));
+    }
+
+    pub fn clear_crash_handler(&mut self) {
+        self.crash_handler = None;
+    }
+
+    /// Writes a Gameboy Doctor line before every executed instruction to `out`, see `trace`.
+    /// Nothing is written while halted or stopped. None stops tracing and flushes the output.
+    pub fn set_cpu_trace(&mut self, out: Option<Box<dyn Write>>) {
+        self.cpu_trace = out.map(CpuTrace::new);
+    }
+
+    /// Writes a `name:` line before the instructions at a label of the symbols set with
+    /// `set_symbols`. Off by default, Gameboy Doctor can't compare such traces.
+    pub fn set_cpu_trace_labels(&mut self, enabled: bool) {
+        self.trace_labels = enabled;
+    }
+
+    /// Labels shown by `describe_address`, `format_pc_history` and cpu traces, e.g. from an
+    /// RGBDS `.sym` file with `SymbolTable::from_file`
+    pub fn set_symbols(&mut self, symbols: Option<SymbolTable>) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> Option<&SymbolTable> {
+        self.symbols.as_ref()
+    }
+
+    /// Label of `address` in the banks currently mapped, e.g. `Main.loop+3`, or the address in
+    /// hex without symbols
+    pub fn describe_address(&self, address: u16) -> String {
+        self.label_of(address)
+            .unwrap_or_else(|| format!("{address:04X}"))
+    }
+
+    /// `debugger::format_history` of `pc_history`, with labels in the banks currently mapped
+    pub fn format_pc_history(&self) -> String {
+        debugger::format_history_with(&self.pc_history(), |pc| self.label_of(pc))
+    }
+
+    fn label_of(&self, address: u16) -> Option<String> {
+        let bank = self.cpu.bus().cartridge_bank(address) as u16;
+        self.symbols.as_ref()?.describe(bank, address)
+    }
+
+    fn crash_report(&self) -> CrashReport {
+        let (message, location) = crash::take_last_panic();
+        let bus = self.cpu.bus();
+        CrashReport {
+            message,
+            location,
+            frame: self.frames,
+            pc: self.cpu.pc(),
+            sp: self.cpu.sp(),
+            registers: self.cpu.registers().clone(),
+            opcode_history: self.cpu.opcode_history(),
+            io: IO_SUMMARY
+                .iter()
+                .map(|&(name, address)| (name, address, bus.peek(address)))
+                .collect(),
+            build: crate::features().to_string(),
+        }
+    }
+
+    /// Executes one instruction, or only waits for the next frame according to the pacing
+    /// while paused. Paused in the background, it sleeps a frame instead.
     pub fn step(&mut self) {
-        let _cycles = self.cpu.step();
+        if self.paused {
+            let bus = self.cpu.bus();
+            self.frame_limiter.wait(|| bus.audio_queued_samples());
+            return;
+        }
+        if self.is_suspended() {
+            // no need to pace a window nobody looks at, only to keep the host cpu idle
+            pacing::sleep(pacing::FRAME_DURATION);
+            return;
+        }
+        self.advance();
+        if self.watch_hit.is_some() {
+            self.paused = true;
+        }
+    }
+
+    fn advance(&mut self) {
+        if let Some(playback) = &mut self.boot_animation {
+            match playback.next_frame() {
+                Some(frame) => {
+                    let bus = self.cpu.bus_mut();
+                    bus.show_frame(frame);
+                    let bus = self.cpu.bus();
+                    self.frame_limiter.wait(|| bus.audio_queued_samples());
+                    self.frame_ready = true;
+                    return;
+                }
+                None => self.boot_animation = None,
+            }
+        }
+
+        if let Some(trace) = &mut self.cpu_trace {
+            if !self.cpu.is_halted() && !self.cpu.is_stopped() {
+                let bus = self.cpu.bus();
+                let (registers, sp, pc) = (self.cpu.registers(), self.cpu.sp(), self.cpu.pc());
+                let label = match &self.symbols {
+                    Some(symbols) if self.trace_labels => {
+                        symbols.label(bus.cartridge_bank(pc) as u16, pc)
+                    }
+                    _ => None,
+                };
+                let recorded = label
+                    .map_or(Ok(()), |name| trace.record_label(name))
+                    .and_then(|_| trace.record(registers, sp, pc, |address| bus.peek(address)));
+                if let Err(e) = recorded {
+                    warn!("Cpu trace stopped: {e}");
+                    self.cpu_trace = None;
+                }
+            }
+        }
+
+        if !self.cpu.is_halted() && !self.cpu.is_stopped() {
+            self.cpu.bus().record_execution(self.cpu.pc());
+        }
+        let profiled = (self.profiler.is_some() || self.hot_spots.is_some()).then(|| {
+            let pc = self.cpu.pc();
+            let halted = self.cpu.is_halted() || self.cpu.is_stopped();
+            (halted, self.cpu.bus().cartridge_bank(pc) as u16, pc)
+        });
+        let cycles = if self.crash_handler.is_some() {
+            match panic::catch_unwind(AssertUnwindSafe(|| self.cpu.step())) {
+                Ok(cycles) => cycles,
+                Err(payload) => {
+                    let report = self.crash_report();
+                    if let Some(handler) = &mut self.crash_handler {
+                        handler(&report);
+                    }
+                    panic::resume_unwind(payload);
+                }
+            }
+        } else {
+            self.cpu.step()
+        };
+        if let Some((halted, bank, pc)) = profiled {
+            if let Some(profiler) = &mut self.profiler {
+                match halted {
+                    true => profiler.record_halted(cycles),
+                    false => profiler.record(bank, pc, cycles),
+                }
+            }
+            if let Some(hot_spots) = &mut self.hot_spots {
+                let rom_offset = (!halted && pc <= 0x7FFF)
+                    .then(|| bank as usize * 0x4000 + (pc & 0x3FFF) as usize);
+                hot_spots.record(rom_offset, cycles);
+            }
+        }
+        let (registers, sp, pc) = (self.cpu.registers(), self.cpu.sp(), self.cpu.pc());
+        self.cpu.bus().resolve_watch_conditions(registers, sp, pc);
+        if let Some(hit) = self.cpu.bus_mut().take_watch_hit() {
+            self.watch_hit.get_or_insert(hit);
+        }
+        if !self.scripts.is_empty() {
+            let writes = self.cpu.bus_mut().take_hooked_writes();
+            if !writes.is_empty() {
+                self.run_scripts(|scripts, api| scripts.on_writes(&writes, api));
+            }
+        }
+
+        if self.cpu.is_frame_buffer_ready() {
+            let bus = self.cpu.bus();
+            self.frame_limiter.wait(|| bus.audio_queued_samples());
+            self.probes.evaluate(|address| bus.peek(address));
+            self.frames += 1;
+            self.frame_changes.update(self.frames, bus.frame_buffer());
+            if let Some(replay) = &mut self.replay {
+                replay.record(bus.frame_buffer());
+            }
+            if let Some(log) = &mut self.input_log {
+                log.record(&bus.joypad().pressed_buttons());
+            }
+            self.frame_ready = true;
+            if !self.scripts.is_empty() {
+                self.run_scripts(Scripts::on_frame);
+            }
+        }
+    }
+
+    /// Applies the writes of the scripts, without reporting them to the write callbacks
+    fn run_scripts(&mut self, callback: impl FnOnce(&mut Scripts, &mut ScriptApi)) {
+        let writes = {
+            let bus = self.cpu.bus();
+            let read = |address| bus.peek(address);
+            let (registers, sp, pc) = (self.cpu.registers(), self.cpu.sp(), self.cpu.pc());
+            let mut api = ScriptApi::new(self.frames, registers, sp, pc, &read);
+            callback(&mut self.scripts, &mut api);
+            api.into_writes()
+        };
+        let bus = self.cpu.bus_mut();
+        for (address, value) in writes {
+            bus.write_byte(address, value);
+        }
+        bus.take_hooked_writes();
     }
 
     pub fn run(&mut self) {
@@ -77,3 +1140,23 @@ impl<'a, L: LCD, E: Send + 'static, H: EventsHandler<E>, S: StereoPlayer> GameBo
         }
     }
 }
+
+/// Core without screen, speakers, inputs or saves for analysis tools and bots, driven with
+/// `run_frame` or `frame_advance`
+pub type HeadlessGameBoy<'a> =
+    GameBoy<'a, lcd::Fake, (), joypad_events_handler::Fake, stereo::Fake>;
+
+impl<'a> HeadlessGameBoy<'a> {
+    /// Panics on a bad rom header with `Config::strict_header`. Nothing needs to be sent to
+    /// `event_rx`, inputs come from `set_button` or `frame_advance`.
+    pub fn headless(cfg: &Config, event_rx: &'a Receiver<()>) -> Self {
+        Self::new(
+            cfg,
+            lcd::Fake,
+            stereo::Fake,
+            joypad_events_handler::Fake,
+            saver::Fake,
+            event_rx,
+        )
+    }
+}
@@ -0,0 +1,139 @@
+//! Input display for speedruns and video recording: an on-screen joypad overlay and a per-frame
+//! input log.
+
+use std::io::{self, Write};
+
+use crate::{
+    joypad::Button,
+    lcd::{FrameBuffer, RGB, RGB_BLACK, RGB_LIGHT_GRAY, RGB_WHITE},
+};
+
+/// Side of a button cell, 1 pixel of border included
+const CELL_SIZE: usize = 4;
+const OVERLAY_MARGIN: usize = 2;
+
+/// Overlay cell (column, row) of each button, d-pad on the left then select, start, B and A
+fn cell(button: Button) -> (usize, usize) {
+    match button {
+        Button::Up => (1, 0),
+        Button::Left => (0, 1),
+        Button::Right => (2, 1),
+        Button::Down => (1, 2),
+        Button::Select => (4, 2),
+        Button::Start => (6, 2),
+        Button::B => (8, 1),
+        Button::A => (10, 0),
+    }
+}
+
+/// Draws the joypad state in the bottom left corner, pressed buttons are filled black
+pub fn draw_input_overlay(frame: &mut FrameBuffer, pressed: &[Button]) {
+    let height = frame.len();
+    for button in Button::ALL {
+        let fill: RGB = match pressed.contains(&button) {
+            true => RGB_BLACK,
+            false => RGB_LIGHT_GRAY,
+        };
+
+        let (col, row) = cell(button);
+        let left = OVERLAY_MARGIN + col * CELL_SIZE;
+        let top = height.saturating_sub(OVERLAY_MARGIN + 3 * CELL_SIZE) + row * CELL_SIZE;
+        let last = CELL_SIZE - 1;
+        for (y, line) in frame.iter_mut().skip(top).take(CELL_SIZE).enumerate() {
+            for (x, pixel) in line.iter_mut().skip(left).take(CELL_SIZE).enumerate() {
+                let border = y == 0 || x == 0 || y == last || x == last;
+                *pixel = if border { RGB_WHITE } else { fill };
+            }
+        }
+    }
+}
+
+/// Buttons held during each recorded frame
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputLog {
+    /// one bit per button, indexed by `Button as usize`
+    frames: Vec<u8>,
+}
+
+impl InputLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, pressed: &[Button]) {
+        let mask = pressed.iter().fold(0, |mask, &b| mask | 1 << b as usize);
+        self.frames.push(mask);
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn buttons(&self, frame: usize) -> Vec<Button> {
+        let mask = self.frames.get(frame).copied().unwrap_or(0);
+        Button::ALL
+            .into_iter()
+            .filter(|&b| mask & 1 << b as usize != 0)
+            .collect()
+    }
+
+    /// One line per frame with a 0/1 column per button
+    pub fn write_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "frame,a,b,select,start,right,left,up,down")?;
+        for (frame, mask) in self.frames.iter().enumerate() {
+            write!(w, "{frame}")?;
+            for button in Button::ALL {
+                write!(w, ",{}", mask >> button as usize & 1)?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = vec![];
+        self.write_csv(&mut csv)
+            .expect("writing to a vec can't fail");
+        String::from_utf8(csv).expect("csv is ascii")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lcd::{PIXELS_HEIGHT, PIXELS_WIDTH};
+
+    use super::*;
+
+    #[test]
+    fn test_draw_input_overlay() {
+        let mut frame = vec![vec![RGB_WHITE; PIXELS_WIDTH]; PIXELS_HEIGHT];
+        draw_input_overlay(&mut frame, &[Button::A]);
+
+        let top = PIXELS_HEIGHT - OVERLAY_MARGIN - 3 * CELL_SIZE;
+        let a = (OVERLAY_MARGIN + 10 * CELL_SIZE + 1, top + 1);
+        let up = (OVERLAY_MARGIN + CELL_SIZE + 1, top + 1);
+        assert_eq!(RGB_BLACK, frame[a.1][a.0]);
+        assert_eq!(RGB_LIGHT_GRAY, frame[up.1][up.0]);
+        assert_eq!(RGB_WHITE, frame[0][0]);
+    }
+
+    #[test]
+    fn test_input_log_csv() {
+        let mut log = InputLog::new();
+        log.record(&[]);
+        log.record(&[Button::Start, Button::Up]);
+
+        assert_eq!(2, log.len());
+        assert_eq!(vec![Button::Start, Button::Up], log.buttons(1));
+        assert_eq!(
+            "frame,a,b,select,start,right,left,up,down\n\
+             0,0,0,0,0,0,0,0,0\n\
+             1,0,0,0,1,0,0,1,0\n",
+            log.to_csv()
+        );
+    }
+}
@@ -0,0 +1,284 @@
+//! Control of several emulators from a single frontend process, e.g. link-cable pairs side by
+//! side. Each `GameBoy` stays on its own thread and polls its `Instance` between frames, the
+//! frontend sends `Command`s to any of them by id through a thread-safe `Registry`.
+//! Native targets only, wasm32 has no threads.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Mutex, OnceLock,
+    },
+};
+
+use crate::{
+    gameboy::GameBoy, joypad::Button, joypad_events_handler::EventsHandler, lcd::LCD,
+    savestate::SaveStateError, stereo::StereoPlayer,
+};
+
+pub type InstanceId = usize;
+
+#[derive(Clone, Debug)]
+pub enum Command {
+    Pause,
+    Resume,
+    /// Holds or releases a button, on top of the inputs coming from the events handler
+    Button(Button, bool),
+    /// Asks for a savestate, sent back through the channel
+    SaveState(Sender<Vec<u8>>),
+    /// Loads a savestate, the outcome is sent back through the channel
+    LoadState(Vec<u8>, Sender<Result<(), SaveStateError>>),
+    /// Ends the emulation loop, see `Instance::is_stopped`
+    Stop,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ControlError {
+    /// Never registered, or its `Instance` was dropped
+    UnknownInstance(InstanceId),
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownInstance(id) => write!(f, "unknown instance {id}"),
+        }
+    }
+}
+
+impl std::error::Error for ControlError {}
+
+#[derive(Default)]
+struct Entries {
+    senders: HashMap<InstanceId, Sender<Command>>,
+    next_id: InstanceId,
+}
+
+/// Instances keyed by id, shareable between threads
+#[derive(Default)]
+pub struct Registry {
+    entries: Mutex<Entries>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Process-wide registry, for frontends that don't want to pass one around
+    pub fn global() -> &'static Registry {
+        static GLOBAL: OnceLock<Registry> = OnceLock::new();
+        GLOBAL.get_or_init(Registry::new)
+    }
+
+    /// Registers a new instance, to be moved to the emulation thread
+    pub fn register(&self) -> Instance {
+        let (tx, rx) = mpsc::channel();
+        let mut entries = self.entries.lock().unwrap();
+        let id = entries.next_id;
+        entries.next_id += 1;
+        entries.senders.insert(id, tx);
+
+        Instance {
+            id,
+            rx,
+            paused: false,
+            stopped: false,
+        }
+    }
+
+    pub fn unregister(&self, id: InstanceId) {
+        self.entries.lock().unwrap().senders.remove(&id);
+    }
+
+    /// Registered instances, in registration order
+    pub fn ids(&self) -> Vec<InstanceId> {
+        let mut ids: Vec<InstanceId> = self
+            .entries
+            .lock()
+            .unwrap()
+            .senders
+            .keys()
+            .copied()
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Queues `command`, applied on the next `Instance::poll`.
+    /// Instances whose `Instance` was dropped are unregistered.
+    pub fn send(&self, id: InstanceId, command: Command) -> Result<(), ControlError> {
+        let mut entries = self.entries.lock().unwrap();
+        let sender = entries
+            .senders
+            .get(&id)
+            .ok_or(ControlError::UnknownInstance(id))?;
+        if sender.send(command).is_err() {
+            entries.senders.remove(&id);
+            return Err(ControlError::UnknownInstance(id));
+        }
+        Ok(())
+    }
+
+    /// Sends `command` to every instance, e.g. to pause them all
+    pub fn broadcast(&self, command: Command) {
+        self.entries
+            .lock()
+            .unwrap()
+            .senders
+            .retain(|_, sender| sender.send(command.clone()).is_ok());
+    }
+}
+
+/// Emulation thread side of a registered instance
+pub struct Instance {
+    id: InstanceId,
+    rx: Receiver<Command>,
+    paused: bool,
+    stopped: bool,
+}
+
+impl Instance {
+    pub fn id(&self) -> InstanceId {
+        self.id
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Applies the pending commands to `gb`. Replies to savestate commands whose receiver
+    /// is gone are dropped.
+    pub fn poll<L: LCD, E: Send + 'static, H: EventsHandler<E>, S: StereoPlayer>(
+        &mut self,
+        gb: &mut GameBoy<L, E, H, S>,
+    ) {
+        for command in self.rx.try_iter() {
+            match command {
+                Command::Pause => self.paused = true,
+                Command::Resume => self.paused = false,
+                Command::Button(button, pressed) => gb.set_button(button, pressed),
+                Command::SaveState(reply) => _ = reply.send(gb.save_state()),
+                Command::LoadState(state, reply) => _ = reply.send(gb.load_state(&state)),
+                Command::Stop => self.stopped = true,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{mpsc, Arc},
+        thread,
+    };
+
+    use crate::{
+        config::Config, joypad_events_handler, lcd, pacing::Pacing, saver, stereo, testing,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_registry_controls_instances_on_their_threads() {
+        let registry = Arc::new(Registry::new());
+        let (report_tx, report_rx) = mpsc::channel();
+
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let instance = registry.register();
+                let report_tx = report_tx.clone();
+                thread::spawn(move || {
+                    let mut instance = instance;
+                    let cfg = Config {
+                        rom: testing::idle_rom(),
+                        headless_mode: true,
+                        pacing: Pacing::Uncapped,
+                        ..Default::default()
+                    };
+                    let (_, rx) = mpsc::channel::<()>();
+                    let mut gb = GameBoy::new(
+                        &cfg,
+                        lcd::Fake,
+                        stereo::Fake,
+                        joypad_events_handler::Fake,
+                        saver::Fake,
+                        &rx,
+                    );
+
+                    while !instance.is_stopped() {
+                        instance.poll(&mut gb);
+                        if !instance.is_paused() {
+                            gb.step_frame();
+                        }
+                        thread::yield_now();
+                    }
+                    report_tx
+                        .send((instance.id(), gb.joypad().pressed_buttons()))
+                        .unwrap();
+                })
+            })
+            .collect();
+
+        assert_eq!(vec![0, 1], registry.ids());
+
+        registry.send(1, Command::Pause).unwrap();
+        registry.send(0, Command::Button(Button::A, true)).unwrap();
+        registry
+            .send(1, Command::Button(Button::Start, true))
+            .unwrap();
+        registry
+            .send(1, Command::Button(Button::Start, false))
+            .unwrap();
+
+        let (reply_tx, reply_rx) = mpsc::channel();
+        registry.send(1, Command::SaveState(reply_tx)).unwrap();
+        let state = reply_rx.recv().unwrap();
+        let (reply_tx, reply_rx) = mpsc::channel();
+        registry
+            .send(0, Command::LoadState(state, reply_tx))
+            .unwrap();
+        assert_eq!(Ok(()), reply_rx.recv().unwrap());
+        let (reply_tx, reply_rx) = mpsc::channel();
+        registry
+            .send(0, Command::LoadState(vec![], reply_tx))
+            .unwrap();
+        assert_eq!(Err(SaveStateError::InvalidMagic), reply_rx.recv().unwrap());
+
+        registry.broadcast(Command::Stop);
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        let mut reports: Vec<_> = report_rx.try_iter().collect();
+        reports.sort_by_key(|r| r.0);
+        assert_eq!(vec![Button::A], reports[0].1);
+        assert!(reports[1].1.is_empty());
+
+        // dropped instances get unregistered
+        assert_eq!(
+            Err(ControlError::UnknownInstance(0)),
+            registry.send(0, Command::Resume)
+        );
+        assert_eq!(vec![1], registry.ids());
+        registry.broadcast(Command::Resume);
+        assert!(registry.ids().is_empty());
+    }
+
+    #[test]
+    fn test_unknown_instance() {
+        let registry = Registry::new();
+        let instance = registry.register();
+        registry.unregister(instance.id());
+
+        assert_eq!(
+            Err(ControlError::UnknownInstance(instance.id())),
+            registry.send(instance.id(), Command::Pause)
+        );
+    }
+}
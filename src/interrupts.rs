@@ -1,4 +1,7 @@
-use crate::memory::MemReadWriter;
+use crate::{
+    memory::MemReadWriter,
+    savestate::{ChunkReader, ChunkWriter, Snapshot},
+};
 
 pub const VBLANK_ISR: u16 = 0x40;
 pub const STAT_LCD_ISR: u16 = 0x48;
@@ -62,7 +65,6 @@ impl InterruptRegisters {
         self.flags[TIMER_BIT] = true;
     }
 
-    #[allow(dead_code)]
     pub fn request_serial(&mut self) {
         self.flags[SERIAL_BIT] = true;
     }
@@ -101,3 +103,18 @@ impl MemReadWriter for InterruptRegisters {
         }
     }
 }
+
+impl Snapshot for InterruptRegisters {
+    const TAG: [u8; 4] = *b"INTR";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, w: &mut ChunkWriter) {
+        w.write_u8(self.enables.read());
+        w.write_u8(self.flags.read());
+    }
+
+    fn load_state(&mut self, r: &mut ChunkReader, _version: u16) {
+        self.enables.write(r.read_u8());
+        self.flags.write(r.read_u8());
+    }
+}
@@ -1,6 +1,10 @@
-use crate::{interrupts::InterruptRegisters, memory::MemReadWriter};
+use crate::{
+    interrupts::InterruptRegisters,
+    memory::MemReadWriter,
+    savestate::{ChunkReader, ChunkWriter, Snapshot},
+};
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Button {
     A,
     B,
@@ -29,6 +33,56 @@ impl From<u8> for Button {
     }
 }
 
+impl std::str::FromStr for Button {
+    type Err = String;
+
+    /// Parses a lowercase button name, e.g. "start" or "up"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "a" => Ok(Button::A),
+            "b" => Ok(Button::B),
+            "select" => Ok(Button::Select),
+            "start" => Ok(Button::Start),
+            "right" => Ok(Button::Right),
+            "left" => Ok(Button::Left),
+            "up" => Ok(Button::Up),
+            "down" => Ok(Button::Down),
+            _ => Err(format!("unknown button: {s}")),
+        }
+    }
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+    ];
+}
+
+/// How opposite directions (left + right, up + down) held at the same time are reported to the game.
+/// They can't be pressed together on a real d-pad and some games glitch when they are.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum SimultaneousPressPolicy {
+    /// Both directions are reported as pressed (useful for TAS)
+    #[default]
+    Allow,
+    /// Only the most recently pressed direction is reported
+    LastPressed,
+    /// Neither direction is reported
+    Cancel,
+}
+
+const DPAD_RIGHT: usize = 0;
+const DPAD_LEFT: usize = 1;
+const DPAD_UP: usize = 2;
+const DPAD_DOWN: usize = 3;
+
 #[derive(Clone, Debug)]
 struct PadState {
     buttons: [bool; 4],
@@ -63,8 +117,15 @@ impl State for [bool; 4] {
 pub struct Joypad {
     select_buttons: bool,
     select_dpad: bool,
-    prev_state: PadState,
+    /// P10-P13 as of the last `check`, the interrupt fires when one of them goes low
+    prev_lines: u8,
+    /// state seen by the game, after applying the simultaneous press policy
     state: PadState,
+    /// raw state reported by the frontend
+    held: PadState,
+    /// most recently pressed direction of each axis (horizontal, vertical)
+    latest_directions: [usize; 2],
+    policy: SimultaneousPressPolicy,
 }
 
 impl Joypad {
@@ -72,27 +133,104 @@ impl Joypad {
         Self {
             select_buttons: false,
             select_dpad: false,
-            prev_state: PadState::new(),
+            prev_lines: 0x0F,
             state: PadState::new(),
+            held: PadState::new(),
+            latest_directions: [DPAD_RIGHT, DPAD_UP],
+            policy: SimultaneousPressPolicy::Allow,
         }
     }
 
+    pub fn set_simultaneous_press_policy(&mut self, policy: SimultaneousPressPolicy) {
+        self.policy = policy;
+        self.apply_policy();
+    }
+
     pub fn update(&mut self, button: Button, pressed: bool) {
         match button as usize {
-            bit @ 0..=3 => self.state.buttons[bit] = pressed,
-            bit @ 4..=7 => self.state.dpad[bit % 4] = pressed,
+            bit @ 0..=3 => self.held.buttons[bit] = pressed,
+            bit @ 4..=7 => {
+                let dir = bit % 4;
+                self.held.dpad[dir] = pressed;
+                if pressed {
+                    self.latest_directions[dir / 2] = dir;
+                }
+            }
             _ => unreachable!(),
         }
+        self.apply_policy();
     }
 
-    fn read(&self) -> u8 {
+    /// Holds exactly the given buttons, releasing every other one
+    pub fn set_pressed(&mut self, buttons: &[Button]) {
+        for button in Button::ALL {
+            self.update(button, buttons.contains(&button));
+        }
+    }
+
+    /// Returns whether the button is pressed, as seen by the game
+    pub fn is_pressed(&self, button: Button) -> bool {
+        match button as usize {
+            bit @ 0..=3 => self.state.buttons[bit],
+            bit @ 4..=7 => self.state.dpad[bit % 4],
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the set of buttons pressed, as seen by the game
+    pub fn pressed_buttons(&self) -> Vec<Button> {
+        Button::ALL
+            .into_iter()
+            .filter(|&b| self.is_pressed(b))
+            .collect()
+    }
+
+    fn apply_policy(&mut self) {
+        self.state = self.held.clone();
+
+        for (axis, (first, second)) in [(DPAD_RIGHT, DPAD_LEFT), (DPAD_UP, DPAD_DOWN)]
+            .into_iter()
+            .enumerate()
+        {
+            if !(self.held.dpad[first] && self.held.dpad[second]) {
+                continue;
+            }
+            match self.policy {
+                SimultaneousPressPolicy::Allow => {}
+                SimultaneousPressPolicy::LastPressed => {
+                    let latest = self.latest_directions[axis];
+                    self.state.dpad[first] = latest == first;
+                    self.state.dpad[second] = latest == second;
+                }
+                SimultaneousPressPolicy::Cancel => {
+                    self.state.dpad[first] = false;
+                    self.state.dpad[second] = false;
+                }
+            }
+        }
+    }
+
+    /// P10-P13 input lines, low when a button of a selected row is pressed. Both rows selected
+    /// share the lines so they read the AND of both, none selected reads 0xF.
+    fn lines(&self) -> u8 {
+        let mut lines = 0x0F;
         if self.select_buttons {
-            self.state.buttons.read()
-        } else if self.select_dpad {
-            self.state.dpad.read()
-        } else {
-            0xFF
+            lines &= self.state.buttons.read();
+        }
+        if self.select_dpad {
+            lines &= self.state.dpad.read();
         }
+        lines
+    }
+
+    /// Whether a pressed button pulls an input line low, what wakes the cpu up from STOP
+    pub fn is_line_low(&self) -> bool {
+        self.lines() != 0x0F
+    }
+
+    fn read(&self) -> u8 {
+        // bits 6-7 are unused, bits 4-5 read back the select lines
+        0xC0 | (!self.select_buttons as u8) << 5 | (!self.select_dpad as u8) << 4 | self.lines()
     }
 
     fn write(&mut self, value: u8) {
@@ -100,24 +238,14 @@ impl Joypad {
         self.select_buttons = value & 0x20 == 0;
     }
 
+    /// Requests the joypad interrupt on a high to low transition of any input line, from a
+    /// button press or from selecting a row with a button already pressed
     pub fn check(&mut self, int_reg: &mut InterruptRegisters) {
-        if !self.select_buttons && !self.select_dpad {
-            return;
+        let lines = self.lines();
+        if self.prev_lines & !lines != 0 {
+            int_reg.request_joypad();
         }
-
-        for bit in 0..self.state.buttons.len() {
-            if self.select_buttons {
-                if !self.prev_state.buttons[bit] && self.state.buttons[bit] {
-                    int_reg.request_joypad();
-                    break;
-                }
-            } else if !self.prev_state.dpad[bit] && self.state.dpad[bit] {
-                int_reg.request_joypad();
-                break;
-            }
-        }
-
-        self.prev_state = self.state.clone();
+        self.prev_lines = lines;
     }
 }
 
@@ -135,3 +263,140 @@ impl MemReadWriter for Joypad {
         }
     }
 }
+
+/// Only the selected lines are stored, the buttons stay the ones held by the frontend
+impl Snapshot for Joypad {
+    const TAG: [u8; 4] = *b"JOYP";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, w: &mut ChunkWriter) {
+        w.write_bool(self.select_buttons);
+        w.write_bool(self.select_dpad);
+    }
+
+    fn load_state(&mut self, r: &mut ChunkReader, _version: u16) {
+        self.select_buttons = r.read_bool();
+        self.select_dpad = r.read_bool();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pressed_buttons_readback() {
+        let mut joypad = Joypad::new();
+
+        joypad.update(Button::A, true);
+        joypad.update(Button::Up, true);
+        joypad.update(Button::Start, true);
+        joypad.update(Button::Start, false);
+
+        assert_eq!(vec![Button::A, Button::Up], joypad.pressed_buttons());
+        assert!(joypad.is_pressed(Button::A));
+        assert!(!joypad.is_pressed(Button::Start));
+    }
+
+    #[test]
+    fn test_simultaneous_press_allow() {
+        let mut joypad = Joypad::new();
+
+        joypad.update(Button::Left, true);
+        joypad.update(Button::Right, true);
+
+        assert_eq!(vec![Button::Right, Button::Left], joypad.pressed_buttons());
+
+        joypad.write_byte(0xFF00, 0x20); // select dpad
+        assert_eq!(0xEC, joypad.read_byte(0xFF00));
+    }
+
+    #[test]
+    fn test_select_lines_matrix() {
+        let mut joypad = Joypad::new();
+        joypad.update(Button::A, true);
+        joypad.update(Button::Left, true);
+
+        joypad.write_byte(0xFF00, 0x30); // nothing selected
+        assert_eq!(0xFF, joypad.read_byte(0xFF00));
+        assert!(!joypad.is_line_low());
+
+        joypad.write_byte(0xFF00, 0x10); // select buttons
+        assert_eq!(0xDE, joypad.read_byte(0xFF00));
+
+        joypad.write_byte(0xFF00, 0x20); // select dpad
+        assert_eq!(0xED, joypad.read_byte(0xFF00));
+
+        joypad.write_byte(0xFF00, 0x00); // both rows share the lines
+        assert_eq!(0xCC, joypad.read_byte(0xFF00));
+        assert!(joypad.is_line_low());
+
+        // unused bits always read 1, writes only touch the select bits
+        joypad.write_byte(0xFF00, 0x0F);
+        assert_eq!(0xCC, joypad.read_byte(0xFF00));
+    }
+
+    #[test]
+    fn test_interrupt_on_line_going_low() {
+        let mut joypad = Joypad::new();
+        let mut int_reg = InterruptRegisters::new();
+        let joypad_requested = |int_reg: &mut InterruptRegisters| {
+            let requested = int_reg.read_byte(0xFF0F) & 0x10 != 0;
+            int_reg.write_byte(0xFF0F, 0);
+            requested
+        };
+
+        // pressed while its row isn't selected, the lines stay high
+        joypad.write_byte(0xFF00, 0x20);
+        joypad.update(Button::Start, true);
+        joypad.check(&mut int_reg);
+        assert!(!joypad_requested(&mut int_reg));
+
+        // selecting the row pulls the line low
+        joypad.write_byte(0xFF00, 0x10);
+        joypad.check(&mut int_reg);
+        assert!(joypad_requested(&mut int_reg));
+
+        // another button on the line already low doesn't fire again
+        joypad.write_byte(0xFF00, 0x00);
+        joypad.update(Button::Down, true);
+        joypad.check(&mut int_reg);
+        assert!(!joypad_requested(&mut int_reg));
+
+        joypad.update(Button::A, true);
+        joypad.check(&mut int_reg);
+        assert!(joypad_requested(&mut int_reg));
+    }
+
+    #[test]
+    fn test_simultaneous_press_last_pressed() {
+        let mut joypad = Joypad::new();
+        joypad.set_simultaneous_press_policy(SimultaneousPressPolicy::LastPressed);
+
+        joypad.update(Button::Up, true);
+        joypad.update(Button::Down, true);
+        assert_eq!(vec![Button::Down], joypad.pressed_buttons());
+
+        joypad.update(Button::Up, false);
+        joypad.update(Button::Up, true);
+        assert_eq!(vec![Button::Up], joypad.pressed_buttons());
+
+        // releasing the latest direction gives back the one still held
+        joypad.update(Button::Up, false);
+        assert_eq!(vec![Button::Down], joypad.pressed_buttons());
+    }
+
+    #[test]
+    fn test_simultaneous_press_cancel() {
+        let mut joypad = Joypad::new();
+        joypad.set_simultaneous_press_policy(SimultaneousPressPolicy::Cancel);
+
+        joypad.update(Button::Left, true);
+        joypad.update(Button::Right, true);
+        joypad.update(Button::Up, true);
+        assert_eq!(vec![Button::Up], joypad.pressed_buttons());
+
+        joypad.update(Button::Right, false);
+        assert_eq!(vec![Button::Left, Button::Up], joypad.pressed_buttons());
+    }
+}
@@ -1,6 +1,6 @@
 use std::sync::mpsc;
 
-use crate::joypad::Joypad;
+use crate::joypad::{Button, Joypad};
 
 pub trait EventsHandler<T> {
     fn handle_events(&mut self, _rx: &mpsc::Receiver<T>, _joypad: &mut Joypad) {}
@@ -9,3 +9,136 @@ pub trait EventsHandler<T> {
 pub struct Fake;
 
 impl EventsHandler<()> for Fake {}
+
+/// Analog axis of a gamepad stick, negative values point left / up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+impl Axis {
+    fn directions(&self) -> (Button, Button) {
+        match self {
+            Self::Horizontal => (Button::Left, Button::Right),
+            Self::Vertical => (Button::Up, Button::Down),
+        }
+    }
+}
+
+/// Input pre-processing layer for frontends forwarding raw OS events.
+/// Collapses key auto-repeat into clean press/release transitions and debounces analog axes
+/// with a hysteresis, so the joypad only sees real transitions (and no spurious interrupts).
+#[derive(Clone, Debug)]
+pub struct InputFilter {
+    pressed: [bool; 8],
+    axes: [Option<Button>; 2],
+    press_threshold: f32,
+    release_threshold: f32,
+}
+
+impl Default for InputFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputFilter {
+    pub fn new() -> Self {
+        Self::with_thresholds(0.5, 0.3)
+    }
+
+    /// An axis direction is pressed past `press_threshold` and released under `release_threshold`
+    pub fn with_thresholds(press_threshold: f32, release_threshold: f32) -> Self {
+        Self {
+            pressed: [false; 8],
+            axes: [None; 2],
+            press_threshold,
+            release_threshold: release_threshold.min(press_threshold),
+        }
+    }
+
+    /// Forwards a digital button event, returns false when it was dropped as a repeat
+    pub fn button(&mut self, joypad: &mut Joypad, button: Button, pressed: bool) -> bool {
+        let held = &mut self.pressed[button as usize];
+        if *held == pressed {
+            return false;
+        }
+
+        *held = pressed;
+        joypad.update(button, pressed);
+        true
+    }
+
+    /// Forwards an analog axis position in [-1.0, 1.0] as d-pad transitions
+    pub fn axis(&mut self, joypad: &mut Joypad, axis: Axis, value: f32) {
+        let (negative, positive) = axis.directions();
+        let current = self.axes[axis as usize];
+
+        let target = if value <= -self.press_threshold {
+            Some(negative)
+        } else if value >= self.press_threshold {
+            Some(positive)
+        } else if value.abs() < self.release_threshold {
+            None
+        } else {
+            current
+        };
+
+        if target == current {
+            return;
+        }
+
+        if let Some(button) = current {
+            self.button(joypad, button, false);
+        }
+        if let Some(button) = target {
+            self.button(joypad, button, true);
+        }
+        self.axes[axis as usize] = target;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_filter_collapses_repeats() {
+        let mut joypad = Joypad::new();
+        let mut filter = InputFilter::new();
+
+        assert!(filter.button(&mut joypad, Button::A, true));
+        assert!(!filter.button(&mut joypad, Button::A, true));
+        assert!(!filter.button(&mut joypad, Button::A, true));
+        assert!(joypad.is_pressed(Button::A));
+
+        assert!(filter.button(&mut joypad, Button::A, false));
+        assert!(!filter.button(&mut joypad, Button::A, false));
+        assert!(!joypad.is_pressed(Button::A));
+    }
+
+    #[test]
+    fn test_input_filter_axis_hysteresis() {
+        let mut joypad = Joypad::new();
+        let mut filter = InputFilter::new();
+
+        filter.axis(&mut joypad, Axis::Horizontal, 0.45);
+        assert!(!joypad.is_pressed(Button::Right));
+
+        filter.axis(&mut joypad, Axis::Horizontal, 0.6);
+        assert!(joypad.is_pressed(Button::Right));
+
+        // noise between both thresholds keeps the direction held
+        filter.axis(&mut joypad, Axis::Horizontal, 0.35);
+        assert!(joypad.is_pressed(Button::Right));
+
+        filter.axis(&mut joypad, Axis::Horizontal, -0.9);
+        assert!(!joypad.is_pressed(Button::Right));
+        assert!(joypad.is_pressed(Button::Left));
+
+        filter.axis(&mut joypad, Axis::Horizontal, 0.1);
+        assert!(!joypad.is_pressed(Button::Left));
+        assert!(joypad.pressed_buttons().is_empty());
+    }
+}
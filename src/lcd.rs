@@ -1,3 +1,8 @@
+use std::{ops::Deref, time::Duration};
+
+use crate::pacing;
+
+/// Pixels rows, top to bottom
 pub type FrameBuffer = Vec<Vec<RGB>>;
 
 pub type RGB = (u8, u8, u8);
@@ -10,6 +15,193 @@ pub const RGB_LIGHT_GRAY: RGB = (170, 170, 170);
 pub const RGB_DARK_GRAY: RGB = (85, 85, 85);
 pub const RGB_BLACK: RGB = (0, 0, 0);
 
+/// Colors of the 4 DMG shades, lightest first. CGB games bring their own colors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DmgPalette(pub [RGB; 4]);
+
+impl DmgPalette {
+    pub const GRAYS: Self = Self([RGB_WHITE, RGB_LIGHT_GRAY, RGB_DARK_GRAY, RGB_BLACK]);
+    /// Green tint of the original DMG screen
+    pub const GREEN: Self = Self([(155, 188, 15), (139, 172, 15), (48, 98, 48), (15, 56, 15)]);
+}
+
+impl Default for DmgPalette {
+    fn default() -> Self {
+        Self::GRAYS
+    }
+}
+
+/// Packed framebuffer layouts, defined in byte order so they don't depend on host endianness
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+    /// r, g, b
+    Rgb888,
+    /// r, g, b, a (always opaque)
+    Rgba8888,
+    /// 5-6-5 bits packed in a little endian u16
+    Rgb565Le,
+    /// 5-6-5 bits packed in a big endian u16, as most SPI displays expect
+    Rgb565Be,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            Self::Rgb888 => 3,
+            Self::Rgba8888 => 4,
+            Self::Rgb565Le | Self::Rgb565Be => 2,
+        }
+    }
+}
+
+fn rgb565((r, g, b): RGB) -> u16 {
+    (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3
+}
+
+fn pack_pixels<const N: usize>(
+    frame: &FrameBuffer,
+    out: &mut Vec<u8>,
+    pack: impl Fn(RGB) -> [u8; N],
+) {
+    out.resize(frame.iter().map(Vec::len).sum::<usize>() * N, 0);
+    for (dst, &pixel) in out.chunks_exact_mut(N).zip(frame.iter().flatten()) {
+        dst.copy_from_slice(&pack(pixel));
+    }
+}
+
+/// Converts `frame` row by row into `out`, reusing its allocation
+pub fn pack_frame(frame: &FrameBuffer, format: PixelFormat, out: &mut Vec<u8>) {
+    match format {
+        PixelFormat::Rgb888 => pack_pixels(frame, out, |(r, g, b)| [r, g, b]),
+        PixelFormat::Rgba8888 => pack_pixels(frame, out, |(r, g, b)| [r, g, b, 0xFF]),
+        PixelFormat::Rgb565Le => pack_pixels(frame, out, |p| rgb565(p).to_le_bytes()),
+        PixelFormat::Rgb565Be => pack_pixels(frame, out, |p| rgb565(p).to_be_bytes()),
+    }
+}
+
+/// Frame handed to the LCD: its pixels and the metadata recorders, pacers and diffing tools
+/// rely on. Derefs to its pixels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub pixels: FrameBuffer,
+    /// Frames completed since power on, the first one is 1
+    pub index: u64,
+    /// Emulated time at which the frame completed
+    pub timestamp: Duration,
+    /// Whether a pixel changed since the previous frame
+    pub dirty: bool,
+    /// Pixels written with a different value since the last completed frame
+    pub(crate) changed: bool,
+}
+
+impl Frame {
+    /// Frame 0 showing `pixels`
+    pub fn new(pixels: FrameBuffer) -> Self {
+        Self {
+            pixels,
+            index: 0,
+            timestamp: Duration::ZERO,
+            dirty: true,
+            changed: false,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.pixels.first().map_or(0, Vec::len)
+    }
+
+    pub fn height(&self) -> usize {
+        self.pixels.len()
+    }
+
+    pub(crate) fn set_pixel(&mut self, x: usize, y: usize, pixel: RGB) {
+        let dst = &mut self.pixels[y][x];
+        self.changed |= *dst != pixel;
+        *dst = pixel;
+    }
+
+    /// Stamps the pixels drawn so far as the next frame
+    pub(crate) fn complete(&mut self) {
+        self.index += 1;
+        self.timestamp = pacing::frames_duration(self.index);
+        self.dirty = self.changed;
+        self.changed = false;
+    }
+}
+
+impl Deref for Frame {
+    type Target = FrameBuffer;
+
+    fn deref(&self) -> &FrameBuffer {
+        &self.pixels
+    }
+}
+
 pub trait LCD {
-    fn draw_buffer(&mut self, _matrix: &FrameBuffer) {}
+    fn draw_buffer(&mut self, _frame: &Frame) {}
+
+    /// Packed format to receive frames in through `draw_packed` instead of `draw_buffer`,
+    /// the conversion then happens once in the output stage
+    fn pixel_format(&self) -> Option<PixelFormat> {
+        None
+    }
+
+    /// Frame packed in `pixel_format`, rows top to bottom
+    fn draw_packed(&mut self, _pixels: &[u8]) {}
+}
+
+pub struct Fake;
+
+impl LCD for Fake {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_frame() {
+        let mut frame = vec![vec![RGB_WHITE; PIXELS_WIDTH]; PIXELS_HEIGHT];
+        frame[0][0] = (0x12, 0x34, 0x56);
+        frame[0][1] = RGB_LIGHT_GRAY;
+
+        let mut out = vec![];
+        pack_frame(&frame, PixelFormat::Rgb888, &mut out);
+        assert_eq!(PIXELS_WIDTH * PIXELS_HEIGHT * 3, out.len());
+        assert_eq!([0x12, 0x34, 0x56, 170, 170, 170], out[..6]);
+
+        pack_frame(&frame, PixelFormat::Rgba8888, &mut out);
+        assert_eq!(PIXELS_WIDTH * PIXELS_HEIGHT * 4, out.len());
+        assert_eq!([0x12, 0x34, 0x56, 0xFF], out[..4]);
+
+        // 0x12 >> 3 = 0b00010, 0x34 >> 2 = 0b001101, 0x56 >> 3 = 0b01010
+        let packed: u16 = (0b00010 << 11) | (0b001101 << 5) | 0b01010;
+        pack_frame(&frame, PixelFormat::Rgb565Le, &mut out);
+        assert_eq!(packed.to_le_bytes(), out[..2]);
+        pack_frame(&frame, PixelFormat::Rgb565Be, &mut out);
+        assert_eq!(packed.to_be_bytes(), out[..2]);
+        assert_eq!([0xFF, 0xFF], out[out.len() - 2..]);
+    }
+
+    #[test]
+    fn test_frame_metadata() {
+        let mut frame = Frame::new(vec![vec![RGB_WHITE; PIXELS_WIDTH]; PIXELS_HEIGHT]);
+        assert_eq!(
+            (PIXELS_WIDTH, PIXELS_HEIGHT),
+            (frame.width(), frame.height())
+        );
+
+        frame.set_pixel(3, 2, RGB_BLACK);
+        frame.complete();
+        assert_eq!(1, frame.index);
+        assert_eq!(pacing::FRAME_DURATION, frame.timestamp);
+        assert!(frame.dirty);
+        assert_eq!(RGB_BLACK, frame[2][3]);
+
+        // same value written again
+        frame.set_pixel(3, 2, RGB_BLACK);
+        frame.complete();
+        assert_eq!(2, frame.index);
+        assert_eq!(pacing::frames_duration(2), frame.timestamp);
+        assert!(!frame.dirty);
+    }
 }
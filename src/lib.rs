@@ -1,25 +1,63 @@
+#[macro_use]
+pub mod logger;
 pub mod apu;
-mod cartridge;
+pub mod barcode_boy;
+mod bus;
+pub mod cartridge;
+pub mod cheat_search;
+pub mod cheats;
+mod clock;
+pub mod compat;
 pub mod config;
 pub mod cpu;
+pub mod crash;
+pub mod cycle_observer;
+pub mod debug;
+pub mod debugger;
+pub mod dmg07;
+pub mod events;
+pub mod expr;
+pub mod features;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod frontend_util;
 pub mod gameboy;
+pub mod input_display;
+pub mod instances;
+mod instr;
 mod interrupts;
 pub mod joypad;
 pub mod joypad_events_handler;
 pub mod lcd;
-#[macro_use]
-pub mod logger;
-mod bus;
-mod instr;
+pub mod link;
 mod mbc;
 mod memory;
 pub mod mode;
 mod oam;
+pub mod observation;
+pub mod pacing;
 pub mod ppu;
+pub mod probes;
+#[cfg(feature = "pyo3")]
+pub mod python;
 mod ram;
-mod registers;
+pub mod registers;
+pub mod replay;
+pub mod romdb;
 pub mod saver;
+pub mod savestate;
+pub mod scripting;
 mod serial;
+pub mod serial_script;
+pub mod splash;
+pub mod stats;
 pub mod stereo;
+pub mod symbols;
+pub mod tcp_link;
+pub mod testing;
 mod timer;
+pub mod trace;
+mod undocumented;
 mod vram;
+
+pub use features::features;
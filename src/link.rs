@@ -0,0 +1,110 @@
+//! Devices plugged into the link port, see https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html
+//!
+//! Transfers are emulated a byte at a time: a device sees the whole byte shifted out by the Game Boy
+//! and answers with the whole byte shifted in.
+
+use std::sync::{Arc, Mutex};
+
+pub trait LinkDevice {
+    /// Transfer clocked by the Game Boy (internal clock): `sent` is shifted out and the returned byte
+    /// is shifted in. Nothing plugged in reads as 0xFF.
+    fn transfer(&mut self, _sent: u8) -> u8 {
+        0xFF
+    }
+
+    /// Non-blocking `transfer`, called after every step once the byte is shifted out, until it
+    /// returns the byte shifted in. Devices waiting on something external (e.g. a network peer)
+    /// implement it so emulation keeps running meanwhile, the default answers with `transfer`.
+    fn poll_transfer(&mut self, sent: u8) -> Option<u8> {
+        Some(self.transfer(sent))
+    }
+
+    /// Called after every step while the Game Boy waits on a transfer clocked by the device
+    /// (external clock), `cycles` being the time elapsed since the last call. Returning a byte
+    /// completes the transfer, `sent` is the byte the Game Boy shifts out in exchange.
+    fn clock(&mut self, _cycles: u8, _sent: u8) -> Option<u8> {
+        None
+    }
+}
+
+/// Nothing plugged in
+pub struct Fake;
+
+impl LinkDevice for Fake {}
+
+#[derive(Debug, Default)]
+struct Port {
+    /// byte the Game Boy waiting on the external clock shifts out, refreshed every step it waits
+    armed: Option<u8>,
+    /// byte shifted in by the peer, completing the external clock transfer
+    received: Option<u8>,
+}
+
+/// One end of a link cable between two `GameBoy` instances of the same process, see `cable`
+pub struct CableEnd {
+    ports: Arc<Mutex<[Port; 2]>>,
+    side: usize,
+}
+
+/// Connects two instances: attach one end to each `GameBoy`.
+///
+/// The Game Boy using the internal clock exchanges its byte with the peer only if the peer is
+/// waiting on the external clock by then, it reads 0xFF otherwise like nothing plugged in.
+/// Stepping both instances in lockstep on one thread (e.g. a frame each in turn) makes the
+/// exchanges deterministic, the ends can also be moved to one thread per instance.
+pub fn cable() -> (CableEnd, CableEnd) {
+    let ports = Arc::new(Mutex::new(Default::default()));
+    (
+        CableEnd {
+            ports: ports.clone(),
+            side: 0,
+        },
+        CableEnd { ports, side: 1 },
+    )
+}
+
+impl LinkDevice for CableEnd {
+    fn transfer(&mut self, sent: u8) -> u8 {
+        let mut ports = self.ports.lock().unwrap();
+        let peer = &mut ports[1 - self.side];
+        match peer.armed.take() {
+            Some(received) => {
+                peer.received = Some(sent);
+                received
+            }
+            None => 0xFF,
+        }
+    }
+
+    fn clock(&mut self, _cycles: u8, sent: u8) -> Option<u8> {
+        let mut ports = self.ports.lock().unwrap();
+        let port = &mut ports[self.side];
+        let received = port.received.take();
+        port.armed = received.is_none().then_some(sent);
+        received
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cable_exchanges_bytes() {
+        let (mut a, mut b) = cable();
+
+        // b isn't waiting yet
+        assert_eq!(0xFF, a.transfer(0x11));
+
+        assert_eq!(None, b.clock(4, 0x22));
+        assert_eq!(None, b.clock(4, 0x33));
+        assert_eq!(0x33, a.transfer(0x44));
+        assert_eq!(Some(0x44), b.clock(4, 0x33));
+        assert_eq!(0xFF, a.transfer(0x55), "b is done waiting");
+
+        // the other way around
+        assert_eq!(None, a.clock(4, 0x66));
+        assert_eq!(0x66, b.transfer(0x77));
+        assert_eq!(Some(0x77), a.clock(4, 0x66));
+    }
+}
@@ -1,4 +1,37 @@
-use crate::{memory::MemReadWriter, saver::GameSave};
+use std::{cell::Cell, io::Error, rc::Rc};
+
+use crate::{
+    cartridge::MapperOverride,
+    debug::{BankUsage, RomCoverage},
+    memory::MemReadWriter,
+    saver::GameSave,
+    savestate::{ChunkReader, ChunkWriter},
+};
+
+/// Banks currently selected by the banking registers, before wrapping to the actual sizes
+struct MappedBanks {
+    /// bank at 0000-3FFF
+    rom0: usize,
+    /// bank at 4000-7FFF
+    romx: usize,
+    /// bank at A000-BFFF, None when ram is disabled
+    ram: Option<usize>,
+}
+
+/// Cartridge controller, on top of the memory map it owns the rom contents
+trait Mapper: MemReadWriter {
+    /// Swaps the rom contents keeping banking registers and ram untouched
+    fn replace_rom(&mut self, rom: Vec<u8>);
+
+    fn mapped_banks(&self) -> MappedBanks;
+
+    /// Banking registers and ram, stored in the cartridge savestate chunk
+    fn save(&self, w: &mut ChunkWriter);
+    fn load(&mut self, r: &mut ChunkReader);
+
+    /// Cartridge accelerometer input, see `MBC7`
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+}
 
 fn right_nibble(byte: u8) -> u8 {
     byte & 0x0F
@@ -18,6 +51,28 @@ impl NoMBC {
     }
 }
 
+impl Mapper for NoMBC {
+    fn replace_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
+    fn mapped_banks(&self) -> MappedBanks {
+        MappedBanks {
+            rom0: 0,
+            romx: 1,
+            ram: Some(0),
+        }
+    }
+
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_bytes(&self.ram[0xA000..]);
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        r.read_into(&mut self.ram[0xA000..]);
+    }
+}
+
 impl MemReadWriter for NoMBC {
     fn read_byte(&self, address: u16) -> u8 {
         match address {
@@ -33,6 +88,74 @@ impl MemReadWriter for NoMBC {
     }
 }
 
+/// https://gbdev.io/pandocs/MBC1.html#wisdom-tree
+///
+/// Unlicensed, the header claims no mapper. Any write to 0000-3FFF maps the 32 KiB bank
+/// numbered by the low byte of the address at 0000-7FFF, the written value is ignored.
+struct WisdomTree {
+    rom: Vec<u8>,
+    bank: u8,
+}
+
+impl WisdomTree {
+    fn new(rom: Vec<u8>) -> Self {
+        Self { rom, bank: 0 }
+    }
+}
+
+/// Wisdom Tree roms have an empty header, their copyright string right after it
+fn is_wisdom_tree(rom: &[u8]) -> bool {
+    let header_empty = rom
+        .get(0x0134..0x014C)
+        .is_some_and(|h| h.iter().all(|&b| b == 0));
+    let signature = rom.get(0x0150..0x0400).is_some_and(|code| {
+        code.windows(11)
+            .any(|w| w == b"WISDOM TREE" || w == b"WISDOM\0TREE")
+    });
+    rom.len() > 0x8000 && header_empty && signature
+}
+
+impl Mapper for WisdomTree {
+    fn replace_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
+    fn mapped_banks(&self) -> MappedBanks {
+        let rom0 = self.bank as usize * 2;
+        MappedBanks {
+            rom0,
+            romx: rom0 + 1,
+            ram: None,
+        }
+    }
+
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_u8(self.bank);
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        self.bank = r.read_u8();
+    }
+}
+
+impl MemReadWriter for WisdomTree {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            ..=0x7FFF => {
+                let addr = self.bank as usize * 0x8000 + address as usize;
+                self.rom[addr % self.rom.len()]
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, _value: u8) {
+        if address <= 0x3FFF {
+            self.bank = address as u8;
+        }
+    }
+}
+
 const MBC1_BANKING_MODE_REG_START_ADDR: u16 = 0x6000;
 const MBC1_BANKING_MODE_REG_END_ADDR: u16 = 0x7FFF;
 
@@ -73,6 +196,10 @@ fn load_saved_ram<S: GameSave>(saver: &S, ram_size: usize) -> Vec<u8> {
     if saved_ram.len() != ram_size {
         warn!("Mismatching ram size and saved ram size.");
         warn!("Skipping saved ram.");
+        // savers without a save load nothing
+        if !saved_ram.is_empty() {
+            saver.load_rejected(ram_size, saved_ram.len());
+        }
         saved_ram = vec![0; ram_size];
     }
 
@@ -129,6 +256,38 @@ impl<S: GameSave> MBC1<S> {
     }
 }
 
+impl<S: GameSave> Mapper for MBC1<S> {
+    fn replace_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
+    fn mapped_banks(&self) -> MappedBanks {
+        let upper = self.ram_or_upper_rom_bank as usize;
+        let advanced = matches!(self.banking_mode, BankingMode::Advanced);
+        MappedBanks {
+            rom0: if advanced { upper << 5 } else { 0 },
+            romx: upper << 5 | self.rom_bank_lower as usize,
+            ram: self.ram_enabled.then_some(if advanced { upper } else { 0 }),
+        }
+    }
+
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_bytes(&self.ram);
+        w.write_u8(self.rom_bank_lower);
+        w.write_u8(self.ram_or_upper_rom_bank);
+        w.write_bool(self.ram_enabled);
+        w.write_bool(matches!(self.banking_mode, BankingMode::Advanced));
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        r.read_into(&mut self.ram);
+        self.rom_bank_lower = r.read_u8();
+        self.ram_or_upper_rom_bank = r.read_u8();
+        self.ram_enabled = r.read_bool();
+        self.banking_mode = BankingMode::from(r.read_u8());
+    }
+}
+
 impl<S: GameSave> MemReadWriter for MBC1<S> {
     fn read_byte(&self, address: u16) -> u8 {
         match address {
@@ -203,6 +362,32 @@ impl<S: GameSave> MBC2<S> {
     }
 }
 
+impl<S: GameSave> Mapper for MBC2<S> {
+    fn replace_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
+    fn mapped_banks(&self) -> MappedBanks {
+        MappedBanks {
+            rom0: 0,
+            romx: self.rom_bank,
+            ram: self.ram_enabled.then_some(0),
+        }
+    }
+
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_bytes(&self.ram);
+        w.write_u8(self.rom_bank as u8);
+        w.write_bool(self.ram_enabled);
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        r.read_into(&mut self.ram);
+        self.rom_bank = (r.read_u8() as usize).max(1);
+        self.ram_enabled = r.read_bool();
+    }
+}
+
 impl<S: GameSave> MemReadWriter for MBC2<S> {
     fn read_byte(&self, address: u16) -> u8 {
         match address {
@@ -247,198 +432,1651 @@ impl<S: GameSave> MemReadWriter for MBC2<S> {
     }
 }
 
-struct MBC5<S: GameSave> {
+/// https://gbdev.io/pandocs/MMM01.html
+///
+/// Multicart mapper: it boots unmapped, with the last 32 KiB of the rom (the menu) at 0000-7FFF
+/// and every register writable. Setting the map enable bit selects a game: the outer bank bits
+/// and the masked low bits can't be written anymore, so the game then sees an MBC1 of its own.
+struct MMM01<S: GameSave> {
     rom: Vec<u8>,
     ram: Vec<u8>,
-    rom_bank_lower: u8,
-    rom_bank_9th_bit: bool,
+    mapped: bool,
     ram_enabled: bool,
+    /// RA14-RA22, 9 bits
+    rom_bank: u16,
+    /// RA13-RA16, 4 bits
     ram_bank: u8,
+    /// bits 1-4 of the rom bank kept once mapped
+    rom_bank_mask: u8,
+    /// bits 0-1 of the ram bank kept once mapped
+    ram_bank_mask: u8,
+    /// banking mode can't be changed once mapped
+    mode_locked: bool,
+    banking_mode: BankingMode,
     saver: S,
 }
 
-impl<S: GameSave> MBC5<S> {
+/// Real dumps keep the first game at the start of the rom and the menu, with the MMM01 header, in
+/// the last 32 KiB. The menu header checksum tells it apart from game data.
+fn is_mmm01(rom: &[u8]) -> bool {
+    if rom.len() <= 0x8000 {
+        return false;
+    }
+    let menu = &rom[rom.len() - 0x8000..];
+    let checksum = menu[0x0134..=0x014C]
+        .iter()
+        .fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1));
+    matches!(menu[0x0147], 0x0B..=0x0D) && menu[0x014D] == checksum
+}
+
+impl<S: GameSave> MMM01<S> {
     fn new(rom: Vec<u8>, ram_size: usize, saver: S) -> Self {
         Self {
             rom,
             ram: load_saved_ram(&saver, ram_size),
-            rom_bank_lower: 1,
-            rom_bank_9th_bit: false,
+            mapped: false,
             ram_enabled: false,
+            rom_bank: 0,
             ram_bank: 0,
+            rom_bank_mask: 0,
+            ram_bank_mask: 0,
+            mode_locked: false,
+            banking_mode: BankingMode::Simple,
             saver,
         }
     }
 
-    fn get_rom_address(&self, address: u16) -> usize {
-        let rom_bank = ((self.rom_bank_9th_bit as u32) << 8) | (self.rom_bank_lower) as u32;
-        let addr = (address - 0x4000) as u32 + rom_bank * 0x4000;
-        addr as usize
+    /// Rom bank bits 0-4 writable through 2000-3FFF
+    fn writable_rom_bank_bits(&self) -> u16 {
+        match self.mapped {
+            true => 0x1F & !((self.rom_bank_mask as u16) << 1),
+            false => 0x1F,
+        }
+    }
+
+    /// Ram bank bits 0-1 writable through 4000-5FFF
+    fn writable_ram_bank_bits(&self) -> u8 {
+        match self.mapped {
+            true => 0x03 & !self.ram_bank_mask,
+            false => 0x03,
+        }
+    }
+
+    /// (0000-3FFF, 4000-7FFF) banks
+    fn rom_banks(&self) -> (usize, usize) {
+        if !self.mapped {
+            // RA15-RA22 are pulled high
+            return (0x1FE, 0x1FF);
+        }
+
+        let writable = self.writable_rom_bank_bits();
+        let mut romx = self.rom_bank;
+        if romx & writable == 0 {
+            romx |= 1;
+        }
+        ((self.rom_bank & !writable) as usize, romx as usize)
+    }
+
+    fn ram_bank(&self) -> usize {
+        match self.banking_mode {
+            BankingMode::Simple => (self.ram_bank & !self.writable_ram_bank_bits()) as usize,
+            BankingMode::Advanced => self.ram_bank as usize,
+        }
     }
 
     fn get_ram_address(&self, address: u16) -> usize {
-        let addr = (address - 0xA000) + self.ram_bank as u16 * 0x2000;
-        addr as usize
+        ((address - 0xA000) as usize + self.ram_bank() * 0x2000) & (self.ram.len() - 1)
     }
 }
 
-impl<S: GameSave> MemReadWriter for MBC5<S> {
+impl<S: GameSave> Mapper for MMM01<S> {
+    fn replace_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
+    fn mapped_banks(&self) -> MappedBanks {
+        let (rom0, romx) = self.rom_banks();
+        MappedBanks {
+            rom0,
+            romx,
+            ram: self.ram_enabled.then_some(self.ram_bank()),
+        }
+    }
+
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_bytes(&self.ram);
+        w.write_bool(self.mapped);
+        w.write_bool(self.ram_enabled);
+        w.write_u16(self.rom_bank);
+        w.write_u8(self.ram_bank);
+        w.write_u8(self.rom_bank_mask);
+        w.write_u8(self.ram_bank_mask);
+        w.write_bool(self.mode_locked);
+        w.write_bool(matches!(self.banking_mode, BankingMode::Advanced));
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        r.read_into(&mut self.ram);
+        self.mapped = r.read_bool();
+        self.ram_enabled = r.read_bool();
+        self.rom_bank = r.read_u16();
+        self.ram_bank = r.read_u8();
+        self.rom_bank_mask = r.read_u8();
+        self.ram_bank_mask = r.read_u8();
+        self.mode_locked = r.read_bool();
+        self.banking_mode = BankingMode::from(r.read_u8());
+    }
+}
+
+impl<S: GameSave> MemReadWriter for MMM01<S> {
     fn read_byte(&self, address: u16) -> u8 {
+        let (rom0, romx) = self.rom_banks();
         match address {
-            ..=0x3FFF => self.rom[address as usize],
+            0x0000..=0x3FFF => self.rom[(rom0 * 0x4000 + address as usize) & (self.rom.len() - 1)],
             0x4000..=0x7FFF => {
-                let addr = self.get_rom_address(address) & (self.rom.len() - 1);
-                self.rom[addr]
-            }
-            0xA000..=0xBFFF => {
-                let mut val = 0xFF;
-                if self.ram_enabled && self.ram.len() > 0 {
-                    let addr = self.get_ram_address(address) & (self.ram.len() - 1);
-                    val = self.ram[addr];
-                }
-
-                val
+                self.rom[(romx * 0x4000 + (address - 0x4000) as usize) & (self.rom.len() - 1)]
             }
-            _ => unreachable!("invalid read address for MBC5: {:#04x}", address),
+            0xA000..=0xBFFF => match self.ram_enabled && !self.ram.is_empty() {
+                true => self.ram[self.get_ram_address(address)],
+                false => 0xFF,
+            },
+            _ => unreachable!("invalid read address for MMM01: {:#04x}", address),
         }
     }
+
     fn write_byte(&mut self, address: u16, value: u8) {
         match address {
-            ..=0x1FFF => {
+            0x0000..=0x1FFF => {
                 let enabled = right_nibble(value) == 0xA;
                 if self.ram_enabled && !enabled {
                     self.saver.save(&self.ram).unwrap();
                 }
                 self.ram_enabled = enabled;
+                if !self.mapped {
+                    self.ram_bank_mask = value >> 4 & 0x03;
+                    self.mapped = value & 0x40 != 0;
+                }
+            }
+            0x2000..=0x3FFF => {
+                let writable = self.writable_rom_bank_bits();
+                self.rom_bank = self.rom_bank & !writable | value as u16 & writable;
+                if !self.mapped {
+                    self.rom_bank = self.rom_bank & !0x60 | (value as u16 & 0x60);
+                }
+            }
+            0x4000..=0x5FFF => {
+                let writable = self.writable_ram_bank_bits();
+                self.ram_bank = self.ram_bank & !writable | value & writable;
+                if !self.mapped {
+                    self.ram_bank = self.ram_bank & 0x03 | value & 0x0C;
+                    self.rom_bank = self.rom_bank & 0x7F | ((value as u16 >> 4) & 0x03) << 7;
+                    self.mode_locked = value & 0x40 != 0;
+                }
+            }
+            0x6000..=0x7FFF => {
+                if !(self.mapped && self.mode_locked) {
+                    self.banking_mode = BankingMode::from(value);
+                }
+                if !self.mapped {
+                    self.rom_bank_mask = value >> 2 & 0x0F;
+                }
             }
-            0x2000..=0x2FFF => self.rom_bank_lower = value,
-            0x3000..=0x3FFF => self.rom_bank_9th_bit = value & 1 != 0,
-            0x4000..=0x5FFF => self.ram_bank = value & 0xF,
             0xA000..=0xBFFF => {
-                if self.ram_enabled && self.ram.len() > 0 {
-                    let addr = self.get_ram_address(address) & (self.ram.len() - 1);
+                if self.ram_enabled && !self.ram.is_empty() {
+                    let addr = self.get_ram_address(address);
                     self.ram[addr] = value;
                 }
             }
-            _ => {}
+            _ => unreachable!("invalid write address MMM01: {:#04x}", address),
         }
     }
 }
 
-fn get_target_mbc<S: GameSave + 'static>(
-    code: u8,
-    rom: Vec<u8>,
-    ram_size: usize,
-    saver: S,
-) -> Box<dyn MemReadWriter> {
-    match code {
-        0x00 => Box::new(NoMBC::new(rom)),
-        0x01..=0x03 => Box::new(MBC1::new(rom, ram_size, saver)),
-        0x05..=0x06 => Box::new(MBC2::new(rom, saver)),
-        0x19..=0x1E => Box::new(MBC5::new(rom, ram_size, saver)),
-        _ => panic!("unimplemented or unreachable: {:#04x}", code),
-    }
+/// Flash size of a GB Memory cartridge, the menu and every game share it
+const GB_MEMORY_ROM_SIZE: usize = 0x10_0000;
+/// Games are written at 128 KiB boundaries
+const GB_MEMORY_BLOCK_SIZE: usize = 0x2_0000;
+const GB_MEMORY_RAM_SIZE: usize = 0x2_0000;
+
+/// Dumped GB Memory images start with the menu, recognized by its header title
+fn is_gb_memory(rom: &[u8]) -> bool {
+    rom.len() == GB_MEMORY_ROM_SIZE && rom[0x0134..0x0143].starts_with(b"NP M-MENU")
 }
 
-pub struct MBC {
-    target_mbc: Box<dyn MemReadWriter>,
+/// Banking scheme of the game mapped by the menu
+#[derive(Clone, Copy, PartialEq)]
+enum GBMemoryMBC {
+    None = 0,
+    MBC1 = 1,
+    MBC3 = 3,
+    MBC5 = 5,
 }
 
-impl MBC {
-    pub fn new<S: GameSave + 'static>(code: u8, rom: Vec<u8>, ram_size: usize, saver: S) -> Self {
-        Self {
-            target_mbc: get_target_mbc(code, rom, ram_size, saver),
+impl GBMemoryMBC {
+    fn from_cartridge_type(code: u8) -> Self {
+        match code {
+            0x01..=0x03 => Self::MBC1,
+            0x0F..=0x13 => Self::MBC3,
+            0x19..=0x1E => Self::MBC5,
+            _ => Self::None,
         }
     }
 }
 
-impl MemReadWriter for MBC {
-    fn read_byte(&self, address: u16) -> u8 {
-        self.target_mbc.read_byte(address)
-    }
-    fn write_byte(&mut self, address: u16, value: u8) {
-        self.target_mbc.write_byte(address, value);
+impl From<u8> for GBMemoryMBC {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => Self::MBC1,
+            3 => Self::MBC3,
+            5 => Self::MBC5,
+            _ => Self::None,
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::saver;
-
-    use super::*;
-
-    // https://gbdev.io/pandocs/MBC1.html#addressing-diagrams
+/// Rom and ram window of the game mapped by the menu
+#[derive(Clone, Copy)]
+struct GBMemoryGame {
+    mbc: GBMemoryMBC,
+    /// first 128 KiB block of the game in the flash
+    block: usize,
+    rom_size: usize,
+    ram_offset: usize,
+    ram_size: usize,
+}
 
-    fn new_mbc1() -> MBC1<saver::Fake> {
-        MBC1::new(vec![], 0x2000, saver::Fake)
+impl GBMemoryGame {
+    /// The menu itself: the first block, banked like an MBC5 over the whole ram
+    fn menu() -> Self {
+        Self {
+            mbc: GBMemoryMBC::MBC5,
+            block: 0,
+            rom_size: GB_MEMORY_BLOCK_SIZE,
+            ram_offset: 0,
+            ram_size: GB_MEMORY_RAM_SIZE,
+        }
     }
 
-    #[test]
-    fn test_mbc1_addressing_bank_0_simple_mode() {
-        let mut mbc1 = new_mbc1();
+    /// Reads the header of the game written at `block`.
+    /// The cartridge keeps the real mapping in a hidden flash sector missing from the dumps,
+    /// so the game ram is placed right after the ram of the games written before it.
+    fn from_header(rom: &[u8], block: usize) -> Option<Self> {
+        let header = |block: usize, offset: usize| rom.get(block * GB_MEMORY_BLOCK_SIZE + offset);
+        let rom_size = |block| header(block, 0x0148).map(|&size| 0x8000usize << (size & 0x0F));
+        let ram_size = |block| match header(block, 0x0149) {
+            Some(0x02) => 0x2000,
+            Some(0x03) => 0x8000,
+            _ => 0,
+        };
 
-        for addr in MBC1_ROM_BANK_0_START_ADDR..=MBC1_ROM_BANK_0_END_ADDR {
-            assert_eq!(addr as usize, mbc1.get_rom_address(addr));
+        let mut ram_offset = 0;
+        let mut previous = 1;
+        while previous < block {
+            ram_offset += ram_size(previous);
+            previous += rom_size(previous)?.div_ceil(GB_MEMORY_BLOCK_SIZE);
         }
-
-        mbc1.write_byte(MBC1_RAM_BANK_NUM_REG_START_ADDR, 1);
-
-        for addr in MBC1_ROM_BANK_0_START_ADDR..=MBC1_ROM_BANK_0_END_ADDR {
-            assert_eq!(addr as usize, mbc1.get_rom_address(addr));
+        if previous != block || ram_offset + ram_size(block) > GB_MEMORY_RAM_SIZE {
+            return None;
         }
+
+        Some(Self {
+            mbc: GBMemoryMBC::from_cartridge_type(*header(block, 0x0147)?),
+            block,
+            rom_size: rom_size(block)?,
+            ram_offset,
+            ram_size: ram_size(block),
+        })
     }
+}
 
-    #[test]
-    fn test_mbc1_addressing_bank_0_advanced_mode() {
-        let mut mbc1 = new_mbc1();
+/// Nintendo Power GB Memory (DMG-MMSA-JPN) multicart
+///
+/// It boots into the menu, the first 128 KiB of the flash banked like an MBC5. The menu
+/// talks to the mapper through 0120-013F: it unlocks it writing 09 to 0120, AA to 0121
+/// and 55 to 0122, then sends commands, each written to 0120 and run on A5 to 013F:
+/// - 80-87 selects the game written at that 128 KiB block
+/// - C0 maps the selected game, which then sees its own MBC until the next reset
+struct GBMemory<S: GameSave> {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    game: GBMemoryGame,
+    mapped: bool,
+    /// unlock sequence bytes received so far
+    unlock: u8,
+    command: u8,
+    selected: Option<GBMemoryGame>,
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    banking_mode: BankingMode,
+    saver: S,
+}
 
-        mbc1.write_byte(MBC1_BANKING_MODE_REG_START_ADDR, 1);
-        mbc1.write_byte(MBC1_RAM_BANK_NUM_REG_START_ADDR, 1);
+impl<S: GameSave> GBMemory<S> {
+    fn new(rom: Vec<u8>, saver: S) -> Self {
+        Self {
+            rom,
+            ram: load_saved_ram(&saver, GB_MEMORY_RAM_SIZE),
+            game: GBMemoryGame::menu(),
+            mapped: false,
+            unlock: 0,
+            command: 0,
+            selected: None,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            banking_mode: BankingMode::Simple,
+            saver,
+        }
+    }
 
-        for addr in MBC1_ROM_BANK_0_START_ADDR..=MBC1_ROM_BANK_0_END_ADDR {
-            assert_eq!((1 << 19) | addr as usize, mbc1.get_rom_address(addr));
+    /// Menu register writes, the unlock sequence has to be sent again after a wrong byte
+    fn write_register(&mut self, address: u16, value: u8) {
+        match (address, value) {
+            (0x0120, 0x09) if self.unlock == 0 => self.unlock = 1,
+            (0x0121, 0xAA) if self.unlock == 1 => self.unlock = 2,
+            (0x0122, 0x55) if self.unlock == 2 => self.unlock = 3,
+            (0x0120, _) if self.unlock == 3 => self.command = value,
+            (0x013F, 0xA5) if self.unlock == 3 => self.run_command(),
+            _ => self.unlock = 0,
         }
     }
 
-    #[test]
-    fn test_mbc1_addressing_bank_01_7f_simple_mode() {
-        let mut mbc1 = new_mbc1();
+    fn run_command(&mut self) {
+        match self.command {
+            0x80..=0x87 => {
+                self.selected = GBMemoryGame::from_header(&self.rom, self.command as usize & 0x07)
+            }
+            0xC0 => {
+                if let Some(game) = self.selected {
+                    self.game = game;
+                    self.mapped = true;
+                    self.rom_bank = 1;
+                    self.ram_bank = 0;
+                    self.banking_mode = BankingMode::Simple;
+                }
+            }
+            _ => {}
+        }
+    }
 
-        mbc1.write_byte(MBC1_ROM_BANK_NUM_REG_START_ADDR, 1);
-        mbc1.write_byte(MBC1_RAM_BANK_NUM_REG_START_ADDR, 1);
+    /// (0000-3FFF, 4000-7FFF) banks within the game
+    fn rom_banks(&self) -> (usize, usize) {
+        let bank = self.rom_bank as usize;
+        match self.game.mbc {
+            GBMemoryMBC::None => (0, 1),
+            GBMemoryMBC::MBC1 => {
+                let low = match bank & 0x1F {
+                    0 => 1,
+                    low => low,
+                };
+                let high = bank & 0x60;
+                match self.banking_mode {
+                    BankingMode::Simple => (0, high | low),
+                    BankingMode::Advanced => (high, high | low),
+                }
+            }
+            GBMemoryMBC::MBC3 => (0, (bank & 0x7F).max(1)),
+            GBMemoryMBC::MBC5 => (0, bank & 0x1FF),
+        }
+    }
 
-        for addr in MBC1_ROM_BANK_01_7F_START_ADDR..=MBC1_ROM_BANK_01_7F_END_ADDR {
-            assert_eq!(
-                (1 << 19) | (1 << 14) | addr as usize,
-                mbc1.get_rom_address(addr)
-            );
+    fn ram_bank(&self) -> usize {
+        match self.game.mbc {
+            GBMemoryMBC::MBC1 if matches!(self.banking_mode, BankingMode::Simple) => 0,
+            _ => self.ram_bank as usize,
         }
     }
 
-    #[test]
-    fn test_mbc1_addressing_bank_01_7f_advanced_mode() {
-        let mut mbc1 = new_mbc1();
+    fn get_rom_address(&self, bank: usize, address: u16) -> usize {
+        let offset = (bank * 0x4000 + (address & 0x3FFF) as usize) & (self.game.rom_size - 1);
+        (self.game.block * GB_MEMORY_BLOCK_SIZE + offset) & (self.rom.len() - 1)
+    }
 
-        mbc1.write_byte(MBC1_BANKING_MODE_REG_START_ADDR, 1);
-        mbc1.write_byte(MBC1_ROM_BANK_NUM_REG_START_ADDR, 1);
-        mbc1.write_byte(MBC1_RAM_BANK_NUM_REG_START_ADDR, 1);
+    fn get_ram_address(&self, address: u16) -> usize {
+        let offset =
+            ((address - 0xA000) as usize + self.ram_bank() * 0x2000) & (self.game.ram_size - 1);
+        self.game.ram_offset + offset
+    }
 
-        for addr in MBC1_ROM_BANK_01_7F_START_ADDR..=MBC1_ROM_BANK_01_7F_END_ADDR {
-            assert_eq!(
-                (1 << 19) | (1 << 14) | addr as usize,
-                mbc1.get_rom_address(addr)
-            );
-        }
+    fn ram_accessible(&self) -> bool {
+        self.ram_enabled && self.game.ram_size > 0
     }
+}
 
-    #[test]
-    fn test_mbc1_addressing_bank_01_7f_0_treated_as_1() {
-        let mut mbc1 = new_mbc1();
+impl<S: GameSave> Mapper for GBMemory<S> {
+    fn replace_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
 
-        mbc1.write_byte(MBC1_ROM_BANK_NUM_REG_START_ADDR, 0);
+    fn mapped_banks(&self) -> MappedBanks {
+        let (rom0, romx) = self.rom_banks();
+        let first_bank = self.game.block * GB_MEMORY_BLOCK_SIZE / 0x4000;
+        let rom_banks = self.game.rom_size / 0x4000;
+        MappedBanks {
+            rom0: first_bank + rom0 % rom_banks,
+            romx: first_bank + romx % rom_banks,
+            ram: self
+                .ram_accessible()
+                .then(|| self.get_ram_address(0xA000) / 0x2000),
+        }
+    }
 
-        for addr in MBC1_ROM_BANK_01_7F_START_ADDR..=MBC1_ROM_BANK_01_7F_END_ADDR {
-            assert_eq!((1 << 14) | addr as usize, mbc1.get_rom_address(addr));
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_bytes(&self.ram);
+        for game in [Some(self.game), self.selected] {
+            w.write_bool(game.is_some());
+            let game = game.unwrap_or(GBMemoryGame::menu());
+            w.write_u8(game.mbc as u8);
+            w.write_u32(game.block as u32);
+            w.write_u32(game.rom_size as u32);
+            w.write_u32(game.ram_offset as u32);
+            w.write_u32(game.ram_size as u32);
+        }
+        w.write_bool(self.mapped);
+        w.write_u8(self.unlock);
+        w.write_u8(self.command);
+        w.write_bool(self.ram_enabled);
+        w.write_u16(self.rom_bank);
+        w.write_u8(self.ram_bank);
+        w.write_bool(matches!(self.banking_mode, BankingMode::Advanced));
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        r.read_into(&mut self.ram);
+        // only the block is trusted, the game is read again from its header like the menu does
+        let mut games = [None, None];
+        for game in &mut games {
+            let present = r.read_bool();
+            r.read_u8();
+            let block = r.read_u32() as usize;
+            for _ in 0..3 {
+                r.read_u32();
+            }
+            *game = match (present, block) {
+                (false, _) => None,
+                (true, 0) => Some(GBMemoryGame::menu()),
+                (true, block) => GBMemoryGame::from_header(&self.rom, block),
+            };
+        }
+        self.game = games[0].unwrap_or(GBMemoryGame::menu());
+        self.selected = games[1];
+        self.mapped = r.read_bool();
+        self.unlock = r.read_u8();
+        self.command = r.read_u8();
+        self.ram_enabled = r.read_bool();
+        self.rom_bank = r.read_u16();
+        self.ram_bank = r.read_u8();
+        self.banking_mode = BankingMode::from(r.read_u8());
+    }
+}
+
+impl<S: GameSave> MemReadWriter for GBMemory<S> {
+    fn read_byte(&self, address: u16) -> u8 {
+        let (rom0, romx) = self.rom_banks();
+        match address {
+            0x0000..=0x3FFF => self.rom[self.get_rom_address(rom0, address)],
+            0x4000..=0x7FFF => self.rom[self.get_rom_address(romx, address)],
+            0xA000..=0xBFFF => match self.ram_accessible() {
+                true => self.ram[self.get_ram_address(address)],
+                false => 0xFF,
+            },
+            _ => unreachable!("invalid read address for GB Memory: {:#04x}", address),
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) {
+        match (self.game.mbc, address) {
+            (_, 0x0120..=0x013F) if !self.mapped => self.write_register(address, value),
+            (_, 0x0000..=0x1FFF) => {
+                let enabled = right_nibble(value) == 0xA;
+                if self.ram_enabled && !enabled {
+                    self.saver.save(&self.ram).unwrap();
+                }
+                self.ram_enabled = enabled;
+            }
+            (GBMemoryMBC::MBC1, 0x2000..=0x3FFF) => {
+                self.rom_bank = self.rom_bank & !0x1F | (value & 0x1F) as u16;
+            }
+            (GBMemoryMBC::MBC1, 0x4000..=0x5FFF) => {
+                self.rom_bank = self.rom_bank & 0x1F | ((value & 0x03) as u16) << 5;
+                self.ram_bank = value & 0x03;
+            }
+            (GBMemoryMBC::MBC1, 0x6000..=0x7FFF) => self.banking_mode = BankingMode::from(value),
+            (GBMemoryMBC::MBC3, 0x2000..=0x3FFF) => self.rom_bank = (value & 0x7F) as u16,
+            (GBMemoryMBC::MBC3, 0x4000..=0x5FFF) => self.ram_bank = value & 0x03,
+            (GBMemoryMBC::MBC5, 0x2000..=0x2FFF) => {
+                self.rom_bank = self.rom_bank & 0x100 | value as u16;
+            }
+            (GBMemoryMBC::MBC5, 0x3000..=0x3FFF) => {
+                self.rom_bank = self.rom_bank & 0xFF | ((value & 0x01) as u16) << 8;
+            }
+            (GBMemoryMBC::MBC5, 0x4000..=0x5FFF) => self.ram_bank = value & 0x0F,
+            (_, 0xA000..=0xBFFF) if self.ram_accessible() => {
+                let addr = self.get_ram_address(address);
+                self.ram[addr] = value;
+            }
+            _ => {}
+        }
+    }
+}
+
+struct MBC5<S: GameSave> {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank_lower: u8,
+    rom_bank_9th_bit: bool,
+    ram_enabled: bool,
+    ram_bank: u8,
+    saver: S,
+}
+
+impl<S: GameSave> MBC5<S> {
+    fn new(rom: Vec<u8>, ram_size: usize, saver: S) -> Self {
+        Self {
+            rom,
+            ram: load_saved_ram(&saver, ram_size),
+            rom_bank_lower: 1,
+            rom_bank_9th_bit: false,
+            ram_enabled: false,
+            ram_bank: 0,
+            saver,
+        }
+    }
+
+    fn get_rom_address(&self, address: u16) -> usize {
+        let rom_bank = ((self.rom_bank_9th_bit as u32) << 8) | (self.rom_bank_lower) as u32;
+        let addr = (address - 0x4000) as u32 + rom_bank * 0x4000;
+        addr as usize
+    }
+
+    fn get_ram_address(&self, address: u16) -> usize {
+        // banks 8-15 lie past 64 KiB, computed in u16 they would wrap onto banks 0-7
+        (address - 0xA000) as usize + self.ram_bank as usize * 0x2000
+    }
+}
+
+impl<S: GameSave> Mapper for MBC5<S> {
+    fn replace_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
+    fn mapped_banks(&self) -> MappedBanks {
+        MappedBanks {
+            rom0: 0,
+            romx: (self.rom_bank_9th_bit as usize) << 8 | self.rom_bank_lower as usize,
+            ram: self.ram_enabled.then_some(self.ram_bank as usize),
+        }
+    }
+
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_bytes(&self.ram);
+        w.write_u8(self.rom_bank_lower);
+        w.write_bool(self.rom_bank_9th_bit);
+        w.write_bool(self.ram_enabled);
+        w.write_u8(self.ram_bank);
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        r.read_into(&mut self.ram);
+        self.rom_bank_lower = r.read_u8();
+        self.rom_bank_9th_bit = r.read_bool();
+        self.ram_enabled = r.read_bool();
+        self.ram_bank = r.read_u8();
+    }
+}
+
+impl<S: GameSave> MemReadWriter for MBC5<S> {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            ..=0x3FFF => self.rom[address as usize],
+            0x4000..=0x7FFF => {
+                let addr = self.get_rom_address(address) & (self.rom.len() - 1);
+                self.rom[addr]
+            }
+            0xA000..=0xBFFF => {
+                let mut val = 0xFF;
+                if self.ram_enabled && self.ram.len() > 0 {
+                    let addr = self.get_ram_address(address) & (self.ram.len() - 1);
+                    val = self.ram[addr];
+                }
+
+                val
+            }
+            _ => unreachable!("invalid read address for MBC5: {:#04x}", address),
+        }
+    }
+    fn write_byte(&mut self, address: u16, value: u8) {
+        match address {
+            ..=0x1FFF => {
+                let enabled = right_nibble(value) == 0xA;
+                if self.ram_enabled && !enabled {
+                    self.saver.save(&self.ram).unwrap();
+                }
+                self.ram_enabled = enabled;
+            }
+            0x2000..=0x2FFF => self.rom_bank_lower = value,
+            0x3000..=0x3FFF => self.rom_bank_9th_bit = value & 1 != 0,
+            0x4000..=0x5FFF => self.ram_bank = value & 0xF,
+            0xA000..=0xBFFF => {
+                if self.ram_enabled && self.ram.len() > 0 {
+                    let addr = self.get_ram_address(address) & (self.ram.len() - 1);
+                    self.ram[addr] = value;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Accelerometer reading when level, see https://gbdev.io/pandocs/MBC7.html
+const MBC7_ACCEL_CENTER: f32 = 0x81D0 as f32;
+/// Accelerometer reading difference for 1g
+const MBC7_ACCEL_GRAVITY: f32 = 0x70 as f32;
+/// Value of the latched accelerometer readings once erased
+const MBC7_ACCEL_ERASED: u16 = 0x8000;
+
+const MBC7_EEPROM_SIZE: usize = 256;
+/// start bit, 2 bits opcode and 8 bits address
+const MBC7_EEPROM_COMMAND_BITS: u8 = 11;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EepromState {
+    /// Shifting in a command, `bits` counts the bits received since the start bit
+    Command { shift: u16, bits: u8 },
+    /// Shifting out words from `address`, `bit` is the next bit of the current word
+    Read { address: u8, bit: u8 },
+    /// Shifting in the word to write at `address`, or to every address
+    WriteData {
+        address: Option<u8>,
+        shift: u16,
+        bits: u8,
+    },
+}
+
+impl EepromState {
+    fn idle() -> Self {
+        Self::Command { shift: 0, bits: 0 }
+    }
+}
+
+/// 93LC56 serial EEPROM, 128 words of 16 bits, driven by bit-banging CS/CLK/DI and reading DO.
+/// Bits are sampled on CLK rising edges while CS is high.
+struct Eeprom {
+    data: Vec<u8>,
+    cs: bool,
+    clk: bool,
+    di: bool,
+    /// DO, high when ready
+    out: bool,
+    write_enabled: bool,
+    state: EepromState,
+}
+
+impl Eeprom {
+    fn new(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            cs: false,
+            clk: false,
+            di: false,
+            out: true,
+            write_enabled: false,
+            state: EepromState::idle(),
+        }
+    }
+
+    fn word(&self, address: u8) -> u16 {
+        let i = (address & 0x7F) as usize * 2;
+        u16::from_le_bytes([self.data[i], self.data[i + 1]])
+    }
+
+    fn set_word(&mut self, address: u8, word: u16) {
+        let i = (address & 0x7F) as usize * 2;
+        self.data[i..i + 2].copy_from_slice(&word.to_le_bytes());
+    }
+
+    fn read(&self) -> u8 {
+        (self.cs as u8) << 7 | (self.clk as u8) << 6 | (self.di as u8) << 1 | self.out as u8
+    }
+
+    /// Returns true when the contents changed
+    fn write(&mut self, value: u8) -> bool {
+        let cs = value & 0x80 != 0;
+        let clk = value & 0x40 != 0;
+        self.di = value & 0x02 != 0;
+
+        let rising = cs && clk && !self.clk;
+        self.clk = clk;
+        if !cs {
+            self.cs = false;
+            self.state = EepromState::idle();
+            self.out = true;
+            return false;
+        }
+        self.cs = true;
+
+        match rising {
+            true => self.clock_bit(),
+            false => false,
+        }
+    }
+
+    fn clock_bit(&mut self) -> bool {
+        let di = self.di as u16;
+        match self.state {
+            // waiting for the start bit
+            EepromState::Command { bits: 0, .. } if di == 0 => false,
+            EepromState::Command { shift, bits } => {
+                let (shift, bits) = (shift << 1 | di, bits + 1);
+                match bits {
+                    MBC7_EEPROM_COMMAND_BITS => self.execute(shift),
+                    _ => {
+                        self.state = EepromState::Command { shift, bits };
+                        false
+                    }
+                }
+            }
+            EepromState::Read { address, bit } => {
+                self.out = self.word(address) >> (15 - bit) & 1 != 0;
+                // sequential read, carries on with the next word
+                self.state = match bit {
+                    15 => EepromState::Read {
+                        address: address.wrapping_add(1) & 0x7F,
+                        bit: 0,
+                    },
+                    _ => EepromState::Read {
+                        address,
+                        bit: bit + 1,
+                    },
+                };
+                false
+            }
+            EepromState::WriteData {
+                address,
+                shift,
+                bits,
+            } => {
+                let (shift, bits) = (shift << 1 | di, bits + 1);
+                if bits < 16 {
+                    self.state = EepromState::WriteData {
+                        address,
+                        shift,
+                        bits,
+                    };
+                    return false;
+                }
+
+                self.state = EepromState::idle();
+                self.out = true;
+                match (self.write_enabled, address) {
+                    (false, _) => false,
+                    (true, Some(address)) => {
+                        self.set_word(address, shift);
+                        true
+                    }
+                    (true, None) => {
+                        (0..0x80).for_each(|address| self.set_word(address, shift));
+                        true
+                    }
+                }
+            }
+        }
+    }
+
+    /// `command` holds the start bit, the opcode and the address
+    fn execute(&mut self, command: u16) -> bool {
+        let opcode = (command >> 8) & 0b11;
+        let address = command as u8;
+        self.state = EepromState::idle();
+
+        match opcode {
+            // READ, a dummy 0 bit precedes the data
+            0b10 => {
+                self.out = false;
+                self.state = EepromState::Read {
+                    address: address & 0x7F,
+                    bit: 0,
+                };
+                false
+            }
+            // WRITE
+            0b01 => {
+                self.state = EepromState::WriteData {
+                    address: Some(address),
+                    shift: 0,
+                    bits: 0,
+                };
+                false
+            }
+            // ERASE
+            0b11 => {
+                if self.write_enabled {
+                    self.set_word(address, 0xFFFF);
+                }
+                self.out = true;
+                self.write_enabled
+            }
+            _ => match address >> 6 {
+                // EWEN
+                0b11 => {
+                    self.write_enabled = true;
+                    false
+                }
+                // EWDS
+                0b00 => {
+                    self.write_enabled = false;
+                    false
+                }
+                // ERAL
+                0b10 => {
+                    if self.write_enabled {
+                        self.data.fill(0xFF);
+                    }
+                    self.out = true;
+                    self.write_enabled
+                }
+                // WRAL
+                _ => {
+                    self.state = EepromState::WriteData {
+                        address: None,
+                        shift: 0,
+                        bits: 0,
+                    };
+                    false
+                }
+            },
+        }
+    }
+
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_bytes(&self.data);
+        w.write_bool(self.cs);
+        w.write_bool(self.clk);
+        w.write_bool(self.di);
+        w.write_bool(self.out);
+        w.write_bool(self.write_enabled);
+        let (kind, address, shift, bits) = match self.state {
+            EepromState::Command { shift, bits } => (0, 0, shift, bits),
+            EepromState::Read { address, bit } => (1, address, 0, bit),
+            EepromState::WriteData {
+                address: Some(address),
+                shift,
+                bits,
+            } => (2, address, shift, bits),
+            EepromState::WriteData {
+                address: None,
+                shift,
+                bits,
+            } => (3, 0, shift, bits),
+        };
+        w.write_u8(kind);
+        w.write_u8(address);
+        w.write_u16(shift);
+        w.write_u8(bits);
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        r.read_into(&mut self.data);
+        self.cs = r.read_bool();
+        self.clk = r.read_bool();
+        self.di = r.read_bool();
+        self.out = r.read_bool();
+        self.write_enabled = r.read_bool();
+        let (kind, address, shift, bits) = (r.read_u8(), r.read_u8(), r.read_u16(), r.read_u8());
+        self.state = match kind {
+            1 => EepromState::Read { address, bit: bits },
+            2 | 3 => EepromState::WriteData {
+                address: (kind == 2).then_some(address),
+                shift,
+                bits,
+            },
+            _ => EepromState::Command { shift, bits },
+        };
+    }
+}
+
+/// https://gbdev.io/pandocs/MBC7.html
+struct MBC7<S: GameSave> {
+    rom: Vec<u8>,
+    rom_bank: u8,
+    /// 0000-1FFF and 4000-5FFF enables, both are needed to reach the registers
+    ram_enabled: (bool, bool),
+    /// tilt in g, positive to the right and towards the bottom
+    tilt: (f32, f32),
+    /// erased readings are latched again
+    latch_armed: bool,
+    latched: (u16, u16),
+    eeprom: Eeprom,
+    saver: S,
+}
+
+impl<S: GameSave> MBC7<S> {
+    fn new(rom: Vec<u8>, saver: S) -> Self {
+        Self {
+            rom,
+            rom_bank: 1,
+            ram_enabled: (false, false),
+            tilt: (0., 0.),
+            latch_armed: false,
+            latched: (MBC7_ACCEL_ERASED, MBC7_ACCEL_ERASED),
+            eeprom: Eeprom::new(load_saved_ram(&saver, MBC7_EEPROM_SIZE)),
+            saver,
+        }
+    }
+
+    fn registers_enabled(&self) -> bool {
+        self.ram_enabled == (true, true)
+    }
+
+    /// The EEPROM is saved after every completed write
+    fn write_eeprom(&mut self, value: u8) {
+        if self.eeprom.write(value) {
+            self.saver.save(&self.eeprom.data).unwrap();
+        }
+    }
+
+    fn accel_reading(tilt: f32) -> u16 {
+        (MBC7_ACCEL_CENTER + MBC7_ACCEL_GRAVITY * tilt.clamp(-4., 4.)) as u16
+    }
+}
+
+impl<S: GameSave> Mapper for MBC7<S> {
+    fn replace_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
+    fn mapped_banks(&self) -> MappedBanks {
+        MappedBanks {
+            rom0: 0,
+            romx: self.rom_bank as usize,
+            ram: self.registers_enabled().then_some(0),
+        }
+    }
+
+    fn save(&self, w: &mut ChunkWriter) {
+        self.eeprom.save(w);
+        w.write_u8(self.rom_bank);
+        w.write_bool(self.ram_enabled.0);
+        w.write_bool(self.ram_enabled.1);
+        w.write_bool(self.latch_armed);
+        w.write_u16(self.latched.0);
+        w.write_u16(self.latched.1);
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        self.eeprom.load(r);
+        self.rom_bank = r.read_u8();
+        self.ram_enabled = (r.read_bool(), r.read_bool());
+        self.latch_armed = r.read_bool();
+        self.latched = (r.read_u16(), r.read_u16());
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+}
+
+impl<S: GameSave> MemReadWriter for MBC7<S> {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            ..=0x3FFF => self.rom[address as usize],
+            0x4000..=0x7FFF => {
+                let addr = (address as usize - 0x4000 + self.rom_bank as usize * 0x4000)
+                    & (self.rom.len() - 1);
+                self.rom[addr]
+            }
+            0xA000..=0xAFFF if self.registers_enabled() => match address & 0xF0 {
+                0x20 => self.latched.0 as u8,
+                0x30 => (self.latched.0 >> 8) as u8,
+                0x40 => self.latched.1 as u8,
+                0x50 => (self.latched.1 >> 8) as u8,
+                0x60 => 0x00,
+                0x80 => self.eeprom.read(),
+                _ => 0xFF,
+            },
+            0xA000..=0xBFFF => 0xFF,
+            _ => unreachable!("invalid read address for MBC7: {:#04x}", address),
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) {
+        match address {
+            ..=0x1FFF => self.ram_enabled.0 = value == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = value,
+            0x4000..=0x5FFF => self.ram_enabled.1 = value == 0x40,
+            0xA000..=0xAFFF if self.registers_enabled() => match (address & 0xF0, value) {
+                (0x00, 0x55) => {
+                    self.latched = (MBC7_ACCEL_ERASED, MBC7_ACCEL_ERASED);
+                    self.latch_armed = true;
+                }
+                (0x10, 0xAA) if self.latch_armed => {
+                    self.latched = (
+                        Self::accel_reading(self.tilt.0),
+                        Self::accel_reading(self.tilt.1),
+                    );
+                    self.latch_armed = false;
+                }
+                (0x80, _) => self.write_eeprom(value),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+/// Bytes appended to the HuC3 save ram: clock seconds and the unix time they were saved at
+const HUC3_RTC_FOOTER_SIZE: usize = 16;
+
+/// Through chrono rather than `SystemTime`, which panics on wasm32 where chrono asks the browser
+fn unix_now() -> u64 {
+    chrono::Utc::now().timestamp().max(0) as u64
+}
+
+/// What A000-BFFF maps to, selected through 0000-1FFF
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum HuC3Mode {
+    RamReadOnly,
+    Ram,
+    /// Writes send a command to the RTC
+    RtcCommand,
+    /// Reads return the last command result
+    RtcResponse,
+    /// Starting a command and polling its completion
+    RtcSemaphore,
+    Infrared,
+    Disabled,
+}
+
+impl From<u8> for HuC3Mode {
+    fn from(value: u8) -> Self {
+        match value & 0x0F {
+            0x00 => Self::RamReadOnly,
+            0x0A => Self::Ram,
+            0x0B => Self::RtcCommand,
+            0x0C => Self::RtcResponse,
+            0x0D => Self::RtcSemaphore,
+            0x0E => Self::Infrared,
+            _ => Self::Disabled,
+        }
+    }
+}
+
+impl HuC3Mode {
+    fn as_u8(&self) -> u8 {
+        match self {
+            Self::RamReadOnly => 0x00,
+            Self::Ram => 0x0A,
+            Self::RtcCommand => 0x0B,
+            Self::RtcResponse => 0x0C,
+            Self::RtcSemaphore => 0x0D,
+            Self::Infrared => 0x0E,
+            Self::Disabled => 0x0F,
+        }
+    }
+}
+
+/// https://gbdev.io/pandocs/HuC3.html
+///
+/// The RTC is a 256 nibbles memory accessed one nibble at a time through commands: 0x00-0x02
+/// hold the minute of the day and 0x03-0x06 the day counter, both running from the host clock
+/// like the battery backed original. A command is written in mode 0x0B, started by clearing the
+/// semaphore (mode 0x0D) and its result read in mode 0x0C.
+struct HuC3<S: GameSave> {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank: u8,
+    ram_bank: u8,
+    mode: HuC3Mode,
+    /// command written in mode 0x0B, run when the semaphore is cleared
+    command: u8,
+    /// (last command, result nibble)
+    response: (u8, u8),
+    access_index: u8,
+    rtc_memory: [u8; 0x100],
+    /// clock seconds minus host seconds
+    rtc_offset: i64,
+    /// host clock, in seconds since the unix epoch
+    now: fn() -> u64,
+    saver: S,
+}
+
+impl<S: GameSave> HuC3<S> {
+    fn new(rom: Vec<u8>, ram_size: usize, saver: S) -> Self {
+        Self::with_clock(rom, ram_size, saver, unix_now)
+    }
+
+    fn with_clock(rom: Vec<u8>, ram_size: usize, saver: S, now: fn() -> u64) -> Self {
+        let mut ram = saver.load().unwrap_or(vec![0; ram_size]);
+        // a save without clock, or no save at all, starts it from 0
+        let mut rtc_offset = -(now() as i64);
+        if ram.len() == ram_size + HUC3_RTC_FOOTER_SIZE {
+            let footer = ram.split_off(ram_size);
+            let seconds = u64::from_le_bytes(footer[..8].try_into().unwrap());
+            let saved_at = u64::from_le_bytes(footer[8..].try_into().unwrap());
+            rtc_offset = seconds as i64 - saved_at as i64;
+        }
+        if ram.len() != ram_size {
+            warn!("Mismatching ram size and saved ram size.");
+            warn!("Skipping saved ram.");
+            if !ram.is_empty() {
+                saver.load_rejected(ram_size, ram.len());
+            }
+            ram = vec![0; ram_size];
+        }
+
+        Self {
+            rom,
+            ram,
+            rom_bank: 1,
+            ram_bank: 0,
+            mode: HuC3Mode::Disabled,
+            command: 0,
+            response: (0, 0),
+            access_index: 0,
+            rtc_memory: [0; 0x100],
+            rtc_offset,
+            now,
+            saver,
+        }
+    }
+
+    fn rtc_seconds(&self) -> u64 {
+        ((self.now)() as i64 + self.rtc_offset).max(0) as u64
+    }
+
+    /// (minute of the day, day counter)
+    fn rtc_time(&self) -> (u16, u16) {
+        let seconds = self.rtc_seconds();
+        (((seconds / 60) % 1440) as u16, (seconds / 86400) as u16)
+    }
+
+    fn set_rtc_time(&mut self, minutes: u16, days: u16) {
+        let seconds_in_minute = self.rtc_seconds() % 60;
+        let seconds = days as u64 * 86400 + (minutes % 1440) as u64 * 60 + seconds_in_minute;
+        self.rtc_offset = seconds as i64 - (self.now)() as i64;
+    }
+
+    fn read_nibble(&self, index: u8) -> u8 {
+        let (minutes, days) = self.rtc_time();
+        match index {
+            0x00..=0x02 => (minutes >> (index * 4)) as u8 & 0x0F,
+            0x03..=0x06 => (days >> ((index - 3) * 4)) as u8 & 0x0F,
+            _ => self.rtc_memory[index as usize],
+        }
+    }
+
+    fn write_nibble(&mut self, index: u8, value: u8) {
+        let (minutes, days) = self.rtc_time();
+        let set = |word: u16, shift: u8| word & !(0x0F << shift) | (value as u16) << shift;
+        match index {
+            0x00..=0x02 => self.set_rtc_time(set(minutes, index * 4), days),
+            0x03..=0x06 => self.set_rtc_time(minutes, set(days, (index - 3) * 4)),
+            _ => self.rtc_memory[index as usize] = value,
+        }
+    }
+
+    fn run_command(&mut self) {
+        let (command, argument) = (self.command >> 4 & 0x07, self.command & 0x0F);
+        let mut result = self.response.1;
+        match command {
+            // read and increment
+            0x1 => {
+                result = self.read_nibble(self.access_index);
+                self.access_index = self.access_index.wrapping_add(1);
+            }
+            // write, and increment
+            0x2 | 0x3 => {
+                self.write_nibble(self.access_index, argument);
+                if command == 0x3 {
+                    self.access_index = self.access_index.wrapping_add(1);
+                }
+                self.save();
+            }
+            0x4 => self.access_index = self.access_index & 0xF0 | argument,
+            0x5 => self.access_index = self.access_index & 0x0F | argument << 4,
+            // extended commands, 0x62 reports the clock as ready
+            0x6 => result = (argument == 0x2) as u8,
+            _ => {}
+        }
+        self.response = (command, result);
+    }
+
+    /// Ram followed by the clock footer
+    fn save(&self) {
+        let mut data = self.ram.clone();
+        data.extend_from_slice(&self.rtc_seconds().to_le_bytes());
+        data.extend_from_slice(&(self.now)().to_le_bytes());
+        self.saver.save(&data).unwrap();
+    }
+
+    fn get_ram_address(&self, address: u16) -> usize {
+        ((address - 0xA000) as usize + self.ram_bank as usize * 0x2000) & (self.ram.len() - 1)
+    }
+}
+
+impl<S: GameSave> Mapper for HuC3<S> {
+    fn replace_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
+    fn mapped_banks(&self) -> MappedBanks {
+        MappedBanks {
+            rom0: 0,
+            romx: self.rom_bank as usize,
+            ram: matches!(self.mode, HuC3Mode::Ram | HuC3Mode::RamReadOnly)
+                .then_some(self.ram_bank as usize),
+        }
+    }
+
+    /// The clock isn't part of the state, it keeps following the host time
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_bytes(&self.ram);
+        w.write_u8(self.rom_bank);
+        w.write_u8(self.ram_bank);
+        w.write_u8(self.mode.as_u8());
+        w.write_u8(self.command);
+        w.write_u8(self.response.0);
+        w.write_u8(self.response.1);
+        w.write_u8(self.access_index);
+        w.write_bytes(&self.rtc_memory);
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        r.read_into(&mut self.ram);
+        self.rom_bank = r.read_u8();
+        self.ram_bank = r.read_u8();
+        self.mode = HuC3Mode::from(r.read_u8());
+        self.command = r.read_u8();
+        self.response = (r.read_u8(), r.read_u8());
+        self.access_index = r.read_u8();
+        r.read_into(&mut self.rtc_memory);
+    }
+}
+
+impl<S: GameSave> MemReadWriter for HuC3<S> {
+    fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            ..=0x3FFF => self.rom[address as usize],
+            0x4000..=0x7FFF => {
+                let addr = (address as usize - 0x4000 + self.rom_bank as usize * 0x4000)
+                    & (self.rom.len() - 1);
+                self.rom[addr]
+            }
+            0xA000..=0xBFFF => match self.mode {
+                HuC3Mode::Ram | HuC3Mode::RamReadOnly if !self.ram.is_empty() => {
+                    self.ram[self.get_ram_address(address)]
+                }
+                HuC3Mode::RtcResponse => 0x80 | self.response.0 << 4 | self.response.1,
+                // commands run instantly, always ready
+                HuC3Mode::RtcSemaphore => 0x01,
+                // no light received
+                HuC3Mode::Infrared => 0xC0,
+                _ => 0xFF,
+            },
+            _ => unreachable!("invalid read address for HuC3: {:#04x}", address),
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) {
+        match address {
+            ..=0x1FFF => {
+                let mode = HuC3Mode::from(value);
+                if self.mode == HuC3Mode::Ram && mode != HuC3Mode::Ram {
+                    self.save();
+                }
+                self.mode = mode;
+            }
+            0x2000..=0x3FFF => self.rom_bank = value & 0x7F,
+            0x4000..=0x5FFF => self.ram_bank = value & 0x03,
+            0xA000..=0xBFFF => match self.mode {
+                HuC3Mode::Ram if !self.ram.is_empty() => {
+                    let addr = self.get_ram_address(address);
+                    self.ram[addr] = value;
+                }
+                HuC3Mode::RtcCommand => self.command = value,
+                HuC3Mode::RtcSemaphore if value & 1 == 0 => self.run_command(),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+/// Cartridge types (0x0147) a mapper is emulated for
+pub(crate) fn is_supported_cartridge_type(code: u8) -> bool {
+    matches!(
+        code,
+        0x00..=0x03 | 0x05..=0x06 | 0x0B..=0x0D | 0x19..=0x1E | 0x22 | 0xFE
+    )
+}
+
+/// Data past the first 32 KiB that isn't padding nor a mirror left by an overdump, so a rom
+/// without mapper in its header needs one to reach it
+fn has_banked_data(rom: &[u8]) -> bool {
+    rom.chunks(0x8000)
+        .skip(1)
+        .any(|chunk| chunk != &rom[..chunk.len()] && chunk.iter().any(|&b| b != chunk[0]))
+}
+
+fn get_target_mbc<S: GameSave + 'static>(
+    code: u8,
+    rom: Vec<u8>,
+    ram_size: usize,
+    saver: S,
+) -> Box<dyn Mapper> {
+    if is_gb_memory(&rom) {
+        return Box::new(GBMemory::new(rom, saver));
+    }
+    if is_mmm01(&rom) {
+        return Box::new(MMM01::new(rom, ram_size, saver));
+    }
+    match code {
+        0x00 if is_wisdom_tree(&rom) => Box::new(WisdomTree::new(rom)),
+        0x00 if has_banked_data(&rom) => {
+            // unlicensed roms leaving the header blank mostly bank through 2000-3FFF
+            warn!("Rom larger than 32 KiB without a mapper in its header, using MBC5.");
+            Box::new(MBC5::new(rom, ram_size, saver))
+        }
+        0x00 => Box::new(NoMBC::new(rom)),
+        0x01..=0x03 => Box::new(MBC1::new(rom, ram_size, saver)),
+        0x05..=0x06 => Box::new(MBC2::new(rom, saver)),
+        0x0B..=0x0D => Box::new(MMM01::new(rom, ram_size, saver)),
+        0x19..=0x1E => Box::new(MBC5::new(rom, ram_size, saver)),
+        0x22 => Box::new(MBC7::new(rom, saver)),
+        0xFE => Box::new(HuC3::new(rom, ram_size, saver)),
+        _ => panic!("unimplemented or unreachable: {:#04x}", code),
+    }
+}
+
+/// Forwards to the frontend saver, keeping the rejected save sizes for `MBC::take_rejected_save`
+struct RejectionRecorder<S: GameSave> {
+    inner: S,
+    rejected: Rc<Cell<Option<(usize, usize)>>>,
+}
+
+impl<S: GameSave> GameSave for RejectionRecorder<S> {
+    fn set_title(&mut self, title: String) {
+        self.inner.set_title(title);
+    }
+
+    fn load(&self) -> Result<Vec<u8>, Error> {
+        self.inner.load()
+    }
+
+    fn save(&self, ram: &[u8]) -> Result<(), Error> {
+        self.inner.save(ram)
+    }
+
+    fn load_rejected(&self, expected: usize, found: usize) {
+        self.rejected.set(Some((expected, found)));
+        self.inner.load_rejected(expected, found);
+    }
+}
+
+pub struct MBC {
+    target_mbc: Box<dyn Mapper>,
+    rom_banks: usize,
+    ram_banks: usize,
+    bank_usage: Option<BankUsage>,
+    coverage: Option<RomCoverage>,
+    /// (expected, found) sizes of a save rejected when loading
+    rejected_save: Rc<Cell<Option<(usize, usize)>>>,
+}
+
+impl MBC {
+    /// `mapper` replaces the header cartridge type `code` when set
+    pub fn new<S: GameSave + 'static>(
+        code: u8,
+        mapper: Option<MapperOverride>,
+        rom: Vec<u8>,
+        ram_size: usize,
+        saver: S,
+    ) -> Self {
+        let code = match mapper {
+            Some(MapperOverride::CartridgeType(code)) => code,
+            _ => code,
+        };
+        let ram_banks = match code {
+            _ if is_gb_memory(&rom) => GB_MEMORY_RAM_SIZE / 0x2000,
+            // MBC2 built-in 512 half bytes, MBC7 EEPROM
+            0x05..=0x06 | 0x22 => 1,
+            _ => ram_size.div_ceil(0x2000),
+        };
+        let rejected_save = Rc::new(Cell::new(None));
+        let saver = RejectionRecorder {
+            inner: saver,
+            rejected: rejected_save.clone(),
+        };
+        let rom_banks = (rom.len() / 0x4000).max(1);
+        let target_mbc: Box<dyn Mapper> = match mapper {
+            Some(MapperOverride::WisdomTree) => Box::new(WisdomTree::new(rom)),
+            _ => get_target_mbc(code, rom, ram_size, saver),
+        };
+        Self {
+            rom_banks,
+            ram_banks,
+            target_mbc,
+            bank_usage: None,
+            coverage: None,
+            rejected_save,
+        }
+    }
+
+    /// (expected, found) sizes of the save rejected when loading, once
+    pub fn take_rejected_save(&self) -> Option<(usize, usize)> {
+        self.rejected_save.take()
+    }
+
+    pub fn replace_rom(&mut self, rom: Vec<u8>) {
+        self.target_mbc.replace_rom(rom);
+    }
+
+    pub fn save(&self, w: &mut ChunkWriter) {
+        self.target_mbc.save(w);
+    }
+
+    pub fn load(&mut self, r: &mut ChunkReader) {
+        self.target_mbc.load(r);
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.target_mbc.set_tilt(x, y);
+    }
+
+    /// Enables or disables per-bank access counting, disabling drops the collected counters
+    pub fn set_bank_usage(&mut self, enabled: bool) {
+        self.bank_usage = match enabled {
+            true => self
+                .bank_usage
+                .take()
+                .or_else(|| Some(BankUsage::new(self.rom_banks, self.ram_banks))),
+            false => None,
+        };
+    }
+
+    pub fn bank_usage(&self) -> Option<&BankUsage> {
+        self.bank_usage.as_ref()
+    }
+
+    /// Enables or disables per rom byte access flags, disabling drops the collected flags
+    pub fn set_rom_coverage(&mut self, enabled: bool) {
+        self.coverage = match enabled {
+            true => self
+                .coverage
+                .take()
+                .or_else(|| Some(RomCoverage::new(self.rom_banks * 0x4000))),
+            false => None,
+        };
+    }
+
+    pub fn rom_coverage(&self) -> Option<&RomCoverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Offset in the rom of the byte mapped at `address`, in 0000-7FFF
+    fn rom_offset(&self, address: u16) -> usize {
+        self.rom_bank(address) * 0x4000 + (address & 0x3FFF) as usize
+    }
+
+    /// Flags the `len` bytes of the instruction at `address` as executed
+    pub fn record_execution(&self, address: u16, len: u16) {
+        let Some(coverage) = &self.coverage else {
+            return;
+        };
+        for address in (address..address.saturating_add(len)).filter(|&a| a <= 0x7FFF) {
+            coverage.record(self.rom_offset(address), RomCoverage::EXECUTED);
+        }
+    }
+
+    /// Rom bank mapped at `address`, in 0000-7FFF
+    pub fn rom_bank(&self, address: u16) -> usize {
+        let banks = self.target_mbc.mapped_banks();
+        match address {
+            ..=0x3FFF => banks.rom0 % self.rom_banks,
+            _ => banks.romx % self.rom_banks,
+        }
+    }
+
+    /// Ram bank mapped at A000-BFFF, None when the ram is disabled or absent
+    pub fn ram_bank(&self) -> Option<usize> {
+        if self.ram_banks == 0 {
+            return None;
+        }
+        self.target_mbc
+            .mapped_banks()
+            .ram
+            .map(|bank| bank % self.ram_banks)
+    }
+
+    /// Counts a cpu or DMA access to the bank currently mapped at `address`
+    pub fn record_access(&self, address: u16, write: bool) {
+        if let (Some(coverage), 0x0000..=0x7FFF) = (&self.coverage, address) {
+            let flag = match write {
+                true => RomCoverage::WRITTEN,
+                false => RomCoverage::READ,
+            };
+            coverage.record(self.rom_offset(address), flag);
+        }
+
+        let Some(usage) = &self.bank_usage else {
+            return;
+        };
+
+        let banks = self.target_mbc.mapped_banks();
+        match (address, write) {
+            (0x0000..=0x7FFF, true) => usage.record_register_write(),
+            (0x0000..=0x3FFF, false) => usage.record_rom_read(banks.rom0 % self.rom_banks),
+            (0x4000..=0x7FFF, false) => usage.record_rom_read(banks.romx % self.rom_banks),
+            (0xA000..=0xBFFF, _) if self.ram_banks > 0 => {
+                if let Some(bank) = banks.ram {
+                    match write {
+                        true => usage.record_ram_write(bank % self.ram_banks),
+                        false => usage.record_ram_read(bank % self.ram_banks),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl MemReadWriter for MBC {
+    fn read_byte(&self, address: u16) -> u8 {
+        self.target_mbc.read_byte(address)
+    }
+    fn write_byte(&mut self, address: u16, value: u8) {
+        self.target_mbc.write_byte(address, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::{Cell, RefCell},
+        io::Error,
+        rc::Rc,
+    };
+
+    use crate::{
+        saver,
+        savestate::{StateReader, StateWriter},
+    };
+
+    use super::*;
+
+    // https://gbdev.io/pandocs/MBC1.html#addressing-diagrams
+
+    fn new_mbc1() -> MBC1<saver::Fake> {
+        MBC1::new(vec![], 0x2000, saver::Fake)
+    }
+
+    #[test]
+    fn test_mbc1_addressing_bank_0_simple_mode() {
+        let mut mbc1 = new_mbc1();
+
+        for addr in MBC1_ROM_BANK_0_START_ADDR..=MBC1_ROM_BANK_0_END_ADDR {
+            assert_eq!(addr as usize, mbc1.get_rom_address(addr));
+        }
+
+        mbc1.write_byte(MBC1_RAM_BANK_NUM_REG_START_ADDR, 1);
+
+        for addr in MBC1_ROM_BANK_0_START_ADDR..=MBC1_ROM_BANK_0_END_ADDR {
+            assert_eq!(addr as usize, mbc1.get_rom_address(addr));
+        }
+    }
+
+    #[test]
+    fn test_mbc1_addressing_bank_0_advanced_mode() {
+        let mut mbc1 = new_mbc1();
+
+        mbc1.write_byte(MBC1_BANKING_MODE_REG_START_ADDR, 1);
+        mbc1.write_byte(MBC1_RAM_BANK_NUM_REG_START_ADDR, 1);
+
+        for addr in MBC1_ROM_BANK_0_START_ADDR..=MBC1_ROM_BANK_0_END_ADDR {
+            assert_eq!((1 << 19) | addr as usize, mbc1.get_rom_address(addr));
+        }
+    }
+
+    #[test]
+    fn test_mbc1_addressing_bank_01_7f_simple_mode() {
+        let mut mbc1 = new_mbc1();
+
+        mbc1.write_byte(MBC1_ROM_BANK_NUM_REG_START_ADDR, 1);
+        mbc1.write_byte(MBC1_RAM_BANK_NUM_REG_START_ADDR, 1);
+
+        for addr in MBC1_ROM_BANK_01_7F_START_ADDR..=MBC1_ROM_BANK_01_7F_END_ADDR {
+            assert_eq!(
+                (1 << 19) | (1 << 14) | addr as usize,
+                mbc1.get_rom_address(addr)
+            );
+        }
+    }
+
+    #[test]
+    fn test_mbc1_addressing_bank_01_7f_advanced_mode() {
+        let mut mbc1 = new_mbc1();
+
+        mbc1.write_byte(MBC1_BANKING_MODE_REG_START_ADDR, 1);
+        mbc1.write_byte(MBC1_ROM_BANK_NUM_REG_START_ADDR, 1);
+        mbc1.write_byte(MBC1_RAM_BANK_NUM_REG_START_ADDR, 1);
+
+        for addr in MBC1_ROM_BANK_01_7F_START_ADDR..=MBC1_ROM_BANK_01_7F_END_ADDR {
+            assert_eq!(
+                (1 << 19) | (1 << 14) | addr as usize,
+                mbc1.get_rom_address(addr)
+            );
+        }
+    }
+
+    #[test]
+    fn test_mbc1_addressing_bank_01_7f_0_treated_as_1() {
+        let mut mbc1 = new_mbc1();
+
+        mbc1.write_byte(MBC1_ROM_BANK_NUM_REG_START_ADDR, 0);
+
+        for addr in MBC1_ROM_BANK_01_7F_START_ADDR..=MBC1_ROM_BANK_01_7F_END_ADDR {
+            assert_eq!((1 << 14) | addr as usize, mbc1.get_rom_address(addr));
         }
     }
 
@@ -477,4 +2115,750 @@ mod tests {
             );
         }
     }
+
+    // https://gbdev.io/pandocs/MBC5.html
+
+    /// 8 MiB rom, the largest MBC5 maps, each bank starting with its 9-bit number
+    fn new_mbc5() -> MBC5<saver::Fake> {
+        let mut rom = vec![0; 512 * 0x4000];
+        for bank in 0..512 {
+            rom[bank * 0x4000..][..2].copy_from_slice(&(bank as u16).to_le_bytes());
+        }
+        MBC5::new(rom, 16 * 0x2000, saver::Fake)
+    }
+
+    fn mbc5_mapped_bank(mbc5: &MBC5<saver::Fake>) -> u16 {
+        u16::from_le_bytes([mbc5.read_byte(0x4000), mbc5.read_byte(0x4001)])
+    }
+
+    #[test]
+    fn test_mbc5_addressing_bank_0() {
+        let mbc5 = new_mbc5();
+
+        for addr in 0x0000..=0x3FFF {
+            assert_eq!(mbc5.rom[addr as usize], mbc5.read_byte(addr));
+        }
+        assert_eq!(1, mbc5_mapped_bank(&mbc5));
+    }
+
+    #[test]
+    fn test_mbc5_addressing_bank_0_selectable() {
+        let mut mbc5 = new_mbc5();
+
+        // unlike MBC1, 0 isn't treated as 1
+        mbc5.write_byte(0x2000, 0);
+
+        for addr in 0x4000..=0x7FFF {
+            assert_eq!((addr - 0x4000) as usize, mbc5.get_rom_address(addr));
+        }
+        assert_eq!(0, mbc5_mapped_bank(&mbc5));
+        assert_eq!(0, mbc5.mapped_banks().romx);
+    }
+
+    #[test]
+    fn test_mbc5_addressing_bank_000_1ff() {
+        let mut mbc5 = new_mbc5();
+
+        for bank in [0x01, 0x7F, 0x80, 0xFF, 0x100, 0x101, 0x180, 0x1FF] {
+            mbc5.write_byte(0x2000, bank as u8);
+            mbc5.write_byte(0x3000, (bank >> 8) as u8);
+
+            for addr in [0x4000, 0x5555, 0x7FFF] {
+                assert_eq!(
+                    (bank << 14) | (addr - 0x4000) as usize,
+                    mbc5.get_rom_address(addr)
+                );
+            }
+            assert_eq!(bank as u16, mbc5_mapped_bank(&mbc5), "bank {bank:#x}");
+            assert_eq!(bank, mbc5.mapped_banks().romx);
+        }
+    }
+
+    #[test]
+    fn test_mbc5_addressing_9th_bit_register() {
+        let mut mbc5 = new_mbc5();
+
+        // only bit 0 is wired, the lower 8 bits are kept
+        mbc5.write_byte(0x2000, 0x34);
+        mbc5.write_byte(0x3FFF, 0xFE);
+        assert_eq!(0x034, mbc5_mapped_bank(&mbc5));
+        mbc5.write_byte(0x3000, 0xFF);
+        assert_eq!(0x134, mbc5_mapped_bank(&mbc5));
+
+        // and the 9th bit is kept when the lower bits change
+        mbc5.write_byte(0x2FFF, 0x00);
+        assert_eq!(0x100, mbc5_mapped_bank(&mbc5));
+    }
+
+    #[test]
+    fn test_mbc5_addressing_rom_smaller_than_bank() {
+        let mut rom = vec![0; 4 * 0x4000];
+        for bank in 0..4 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        let mut mbc5 = MBC5::new(rom, 0, saver::Fake);
+
+        // banks past the rom size wrap around
+        mbc5.write_byte(0x2000, 0x06);
+        assert_eq!(2, mbc5.read_byte(0x4000));
+        mbc5.write_byte(0x3000, 1);
+        assert_eq!(2, mbc5.read_byte(0x4000));
+        mbc5.write_byte(0x2000, 0x00);
+        assert_eq!(0, mbc5.read_byte(0x4000));
+    }
+
+    #[test]
+    fn test_mbc5_addressing_ram() {
+        let mut mbc5 = new_mbc5();
+        mbc5.write_byte(0x0000, 0x0A);
+
+        for bank in 0..16 {
+            // only the lower nibble selects the bank
+            mbc5.write_byte(0x4000, 0xF0 | bank);
+            for addr in [0xA000, 0xB234, 0xBFFF] {
+                assert_eq!(
+                    ((bank as usize) << 13) | (addr - 0xA000) as usize,
+                    mbc5.get_ram_address(addr)
+                );
+            }
+            mbc5.write_byte(0xA000, bank);
+        }
+        for bank in 0..16 {
+            mbc5.write_byte(0x4000, bank);
+            assert_eq!(bank, mbc5.read_byte(0xA000));
+        }
+    }
+
+    /// 1 MiB rom, each bank starting with its number
+    fn new_mmm01() -> MMM01<saver::Fake> {
+        let mut rom = vec![0; 64 * 0x4000];
+        for bank in 0..64 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        MMM01::new(rom, 4 * 0x2000, saver::Fake)
+    }
+
+    #[test]
+    fn test_mmm01_boots_unmapped() {
+        let mut mmm01 = new_mmm01();
+        assert_eq!(62, mmm01.read_byte(0x0000));
+        assert_eq!(63, mmm01.read_byte(0x4000));
+
+        mmm01.write_byte(0x2000, 0x05);
+        assert_eq!(63, mmm01.read_byte(0x4000), "the menu stays mapped");
+    }
+
+    #[test]
+    fn test_mmm01_detected_from_the_menu_header() {
+        let mut rom = new_mmm01().rom;
+        // the first game header, menu header in the last 32 KiB
+        let menu = rom.len() - 0x8000;
+        rom[0x0147] = 0x01;
+        rom[menu + 0x0147] = 0x0B;
+        assert!(!is_mmm01(&rom), "bad header checksum");
+        rom[menu + 0x014D] = 0xDC;
+        assert!(is_mmm01(&rom));
+        let mbc = MBC::new(0x01, None, rom, 4 * 0x2000, saver::Fake);
+        assert_eq!(62, mbc.read_byte(0x0000), "the menu is mapped");
+    }
+
+    #[test]
+    fn test_mmm01_maps_a_game() {
+        let mut mmm01 = new_mmm01();
+        // 256 KiB game at bank 32: bank bit 4 masked, outer bank bit 5 set
+        mmm01.write_byte(0x6000, 0b1000 << 2);
+        mmm01.write_byte(0x2000, 0x20);
+        mmm01.write_byte(0x0000, 0x40);
+
+        assert_eq!(32, mmm01.read_byte(0x0000));
+        assert_eq!(33, mmm01.read_byte(0x4000), "bank 0 treated as 1");
+
+        mmm01.write_byte(0x2000, 0x1F);
+        assert_eq!(47, mmm01.read_byte(0x4000), "masked bit kept");
+        mmm01.write_byte(0x2000, 0x65);
+        assert_eq!(37, mmm01.read_byte(0x4000), "outer bits locked");
+        assert_eq!(32, mmm01.read_byte(0x0000));
+
+        // the game can't unmap itself
+        mmm01.write_byte(0x0000, 0x00);
+        mmm01.write_byte(0x6000, 0x00);
+        assert_eq!(37, mmm01.read_byte(0x4000));
+        assert_eq!(0b1000, mmm01.rom_bank_mask);
+    }
+
+    #[test]
+    fn test_mmm01_ram_banking() {
+        let mut mmm01 = new_mmm01();
+        // game with one ram bank at bank 2: both low bits masked, locked in simple mode
+        mmm01.write_byte(0x4000, 0x02);
+        mmm01.write_byte(0x0000, 0x7A);
+        mmm01.write_byte(0x4000, 0x01);
+        mmm01.write_byte(0xA000, 0x42);
+        assert_eq!(Some(2), mmm01.mapped_banks().ram);
+
+        let mut mmm01 = new_mmm01();
+        // advanced mode locked before mapping, the game banks with the low bits
+        mmm01.write_byte(0x6000, 0x01);
+        mmm01.write_byte(0x4000, 0x40);
+        mmm01.write_byte(0x0000, 0x4A);
+        mmm01.write_byte(0x6000, 0x00);
+        mmm01.write_byte(0x4000, 0x03);
+        mmm01.write_byte(0xA000, 0x42);
+        assert_eq!(Some(3), mmm01.mapped_banks().ram);
+        mmm01.write_byte(0x4000, 0x00);
+        assert_eq!(0x00, mmm01.read_byte(0xA000));
+        mmm01.write_byte(0x4000, 0x03);
+        assert_eq!(0x42, mmm01.read_byte(0xA000));
+    }
+
+    /// Menu at block 0, a 256 KiB MBC1 game with 8 KiB of ram at block 1 and a 128 KiB MBC5
+    /// game with 32 KiB of ram at block 3, each bank starting with its number in the flash
+    fn new_gb_memory() -> GBMemory<saver::Fake> {
+        let mut rom = vec![0; GB_MEMORY_ROM_SIZE];
+        for bank in 0..64 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom[0x0134..0x0143].copy_from_slice(b"NP M-MENU MENU\0");
+        for (block, cartridge_type, rom_size, ram_size) in
+            [(1, 0x03, 0x03, 0x02), (3, 0x1B, 0x02, 0x03)]
+        {
+            let header = block * GB_MEMORY_BLOCK_SIZE + 0x0147;
+            rom[header..header + 3].copy_from_slice(&[cartridge_type, rom_size, ram_size]);
+        }
+        assert!(is_gb_memory(&rom));
+        GBMemory::new(rom, saver::Fake)
+    }
+
+    fn map_gb_memory_game(gbm: &mut GBMemory<saver::Fake>, block: u8) {
+        for (address, value) in [(0x0120, 0x09), (0x0121, 0xAA), (0x0122, 0x55)] {
+            gbm.write_byte(address, value);
+        }
+        for command in [0x80 | block, 0xC0] {
+            gbm.write_byte(0x0120, command);
+            gbm.write_byte(0x013F, 0xA5);
+        }
+    }
+
+    #[test]
+    fn test_gb_memory_boots_into_menu() {
+        let mut gbm = new_gb_memory();
+        assert_eq!(0, gbm.read_byte(0x0000));
+        assert_eq!(1, gbm.read_byte(0x4000));
+        gbm.write_byte(0x2000, 0x07);
+        assert_eq!(7, gbm.read_byte(0x4000));
+        gbm.write_byte(0x2000, 0x08);
+        assert_eq!(0, gbm.read_byte(0x4000), "the menu only sees its 128 KiB");
+
+        // the whole ram is banked by the menu
+        gbm.write_byte(0x0000, 0x0A);
+        gbm.write_byte(0x4000, 0x0F);
+        gbm.write_byte(0xA000, 0x42);
+        assert_eq!(0x42, gbm.ram[0x1E000]);
+
+        // locked without the unlock sequence
+        gbm.write_byte(0x0120, 0x81);
+        gbm.write_byte(0x013F, 0xA5);
+        gbm.write_byte(0x0120, 0xC0);
+        gbm.write_byte(0x013F, 0xA5);
+        assert!(!gbm.mapped);
+    }
+
+    #[test]
+    fn test_gb_memory_maps_games() {
+        let mut gbm = new_gb_memory();
+        map_gb_memory_game(&mut gbm, 1);
+        assert_eq!(8, gbm.read_byte(0x0000));
+        assert_eq!(9, gbm.read_byte(0x4000));
+        gbm.write_byte(0x2000, 0x00);
+        assert_eq!(9, gbm.read_byte(0x4000), "MBC1 bank 0 treated as 1");
+        gbm.write_byte(0x2000, 0x13);
+        assert_eq!(11, gbm.read_byte(0x4000), "wrapped to the game size");
+        assert_eq!(8 + 3, gbm.mapped_banks().romx);
+
+        // the registers belong to the game once mapped
+        gbm.write_byte(0x0120, 0x0A);
+        gbm.write_byte(0xA000, 0x42);
+        assert_eq!(0x42, gbm.ram[0]);
+        map_gb_memory_game(&mut gbm, 3);
+        assert_eq!(8, gbm.read_byte(0x0000));
+
+        let mut gbm = new_gb_memory();
+        map_gb_memory_game(&mut gbm, 3);
+        assert_eq!(24, gbm.read_byte(0x0000));
+        gbm.write_byte(0x2000, 0x00);
+        assert_eq!(24, gbm.read_byte(0x4000), "MBC5 bank 0 kept");
+        gbm.write_byte(0x0000, 0x0A);
+        gbm.write_byte(0x4000, 0x03);
+        gbm.write_byte(0xBFFF, 0x42);
+        assert_eq!(
+            0x42,
+            gbm.ram[0x2000 + 0x7FFF],
+            "ram placed after the first game"
+        );
+    }
+
+    #[test]
+    fn test_gb_memory_rejects_empty_blocks() {
+        let mut gbm = new_gb_memory();
+        // block 2 lies inside the first game, block 0 is the menu
+        for block in [0, 2] {
+            map_gb_memory_game(&mut gbm, block);
+            assert!(!gbm.mapped);
+        }
+    }
+
+    #[test]
+    fn test_gb_memory_load_rebuilds_games() {
+        let mut gbm = new_gb_memory();
+        map_gb_memory_game(&mut gbm, 1);
+        gbm.write_byte(0x2000, 0x03);
+        let mut w = ChunkWriter::default();
+        gbm.save(&mut w);
+        let mut state = StateWriter::new();
+        state.write_chunk(*b"CART", 1, w);
+        let state = state.finish();
+        let (_, mut r) = StateReader::new(&state).unwrap().chunk(*b"CART").unwrap();
+
+        let mut restored = new_gb_memory();
+        restored.load(&mut r);
+        assert_eq!(11, restored.read_byte(0x4000));
+
+        // no ram, then a mapped game at block 1 with a zero rom size and a ram offset past the
+        // ram, and a selected game at a block past the rom
+        let mut corrupt = vec![0; 4];
+        for (block, rom_size, ram_offset) in [(1u32, 0u32, 0xFF_FFFFu32), (9, 0x8000, 0)] {
+            corrupt.extend([1, 5]);
+            for field in [block, rom_size, ram_offset, 0x2000] {
+                corrupt.extend(field.to_le_bytes());
+            }
+        }
+        corrupt.extend([1, 0, 0, 1, 0x03, 0x00, 0, 0]);
+        let mut gbm = new_gb_memory();
+        gbm.load(&mut ChunkReader::new(&corrupt));
+        assert_eq!(8 + 3, gbm.mapped_banks().romx);
+        gbm.write_byte(0xA000, 0x42);
+        assert_eq!(0x42, gbm.read_byte(0xA000));
+        assert!(gbm.selected.is_none());
+    }
+
+    /// 256 KiB rom, each 32 KiB bank starting with its number
+    fn wisdom_tree_rom() -> Vec<u8> {
+        let mut rom = vec![0; 8 * 0x8000];
+        for bank in 0..8 {
+            rom[bank * 0x8000] = bank as u8;
+            rom[bank * 0x8000 + 0x4000] = 0x80 | bank as u8;
+        }
+        rom[0x0200..0x020B].copy_from_slice(b"WISDOM TREE");
+        rom
+    }
+
+    #[test]
+    fn test_wisdom_tree() {
+        let rom = wisdom_tree_rom();
+        assert!(is_wisdom_tree(&rom));
+        let mut mbc = MBC::new(0x00, None, rom, 0, saver::Fake);
+        assert_eq!(0x80, mbc.read_byte(0x4000));
+
+        // the address selects the bank, not the value
+        mbc.write_byte(0x0003, 0x00);
+        assert_eq!(3, mbc.read_byte(0x0000));
+        assert_eq!(0x83, mbc.read_byte(0x4000));
+        assert_eq!((6, 7), (mbc.rom_bank(0x0000), mbc.rom_bank(0x4000)));
+        mbc.write_byte(0x2109, 0x01);
+        assert_eq!(1, mbc.read_byte(0x0000), "wrapped to the rom size");
+        mbc.write_byte(0x4005, 0x01);
+        assert_eq!(1, mbc.read_byte(0x0000));
+        assert_eq!(0xFF, mbc.read_byte(0xA000));
+    }
+
+    #[test]
+    fn test_mapper_override() {
+        let mut rom = wisdom_tree_rom();
+        rom[0x0200] = 0;
+        assert!(!is_wisdom_tree(&rom));
+
+        // blank header too large for no mapper
+        let mut mbc = MBC::new(0x00, None, rom.clone(), 0, saver::Fake);
+        mbc.write_byte(0x2000, 0x03);
+        assert_eq!(0x81, mbc.read_byte(0x4000));
+
+        // padded or overdumped roms keep no mapper
+        for padding in [vec![0xFF; 0x8000], rom[..0x8000].to_vec()] {
+            let mut padded = rom[..0x8000].to_vec();
+            padded.extend(padding);
+            assert!(!has_banked_data(&padded));
+            let mut mbc = MBC::new(0x00, None, padded, 0, saver::Fake);
+            mbc.write_byte(0x2000, 0x03);
+            assert_eq!(rom[0x4000], mbc.read_byte(0x4000));
+        }
+
+        let mut mbc = MBC::new(
+            0x00,
+            Some(MapperOverride::WisdomTree),
+            rom.clone(),
+            0,
+            saver::Fake,
+        );
+        mbc.write_byte(0x0002, 0x00);
+        assert_eq!(2, mbc.read_byte(0x0000));
+
+        let mut mbc = MBC::new(
+            0x00,
+            Some(MapperOverride::CartridgeType(0x01)),
+            rom,
+            0,
+            saver::Fake,
+        );
+        mbc.write_byte(0x2000, 0x00);
+        assert_eq!(0x80, mbc.read_byte(0x4000), "MBC1 bank 0 treated as 1");
+    }
+
+    #[test]
+    fn test_bank_usage() {
+        // MBC1+RAM, 8 rom banks, 4 ram banks
+        let mut mbc = MBC::new(0x03, None, vec![0; 8 * 0x4000], 0x8000, saver::Fake);
+        mbc.record_access(0x4000, false);
+        assert!(mbc.bank_usage().is_none());
+
+        mbc.set_bank_usage(true);
+        mbc.record_access(0x0100, false);
+        mbc.record_access(0x4000, false);
+        mbc.write_byte(0x2000, 5);
+        mbc.record_access(0x2000, true);
+        mbc.record_access(0x7FFF, false);
+        // bank 13 wraps to 5
+        mbc.write_byte(0x2000, 13);
+        mbc.record_access(0x4000, false);
+
+        // ram disabled, not counted
+        mbc.record_access(0xA000, true);
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0x6000, 1);
+        mbc.write_byte(0x4000, 2);
+        mbc.record_access(0xA000, true);
+        mbc.record_access(0xA001, false);
+
+        let usage = mbc.bank_usage().unwrap();
+        assert_eq!(8, usage.rom_banks());
+        assert_eq!(4, usage.ram_banks());
+        assert_eq!(vec![0, 1, 5], usage.used_rom_banks());
+        assert_eq!(2, usage.rom_reads(5));
+        assert_eq!(1, usage.register_writes());
+        assert_eq!(vec![2], usage.used_ram_banks());
+        assert_eq!((1, 1), (usage.ram_reads(2), usage.ram_writes(2)));
+
+        mbc.set_bank_usage(false);
+        assert!(mbc.bank_usage().is_none());
+    }
+
+    #[test]
+    fn test_savestate_restores_banking() {
+        // MBC1+RAM, 8 rom banks, 4 ram banks
+        let rom: Vec<u8> = (0..8 * 0x4000).map(|i| (i / 0x4000) as u8).collect();
+        let mut mbc = MBC::new(0x03, None, rom.clone(), 0x8000, saver::Fake);
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0x6000, 1);
+        mbc.write_byte(0x4000, 2);
+        mbc.write_byte(0x2000, 5);
+        mbc.write_byte(0xA000, 0x42);
+
+        let mut w = ChunkWriter::default();
+        mbc.save(&mut w);
+
+        let mut state = StateWriter::new();
+        state.write_chunk(*b"CART", 1, w);
+        let state = state.finish();
+        let (_, mut r) = StateReader::new(&state).unwrap().chunk(*b"CART").unwrap();
+
+        let mut restored = MBC::new(0x03, None, rom, 0x8000, saver::Fake);
+        restored.load(&mut r);
+        assert_eq!(5, restored.read_byte(0x4000));
+        assert_eq!(0x42, restored.read_byte(0xA000));
+        restored.write_byte(0x4000, 0);
+        assert_ne!(0x42, restored.read_byte(0xA000));
+    }
+
+    /// Keeps the last saved ram
+    struct RecordingSaver(Rc<RefCell<Vec<u8>>>);
+
+    impl GameSave for RecordingSaver {
+        fn load(&self) -> Result<Vec<u8>, Error> {
+            Ok(self.0.borrow().clone())
+        }
+
+        fn save(&self, ram: &[u8]) -> Result<(), Error> {
+            *self.0.borrow_mut() = ram.to_vec();
+            Ok(())
+        }
+    }
+
+    fn new_mbc7() -> (MBC7<RecordingSaver>, Rc<RefCell<Vec<u8>>>) {
+        let saved = Rc::new(RefCell::new(vec![]));
+        let mut rom = vec![0; 8 * 0x4000];
+        rom[5 * 0x4000] = 5;
+        let mut mbc7 = MBC7::new(rom, RecordingSaver(saved.clone()));
+        mbc7.write_byte(0x0000, 0x0A);
+        mbc7.write_byte(0x4000, 0x40);
+        (mbc7, saved)
+    }
+
+    /// Shifts the `count` low bits of `bits` into the EEPROM, MSB first, and returns DO after
+    /// each rising clock edge
+    fn eeprom_shift(mbc7: &mut MBC7<RecordingSaver>, bits: u32, count: u8) -> Vec<bool> {
+        (0..count)
+            .rev()
+            .map(|i| {
+                let di = ((bits >> i) & 1) as u8 * 0x02;
+                mbc7.write_byte(0xA080, 0x80 | di);
+                mbc7.write_byte(0xA080, 0xC0 | di);
+                mbc7.read_byte(0xA080) & 1 != 0
+            })
+            .collect()
+    }
+
+    /// Runs a command with chip select held, from start bit to the last data bit
+    fn eeprom_command(mbc7: &mut MBC7<RecordingSaver>, command: u32, count: u8) -> Vec<bool> {
+        mbc7.write_byte(0xA080, 0x80);
+        let out = eeprom_shift(mbc7, command, count);
+        mbc7.write_byte(0xA080, 0x00);
+        out
+    }
+
+    #[test]
+    fn test_mbc7_eeprom() {
+        // start bit, 2 bits opcode and 8 bits address
+        const EWEN: u32 = 0b100_1100_0000;
+        const EWDS: u32 = 0b100_0000_0000;
+        const WRITE_5: u32 = 0b101_0000_0101;
+        const WRITE_6: u32 = 0b101_0000_0110;
+        const READ_5: u32 = 0b110_0000_0101;
+
+        let (mut mbc7, saved) = new_mbc7();
+
+        // writes are ignored until enabled
+        eeprom_command(&mut mbc7, WRITE_5 << 16 | 0x1234, 27);
+        assert!(saved.borrow().is_empty());
+
+        eeprom_command(&mut mbc7, EWEN, 11);
+        let out = eeprom_command(&mut mbc7, WRITE_5 << 16 | 0xBEEF, 27);
+        assert!(out[26], "ready once written");
+        assert_eq!(&[0xEF, 0xBE], &saved.borrow()[10..12]);
+
+        eeprom_command(&mut mbc7, EWDS, 11);
+        eeprom_command(&mut mbc7, WRITE_6 << 16 | 0x1234, 27);
+        assert_eq!(&[0, 0], &saved.borrow()[12..14]);
+
+        // dummy 0 after the address, then the words from the address on
+        mbc7.write_byte(0xA080, 0x80);
+        let command_out = eeprom_shift(&mut mbc7, READ_5, 11);
+        assert!(!command_out[10]);
+        let out = eeprom_shift(&mut mbc7, 0, 32);
+        let word = |bits: &[bool]| bits.iter().fold(0u16, |w, &b| w << 1 | b as u16);
+        assert_eq!(0xBEEF, word(&out[..16]));
+        assert_eq!(0x0000, word(&out[16..]));
+        mbc7.write_byte(0xA080, 0x00);
+        assert_eq!(0x01, mbc7.read_byte(0xA080));
+    }
+
+    #[test]
+    fn test_mbc7_accelerometer() {
+        let (mut mbc7, _) = new_mbc7();
+        mbc7.set_tilt(0.5, -1.);
+        let read = |mbc7: &MBC7<_>| {
+            [0xA020, 0xA030, 0xA040, 0xA050].map(|address| mbc7.read_byte(address))
+        };
+
+        assert_eq!([0x00, 0x80, 0x00, 0x80], read(&mbc7));
+        // latching needs the readings to be erased first
+        mbc7.write_byte(0xA010, 0xAA);
+        assert_eq!([0x00, 0x80, 0x00, 0x80], read(&mbc7));
+
+        mbc7.write_byte(0xA000, 0x55);
+        mbc7.write_byte(0xA010, 0xAA);
+        assert_eq!([0x08, 0x82, 0x60, 0x81], read(&mbc7));
+        // the latched readings stay until erased again
+        mbc7.set_tilt(0., 0.);
+        mbc7.write_byte(0xA010, 0xAA);
+        assert_eq!([0x08, 0x82, 0x60, 0x81], read(&mbc7));
+
+        // both enables are needed to reach the registers
+        mbc7.write_byte(0x4000, 0x00);
+        assert_eq!([0xFF; 4], read(&mbc7));
+    }
+
+    #[test]
+    fn test_mbc7_rom_banking() {
+        let (mut mbc7, _) = new_mbc7();
+        mbc7.write_byte(0x2000, 5);
+        assert_eq!(5, mbc7.read_byte(0x4000));
+        assert_eq!(5, mbc7.mapped_banks().romx);
+    }
+
+    thread_local! {
+        static HUC3_CLOCK: Cell<u64> = const { Cell::new(1_000_000) };
+    }
+
+    fn huc3_clock() -> u64 {
+        HUC3_CLOCK.get()
+    }
+
+    fn advance_huc3_clock(seconds: u64) {
+        HUC3_CLOCK.set(HUC3_CLOCK.get() + seconds);
+    }
+
+    fn new_huc3(saved: Rc<RefCell<Vec<u8>>>) -> HuC3<RecordingSaver> {
+        let mut rom = vec![0; 16 * 0x4000];
+        rom[9 * 0x4000] = 9;
+        HuC3::with_clock(rom, 4 * 0x2000, RecordingSaver(saved), huc3_clock)
+    }
+
+    /// Sends an RTC command through the semaphore and returns the response byte
+    fn huc3_command(huc3: &mut HuC3<RecordingSaver>, command: u8) -> u8 {
+        huc3.write_byte(0x0000, 0x0B);
+        huc3.write_byte(0xA000, command);
+        huc3.write_byte(0x0000, 0x0D);
+        assert_eq!(0x01, huc3.read_byte(0xA000), "ready");
+        huc3.write_byte(0xA000, 0xFE);
+        huc3.write_byte(0x0000, 0x0C);
+        huc3.read_byte(0xA000)
+    }
+
+    fn huc3_read_nibbles(huc3: &mut HuC3<RecordingSaver>, index: u8, count: u8) -> Vec<u8> {
+        huc3_command(huc3, 0x40 | (index & 0x0F));
+        huc3_command(huc3, 0x50 | index >> 4);
+        (0..count)
+            .map(|_| huc3_command(huc3, 0x10) & 0x0F)
+            .collect()
+    }
+
+    #[test]
+    fn test_huc3_banking() {
+        let mut huc3 = new_huc3(Rc::default());
+        huc3.write_byte(0x2000, 9);
+        assert_eq!(9, huc3.read_byte(0x4000));
+
+        huc3.write_byte(0x0000, 0x0A);
+        huc3.write_byte(0x4000, 2);
+        huc3.write_byte(0xA000, 0x42);
+        assert_eq!(Some(2), huc3.mapped_banks().ram);
+        huc3.write_byte(0x4000, 1);
+        assert_eq!(0x00, huc3.read_byte(0xA000));
+
+        // read only
+        huc3.write_byte(0x0000, 0x00);
+        huc3.write_byte(0x4000, 2);
+        huc3.write_byte(0xA000, 0x24);
+        assert_eq!(0x42, huc3.read_byte(0xA000));
+
+        huc3.write_byte(0x0000, 0x0E);
+        assert_eq!(0xC0, huc3.read_byte(0xA000), "no infrared light");
+        huc3.write_byte(0x0000, 0x03);
+        assert_eq!(0xFF, huc3.read_byte(0xA000));
+        assert_eq!(None, huc3.mapped_banks().ram);
+    }
+
+    #[test]
+    fn test_huc3_rtc_commands() {
+        let mut huc3 = new_huc3(Rc::default());
+        assert_eq!(0xE1, huc3_command(&mut huc3, 0x62), "clock ready");
+
+        // 1 day, 2 hours and 3 minutes later: minute 123 (0x07B) of day 1
+        advance_huc3_clock(86400 + 123 * 60);
+        assert_eq!(
+            vec![0xB, 0x7, 0x0, 0x1, 0x0, 0x0, 0x0],
+            huc3_read_nibbles(&mut huc3, 0x00, 7)
+        );
+        assert_eq!(0x90, huc3_command(&mut huc3, 0x10) & 0xF0, "read response");
+
+        // set minute 0x021 of day 0x123
+        huc3_command(&mut huc3, 0x40);
+        huc3_command(&mut huc3, 0x50);
+        for nibble in [0x1, 0x2, 0x0, 0x3, 0x2, 0x1] {
+            huc3_command(&mut huc3, 0x30 | nibble);
+        }
+        huc3_command(&mut huc3, 0x20);
+        assert_eq!(
+            vec![0x1, 0x2, 0x0, 0x3, 0x2, 0x1, 0x0],
+            huc3_read_nibbles(&mut huc3, 0x00, 7)
+        );
+
+        // generic memory past the clock
+        huc3_command(&mut huc3, 0x40);
+        huc3_command(&mut huc3, 0x51);
+        huc3_command(&mut huc3, 0x3C);
+        assert_eq!(vec![0xC], huc3_read_nibbles(&mut huc3, 0x10, 1));
+
+        // nothing runs while the semaphore isn't cleared
+        huc3.write_byte(0x0000, 0x0B);
+        huc3.write_byte(0xA000, 0x41);
+        huc3.write_byte(0x0000, 0x0D);
+        huc3.write_byte(0xA000, 0x01);
+        assert_eq!(0x11, huc3.access_index);
+    }
+
+    #[test]
+    fn test_huc3_persists_clock_with_ram() {
+        let saved = Rc::new(RefCell::new(vec![]));
+        let mut huc3 = new_huc3(saved.clone());
+        huc3.write_byte(0x0000, 0x0A);
+        huc3.write_byte(0xA000, 0x99);
+        assert!(saved.borrow().is_empty());
+        advance_huc3_clock(60);
+        huc3.write_byte(0x0000, 0x0B);
+        assert_eq!(4 * 0x2000 + HUC3_RTC_FOOTER_SIZE, saved.borrow().len());
+        assert_eq!(0x99, saved.borrow()[0]);
+        let minutes = huc3.rtc_time().0;
+
+        // the clock keeps running while the game is off
+        advance_huc3_clock(5 * 60);
+        let mut huc3 = new_huc3(saved.clone());
+        assert_eq!(minutes + 5, huc3.rtc_time().0);
+        huc3.write_byte(0x0000, 0x0A);
+        assert_eq!(0x99, huc3.read_byte(0xA000));
+
+        // a save without clock keeps the ram
+        saved.borrow_mut().truncate(4 * 0x2000);
+        let mut huc3 = new_huc3(saved.clone());
+        assert_eq!((0, 0), huc3.rtc_time());
+        huc3.write_byte(0x0000, 0x0A);
+        assert_eq!(0x99, huc3.read_byte(0xA000));
+    }
+
+    #[test]
+    fn test_rejected_save() {
+        let saved = Rc::new(RefCell::new(vec![0x42; 0x2000]));
+        let mbc = MBC::new(
+            0x03,
+            None,
+            vec![0; 2 * 0x4000],
+            0x2000,
+            RecordingSaver(saved.clone()),
+        );
+        assert_eq!(None, mbc.take_rejected_save());
+
+        // a truncated save starts the game with blank ram
+        saved.borrow_mut().truncate(0x1000);
+        let mut mbc = MBC::new(
+            0x03,
+            None,
+            vec![0; 2 * 0x4000],
+            0x2000,
+            RecordingSaver(saved.clone()),
+        );
+        assert_eq!(Some((0x2000, 0x1000)), mbc.take_rejected_save());
+        assert_eq!(None, mbc.take_rejected_save());
+        mbc.write_byte(0x0000, 0x0A);
+        assert_eq!(0x00, mbc.read_byte(0xA000));
+
+        // no save at all isn't a rejection
+        saved.borrow_mut().clear();
+        let mbc = MBC::new(
+            0x03,
+            None,
+            vec![0; 2 * 0x4000],
+            0x2000,
+            RecordingSaver(saved),
+        );
+        assert_eq!(None, mbc.take_rejected_save());
+    }
 }
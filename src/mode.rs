@@ -1,5 +1,44 @@
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub enum Mode {
+    #[default]
     DMG,
     CGB,
 }
+
+/// Hardware revision, affecting the register values left by the boot rom and a few quirks. See
+/// `Config::model`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Model {
+    /// Early Game Boy, different boot register values
+    DMG0,
+    #[default]
+    DMG,
+    /// Game Boy Pocket, A is 0xFF after boot
+    MGB,
+    /// Early Game Boy Color, booting like the CGB here
+    CGB0,
+    CGB,
+}
+
+impl Model {
+    /// Usual revision of `mode`
+    pub fn of(mode: &Mode) -> Self {
+        match mode {
+            Mode::DMG => Self::DMG,
+            Mode::CGB => Self::CGB,
+        }
+    }
+
+    pub fn mode(self) -> Mode {
+        match self {
+            Self::DMG0 | Self::DMG | Self::MGB => Mode::DMG,
+            Self::CGB0 | Self::CGB => Mode::CGB,
+        }
+    }
+
+    /// Monochrome models have the OAM corruption bug and the DMG APU quirks (wave ram access and
+    /// retrigger while on, length timers writable while powered off)
+    pub fn is_monochrome(self) -> bool {
+        matches!(self.mode(), Mode::DMG)
+    }
+}
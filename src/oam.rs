@@ -1,4 +1,7 @@
-use crate::memory::MemReadWriter;
+use crate::{
+    memory::MemReadWriter,
+    savestate::{ChunkReader, ChunkWriter},
+};
 
 pub const BASE_ADDRESS: u16 = 0xFE00;
 
@@ -11,6 +14,31 @@ impl OAM {
     pub fn new() -> Self {
         Self { mem: [0; 0xFEA0] }
     }
+
+    /// Write corruption of the OAM bug: the first word of `row` is mixed with the previous row,
+    /// whose last 3 words are copied over. The first row is never corrupted.
+    pub(crate) fn corrupt_write_row(&mut self, row: usize) {
+        if row == 0 || row >= 20 {
+            return;
+        }
+        let start = BASE_ADDRESS as usize + row * 8;
+        let word = |mem: &[u8], at: usize| u16::from_le_bytes([mem[at], mem[at + 1]]);
+        let a = word(&self.mem, start);
+        let b = word(&self.mem, start - 8);
+        let c = word(&self.mem, start - 4);
+        let corrupted = ((a ^ c) & (b ^ c)) ^ c;
+        self.mem[start..start + 2].copy_from_slice(&corrupted.to_le_bytes());
+        self.mem.copy_within(start - 6..start, start + 2);
+    }
+
+    /// Stored in the PPU savestate chunk
+    pub(crate) fn save(&self, w: &mut ChunkWriter) {
+        w.write_bytes(&self.mem[BASE_ADDRESS as usize..]);
+    }
+
+    pub(crate) fn load(&mut self, r: &mut ChunkReader) {
+        r.read_into(&mut self.mem[BASE_ADDRESS as usize..]);
+    }
 }
 
 impl MemReadWriter for OAM {
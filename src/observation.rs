@@ -0,0 +1,86 @@
+use std::ops::RangeInclusive;
+
+/// Memory regions captured once per frame, laid out back to back in `data` in the configured order
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Observation {
+    pub frame: u64,
+    pub data: Vec<u8>,
+    /// (address, new value) of every observed byte that changed since the previous observation
+    pub changes: Vec<(u16, u8)>,
+}
+
+/// Compact per-frame memory snapshots, meant for reinforcement-learning harnesses driving the
+/// emulator with `GameBoy::frame_advance`
+#[derive(Clone, Debug, Default)]
+pub struct MemoryObserver {
+    regions: Vec<RangeInclusive<u16>>,
+    previous: Option<Vec<u8>>,
+    frame: u64,
+}
+
+impl MemoryObserver {
+    pub fn new(regions: Vec<RangeInclusive<u16>>) -> Self {
+        Self {
+            regions,
+            previous: None,
+            frame: 0,
+        }
+    }
+
+    pub fn regions(&self) -> &[RangeInclusive<u16>] {
+        &self.regions
+    }
+
+    /// Snapshots the regions with the given memory reader.
+    /// The first observation reports every byte as changed.
+    pub fn observe<M: Fn(u16) -> u8>(&mut self, read: M) -> Observation {
+        self.frame += 1;
+
+        let addresses = || self.regions.iter().flat_map(|r| r.clone());
+        let data: Vec<u8> = addresses().map(&read).collect();
+
+        let changes = addresses()
+            .zip(data.iter())
+            .enumerate()
+            .filter(|(i, (_, &v))| self.previous.as_ref().is_none_or(|p| p[*i] != v))
+            .map(|(_, (address, &v))| (address, v))
+            .collect();
+
+        self.previous = Some(data.clone());
+
+        Observation {
+            frame: self.frame,
+            data,
+            changes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    #[test]
+    fn test_observer_snapshots_and_diffs() {
+        let mem = RefCell::new(vec![0u8; 0x10000]);
+        mem.borrow_mut()[0xC000] = 1;
+        mem.borrow_mut()[0xFF80] = 2;
+
+        let mut observer = MemoryObserver::new(vec![0xC000..=0xC001, 0xFF80..=0xFF80]);
+
+        let first = observer.observe(|a| mem.borrow()[a as usize]);
+        assert_eq!(1, first.frame);
+        assert_eq!(vec![1, 0, 2], first.data);
+        assert_eq!(vec![(0xC000, 1), (0xC001, 0), (0xFF80, 2)], first.changes);
+
+        mem.borrow_mut()[0xC001] = 7;
+        let second = observer.observe(|a| mem.borrow()[a as usize]);
+        assert_eq!(vec![1, 7, 2], second.data);
+        assert_eq!(vec![(0xC001, 7)], second.changes);
+
+        let third = observer.observe(|a| mem.borrow()[a as usize]);
+        assert!(third.changes.is_empty());
+    }
+}
@@ -0,0 +1,347 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Dots (4.194304 MHz clock ticks) per emulated frame
+pub const FRAME_DOTS: u64 = 70224;
+pub const DOTS_PER_SECOND: u64 = 4_194_304;
+
+/// Duration of an emulated frame: 70224 dots at 4.194304 MHz (~59.7275 Hz), rounded down to the
+/// nanosecond. Use `frames_duration` to avoid accumulating the rounding error.
+pub const FRAME_DURATION: Duration = Duration::from_nanos(16_742_706);
+
+/// Exact duration of `frames` emulated frames, rounded down to the nanosecond
+pub fn frames_duration(frames: u64) -> Duration {
+    let nanos = frames as u128 * FRAME_DOTS as u128 * 1_000_000_000 / DOTS_PER_SECOND as u128;
+    Duration::from_nanos(nanos as u64)
+}
+
+/// Amount of queued audio samples (interleaved left/right values, as given to `StereoPlayer::play`)
+/// above which the audio-clock pacing waits for the frontend to consume them
+pub const AUDIO_QUEUE_TARGET: usize = crate::apu::SAMPLES_BUFFER_SIZE * 4;
+
+const AUDIO_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Browsers can't block their thread nor read `Instant`: the core never waits on wasm32, its
+/// frontends drive `GameBoy::step_frame` from `requestAnimationFrame` instead
+const CAN_BLOCK: bool = !cfg!(target_arch = "wasm32");
+
+/// Blocks the current thread, returns right away on wasm32
+pub(crate) fn sleep(duration: Duration) {
+    if CAN_BLOCK {
+        thread::sleep(duration);
+    }
+}
+
+/// Strategy used by `GameBoy` to pace emulation at frame boundaries.
+/// `Video` and `Audio` don't wait on wasm32, where the browser paces the frames.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Pacing {
+    /// Frames are paced by a timer running at the emulated video refresh rate
+    Video,
+    /// Emulation is paced by audio consumption: the core waits while the
+    /// `StereoPlayer` reports more than `AUDIO_QUEUE_TARGET` queued samples
+    Audio,
+    /// No pacing at all, the frontend is responsible for timing
+    #[default]
+    Uncapped,
+    /// The core calls the `FramePacer` set with `GameBoy::set_frame_pacer` at frame boundaries,
+    /// uncapped until one is set
+    Delegated,
+}
+
+/// What `GameBoy::set_background` does while the frontend window is out of focus
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BackgroundPolicy {
+    /// Pauses the emulation, and with it the audio
+    #[default]
+    Pause,
+    /// Keeps running silently
+    Mute,
+    /// Keeps running as if in the foreground
+    Run,
+}
+
+/// Frontend pacing called at frame boundaries with `Pacing::Delegated`, right after the frame
+/// was handed to the `LCD`: e.g. blocking until the next vsync for beam chasing or low latency
+/// presentation. Fast-forward is then up to the frontend.
+pub trait FramePacer {
+    /// Blocks until the next frame is allowed to start
+    fn wait_frame(&mut self);
+}
+
+impl<F: FnMut()> FramePacer for F {
+    fn wait_frame(&mut self) {
+        self()
+    }
+}
+
+pub struct FrameLimiter {
+    pacing: Pacing,
+    pacer: Option<Box<dyn FramePacer>>,
+    /// fast-forward factor, 1 is real time
+    speed: u32,
+    /// deadlines are computed from the frames elapsed since `epoch`, so they don't drift
+    epoch: Option<Instant>,
+    frames: u64,
+}
+
+impl FrameLimiter {
+    pub fn new(pacing: Pacing) -> Self {
+        Self {
+            pacing,
+            pacer: None,
+            speed: 1,
+            epoch: None,
+            frames: 0,
+        }
+    }
+
+    /// Runs video paced emulation `speed` times faster.
+    /// Audio pacing follows the amount of audio kept by `stereo::FastForward` instead.
+    pub fn set_speed(&mut self, speed: u32) {
+        self.speed = speed.max(1);
+        self.epoch = None;
+    }
+
+    /// Switches pacing at runtime, the next frame starts a new schedule
+    pub fn set_pacing(&mut self, pacing: Pacing) {
+        self.pacing = pacing;
+        self.epoch = None;
+    }
+
+    /// Starts a new schedule from the next frame, e.g. after a pause, instead of catching up
+    pub fn resync(&mut self) {
+        self.epoch = None;
+    }
+
+    /// Delegates pacing to `pacer`, switching to `Pacing::Delegated`
+    pub fn set_pacer(&mut self, pacer: Box<dyn FramePacer>) {
+        self.pacer = Some(pacer);
+        self.set_pacing(Pacing::Delegated);
+    }
+
+    fn frame_duration(&self) -> Duration {
+        FRAME_DURATION / self.speed
+    }
+
+    fn elapsed(&self, frames: u64) -> Duration {
+        frames_duration(frames) / self.speed
+    }
+
+    /// Blocks until the next frame is allowed to start.
+    /// `queued_samples` is only called for audio-clock pacing.
+    pub fn wait<F: Fn() -> usize>(&mut self, queued_samples: F) {
+        match self.pacing {
+            Pacing::Video | Pacing::Audio if !CAN_BLOCK => {}
+            Pacing::Video => self.wait_video(),
+            Pacing::Audio => {
+                while queued_samples() > AUDIO_QUEUE_TARGET {
+                    sleep(AUDIO_POLL_INTERVAL);
+                }
+            }
+            Pacing::Uncapped => {}
+            Pacing::Delegated => {
+                if let Some(pacer) = &mut self.pacer {
+                    pacer.wait_frame();
+                }
+            }
+        }
+    }
+
+    fn wait_video(&mut self) {
+        let now = Instant::now();
+        let Some(epoch) = self.epoch else {
+            self.epoch = Some(now);
+            self.frames = 1;
+            return;
+        };
+        let deadline = epoch + self.elapsed(self.frames);
+
+        if deadline > now {
+            sleep(deadline - now);
+        } else if now - deadline > self.frame_duration() {
+            // too far behind (e.g. the frontend was suspended), resync instead of catching up
+            self.epoch = Some(now);
+            self.frames = 0;
+        }
+        self.frames += 1;
+    }
+}
+
+/// Locks emulation onto a display refreshing at a rate other than the emulated ~59.7275 Hz,
+/// for frontends presenting on vsync: 60 Hz displays run slightly too fast, so once every
+/// ~220 refreshes no frame gets emulated and the previous one is shown again. Faster displays
+/// get duplicated frames, slower ones some refreshes running two frames.
+///
+/// Frontends preferring smooth motion can instead run exactly one frame per refresh and resample
+/// audio by `speed_ratio`, the game then runs that much faster.
+pub struct VsyncPacer {
+    /// display refresh rate in millihertz
+    refresh_mhz: u64,
+    /// emulated time owed, in dots scaled by `refresh_mhz` to stay in integers
+    debt: u64,
+}
+
+impl VsyncPacer {
+    /// `refresh_mhz`: display refresh rate in millihertz, e.g. 59940 for 59.94 Hz
+    pub fn new(refresh_mhz: u32) -> Self {
+        let refresh_mhz = refresh_mhz.max(1) as u64;
+        Self {
+            refresh_mhz,
+            // half a frame ahead, so the first refreshes always show a frame
+            debt: FRAME_DOTS * refresh_mhz / 2,
+        }
+    }
+
+    /// Emulated frames to run before presenting the next refresh
+    pub fn frames_for_refresh(&mut self) -> u64 {
+        let frame_cost = FRAME_DOTS * self.refresh_mhz;
+        self.debt += DOTS_PER_SECOND * 1000;
+        let frames = self.debt / frame_cost;
+        self.debt %= frame_cost;
+        frames
+    }
+
+    /// Display refresh rate over the emulated frame rate, ~1.0046 for 60 Hz
+    pub fn speed_ratio(&self) -> f64 {
+        (self.refresh_mhz * FRAME_DOTS) as f64 / (DOTS_PER_SECOND * 1000) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    #[test]
+    fn test_video_pacing_waits_one_frame() {
+        let mut limiter = FrameLimiter::new(Pacing::Video);
+
+        let start = Instant::now();
+        limiter.wait(|| 0);
+        limiter.wait(|| 0);
+        limiter.wait(|| 0);
+
+        assert!(Instant::now() - start >= FRAME_DURATION * 2);
+    }
+
+    #[test]
+    fn test_audio_pacing_waits_for_consumption() {
+        let mut limiter = FrameLimiter::new(Pacing::Audio);
+
+        let queued = Cell::new(AUDIO_QUEUE_TARGET + 3);
+        limiter.wait(|| {
+            let q = queued.get();
+            queued.set(q - 1);
+            q
+        });
+
+        assert_eq!(AUDIO_QUEUE_TARGET - 1, queued.get());
+    }
+
+    #[test]
+    fn test_fast_forward_shortens_frames() {
+        let mut limiter = FrameLimiter::new(Pacing::Video);
+        limiter.set_speed(4);
+        assert_eq!(FRAME_DURATION / 4, limiter.frame_duration());
+
+        limiter.set_speed(0);
+        assert_eq!(FRAME_DURATION, limiter.frame_duration());
+    }
+
+    #[test]
+    fn test_exact_frame_duration() {
+        assert_eq!(FRAME_DURATION, frames_duration(1));
+        // a frame is 16742706.29 ns, rounding each frame would lose 17 us per minute
+        assert_eq!(Duration::from_nanos(1_004_562_377), frames_duration(60));
+        // 70224 / 4194304 = 4389 / 262144
+        assert_eq!(Duration::from_secs(4389), frames_duration(262_144));
+    }
+
+    #[test]
+    fn test_video_pacing_doesnt_drift() {
+        let mut limiter = FrameLimiter::new(Pacing::Video);
+        limiter.set_speed(16);
+
+        let start = Instant::now();
+        for _ in 0..=32 {
+            limiter.wait(|| 0);
+        }
+
+        assert!(Instant::now() - start >= frames_duration(32) / 16);
+    }
+
+    #[test]
+    fn test_vsync_pacer_60hz() {
+        let mut pacer = VsyncPacer::new(60_000);
+
+        // 60 refreshes show ~59.73 frames, one refresh out of ~220 repeats a frame
+        let frames: Vec<u64> = (0..600).map(|_| pacer.frames_for_refresh()).collect();
+        assert!(frames.iter().all(|&f| f <= 1));
+        assert_eq!(597, frames.iter().sum::<u64>());
+        assert_eq!(1, frames[0]);
+        assert!((pacer.speed_ratio() - 1.004562).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_vsync_pacer_slow_and_fast_displays() {
+        let mut pacer = VsyncPacer::new(30_000);
+        let frames: u64 = (0..300).map(|_| pacer.frames_for_refresh()).sum();
+        assert_eq!(597, frames);
+
+        let mut pacer = VsyncPacer::new(144_000);
+        let frames: u64 = (0..1440).map(|_| pacer.frames_for_refresh()).sum();
+        assert_eq!(597, frames);
+
+        // matching rates run one frame per refresh
+        let mut pacer = VsyncPacer::new(59_727);
+        assert!((0..1000).all(|_| pacer.frames_for_refresh() == 1));
+    }
+
+    #[test]
+    fn test_uncapped_pacing_never_waits() {
+        let mut limiter = FrameLimiter::new(Pacing::Uncapped);
+
+        limiter.wait(|| unreachable!());
+    }
+
+    #[test]
+    fn test_delegated_pacing() {
+        let mut limiter = FrameLimiter::new(Pacing::Delegated);
+        limiter.wait(|| unreachable!());
+
+        let waits = Rc::new(Cell::new(0));
+        let counter = waits.clone();
+        limiter.set_pacer(Box::new(move || counter.set(counter.get() + 1)));
+        limiter.wait(|| unreachable!());
+        limiter.wait(|| unreachable!());
+        assert_eq!(2, waits.get());
+
+        // the pacer is kept for when pacing is delegated again
+        limiter.set_pacing(Pacing::Uncapped);
+        limiter.wait(|| unreachable!());
+        limiter.set_pacing(Pacing::Delegated);
+        limiter.wait(|| unreachable!());
+        assert_eq!(3, waits.get());
+    }
+
+    #[test]
+    fn test_set_pacing() {
+        let mut limiter = FrameLimiter::new(Pacing::Uncapped);
+        limiter.set_pacing(Pacing::Audio);
+
+        let queued = Cell::new(AUDIO_QUEUE_TARGET + 1);
+        limiter.wait(|| {
+            let q = queued.get();
+            queued.set(q - 1);
+            q
+        });
+        assert_eq!(AUDIO_QUEUE_TARGET - 1, queued.get());
+
+        limiter.set_pacing(Pacing::Uncapped);
+        limiter.wait(|| unreachable!());
+    }
+}
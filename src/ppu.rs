@@ -1,21 +1,27 @@
 use std::array;
 
 use crate::{
+    clock::{ClockDomain, Clocked},
     config::Config,
     interrupts::InterruptRegisters,
-    lcd::{
-        self, LCD, PIXELS_HEIGHT, PIXELS_WIDTH, RGB_BLACK, RGB_DARK_GRAY, RGB_LIGHT_GRAY, RGB_WHITE,
-    },
+    lcd::{self, DmgPalette, PIXELS_HEIGHT, PIXELS_WIDTH},
     memory::MemReadWriter,
-    mode, oam, vram,
+    mode, oam, pacing,
+    savestate::{ChunkReader, ChunkWriter, SaveStateError, Snapshot, StateReader},
+    vram,
 };
 
 const OAM_DOTS: u32 = 80;
 const VRAM_DOTS: u32 = 172;
+/// Dots spent filling the pixel pipeline at the start of mode 3 before the first pixel is pushed
+const PIXEL_PIPELINE_DELAY: u32 = VRAM_DOTS - PIXELS_WIDTH as u32;
 const SCANLINE_DOTS: u32 = 456;
 
 const DOTS_PER_FRAME: u32 = 70224;
 
+/// https://gbdev.io/pandocs/OAM.html#selection-priority
+const MAX_LINE_OBJECTS: usize = 10;
+
 const BIT_7: u8 = 1 << 7;
 
 pub const BG_COLOR_PALETTE_SPEC_REG: u16 = 0xFF68;
@@ -44,13 +50,8 @@ impl From<u8> for GrayShade {
 }
 
 impl GrayShade {
-    fn to_rgb(&self) -> lcd::RGB {
-        match self {
-            Self::White => RGB_WHITE,
-            Self::LightGray => RGB_LIGHT_GRAY,
-            Self::DarkGray => RGB_DARK_GRAY,
-            Self::Black => RGB_BLACK,
-        }
+    fn to_rgb(self, colors: &DmgPalette) -> lcd::RGB {
+        colors.0[self as usize]
     }
 }
 
@@ -69,16 +70,17 @@ trait Palette {
 #[derive(Clone)]
 struct MonochromePalette {
     palette: [GrayShade; 4],
+    colors: DmgPalette,
 }
 
 impl Palette for MonochromePalette {
     fn get_color_from_id(&self, id: u8) -> lcd::RGB {
-        self.palette[id as usize].to_rgb()
+        self.palette[id as usize].to_rgb(&self.colors)
     }
 }
 
 impl MonochromePalette {
-    fn new() -> Self {
+    fn new(colors: DmgPalette) -> Self {
         Self {
             palette: [
                 GrayShade::White,
@@ -86,6 +88,7 @@ impl MonochromePalette {
                 GrayShade::DarkGray,
                 GrayShade::Black,
             ],
+            colors,
         }
     }
 
@@ -420,24 +423,46 @@ pub enum DMARequest {
     },
 }
 
+impl DMARequest {
+    /// CPU cycles the CPU is stalled for while the transfer runs.
+    /// VRAM DMA moves a 0x10 bytes block every 32 dots whatever the speed mode,
+    /// which costs twice as many CPU cycles in double speed mode.
+    /// https://gbdev.io/pandocs/CGB_Registers.html#ff55--hdma5-cgb-mode-only-vram-dma-lengthmodestart
+    /// OAM DMA doesn't stall the CPU.
+    pub fn stall_cycles(&self, double_speed_mode: bool) -> u32 {
+        match self {
+            Self::OAM(_) => 0,
+            Self::VRAM { len, .. } => {
+                let dots = (*len as u32).div_ceil(0x10) * 32;
+                if double_speed_mode {
+                    dots * 2
+                } else {
+                    dots
+                }
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 enum ObjectPriorityMode {
     DMG,
     CGB,
 }
 
+/// https://gbdev.io/pandocs/CGB_Registers.html#ff6c--opri-cgb-mode-only-object-priority-mode
 impl ObjectPriorityMode {
     fn new(value: u8) -> Self {
         match value & 1 {
-            0 => Self::DMG,
-            _ => Self::CGB,
+            0 => Self::CGB,
+            _ => Self::DMG,
         }
     }
 
     fn as_u8(&self) -> u8 {
         match self {
-            Self::DMG => 0,
-            Self::CGB => 1,
+            Self::CGB => 0,
+            Self::DMG => 1,
         }
     }
 }
@@ -453,22 +478,19 @@ fn cgb_has_obj_priority_over_bg(
     !oam_attr_bg_priority && !bg_attr_bg_priority
 }
 
+/// Holds no frontend handle: completed frames are handed out by the bus, see `PPU::step`
 #[derive(Clone)]
-pub struct PPU<L: LCD + 'static> {
+pub struct PPU {
     gb_mode: mode::Mode,
 
-    headless_mode: bool,
-
     dots: u32,
 
-    frame_buffer: lcd::FrameBuffer,
+    frame: lcd::Frame,
     frame_buffer_ready: bool,
 
     vram: vram::VRAM,
     oam: oam::OAM,
 
-    lcd: L,
-
     lcdc: LCDC,
 
     ly: u8,
@@ -479,6 +501,17 @@ pub struct PPU<L: LCD + 'static> {
     stat: Stat,
     /// https://gbdev.io/pandocs/Interrupt_Sources.html#int-48--stat-interrupt
     stat_int_line: bool,
+    /// The OAM STAT condition also holds for an instant when entering VBlank, as line 144
+    /// briefly starts like any other line. See `Config::disable_vblank_oam_stat`.
+    vblank_oam_stat: bool,
+    /// Set when entering VBlank, until the next STAT line update
+    entering_vblank: bool,
+    /// Hardware draws at most 10 objects per line, see `Config::disable_sprite_limit`
+    sprite_limit: bool,
+    /// Monochrome models corrupt OAM on `INC rr`/`DEC rr` pointing there during the OAM scan
+    oam_bug: bool,
+    /// set on entering VBlank until `take_vblank_started`, independently of the STAT logic
+    vblank_started: bool,
 
     scy: u8,
     scx: u8,
@@ -494,8 +527,12 @@ pub struct PPU<L: LCD + 'static> {
     obj_palette_ram: ColorPaletteRAM,
 
     line_objects: Vec<ObjectAttributes>,
+    /// Pixels of the current line already pushed to the frame buffer during mode 3
+    line_x: u8,
 
     dma_request: Option<DMARequest>,
+    /// Last value written to FF46, read back as is
+    oam_dma_source: u8,
     pending_dma_request: Option<DMARequest>,
 
     frame_cycles_acc: u32,
@@ -511,25 +548,21 @@ pub struct PPU<L: LCD + 'static> {
     object_priority_mode: ObjectPriorityMode,
 }
 
-impl<L: lcd::LCD> PPU<L> {
-    pub fn new(cfg: &Config, vram: vram::VRAM, oam: oam::OAM, lcd: L) -> Self {
+impl PPU {
+    pub fn new(cfg: &Config, vram: vram::VRAM, oam: oam::OAM) -> Self {
         let skip_boot = cfg.bootrom.is_none();
 
         Self {
             gb_mode: cfg.mode.clone(),
 
-            headless_mode: cfg.headless_mode,
-
             dots: 0,
 
-            frame_buffer: vec![vec![(0, 0, 0); PIXELS_WIDTH]; PIXELS_HEIGHT],
+            frame: lcd::Frame::new(vec![vec![(0, 0, 0); PIXELS_WIDTH]; PIXELS_HEIGHT]),
             frame_buffer_ready: false,
 
             vram,
             oam,
 
-            lcd,
-
             lcdc: if skip_boot {
                 let mut lcdc = LCDC::new();
                 lcdc.write(0x91); // https://gbdev.io/pandocs/Power_Up_Sequence.html
@@ -551,6 +584,11 @@ impl<L: lcd::LCD> PPU<L> {
                 Stat::new()
             },
             stat_int_line: false,
+            vblank_oam_stat: !cfg.disable_vblank_oam_stat,
+            entering_vblank: false,
+            sprite_limit: !cfg.disable_sprite_limit,
+            oam_bug: cfg.effective_model().is_monochrome(),
+            vblank_started: false,
 
             scy: 0,
             scx: 0,
@@ -559,15 +597,21 @@ impl<L: lcd::LCD> PPU<L> {
             wx: 0,
             window_internal_line_counter: 0,
 
-            monochrome_bg_palette: MonochromePalette::new(),
-            monochrome_obj_palettes: array::from_fn(|_| MonochromePalette::new()),
+            monochrome_bg_palette: MonochromePalette::new(cfg.palette),
+            monochrome_obj_palettes: array::from_fn(|_| MonochromePalette::new(cfg.palette)),
 
             bg_palette_ram: ColorPaletteRAM::new(),
             obj_palette_ram: ColorPaletteRAM::new(),
 
             line_objects: vec![],
+            line_x: 0,
 
             dma_request: None,
+            // https://gbdev.io/pandocs/Power_Up_Sequence.html#hardware-registers
+            oam_dma_source: match cfg.mode {
+                mode::Mode::DMG => 0xFF,
+                mode::Mode::CGB => 0x00,
+            },
             pending_dma_request: None,
 
             frame_cycles_acc: 0,
@@ -580,13 +624,10 @@ impl<L: lcd::LCD> PPU<L> {
 
             vram_dma_transfer_len: 0,
 
-            object_priority_mode: ObjectPriorityMode::DMG,
-        }
-    }
-
-    fn draw_frame_buffer(&mut self) {
-        if !self.headless_mode {
-            self.lcd.draw_buffer(&self.frame_buffer);
+            object_priority_mode: match cfg.mode {
+                mode::Mode::DMG => ObjectPriorityMode::DMG,
+                mode::Mode::CGB => ObjectPriorityMode::CGB,
+            },
         }
     }
 
@@ -622,7 +663,14 @@ impl<L: lcd::LCD> PPU<L> {
     fn buffer_pix_bg(&mut self, x: u8, bg_win_color_id: &mut u8, bg_win_attr_priority: &mut bool) {
         match self.gb_mode {
             mode::Mode::DMG => {
+                // https://gbdev.io/pandocs/LCDC.html#non-cgb-mode-dmg-sgb-and-cgb-in-compatibility-mode-bg-and-window-display
+                // bg and window become blank (white), color id stays 0 for objects priority
                 if !self.lcdc.bg_win_enable_or_priority {
+                    self.frame.set_pixel(
+                        x as usize,
+                        self.ly as usize,
+                        GrayShade::White.to_rgb(&self.monochrome_bg_palette.colors),
+                    );
                     return;
                 }
             }
@@ -689,7 +737,7 @@ impl<L: lcd::LCD> PPU<L> {
             .get_bg_palette(tile_attributes)
             .get_color_from_id(color_id);
 
-        self.frame_buffer[self.ly as usize][x as usize] = pixel;
+        self.frame.set_pixel(x as usize, self.ly as usize, pixel);
     }
 
     fn buffer_pix_win(&mut self, x: u8, bg_win_color_id: &mut u8, bg_win_attr_priority: &mut bool) {
@@ -764,7 +812,7 @@ impl<L: lcd::LCD> PPU<L> {
             .get_bg_palette(tile_attributes)
             .get_color_from_id(color_id);
 
-        self.frame_buffer[self.ly as usize][x as usize] = pixel;
+        self.frame.set_pixel(x as usize, self.ly as usize, pixel);
     }
 
     fn buffer_pix_obj(&mut self, x: u8, bg_win_color_id: u8, bg_win_attr_priority: bool) {
@@ -772,26 +820,9 @@ impl<L: lcd::LCD> PPU<L> {
             return;
         }
 
+        // line objects are in priority order, the first opaque pixel wins even when it then
+        // loses against the background
         for obj_attr in &self.line_objects {
-            match self.gb_mode {
-                mode::Mode::CGB => {
-                    if bg_win_color_id != 0
-                        && !cgb_has_obj_priority_over_bg(
-                            self.lcdc.bg_win_enable_or_priority,
-                            obj_attr.flags.bg_win_priority,
-                            bg_win_attr_priority,
-                        )
-                    {
-                        continue;
-                    }
-                }
-                mode::Mode::DMG => {
-                    if obj_attr.flags.bg_win_priority && bg_win_color_id != 0 {
-                        continue;
-                    }
-                }
-            }
-
             let is_in_tile = x as isize >= obj_attr.x_pos as isize - 8 && x < obj_attr.x_pos;
             if !is_in_tile {
                 continue;
@@ -837,9 +868,29 @@ impl<L: lcd::LCD> PPU<L> {
                 continue;
             }
 
+            match self.gb_mode {
+                mode::Mode::CGB => {
+                    if bg_win_color_id != 0
+                        && !cgb_has_obj_priority_over_bg(
+                            self.lcdc.bg_win_enable_or_priority,
+                            obj_attr.flags.bg_win_priority,
+                            bg_win_attr_priority,
+                        )
+                    {
+                        return;
+                    }
+                }
+                mode::Mode::DMG => {
+                    if obj_attr.flags.bg_win_priority && bg_win_color_id != 0 {
+                        return;
+                    }
+                }
+            }
+
             let pixel = self.get_obj_palette(obj_attr).get_color_from_id(color_id);
 
-            self.frame_buffer[self.ly as usize][x as usize] = pixel;
+            self.frame.set_pixel(x as usize, self.ly as usize, pixel);
+            return;
         }
     }
 
@@ -856,9 +907,32 @@ impl<L: lcd::LCD> PPU<L> {
         self.buffer_pix_obj(x, bg_win_color_id, bg_win_attr_priority);
     }
 
+    #[cfg(test)]
     fn buffer_line(&mut self) {
-        for x in 0..PIXELS_WIDTH as u8 {
-            self.buffer_pix(x);
+        self.line_x = 0;
+        self.render_until(PIXELS_WIDTH as u8);
+    }
+
+    /// Pushes the pixels of the current line up to `end_x` (excluded)
+    fn render_until(&mut self, end_x: u8) {
+        while self.line_x < end_x {
+            self.buffer_pix(self.line_x);
+            self.line_x += 1;
+        }
+    }
+
+    /// Pixel position reached by the renderer at the current dot of mode 3
+    fn current_line_x(&self) -> u8 {
+        let vram_dots = self.dots.saturating_sub(OAM_DOTS + PIXEL_PIPELINE_DELAY);
+        vram_dots.min(PIXELS_WIDTH as u32) as u8
+    }
+
+    /// Registers affecting rendering written during mode 3 only apply to the pixels not yet pushed,
+    /// so catch up the renderer to the current dot before the write lands.
+    /// https://github.com/mattcurrie/mealybug-tearoom-tests
+    fn latch_mid_scanline_write(&mut self) {
+        if self.lcdc.lcd_ppu_enable && matches!(self.mode, Mode::VRAM) {
+            self.render_until(self.current_line_x());
         }
     }
 
@@ -879,12 +953,17 @@ impl<L: lcd::LCD> PPU<L> {
             self.line_objects.push(obj_attr);
         }
 
+        // selection only looks at OAM order, X only matters for the draw priority
+        // https://gbdev.io/pandocs/OAM.html#selection-priority
+        if self.sprite_limit {
+            self.line_objects.truncate(MAX_LINE_OBJECTS);
+        }
+
+        // stable sort: on equal X the first object in OAM wins
         match self.object_priority_mode {
             ObjectPriorityMode::DMG => self.line_objects.sort_by_key(|o| o.x_pos),
             ObjectPriorityMode::CGB => {}
         }
-
-        self.line_objects.truncate(10);
     }
 
     fn get_bg_tile_attributes(&self, address: u16) -> Option<BGMapAttributes> {
@@ -908,7 +987,9 @@ impl<L: lcd::LCD> PPU<L> {
             && self.stat.hblank_int_select)
             || (self.mode.clone() as u8 == Mode::OAM as u8 && self.stat.oam_int_select)
             || (self.mode.clone() as u8 == Mode::VBlank as u8 && self.stat.vblank_int_select)
-            || (self.ly == self.lyc && self.stat.lyc_int_select);
+            || (self.ly == self.lyc && self.stat.lyc_int_select)
+            || (self.entering_vblank && self.vblank_oam_stat && self.stat.oam_int_select);
+        self.entering_vblank = false;
     }
 
     fn handle_stat_int(&mut self, int_reg: &mut InterruptRegisters) {
@@ -922,6 +1003,8 @@ impl<L: lcd::LCD> PPU<L> {
     fn enter_vblank(&mut self, int_reg: &mut InterruptRegisters) {
         int_reg.request_vblank();
         self.mode = Mode::VBlank;
+        self.entering_vblank = true;
+        self.vblank_started = true;
         self.window_internal_line_counter = 0;
     }
 
@@ -935,25 +1018,52 @@ impl<L: lcd::LCD> PPU<L> {
         }
 
         self.search_line_objects();
+        self.line_x = 0;
 
         self.mode = Mode::VRAM;
     }
 
     /// https://gbdev.io/pandocs/Rendering.html#mode-3-length
     fn compute_vram_mode_penalty(&self) -> u32 {
-        // quick attempt, probably not very accurate
-        // (self.scx as u32 % 8)
-        //     + ((self.is_win_enabled() && self.wx > 0) as u32 * 6)
-        //     + (self.line_objects.iter().fold(0, |acc, _o| acc + 1) * 6)
-        0
+        // TODO: scx fine scroll and window penalties
+        self.compute_objects_penalty()
+    }
+
+    /// https://gbdev.io/pandocs/Rendering.html#obj-penalty-algorithm
+    fn compute_objects_penalty(&self) -> u32 {
+        if !self.lcdc.obj_enable {
+            return 0;
+        }
+
+        let mut considered_tiles = [false; 22];
+        let mut penalty = 0;
+        // without the sprite limit, the line keeps the length of one with 10 objects
+        for obj in self.line_objects.iter().take(MAX_LINE_OBJECTS) {
+            // objects fully past the right edge are never fetched
+            if obj.x_pos as usize >= PIXELS_WIDTH + 8 {
+                continue;
+            }
+
+            penalty += 6;
+
+            let x = obj.x_pos as u32 + (self.scx as u32 % 8);
+            let tile = (x / 8) as usize;
+            if !considered_tiles[tile] {
+                considered_tiles[tile] = true;
+                penalty += 5u32.saturating_sub(x % 8);
+            }
+        }
+
+        penalty
     }
 
     fn handle_vram_mode(&mut self) {
         if self.dots < OAM_DOTS + VRAM_DOTS + self.compute_vram_mode_penalty() {
+            self.render_until(self.current_line_x());
             return;
         }
 
-        self.buffer_line();
+        self.render_until(PIXELS_WIDTH as u8);
 
         self.mode = Mode::HBlank;
     }
@@ -1024,8 +1134,8 @@ impl<L: lcd::LCD> PPU<L> {
                 } else {
                     self.vram_dma_transfer_len -= 1;
                     self.pending_dma_request = Some(DMARequest::VRAM {
-                        src: src + 0x10,
-                        dst: dst + 0x10,
+                        src: src.wrapping_add(0x10),
+                        dst: dst.wrapping_add(0x10),
                         len: 0x10,
                         is_hdma: true,
                     });
@@ -1037,15 +1147,61 @@ impl<L: lcd::LCD> PPU<L> {
         self.dma_request = None;
     }
 
+    /// Whether VBlank started since the last call
+    pub fn take_vblank_started(&mut self) -> bool {
+        std::mem::take(&mut self.vblank_started)
+    }
+
     pub fn is_frame_buffer_ready(&mut self) -> bool {
         let ready = self.frame_buffer_ready;
         self.frame_buffer_ready = false;
         ready
     }
 
-    pub fn step(&mut self, int_reg: &mut InterruptRegisters, cycles: u8) {
+    pub fn frame_buffer(&self) -> &lcd::Frame {
+        &self.frame
+    }
+
+    pub fn ly(&self) -> u8 {
+        self.ly
+    }
+
+    pub fn set_vblank_oam_stat(&mut self, enabled: bool) {
+        self.vblank_oam_stat = enabled;
+    }
+
+    pub fn set_sprite_limit(&mut self, enabled: bool) {
+        self.sprite_limit = enabled;
+    }
+
+    /// Colors of the DMG shades from the next pixel on, CGB mode ignores them
+    pub fn set_palette(&mut self, colors: DmgPalette) {
+        self.monochrome_bg_palette.colors = colors;
+        for palette in &mut self.monochrome_obj_palettes {
+            palette.colors = colors;
+        }
+    }
+
+    /// Corrupts the OAM row being scanned, https://gbdev.io/pandocs/OAM_Corruption_Bug.html.
+    /// Only the `INC rr`/`DEC rr` trigger is emulated.
+    pub fn trigger_oam_bug(&mut self) {
+        if self.oam_bug && self.lcdc.lcd_ppu_enable && matches!(self.mode, Mode::OAM) {
+            // one row of 8 bytes scanned per M-cycle
+            self.oam.corrupt_write_row(self.dots as usize / 4);
+        }
+    }
+
+    /// Returns true when a frame has been completed and is ready to be drawn
+    pub fn step(&mut self, int_reg: &mut InterruptRegisters, cycles: u8) -> bool {
         if !self.lcdc.lcd_ppu_enable {
-            return;
+            // nothing to draw but frames keep their pace, frame-granular loops don't stall
+            self.frame_cycles_acc = self.frame_cycles_acc.wrapping_add(cycles as u32);
+            if self.frame_cycles_acc >= DOTS_PER_FRAME {
+                self.frame_cycles_acc -= DOTS_PER_FRAME;
+                self.frame.complete();
+                self.frame_buffer_ready = true;
+            }
+            return false;
         }
 
         self.dots += cycles as u32;
@@ -1062,13 +1218,21 @@ impl<L: lcd::LCD> PPU<L> {
         self.frame_cycles_acc = self.frame_cycles_acc.wrapping_add(cycles as u32);
         if self.frame_cycles_acc >= DOTS_PER_FRAME {
             self.frame_cycles_acc -= DOTS_PER_FRAME;
-            self.draw_frame_buffer();
+            self.frame.complete();
             self.frame_buffer_ready = true;
+            return true;
         }
+
+        false
     }
 }
 
-impl<L: lcd::LCD> MemReadWriter for PPU<L> {
+/// Dots keep their pace in double speed mode
+impl Clocked for PPU {
+    const DOMAIN: ClockDomain = ClockDomain::System;
+}
+
+impl MemReadWriter for PPU {
     fn read_byte(&self, address: u16) -> u8 {
         match self.gb_mode {
             mode::Mode::CGB => match address {
@@ -1102,7 +1266,7 @@ impl<L: lcd::LCD> MemReadWriter for PPU<L> {
             0xFF43 => self.scx,
             0xFF44 => self.ly,
             0xFF45 => self.lyc,
-            0xFF46 => 0xFF,
+            0xFF46 => self.oam_dma_source,
             0xFF47 => self.monochrome_bg_palette.read(),
             0xFF48 => self.monochrome_obj_palettes[0].read(),
             0xFF49 => self.monochrome_obj_palettes[1].read(),
@@ -1122,8 +1286,12 @@ impl<L: lcd::LCD> MemReadWriter for PPU<L> {
                     let src = (self.high_vram_dma_src as u16) << 8 | self.low_vram_dma_src as u16;
                     let dst = (self.high_vram_dma_dst as u16) << 8 | self.low_vram_dma_dst as u16;
                     self.vram_dma_transfer_len = value & 0x7F;
-                    let len = (self.vram_dma_transfer_len as u16 + 1) * 0x10;
                     let is_hdma = value & BIT_7 == BIT_7;
+                    // HBlank DMA moves one block per HBlank, see `dma_transfer_done`
+                    let len = match is_hdma {
+                        true => 0x10,
+                        false => (self.vram_dma_transfer_len as u16 + 1) * 0x10,
+                    };
                     let req = DMARequest::VRAM {
                         src,
                         dst,
@@ -1149,6 +1317,11 @@ impl<L: lcd::LCD> MemReadWriter for PPU<L> {
             _ => {}
         }
 
+        match address {
+            0xFF40 | 0xFF42 | 0xFF43 | 0xFF47..=0xFF4B => self.latch_mid_scanline_write(),
+            _ => {}
+        }
+
         match address {
             0x8000..=0x9FFF | 0xFF4F => self.vram.write_byte(address, value),
             0xFE00..=0xFE9F => self.oam.write_byte(address, value),
@@ -1166,7 +1339,10 @@ impl<L: lcd::LCD> MemReadWriter for PPU<L> {
             0xFF43 => self.scx = value,
             0xFF44 => self.ly = 0,
             0xFF45 => self.lyc = value,
-            0xFF46 => self.dma_request = Some(DMARequest::OAM(value)),
+            0xFF46 => {
+                self.oam_dma_source = value;
+                self.dma_request = Some(DMARequest::OAM(value));
+            }
             0xFF47 => self.monochrome_bg_palette.update(value),
             0xFF48 => self.monochrome_obj_palettes[0].update(value),
             0xFF49 => self.monochrome_obj_palettes[1].update(value),
@@ -1177,9 +1353,229 @@ impl<L: lcd::LCD> MemReadWriter for PPU<L> {
     }
 }
 
+impl ObjectFlags {
+    fn as_u8(&self) -> u8 {
+        self.cgb_palette
+            | self.vram_bank << 3
+            | self.dmg_palette << 4
+            | (self.x_flip as u8) << 5
+            | (self.y_flip as u8) << 6
+            | (self.bg_win_priority as u8) << 7
+    }
+}
+
+impl ColorPaletteRAM {
+    fn save(&self, w: &mut ChunkWriter) {
+        w.write_u8(self.spec.read());
+        for palette in &self.data {
+            w.write_bytes(&palette.color_data);
+        }
+    }
+
+    fn load(&mut self, r: &mut ChunkReader) {
+        self.spec.write(r.read_u8());
+        for palette in &mut self.data {
+            r.read_into(&mut palette.color_data);
+        }
+    }
+}
+
+fn save_dma_request(w: &mut ChunkWriter, request: &Option<DMARequest>) {
+    match request {
+        None => w.write_u8(0),
+        Some(DMARequest::OAM(source)) => {
+            w.write_u8(1);
+            w.write_u8(*source);
+        }
+        Some(DMARequest::VRAM {
+            src,
+            dst,
+            len,
+            is_hdma,
+        }) => {
+            w.write_u8(2);
+            w.write_u16(*src);
+            w.write_u16(*dst);
+            w.write_u16(*len);
+            w.write_bool(*is_hdma);
+        }
+    }
+}
+
+fn load_dma_request(r: &mut ChunkReader) -> Option<DMARequest> {
+    match r.read_u8() {
+        1 => Some(DMARequest::OAM(r.read_u8())),
+        2 => Some(DMARequest::VRAM {
+            src: r.read_u16(),
+            dst: r.read_u16(),
+            len: r.read_u16(),
+            is_hdma: r.read_bool(),
+        }),
+        _ => None,
+    }
+}
+
+impl PPU {
+    /// Rejects a position in the frame the rendering can't resume from, before anything is loaded
+    pub fn check_state(&self, state: &StateReader) -> Result<(), SaveStateError> {
+        let (_, mut r) = state
+            .chunk(Self::TAG)
+            .ok_or(SaveStateError::MissingChunk(Self::TAG))?;
+        let dots = r.read_u32();
+        // frame, frame ready, vram, vram bank, oam, lcdc
+        _ = r.read_bytes();
+        _ = r.read_bool();
+        _ = r.read_bytes();
+        _ = r.read_u8();
+        _ = r.read_bytes();
+        _ = r.read_u8();
+        let ly = r.read_u8();
+        _ = r.read_u8();
+        let mode = r.read_u8();
+
+        let drawn_line = ly < PIXELS_HEIGHT as u8 || mode == Mode::VBlank as u8;
+        match dots < SCANLINE_DOTS && ly <= 153 && mode <= Mode::VRAM as u8 && drawn_line {
+            true => Ok(()),
+            false => Err(SaveStateError::Corrupt(Self::TAG)),
+        }
+    }
+}
+
+impl Snapshot for PPU {
+    const TAG: [u8; 4] = *b"PPU ";
+    const VERSION: u16 = 2;
+
+    fn save_state(&self, w: &mut ChunkWriter) {
+        w.write_u32(self.dots);
+        let mut frame = vec![];
+        lcd::pack_frame(&self.frame, lcd::PixelFormat::Rgb888, &mut frame);
+        w.write_bytes(&frame);
+        w.write_bool(self.frame_buffer_ready);
+
+        self.vram.save(w);
+        self.oam.save(w);
+
+        w.write_u8(self.lcdc.read());
+        w.write_u8(self.ly);
+        w.write_u8(self.lyc);
+        w.write_u8(self.mode.clone() as u8);
+        w.write_u8(self.stat.read(self.mode.clone(), false));
+        w.write_bool(self.stat_int_line);
+        w.write_u8(self.scy);
+        w.write_u8(self.scx);
+        w.write_u8(self.wy);
+        w.write_u8(self.wx);
+        w.write_u8(self.window_internal_line_counter);
+
+        w.write_u8(self.monochrome_bg_palette.read());
+        for palette in &self.monochrome_obj_palettes {
+            w.write_u8(palette.read());
+        }
+        self.bg_palette_ram.save(w);
+        self.obj_palette_ram.save(w);
+
+        w.write_u8(self.line_objects.len() as u8);
+        for object in &self.line_objects {
+            w.write_u8(object.y_pos);
+            w.write_u8(object.x_pos);
+            w.write_u8(object.tile_index);
+            w.write_u8(object.flags.as_u8());
+        }
+        w.write_u8(self.line_x);
+
+        save_dma_request(w, &self.dma_request);
+        w.write_u8(self.oam_dma_source);
+        save_dma_request(w, &self.pending_dma_request);
+        w.write_u32(self.frame_cycles_acc);
+        w.write_u8(self.high_vram_dma_src);
+        w.write_u8(self.low_vram_dma_src);
+        w.write_u8(self.high_vram_dma_dst);
+        w.write_u8(self.low_vram_dma_dst);
+        w.write_u8(self.vram_dma_transfer_len);
+        w.write_u8(self.object_priority_mode.as_u8());
+        w.write_u64(self.frame.index);
+        w.write_bool(self.frame.dirty);
+        w.write_bool(self.frame.changed);
+    }
+
+    fn load_state(&mut self, r: &mut ChunkReader, version: u16) {
+        self.dots = r.read_u32();
+        let frame = r.read_bytes();
+        for (pixel, rgb) in self
+            .frame
+            .pixels
+            .iter_mut()
+            .flatten()
+            .zip(frame.chunks_exact(3))
+        {
+            *pixel = (rgb[0], rgb[1], rgb[2]);
+        }
+        self.frame_buffer_ready = r.read_bool();
+
+        self.vram.load(r);
+        self.oam.load(r);
+
+        self.lcdc.write(r.read_u8());
+        self.ly = r.read_u8();
+        self.lyc = r.read_u8();
+        self.mode = match r.read_u8() {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OAM,
+            _ => Mode::VRAM,
+        };
+        self.stat.write(r.read_u8());
+        self.stat_int_line = r.read_bool();
+        self.scy = r.read_u8();
+        self.scx = r.read_u8();
+        self.wy = r.read_u8();
+        self.wx = r.read_u8();
+        self.window_internal_line_counter = r.read_u8();
+
+        self.monochrome_bg_palette.update(r.read_u8());
+        for palette in &mut self.monochrome_obj_palettes {
+            palette.update(r.read_u8());
+        }
+        self.bg_palette_ram.load(r);
+        self.obj_palette_ram.load(r);
+
+        self.line_objects = (0..r.read_u8())
+            .map(|_| ObjectAttributes {
+                y_pos: r.read_u8(),
+                x_pos: r.read_u8(),
+                tile_index: r.read_u8(),
+                flags: ObjectFlags::from(r.read_u8()),
+            })
+            .collect();
+        self.line_x = r.read_u8();
+
+        self.dma_request = load_dma_request(r);
+        self.oam_dma_source = r.read_u8();
+        self.pending_dma_request = load_dma_request(r);
+        self.frame_cycles_acc = r.read_u32();
+        self.high_vram_dma_src = r.read_u8();
+        self.low_vram_dma_src = r.read_u8();
+        self.high_vram_dma_dst = r.read_u8();
+        self.low_vram_dma_dst = r.read_u8();
+        self.vram_dma_transfer_len = r.read_u8();
+        self.object_priority_mode = ObjectPriorityMode::new(r.read_u8());
+        if version >= 2 {
+            self.frame.index = r.read_u64();
+            self.frame.timestamp = pacing::frames_duration(self.frame.index);
+            self.frame.dirty = r.read_bool();
+            self.frame.changed = r.read_bool();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{oam::OAM, vram::VRAM};
+    use crate::{
+        lcd::{RGB_BLACK, RGB_LIGHT_GRAY, RGB_WHITE},
+        oam::OAM,
+        savestate::StateWriter,
+        vram::VRAM,
+    };
 
     use super::*;
 
@@ -1246,9 +1642,6 @@ mod tests {
         );
     }
 
-    struct DummyLCD;
-    impl LCD for DummyLCD {}
-
     fn cgb_color_palette_access_no_auto_inc(spec_reg: u16, data_reg: u16) {
         let mut ppu = PPU::new(
             &Config {
@@ -1257,10 +1650,10 @@ mod tests {
                 headless_mode: false,
                 bootrom: None,
                 log_file_path: None,
+                ..Default::default()
             },
             VRAM::new(mode::Mode::CGB),
             OAM::new(),
-            DummyLCD,
         );
 
         for i in 0..64 {
@@ -1283,10 +1676,10 @@ mod tests {
                 headless_mode: false,
                 bootrom: None,
                 log_file_path: None,
+                ..Default::default()
             },
             VRAM::new(mode::Mode::CGB),
             OAM::new(),
-            DummyLCD,
         );
 
         let auto_inc_bit = BIT_7;
@@ -1304,6 +1697,260 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_dmg_bg_disabled_draws_white() {
+        let mut ppu = PPU::new(
+            &Config {
+                mode: mode::Mode::DMG,
+                rom: vec![],
+                headless_mode: false,
+                bootrom: None,
+                log_file_path: None,
+                ..Default::default()
+            },
+            VRAM::new(mode::Mode::DMG),
+            OAM::new(),
+        );
+
+        // a fully black tile at index 0 and a palette mapping every id to black
+        for addr in 0x8000..0x8010 {
+            ppu.write_byte(addr, 0xFF);
+        }
+        ppu.write_byte(0xFF47, 0xFF);
+
+        ppu.buffer_line();
+        assert!(ppu.frame[0].iter().all(|&p| p == RGB_BLACK));
+
+        ppu.write_byte(0xFF40, 0x90); // lcd on, bg/win disabled
+        ppu.buffer_line();
+        assert!(ppu.frame[0].iter().all(|&p| p == RGB_WHITE));
+    }
+
+    #[test]
+    fn test_dmg_mid_scanline_bgp_write() {
+        let mut ppu = PPU::new(
+            &Config {
+                mode: mode::Mode::DMG,
+                rom: vec![],
+                headless_mode: false,
+                bootrom: None,
+                log_file_path: None,
+                ..Default::default()
+            },
+            VRAM::new(mode::Mode::DMG),
+            OAM::new(),
+        );
+        let mut int_reg = InterruptRegisters::new();
+
+        for addr in 0x8000..0x8010 {
+            ppu.write_byte(addr, 0xFF);
+        }
+        ppu.write_byte(0xFF47, 0xFF);
+
+        // restart the lcd to begin a fresh line
+        ppu.write_byte(0xFF40, 0x00);
+        ppu.write_byte(0xFF40, 0x91);
+
+        ppu.step(&mut int_reg, OAM_DOTS as u8);
+        ppu.step(&mut int_reg, (PIXEL_PIPELINE_DELAY + 40) as u8);
+        ppu.write_byte(0xFF47, 0x00);
+        ppu.step(&mut int_reg, 200);
+
+        assert!(ppu.frame[0][..40].iter().all(|&p| p == RGB_BLACK));
+        assert!(ppu.frame[0][40..].iter().all(|&p| p == RGB_WHITE));
+    }
+
+    #[test]
+    fn test_objects_lengthen_vram_mode() {
+        let run_line = |objects: u8| {
+            let mut ppu = PPU::new(
+                &Config {
+                    mode: mode::Mode::DMG,
+                    rom: vec![],
+                    headless_mode: false,
+                    bootrom: None,
+                    log_file_path: None,
+                    ..Default::default()
+                },
+                VRAM::new(mode::Mode::DMG),
+                OAM::new(),
+            );
+            let mut int_reg = InterruptRegisters::new();
+
+            // overlapping objects all visible on line 0
+            for i in 0..objects as u16 {
+                ppu.write_byte(0xFE00 + i * 4, 16);
+                ppu.write_byte(0xFE00 + i * 4 + 1, 8);
+            }
+
+            ppu.write_byte(0xFF40, 0x00);
+            ppu.write_byte(0xFF40, 0x93); // lcd, objects and bg on
+
+            let mut vram_dots = 0;
+            for _ in 0..SCANLINE_DOTS {
+                ppu.step(&mut int_reg, 1);
+                if ppu.read_byte(0xFF41) & 0b11 == 3 {
+                    vram_dots += 1;
+                }
+            }
+            vram_dots
+        };
+
+        assert_eq!(VRAM_DOTS, run_line(0));
+        // first object pays the 5 dots tile fetch stall, the others share its tile
+        assert_eq!(VRAM_DOTS + 10 * 6 + 5, run_line(10));
+        // only 10 objects are selected per line
+        assert_eq!(VRAM_DOTS + 10 * 6 + 5, run_line(12));
+    }
+
+    #[test]
+    fn test_dmg_crowded_line_object_selection() {
+        let mut ppu = PPU::new(
+            &Config {
+                mode: mode::Mode::DMG,
+                rom: vec![],
+                headless_mode: false,
+                bootrom: None,
+                log_file_path: None,
+                ..Default::default()
+            },
+            VRAM::new(mode::Mode::DMG),
+            OAM::new(),
+        );
+
+        ppu.write_byte(0xFF40, 0x00);
+        // tile 1 fully opaque, white background, OBP0 black, OBP1 light gray
+        for addr in 0x8010..0x8020 {
+            ppu.write_byte(addr, 0xFF);
+        }
+        ppu.write_byte(0xFF47, 0x00);
+        ppu.write_byte(0xFF48, 0xFF);
+        ppu.write_byte(0xFF49, 0x55);
+
+        fn write_obj(ppu: &mut PPU, i: u16, x: u8, flags: u8) {
+            ppu.write_byte(0xFE00 + i * 4, 16);
+            ppu.write_byte(0xFE00 + i * 4 + 1, x);
+            ppu.write_byte(0xFE00 + i * 4 + 2, 1);
+            ppu.write_byte(0xFE00 + i * 4 + 3, flags);
+        }
+        // the first 10 objects in OAM cover the right half of the line
+        for i in 0..10 {
+            write_obj(&mut ppu, i, 88 + i as u8 * 8, 0x00);
+        }
+        // an 11th object with the lowest X is still dropped
+        write_obj(&mut ppu, 10, 8, 0x00);
+
+        ppu.write_byte(0xFF40, 0x93);
+        ppu.search_line_objects();
+        ppu.buffer_line();
+
+        assert!(ppu.frame[0][..8].iter().all(|&p| p == RGB_WHITE));
+        assert!(ppu.frame[0][80..].iter().all(|&p| p == RGB_BLACK));
+
+        // overlapping objects: the lowest X wins, then the first in OAM
+        ppu.write_byte(0xFF40, 0x00);
+        for i in 0..11 {
+            write_obj(&mut ppu, i, 0, 0x00);
+        }
+        write_obj(&mut ppu, 0, 20, 0x00);
+        write_obj(&mut ppu, 1, 16, 0x10);
+        write_obj(&mut ppu, 2, 40, 0x00);
+        write_obj(&mut ppu, 3, 40, 0x10);
+
+        ppu.write_byte(0xFF40, 0x93);
+        ppu.search_line_objects();
+        ppu.buffer_line();
+
+        let line = &ppu.frame[0];
+        assert!(line[8..16].iter().all(|&p| p == RGB_LIGHT_GRAY));
+        assert!(line[16..20].iter().all(|&p| p == RGB_BLACK));
+        assert!(line[32..40].iter().all(|&p| p == RGB_BLACK));
+    }
+
+    #[test]
+    fn test_oam_bug() {
+        for (model, corrupts) in [(mode::Model::MGB, true), (mode::Model::CGB, false)] {
+            let mut ppu = PPU::new(
+                &Config {
+                    mode: model.mode(),
+                    model: Some(model),
+                    ..Default::default()
+                },
+                VRAM::new(model.mode()),
+                OAM::new(),
+            );
+            let mut int_reg = InterruptRegisters::new();
+            for i in 0..0xA0 {
+                ppu.write_byte(0xFE00 + i, i as u8);
+            }
+            // outside of the OAM scan
+            ppu.trigger_oam_bug();
+
+            ppu.write_byte(0xFF40, 0x93);
+            while !(matches!(ppu.mode, Mode::OAM) && ppu.dots == 8) {
+                ppu.step(&mut int_reg, 1);
+            }
+            ppu.trigger_oam_bug();
+
+            let oam: Vec<u8> = (0xFE00..0xFEA0).map(|a| ppu.read_byte(a)).collect();
+            let mut expected: Vec<u8> = (0..0xA0).collect();
+            if corrupts {
+                // ((a ^ c) & (b ^ c)) ^ c gives back the first word of row 1 with this pattern
+                expected.copy_within(0x08..0x10, 0x10);
+            }
+            assert_eq!(expected, oam, "{model:?}");
+        }
+    }
+
+    #[test]
+    fn test_sprite_limit_disabled() {
+        let mut ppu = PPU::new(
+            &Config {
+                mode: mode::Mode::DMG,
+                disable_sprite_limit: true,
+                ..Default::default()
+            },
+            VRAM::new(mode::Mode::DMG),
+            OAM::new(),
+        );
+        let mut int_reg = InterruptRegisters::new();
+
+        ppu.write_byte(0xFF40, 0x00);
+        // tile 1 fully opaque, white background, OBP0 black
+        for addr in 0x8010..0x8020 {
+            ppu.write_byte(addr, 0xFF);
+        }
+        ppu.write_byte(0xFF47, 0x00);
+        ppu.write_byte(0xFF48, 0xFF);
+        // the first 10 objects in OAM cover the right half of the line, the 11th the left edge
+        for i in 0..11u16 {
+            let x = if i < 10 { 88 + i as u8 * 8 } else { 8 };
+            ppu.write_byte(0xFE00 + i * 4, 16);
+            ppu.write_byte(0xFE00 + i * 4 + 1, x);
+            ppu.write_byte(0xFE00 + i * 4 + 2, 1);
+        }
+
+        ppu.write_byte(0xFF40, 0x93);
+        let mut vram_dots = 0;
+        for _ in 0..SCANLINE_DOTS {
+            ppu.step(&mut int_reg, 1);
+            if ppu.read_byte(0xFF41) & 0b11 == 3 {
+                vram_dots += 1;
+            }
+        }
+        assert!(ppu.frame[0][..8].iter().all(|&p| p == RGB_BLACK));
+        assert!(ppu.frame[0][80..].iter().all(|&p| p == RGB_BLACK));
+        // the line lasts as long as with the 10 objects the hardware selects
+        assert!(vram_dots <= VRAM_DOTS + 10 * 11, "{vram_dots} dots");
+
+        ppu.set_sprite_limit(true);
+        ppu.search_line_objects();
+        ppu.buffer_line();
+        let line = &ppu.frame[ppu.ly as usize];
+        assert!(line[..8].iter().all(|&p| p == RGB_WHITE));
+        assert!(line[80..].iter().all(|&p| p == RGB_BLACK));
+    }
+
     #[test]
     fn test_cgb_bg_to_obj_priority() {
         // https://gbdev.io/pandocs/Tile_Maps.html#bg-to-obj-priority-in-cgb-mode
@@ -1373,4 +2020,118 @@ mod tests {
             assert_eq!(tc.expected, got);
         }
     }
+
+    #[test]
+    fn test_oam_dma_register_readback() {
+        let mut ppu = PPU::new(
+            &Config {
+                mode: mode::Mode::DMG,
+                rom: vec![],
+                headless_mode: false,
+                bootrom: None,
+                log_file_path: None,
+                ..Default::default()
+            },
+            VRAM::new(mode::Mode::DMG),
+            OAM::new(),
+        );
+
+        assert_eq!(0xFF, ppu.read_byte(0xFF46));
+        ppu.write_byte(0xFF46, 0xC1);
+        assert_eq!(0xC1, ppu.read_byte(0xFF46));
+        assert!(matches!(
+            ppu.check_dma_request(),
+            Some(DMARequest::OAM(0xC1))
+        ));
+    }
+
+    #[test]
+    fn test_vram_dma_stall_cycles() {
+        let gdma = DMARequest::VRAM {
+            src: 0xC000,
+            dst: 0x8000,
+            len: 0x80,
+            is_hdma: false,
+        };
+        assert_eq!(256, gdma.stall_cycles(false));
+        assert_eq!(512, gdma.stall_cycles(true));
+
+        let hdma = DMARequest::VRAM {
+            src: 0xC000,
+            dst: 0x8000,
+            len: 0x10,
+            is_hdma: true,
+        };
+        assert_eq!(32, hdma.stall_cycles(false));
+        assert_eq!(64, hdma.stall_cycles(true));
+
+        assert_eq!(0, DMARequest::OAM(0xC0).stall_cycles(true));
+    }
+
+    /// Sets STAT to `stat`, runs up to the end of line 143 and returns whether a STAT interrupt
+    /// is requested when entering VBlank
+    fn stat_int_entering_vblank(stat: u8, disable_vblank_oam_stat: bool) -> bool {
+        let mut ppu = PPU::new(
+            &Config {
+                mode: mode::Mode::DMG,
+                disable_vblank_oam_stat,
+                ..Default::default()
+            },
+            VRAM::new(mode::Mode::DMG),
+            OAM::new(),
+        );
+        let mut int_reg = InterruptRegisters::new();
+
+        ppu.write_byte(0xFF41, stat);
+        ppu.write_byte(0xFF40, 0x00);
+        ppu.write_byte(0xFF40, 0x91);
+
+        while !(ppu.read_byte(0xFF44) == 143 && ppu.read_byte(0xFF41) & 0b11 == 0) {
+            ppu.step(&mut int_reg, 4);
+        }
+        int_reg.write_byte(0xFF0F, 0);
+        while ppu.read_byte(0xFF44) == 143 {
+            ppu.step(&mut int_reg, 4);
+        }
+
+        let flags = int_reg.read_byte(0xFF0F);
+        assert_eq!(0x01, flags & 0x01, "vblank requested");
+        flags & 0x02 != 0
+    }
+
+    #[test]
+    fn test_vblank_oam_stat_quirk() {
+        // mode 2 select alone fires on line 144 too
+        assert!(stat_int_entering_vblank(0x20, false));
+        assert!(!stat_int_entering_vblank(0x20, true));
+        // nothing selected
+        assert!(!stat_int_entering_vblank(0x00, false));
+        // the line already high from the hblank source blocks it
+        assert!(!stat_int_entering_vblank(0x28, false));
+        // mode 1 select fires as usual when the quirk is disabled
+        assert!(stat_int_entering_vblank(0x10, true));
+    }
+
+    #[test]
+    fn test_check_state_rejects_positions_out_of_the_frame() {
+        let cfg = Config::default();
+        let mut ppu = PPU::new(&cfg, VRAM::new(mode::Mode::DMG), OAM::new());
+        let check = |ppu: &PPU| {
+            let mut w = StateWriter::new();
+            w.write(ppu);
+            let state = w.finish();
+            ppu.check_state(&StateReader::new(&state).unwrap())
+        };
+        assert_eq!(Ok(()), check(&ppu));
+
+        ppu.ly = 200;
+        assert_eq!(Err(SaveStateError::Corrupt(PPU::TAG)), check(&ppu));
+        ppu.ly = 150;
+        ppu.mode = Mode::VRAM;
+        assert_eq!(Err(SaveStateError::Corrupt(PPU::TAG)), check(&ppu));
+        ppu.mode = Mode::VBlank;
+        assert_eq!(Ok(()), check(&ppu));
+        ppu.dots = SCANLINE_DOTS;
+        assert_eq!(Err(SaveStateError::Corrupt(PPU::TAG)), check(&ppu));
+    }
 }
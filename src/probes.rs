@@ -0,0 +1,173 @@
+/// Comparison applied between the probed memory value and the condition operand
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn matches(&self, lhs: u8, rhs: u8) -> bool {
+        match self {
+            Self::Equal => lhs == rhs,
+            Self::NotEqual => lhs != rhs,
+            Self::Less => lhs < rhs,
+            Self::LessOrEqual => lhs <= rhs,
+            Self::Greater => lhs > rhs,
+            Self::GreaterOrEqual => lhs >= rhs,
+        }
+    }
+}
+
+/// A single memory condition, RetroAchievements style.
+/// When `delta` is set the value the address held on the previous frame is compared instead
+/// of the current one, which allows expressing transitions (e.g. `delta(addr) == 0 && addr == 1`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    pub address: u16,
+    pub comparison: Comparison,
+    pub value: u8,
+    pub delta: bool,
+}
+
+pub type ProbeId = usize;
+
+/// Emitted once when every condition of a probe becomes true
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProbeEvent {
+    pub id: ProbeId,
+    pub frame: u64,
+}
+
+#[derive(Clone, Debug)]
+struct Probe {
+    id: ProbeId,
+    conditions: Vec<Condition>,
+    previous: Vec<Option<u8>>,
+    triggered: bool,
+}
+
+/// Set of probes evaluated once per frame against the memory map
+#[derive(Clone, Debug, Default)]
+pub struct MemoryProbes {
+    probes: Vec<Probe>,
+    next_id: ProbeId,
+    frame: u64,
+    events: Vec<ProbeEvent>,
+}
+
+impl MemoryProbes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a probe whose conditions must all hold for it to trigger
+    pub fn register(&mut self, conditions: Vec<Condition>) -> ProbeId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.probes.push(Probe {
+            id,
+            previous: vec![None; conditions.len()],
+            conditions,
+            triggered: false,
+        });
+        id
+    }
+
+    pub fn unregister(&mut self, id: ProbeId) {
+        self.probes.retain(|p| p.id != id);
+    }
+
+    pub fn clear(&mut self) {
+        self.probes.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.probes.is_empty()
+    }
+
+    /// Evaluates every probe with the given memory reader.
+    /// A probe only emits an event on the frame its conditions become true, not while they stay true.
+    pub fn evaluate<M: Fn(u16) -> u8>(&mut self, read: M) {
+        self.frame += 1;
+
+        for probe in self.probes.iter_mut() {
+            let mut all_match = true;
+            for (cond, prev) in probe.conditions.iter().zip(probe.previous.iter_mut()) {
+                let current = read(cond.address);
+                let lhs = if cond.delta { *prev } else { Some(current) };
+                *prev = Some(current);
+
+                // delta conditions can't match until a previous frame value exists
+                all_match &= lhs.is_some_and(|v| cond.comparison.matches(v, cond.value));
+            }
+
+            if all_match && !probe.triggered {
+                self.events.push(ProbeEvent {
+                    id: probe.id,
+                    frame: self.frame,
+                });
+            }
+            probe.triggered = all_match;
+        }
+    }
+
+    /// Returns and clears the events emitted since the last call
+    pub fn drain_events(&mut self) -> Vec<ProbeEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_triggers_once_on_rising_edge() {
+        let mut probes = MemoryProbes::new();
+        let id = probes.register(vec![Condition {
+            address: 0xC000,
+            comparison: Comparison::GreaterOrEqual,
+            value: 3,
+            delta: false,
+        }]);
+
+        for v in [1, 3, 4, 0, 5] {
+            probes.evaluate(|_| v);
+        }
+
+        assert_eq!(
+            vec![ProbeEvent { id, frame: 2 }, ProbeEvent { id, frame: 5 }],
+            probes.drain_events()
+        );
+        assert!(probes.drain_events().is_empty());
+    }
+
+    #[test]
+    fn test_probe_delta_transition() {
+        let mut probes = MemoryProbes::new();
+        let id = probes.register(vec![
+            Condition {
+                address: 0xC000,
+                comparison: Comparison::Equal,
+                value: 0,
+                delta: true,
+            },
+            Condition {
+                address: 0xC000,
+                comparison: Comparison::Equal,
+                value: 1,
+                delta: false,
+            },
+        ]);
+
+        for v in [1, 0, 1, 1] {
+            probes.evaluate(|_| v);
+        }
+
+        assert_eq!(vec![ProbeEvent { id, frame: 3 }], probes.drain_events());
+    }
+}
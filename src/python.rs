@@ -0,0 +1,138 @@
+// pyo3 0.22 macros expand into conversions clippy flags on the user code
+#![allow(clippy::useless_conversion)]
+
+use std::{ops::RangeInclusive, sync::mpsc};
+
+use numpy::{PyArray1, PyArray3, PyArrayMethods};
+use pyo3::{exceptions::PyValueError, prelude::*, types::PyBytes};
+
+use crate::{
+    config::Config, gameboy::HeadlessGameBoy, joypad::Button, lcd, mode::Mode, pacing::Pacing,
+};
+
+type Core = HeadlessGameBoy<'static>;
+
+/// # Safety
+///
+/// The core borrows `rx`, which must outlive it
+unsafe fn new_core(rom: Vec<u8>, cgb: bool, rx: &mpsc::Receiver<()>) -> Box<Core> {
+    let cfg = Config {
+        mode: if cgb { Mode::CGB } else { Mode::DMG },
+        rom,
+        headless_mode: true,
+        pacing: Pacing::Uncapped,
+        ..Default::default()
+    };
+
+    let rx: &'static mpsc::Receiver<()> = &*(rx as *const _);
+    Box::new(HeadlessGameBoy::headless(&cfg, rx))
+}
+
+/// Gym-style environment: `reset`, then `frame_advance(["a", "right"])` returning the observed
+/// memory regions, `screen()` as a (144, 160, 3) uint8 numpy array, `save_state`/`load_state`
+/// to branch episodes.
+#[pyclass(unsendable, name = "GameBoy")]
+pub struct PyGameBoy {
+    rom: Vec<u8>,
+    cgb: bool,
+    regions: Vec<RangeInclusive<u16>>,
+    core: Box<Core>,
+    /// events receiver borrowed by the core, nobody sends to it as inputs only come from
+    /// `frame_advance`. Boxed so it doesn't move, declared after the core to be dropped last.
+    rx: Box<mpsc::Receiver<()>>,
+}
+
+#[pymethods]
+impl PyGameBoy {
+    #[new]
+    #[pyo3(signature = (rom, cgb = false))]
+    fn new(rom: Vec<u8>, cgb: bool) -> Self {
+        let rx = Box::new(mpsc::channel().1);
+        Self {
+            // SAFETY: `rx` is owned by the wrapper and dropped after the core
+            core: unsafe { new_core(rom.clone(), cgb, &rx) },
+            rom,
+            cgb,
+            regions: vec![],
+            rx,
+        }
+    }
+
+    /// Loads a new rom, restarting the emulator
+    fn load_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+        self.reset();
+    }
+
+    /// Restarts the emulator on the current rom, keeping the observed regions
+    fn reset(&mut self) {
+        // SAFETY: the receiver is kept for the lifetime of the wrapper
+        self.core = unsafe { new_core(self.rom.clone(), self.cgb, &self.rx) };
+        self.core.set_observed_regions(self.regions.clone());
+    }
+
+    /// Inclusive (start, end) address ranges returned by `frame_advance`
+    fn set_observed_regions(&mut self, regions: Vec<(u16, u16)>) {
+        self.regions = regions.into_iter().map(|(s, e)| s..=e).collect();
+        self.core.set_observed_regions(self.regions.clone());
+    }
+
+    /// Holds exactly `buttons` for one frame, returns the observed memory regions
+    #[pyo3(signature = (buttons = vec![]))]
+    fn frame_advance<'py>(
+        &mut self,
+        py: Python<'py>,
+        buttons: Vec<String>,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let buttons = buttons
+            .iter()
+            .map(|b| b.parse::<Button>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(PyValueError::new_err)?;
+
+        let observation = self.core.frame_advance(&buttons);
+        Ok(PyBytes::new_bound(py, &observation.data))
+    }
+
+    fn screen<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray3<u8>>> {
+        let pixels: Vec<u8> = self
+            .core
+            .frame_buffer()
+            .iter()
+            .flatten()
+            .flat_map(|&(r, g, b)| [r, g, b])
+            .collect();
+
+        PyArray1::from_vec_bound(py, pixels).reshape([lcd::PIXELS_HEIGHT, lcd::PIXELS_WIDTH, 3])
+    }
+
+    fn read_memory(&self, address: u16) -> u8 {
+        self.core.read_memory(address)
+    }
+
+    fn write_memory(&mut self, address: u16, value: u8) {
+        self.core.write_memory(address, value);
+    }
+
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.core.save_state())
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> PyResult<()> {
+        self.core
+            .load_state(state)
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+}
+
+/// Build configuration, to include in bug reports
+#[pyfunction]
+fn features() -> String {
+    crate::features().to_string()
+}
+
+#[pymodule]
+fn gamuboy(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyGameBoy>()?;
+    m.add_function(wrap_pyfunction!(features, m)?)
+}
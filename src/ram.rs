@@ -1,4 +1,8 @@
-use crate::{memory::MemReadWriter, mode::Mode};
+use crate::{
+    memory::MemReadWriter,
+    mode::Mode,
+    savestate::{ChunkReader, ChunkWriter, Snapshot},
+};
 
 const WRAM_BANK0_START_ADDR: u16 = 0xC000;
 const WRAM_BANK0_END_ADDR: u16 = 0xCFFF;
@@ -35,6 +39,11 @@ impl RAM {
         }
     }
 
+    /// Work ram bank mapped at D000-DFFF, always 1 on DMG
+    pub fn wram_bank(&self) -> u8 {
+        self.wram_bank
+    }
+
     fn get_switchable_wram_addr(&self, address: u16) -> usize {
         match self.mode {
             Mode::DMG => address as usize,
@@ -64,9 +73,8 @@ impl MemReadWriter for RAM {
             HIGH_RAM_START_ADDR..=HIGH_RAM_END_ADDR => {
                 self.high_ram[(address - HIGH_RAM_START_ADDR) as usize]
             }
-            ECHO_RAM_START_ADDR..=ECHO_RAM_END_ADDR => {
-                self.wram_bank0[(address.wrapping_sub(0x2000) - WRAM_BANK0_START_ADDR) as usize]
-            }
+            // mirrors 0xC000-0xDDFF, both wram banks
+            ECHO_RAM_START_ADDR..=ECHO_RAM_END_ADDR => self.read_byte(address - 0x2000),
             _ => 0xFF,
         }
     }
@@ -91,15 +99,32 @@ impl MemReadWriter for RAM {
             HIGH_RAM_START_ADDR..=HIGH_RAM_END_ADDR => {
                 self.high_ram[(address - HIGH_RAM_START_ADDR) as usize] = value
             }
-            ECHO_RAM_START_ADDR..=ECHO_RAM_END_ADDR => {
-                self.wram_bank0[(address.wrapping_sub(0x2000) - WRAM_BANK0_START_ADDR) as usize] =
-                    value
-            }
+            ECHO_RAM_START_ADDR..=ECHO_RAM_END_ADDR => self.write_byte(address - 0x2000, value),
             _ => {}
         }
     }
 }
 
+impl Snapshot for RAM {
+    const TAG: [u8; 4] = *b"WRAM";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, w: &mut ChunkWriter) {
+        w.write_bytes(&self.wram_bank0);
+        w.write_bytes(&self.wram_bank1_7);
+        w.write_bytes(&self.high_ram);
+        w.write_u8(self.wram_bank);
+    }
+
+    fn load_state(&mut self, r: &mut ChunkReader, _version: u16) {
+        r.read_into(&mut self.wram_bank0);
+        r.read_into(&mut self.wram_bank1_7);
+        r.read_into(&mut self.high_ram);
+        // clamped like 0xFF70 writes, a corrupt bank would index out of bounds
+        self.wram_bank = (r.read_u8() & 0b111).max(1);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -123,4 +148,36 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_cgb_echo_ram_mirrors_switchable_wram() {
+        let mut ram = RAM::new(Mode::CGB);
+
+        ram.write_byte(0xFF70, 3);
+        ram.write_byte(0xF123, 0x42);
+        assert_eq!(0x42, ram.read_byte(0xD123));
+
+        ram.write_byte(0xC456, 0x24);
+        assert_eq!(0x24, ram.read_byte(0xE456));
+
+        ram.write_byte(0xFF70, 1);
+        assert_eq!(0, ram.read_byte(0xF123));
+    }
+
+    #[test]
+    fn test_load_state_clamps_wram_bank() {
+        let mut ram = RAM::new(Mode::CGB);
+
+        // truncated chunk, the bank reads back as 0
+        ram.load_state(&mut ChunkReader::new(&[]), RAM::VERSION);
+        assert_eq!(1, ram.wram_bank());
+        assert_eq!(0, ram.read_byte(0xD000));
+
+        // empty memory fields, then an out of range bank
+        let mut payload = [0; 13];
+        payload[12] = 0x0B;
+        ram.load_state(&mut ChunkReader::new(&payload), RAM::VERSION);
+        assert_eq!(3, ram.wram_bank());
+        ram.read_byte(0xDFFF);
+    }
 }
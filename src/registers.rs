@@ -1,19 +1,24 @@
-use crate::mode::Mode;
+//! CPU registers, read-only outside the core: see `GameBoy::registers`.
 
-#[derive(Debug)]
+use std::fmt;
+
+use crate::mode::Model;
+
+/// F is kept as flags, its low nibble always reads 0 like on hardware
+#[derive(Clone, Debug, PartialEq)]
 pub struct Registers {
-    pub a: u8,
-    pub b: u8,
-    pub c: u8,
-    pub d: u8,
-    pub e: u8,
-    pub f: FlagsRegister,
-    pub h: u8,
-    pub l: u8,
+    pub(crate) a: u8,
+    pub(crate) b: u8,
+    pub(crate) c: u8,
+    pub(crate) d: u8,
+    pub(crate) e: u8,
+    pub(crate) f: FlagsRegister,
+    pub(crate) h: u8,
+    pub(crate) l: u8,
 }
 
 impl Registers {
-    pub fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             a: 0,
             b: 0,
@@ -26,10 +31,29 @@ impl Registers {
         }
     }
 
-    pub fn new_post_boot(mode: Mode) -> Self {
-        match mode {
-            Mode::DMG => Self::new_post_boot_dmg(),
-            Mode::CGB => Self::new_post_boot_cgb(),
+    /// Values left by the boot rom of `model`, that games and test roms check to detect it
+    pub(crate) fn new_post_boot(model: Model) -> Self {
+        match model {
+            Model::DMG0 => Self::new_post_boot_dmg0(),
+            Model::DMG => Self::new_post_boot_dmg(),
+            Model::MGB => Self {
+                a: 0xFF,
+                ..Self::new_post_boot_dmg()
+            },
+            Model::CGB0 | Model::CGB => Self::new_post_boot_cgb(),
+        }
+    }
+
+    fn new_post_boot_dmg0() -> Self {
+        Self {
+            a: 0x01,
+            b: 0xFF,
+            c: 0x13,
+            d: 0x00,
+            e: 0xC1,
+            f: FlagsRegister::new(),
+            h: 0x84,
+            l: 0x03,
         }
     }
 
@@ -46,24 +70,61 @@ impl Registers {
         }
     }
 
+    /// CGB boot rom handing over to a CGB game, CGB0 included
     fn new_post_boot_cgb() -> Self {
         Self {
             a: 0x11,
             b: 0x00,
-            c: 0x13,
-            d: 0x00,
-            e: 0xD8,
-            f: FlagsRegister::new_debug(),
-            h: 0x01,
-            l: 0x4D,
+            c: 0x00,
+            d: 0xFF,
+            e: 0x56,
+            f: FlagsRegister::from(0x80),
+            h: 0x00,
+            l: 0x0D,
         }
     }
 
+    pub fn a(&self) -> u8 {
+        self.a
+    }
+
+    pub fn f(&self) -> u8 {
+        u8::from(self.f.clone())
+    }
+
+    pub fn flags(&self) -> &FlagsRegister {
+        &self.f
+    }
+
+    pub fn b(&self) -> u8 {
+        self.b
+    }
+
+    pub fn c(&self) -> u8 {
+        self.c
+    }
+
+    pub fn d(&self) -> u8 {
+        self.d
+    }
+
+    pub fn e(&self) -> u8 {
+        self.e
+    }
+
+    pub fn h(&self) -> u8 {
+        self.h
+    }
+
+    pub fn l(&self) -> u8 {
+        self.l
+    }
+
     pub fn get_af(&self) -> u16 {
         as_16bits(self.a, u8::from(self.f.clone()))
     }
 
-    pub fn set_af(&mut self, value: u16) {
+    pub(crate) fn set_af(&mut self, value: u16) {
         self.a = get_16bits_left(value);
         self.f = FlagsRegister::from(get_16bits_right(value));
     }
@@ -72,7 +133,7 @@ impl Registers {
         as_16bits(self.b, self.c)
     }
 
-    pub fn set_bc(&mut self, value: u16) {
+    pub(crate) fn set_bc(&mut self, value: u16) {
         self.b = get_16bits_left(value);
         self.c = get_16bits_right(value);
     }
@@ -81,7 +142,7 @@ impl Registers {
         as_16bits(self.d, self.e)
     }
 
-    pub fn set_de(&mut self, value: u16) {
+    pub(crate) fn set_de(&mut self, value: u16) {
         self.d = get_16bits_left(value);
         self.e = get_16bits_right(value);
     }
@@ -90,12 +151,26 @@ impl Registers {
         as_16bits(self.h, self.l)
     }
 
-    pub fn set_hl(&mut self, value: u16) {
+    pub(crate) fn set_hl(&mut self, value: u16) {
         self.h = get_16bits_left(value);
         self.l = get_16bits_right(value);
     }
 }
 
+/// `AF=01B0 BC=0013 DE=00D8 HL=014D`, as in crash reports
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "AF={:04X} BC={:04X} DE={:04X} HL={:04X}",
+            self.get_af(),
+            self.get_bc(),
+            self.get_de(),
+            self.get_hl()
+        )
+    }
+}
+
 fn as_16bits(left: u8, right: u8) -> u16 {
     (left as u16) << 8 | right as u16
 }
@@ -108,7 +183,7 @@ fn get_16bits_right(value: u16) -> u8 {
     value as u8
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct FlagsRegister {
     pub zero: bool,
     pub subtract: bool,
@@ -160,3 +235,46 @@ impl From<u8> for FlagsRegister {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_f_low_nibble_reads_zero() {
+        let mut registers = Registers::new();
+        registers.set_af(0x12FF);
+        assert_eq!(0x12F0, registers.get_af());
+        assert_eq!(0xF0, registers.f());
+        assert!(registers.flags().zero && registers.flags().carry);
+
+        registers.set_af(0x345F);
+        assert_eq!(0x3450, registers.get_af());
+        assert!(!registers.flags().zero && registers.flags().subtract);
+    }
+
+    #[test]
+    fn test_post_boot_models() {
+        for (model, registers) in [
+            (Model::DMG0, "AF=0100 BC=FF13 DE=00C1 HL=8403"),
+            (Model::DMG, "AF=01B0 BC=0013 DE=00D8 HL=014D"),
+            (Model::MGB, "AF=FFB0 BC=0013 DE=00D8 HL=014D"),
+            (Model::CGB0, "AF=1180 BC=0000 DE=FF56 HL=000D"),
+            (Model::CGB, "AF=1180 BC=0000 DE=FF56 HL=000D"),
+        ] {
+            assert_eq!(
+                registers,
+                Registers::new_post_boot(model).to_string(),
+                "{model:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            "AF=01B0 BC=0013 DE=00D8 HL=014D",
+            Registers::new_post_boot(Model::DMG).to_string()
+        );
+    }
+}
@@ -0,0 +1,391 @@
+//! Instant replay: a rolling buffer of the last seconds of frames, so users can capture what just
+//! happened, exported as an animated GIF with the `replay-gif` feature. See
+//! `Config::replay_seconds` and `GameBoy::export_replay_gif`.
+//!
+//! Every other frame is kept, about 30 per second. Loading a savestate clears the buffer, the
+//! frames before it belong to another timeline.
+
+use std::{collections::VecDeque, time::Duration};
+
+use crate::{
+    lcd::{Frame, RGB},
+    pacing,
+};
+
+/// Only frames whose index is a multiple of it are kept
+pub const FRAME_STEP: u64 = 2;
+
+/// Frame kept in the replay, pixels row by row
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplayFrame {
+    pub index: u64,
+    /// Emulated time since power on
+    pub timestamp: Duration,
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<RGB>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ReplayBuffer {
+    frames: VecDeque<ReplayFrame>,
+    capacity: usize,
+}
+
+impl ReplayBuffer {
+    /// Keeps the last `seconds` of emulated time
+    pub fn new(seconds: u32) -> Self {
+        let frames = (seconds as u64 * pacing::DOTS_PER_SECOND)
+            .div_ceil(pacing::FRAME_DOTS * FRAME_STEP) as usize;
+        Self {
+            frames: VecDeque::with_capacity(frames),
+            capacity: frames,
+        }
+    }
+
+    pub(crate) fn record(&mut self, frame: &Frame) {
+        if self.capacity == 0 || !frame.index.is_multiple_of(FRAME_STEP) {
+            return;
+        }
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(ReplayFrame {
+            index: frame.index,
+            timestamp: frame.timestamp,
+            width: frame.width(),
+            height: frame.height(),
+            pixels: frame.pixels.iter().flatten().copied().collect(),
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Oldest first
+    pub fn frames(&self) -> impl Iterator<Item = &ReplayFrame> {
+        self.frames.iter()
+    }
+
+    /// Frames of the last `duration` of emulated time, oldest first
+    pub fn last(&self, duration: Duration) -> Vec<&ReplayFrame> {
+        let Some(newest) = self.frames.back() else {
+            return vec![];
+        };
+        let start = newest.timestamp.saturating_sub(duration);
+        self.frames
+            .iter()
+            .filter(|f| f.timestamp >= start)
+            .collect()
+    }
+}
+
+/// Looping GIF of `frames`, each shown until the next one's timestamp. Colors past 256 are
+/// reduced to a fixed 3-3-2 palette.
+#[cfg(feature = "replay-gif")]
+pub fn encode_gif(frames: &[&ReplayFrame]) -> Vec<u8> {
+    use std::collections::HashMap;
+
+    let (width, height) = frames.first().map_or((0, 0), |f| (f.width, f.height));
+
+    let mut palette: Vec<RGB> = vec![];
+    let mut indices: HashMap<RGB, u8> = HashMap::new();
+    'colors: for frame in frames {
+        for &color in &frame.pixels {
+            if !indices.contains_key(&color) {
+                if palette.len() == 256 {
+                    palette.clear();
+                    indices.clear();
+                    break 'colors;
+                }
+                indices.insert(color, palette.len() as u8);
+                palette.push(color);
+            }
+        }
+    }
+    let quantized = palette.is_empty() && !frames.is_empty();
+    if quantized {
+        palette = (0..=255u8)
+            .map(|i| {
+                let scale = |value: u8, max: u8| (value as u16 * 255 / max as u16) as u8;
+                (scale(i >> 5, 7), scale((i >> 2) & 7, 7), scale(i & 3, 3))
+            })
+            .collect();
+    }
+    let index_of = |color: RGB| match quantized {
+        true => (color.0 & 0xE0) | ((color.1 >> 3) & 0x1C) | (color.2 >> 6),
+        false => indices[&color],
+    };
+
+    let mut gif = b"GIF89a".to_vec();
+    gif.extend_from_slice(&(width as u16).to_le_bytes());
+    gif.extend_from_slice(&(height as u16).to_le_bytes());
+    // global color table of 256 entries
+    gif.extend_from_slice(&[0xF7, 0, 0]);
+    for i in 0..256 {
+        let (r, g, b) = palette.get(i).copied().unwrap_or_default();
+        gif.extend_from_slice(&[r, g, b]);
+    }
+    // loop forever
+    gif.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+    gif.extend_from_slice(b"NETSCAPE2.0");
+    gif.extend_from_slice(&[0x03, 0x01, 0x00, 0x00, 0x00]);
+
+    // delays in centiseconds, rounded from the start so they don't drift
+    let centis = |t: Duration| (t.as_micros() as u64 + 5_000) / 10_000;
+    for (i, frame) in frames.iter().enumerate() {
+        let next = match frames.get(i + 1) {
+            Some(next) => next.timestamp,
+            None => frame.timestamp + pacing::frames_duration(FRAME_STEP),
+        };
+        let delay = (centis(next) - centis(frame.timestamp)) as u16;
+        gif.extend_from_slice(&[0x21, 0xF9, 0x04, 0x00]);
+        gif.extend_from_slice(&delay.to_le_bytes());
+        gif.extend_from_slice(&[0x00, 0x00]);
+
+        gif.push(0x2C);
+        gif.extend_from_slice(&[0, 0, 0, 0]);
+        gif.extend_from_slice(&(frame.width as u16).to_le_bytes());
+        gif.extend_from_slice(&(frame.height as u16).to_le_bytes());
+        gif.push(0x00);
+
+        let pixels: Vec<u8> = frame.pixels.iter().map(|&c| index_of(c)).collect();
+        gif.push(8);
+        for block in lzw_encode(&pixels).chunks(255) {
+            gif.push(block.len() as u8);
+            gif.extend_from_slice(block);
+        }
+        gif.push(0x00);
+    }
+
+    gif.push(0x3B);
+    gif
+}
+
+/// GIF flavored LZW with 8 bits symbols, codes growing up to 12 bits, as giflib does
+#[cfg(feature = "replay-gif")]
+fn lzw_encode(symbols: &[u8]) -> Vec<u8> {
+    use std::collections::HashMap;
+
+    const CLEAR: u16 = 256;
+    const END: u16 = 257;
+    const MAX_CODE: u16 = 4095;
+
+    struct Output {
+        bytes: Vec<u8>,
+        bits: u32,
+        pending: u32,
+        size: u32,
+    }
+
+    impl Output {
+        fn emit(&mut self, code: u16, next: u16) {
+            self.bits |= (code as u32) << self.pending;
+            self.pending += self.size;
+            while self.pending >= 8 {
+                self.bytes.push(self.bits as u8);
+                self.bits >>= 8;
+                self.pending -= 8;
+            }
+            if next as u32 >= 1 << self.size && self.size < 12 {
+                self.size += 1;
+            }
+        }
+
+        fn finish(mut self, next: u16) -> Vec<u8> {
+            self.emit(END, next);
+            if self.pending > 0 {
+                self.bytes.push(self.bits as u8);
+            }
+            self.bytes
+        }
+    }
+
+    let mut out = Output {
+        bytes: vec![],
+        bits: 0,
+        pending: 0,
+        size: 9,
+    };
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = END + 1;
+    out.emit(CLEAR, next);
+
+    let Some((&first, rest)) = symbols.split_first() else {
+        return out.finish(next);
+    };
+    let mut prefix = first as u16;
+    for &symbol in rest {
+        if let Some(&code) = codes.get(&(prefix, symbol)) {
+            prefix = code;
+            continue;
+        }
+        out.emit(prefix, next);
+        if next >= MAX_CODE {
+            out.emit(CLEAR, next);
+            codes.clear();
+            next = END + 1;
+            out.size = 9;
+        } else {
+            codes.insert((prefix, symbol), next);
+            next += 1;
+        }
+        prefix = symbol as u16;
+    }
+    out.emit(prefix, next);
+    out.finish(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lcd::{self, RGB_WHITE};
+
+    use super::*;
+
+    fn frame(index: u64, color: RGB) -> Frame {
+        let mut frame = Frame::new(vec![vec![color; lcd::PIXELS_WIDTH]; lcd::PIXELS_HEIGHT]);
+        frame.index = index;
+        frame.timestamp = pacing::frames_duration(index);
+        frame
+    }
+
+    #[test]
+    fn test_replay_buffer() {
+        let mut replay = ReplayBuffer::new(1);
+        assert_eq!(0, replay.len());
+        for index in 1..=200 {
+            replay.record(&frame(index, RGB_WHITE));
+        }
+        // 1 second holds 30 of the even frames
+        assert_eq!(30, replay.len());
+        assert_eq!(142, replay.frames().next().unwrap().index);
+        assert_eq!(200, replay.frames().last().unwrap().index);
+        assert_eq!(
+            lcd::PIXELS_WIDTH * lcd::PIXELS_HEIGHT,
+            replay.frames().next().unwrap().pixels.len()
+        );
+
+        let last = replay.last(pacing::frames_duration(11));
+        assert_eq!(
+            vec![190, 192, 194, 196, 198, 200],
+            last.iter().map(|f| f.index).collect::<Vec<_>>()
+        );
+
+        replay.clear();
+        assert!(replay.is_empty());
+        assert!(replay.last(Duration::from_secs(1)).is_empty());
+
+        let mut off = ReplayBuffer::new(0);
+        off.record(&frame(2, RGB_WHITE));
+        assert!(off.is_empty());
+    }
+
+    /// Reference GIF LZW decoder
+    #[cfg(feature = "replay-gif")]
+    fn lzw_decode(data: &[u8]) -> Vec<u8> {
+        let (mut bits, mut pending, mut size) = (0u32, 0u32, 9u32);
+        let mut bytes = data.iter();
+        let mut table: Vec<Vec<u8>> = vec![];
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            *table = (0..=255u8).map(|b| vec![b]).collect();
+            table.push(vec![]);
+            table.push(vec![]);
+        };
+        reset(&mut table);
+        let mut out = vec![];
+        let mut previous: Option<Vec<u8>> = None;
+        loop {
+            while pending < size {
+                bits |= (*bytes.next().unwrap() as u32) << pending;
+                pending += 8;
+            }
+            let code = (bits & ((1 << size) - 1)) as usize;
+            bits >>= size;
+            pending -= size;
+            match code {
+                256 => {
+                    reset(&mut table);
+                    size = 9;
+                    previous = None;
+                    continue;
+                }
+                257 => return out,
+                _ => {}
+            }
+            let entry = match (table.get(code), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(prev)) => [&prev[..], &prev[..1]].concat(),
+                (None, None) => panic!("bad first code"),
+            };
+            if let Some(prev) = previous {
+                if table.len() < 4096 {
+                    table.push([&prev[..], &entry[..1]].concat());
+                }
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            out.extend_from_slice(&entry);
+            previous = Some(entry);
+        }
+    }
+
+    #[cfg(feature = "replay-gif")]
+    #[test]
+    fn test_lzw_roundtrip() {
+        let mut seed = 1u32;
+        let mut noise = || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as u8
+        };
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![7],
+            vec![0; 23040],
+            (0..23040).map(|i| (i % 4) as u8).collect(),
+            // enough distinct strings to fill the table and clear it
+            (0..100_000).map(|_| noise()).collect(),
+        ];
+        for input in inputs {
+            assert_eq!(
+                input,
+                lzw_decode(&lzw_encode(&input)),
+                "{} symbols",
+                input.len()
+            );
+        }
+    }
+
+    #[cfg(feature = "replay-gif")]
+    #[test]
+    fn test_encode_gif() {
+        use crate::lcd::RGB_BLACK;
+
+        let mut replay = ReplayBuffer::new(1);
+        replay.record(&frame(2, RGB_WHITE));
+        replay.record(&frame(4, RGB_BLACK));
+        let gif = encode_gif(&replay.last(Duration::from_secs(1)));
+
+        assert!(gif.starts_with(b"GIF89a\xA0\x00\x90\x00\xF7"));
+        assert_eq!(Some(&0x3B), gif.last());
+        // white then black in the palette
+        assert_eq!([255, 255, 255, 0, 0, 0], gif[13..19]);
+        // two frames 2 emulated frames apart, 3.35 cs rounded from the start
+        let delays: Vec<u16> = gif
+            .windows(4)
+            .enumerate()
+            .filter(|(_, w)| *w == [0x21, 0xF9, 0x04, 0x00])
+            .map(|(i, _)| u16::from_le_bytes([gif[i + 4], gif[i + 5]]))
+            .collect();
+        assert_eq!(vec![4, 3], delays);
+    }
+}
@@ -0,0 +1,194 @@
+//! ROM identification by content hash (e.g. No-Intro DATs) rather than by header title,
+//! which is often duplicated across games or filled with garbage.
+
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+/// CRC32 of the whole rom, the key used by No-Intro DATs
+pub fn rom_crc32(rom: &[u8]) -> u32 {
+    const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    CRC32.checksum(rom)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Region {
+    Japan,
+    USA,
+    Europe,
+    World,
+    Unknown,
+}
+
+impl FromStr for Region {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "japan" | "jpn" | "jp" => Self::Japan,
+            "usa" | "us" => Self::USA,
+            "europe" | "eur" | "eu" => Self::Europe,
+            "world" => Self::World,
+            "" | "unknown" => Self::Unknown,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// Canonical identity of a known rom
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub region: Region,
+}
+
+impl RomInfo {
+    /// Title usable as a file name on every platform
+    pub fn file_name(&self) -> String {
+        self.title
+            .chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            })
+            .collect()
+    }
+}
+
+/// Pluggable rom lookup, frontends can back it with any DAT source
+pub trait RomDatabase {
+    fn lookup(&self, crc32: u32) -> Option<RomInfo>;
+
+    fn lookup_rom(&self, rom: &[u8]) -> Option<RomInfo> {
+        self.lookup(rom_crc32(rom))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid rom database entry at line {}", self.line)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Compact in-memory database
+#[derive(Clone, Debug, Default)]
+pub struct HashDatabase {
+    entries: HashMap<u32, RomInfo>,
+}
+
+impl HashDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses one `crc32<TAB>region<TAB>title` entry per line, crc32 in hex.
+    /// Empty lines and lines starting with `#` are skipped.
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut db = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let err = ParseError { line: i + 1 };
+            let mut fields = line.splitn(3, '\t');
+            let (Some(crc), Some(region), Some(title)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(err);
+            };
+
+            let crc = u32::from_str_radix(crc.trim(), 16).map_err(|_| err)?;
+            let region = region.trim().parse().unwrap_or(Region::Unknown);
+            db.insert(
+                crc,
+                RomInfo {
+                    title: title.trim().to_string(),
+                    region,
+                },
+            );
+        }
+
+        Ok(db)
+    }
+
+    pub fn insert(&mut self, crc32: u32, info: RomInfo) {
+        self.entries.insert(crc32, info);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl RomDatabase for HashDatabase {
+    fn lookup(&self, crc32: u32) -> Option<RomInfo> {
+        self.entries.get(&crc32).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_database() {
+        let db = HashDatabase::parse(
+            "# crc32\tregion\ttitle\n\
+             \n\
+             90776841\tWorld\tTetris (World) (Rev 1)\n\
+             ABCDEF01\tmoon\tSome: Game?\n",
+        )
+        .unwrap();
+        assert_eq!(2, db.len());
+
+        assert_eq!(
+            Some(RomInfo {
+                title: "Tetris (World) (Rev 1)".to_string(),
+                region: Region::World,
+            }),
+            db.lookup(0x90776841)
+        );
+
+        let info = db.lookup(0xABCDEF01).unwrap();
+        assert_eq!(Region::Unknown, info.region);
+        assert_eq!("Some_ Game_", info.file_name());
+
+        assert_eq!(None, db.lookup(0));
+
+        assert_eq!(
+            Err(ParseError { line: 1 }),
+            HashDatabase::parse("zz\tWorld\tTitle").map(|_| ())
+        );
+        assert_eq!(
+            Err(ParseError { line: 2 }),
+            HashDatabase::parse("\n00000001\tWorld").map(|_| ())
+        );
+    }
+
+    #[test]
+    fn test_lookup_rom() {
+        let rom = vec![0; 0x8000];
+        let info = RomInfo {
+            title: "Blank".to_string(),
+            region: Region::Japan,
+        };
+
+        let mut db = HashDatabase::new();
+        db.insert(rom_crc32(&rom), info.clone());
+        assert_eq!(Some(info), db.lookup_rom(&rom));
+        assert_eq!(None, db.lookup_rom(&rom[..0x4000]));
+    }
+}
@@ -1,9 +1,13 @@
 use std::{
-    fs::{self, create_dir, File},
+    cell::{Cell, RefCell},
+    fs::{self, File},
     io::{Error, Write},
-    path::{Path, PathBuf},
+    path::PathBuf,
+    time::{Duration, Instant},
 };
 
+use crc::{Crc, CRC_32_ISO_HDLC};
+
 pub trait GameSave {
     fn set_title(&mut self, _title: String) {}
 
@@ -14,27 +18,49 @@ pub trait GameSave {
     fn save(&self, _ram: &[u8]) -> Result<(), Error> {
         Ok(())
     }
+
+    /// Called when the loaded save doesn't fit the `expected` cartridge ram size: the game starts
+    /// with blank ram instead, also reported as `Event::SaveRejected`
+    fn load_rejected(&self, _expected: usize, _found: usize) {}
 }
 
 pub struct Fake;
 
 impl GameSave for Fake {}
 
+/// Marks the footer `FileSaver` appends to the ram: the magic then the CRC32 of the ram
+const SAVE_FOOTER_MAGIC: [u8; 4] = *b"GBSV";
+const SAVE_FOOTER_SIZE: usize = 8;
+
+fn save_checksum(ram: &[u8]) -> u32 {
+    const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    CRC32.checksum(ram)
+}
+
+/// Saves `<title>.sav` files in a directory. A save is written to a temporary file renamed over
+/// the previous one, so a crash mid-write leaves the previous save intact, and ends with a
+/// checksum footer to detect corrupted saves. Saves without footer (older versions, other
+/// emulators) load as they are.
 pub struct FileSaver {
     save_path: PathBuf,
 }
 
 impl FileSaver {
+    /// Saves in `./save`
     pub fn new() -> Result<Self, Error> {
-        let save_path = Path::new("save");
-        if !save_path.is_dir() {
-            create_dir("save")?;
-        }
+        Self::in_dir("save")
+    }
 
-        let save_path = save_path.to_path_buf();
+    pub fn in_dir<P: Into<PathBuf>>(dir: P) -> Result<Self, Error> {
+        let save_path = dir.into();
+        fs::create_dir_all(&save_path)?;
 
         Ok(Self { save_path })
     }
+
+    fn temp_path(&self) -> PathBuf {
+        self.save_path.with_extension("sav.tmp")
+    }
 }
 
 impl GameSave for FileSaver {
@@ -43,13 +69,298 @@ impl GameSave for FileSaver {
     }
 
     fn load(&self) -> Result<Vec<u8>, Error> {
-        fs::read(self.save_path.clone())
+        let mut ram = fs::read(&self.save_path)?;
+        let Some(footer_start) = ram.len().checked_sub(SAVE_FOOTER_SIZE) else {
+            return Ok(ram);
+        };
+        if ram[footer_start..footer_start + 4] != SAVE_FOOTER_MAGIC {
+            return Ok(ram);
+        }
+
+        let footer = ram.split_off(footer_start);
+        let checksum = u32::from_le_bytes(footer[4..].try_into().unwrap());
+        if checksum != save_checksum(&ram) {
+            warn!(
+                "Save checksum mismatch, {} may be corrupted.",
+                self.save_path.display()
+            );
+        }
+        Ok(ram)
     }
 
     fn save(&self, ram: &[u8]) -> Result<(), Error> {
-        let mut file = File::create(self.save_path.clone())?;
+        let temp_path = self.temp_path();
+        let mut file = File::create(&temp_path)?;
         file.write_all(ram)?;
+        file.write_all(&SAVE_FOOTER_MAGIC)?;
+        file.write_all(&save_checksum(ram).to_le_bytes())?;
+        file.sync_all()?;
+        fs::rename(temp_path, &self.save_path)
+    }
+}
 
-        Ok(())
+/// Adapter writing to `inner` at most once per `interval`: games toggling the ram enable
+/// register save on every disable, which stutters emulation on slow storage. Saves in between
+/// only keep the latest ram, written by the next save once the interval elapsed, by `flush` or
+/// when the saver is dropped along with the `GameBoy`.
+/// Native targets only, `Instant` isn't available on wasm32.
+pub struct CoalescingSaver<S: GameSave> {
+    inner: S,
+    interval: Duration,
+    last_save: Cell<Option<Instant>>,
+    pending: RefCell<Option<Vec<u8>>>,
+}
+
+impl<S: GameSave> CoalescingSaver<S> {
+    pub fn new(inner: S, interval: Duration) -> Self {
+        Self {
+            inner,
+            interval,
+            last_save: Cell::new(None),
+            pending: RefCell::new(None),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Writes the ram kept since the last write, if any
+    pub fn flush(&self) -> Result<(), Error> {
+        match self.pending.borrow_mut().take() {
+            Some(ram) => self.write(&ram),
+            None => Ok(()),
+        }
+    }
+
+    fn write(&self, ram: &[u8]) -> Result<(), Error> {
+        self.last_save.set(Some(Instant::now()));
+        self.inner.save(ram)
+    }
+}
+
+impl<S: GameSave> GameSave for CoalescingSaver<S> {
+    fn set_title(&mut self, title: String) {
+        self.inner.set_title(title);
+    }
+
+    fn load(&self) -> Result<Vec<u8>, Error> {
+        match &*self.pending.borrow() {
+            Some(ram) => Ok(ram.clone()),
+            None => self.inner.load(),
+        }
+    }
+
+    fn save(&self, ram: &[u8]) -> Result<(), Error> {
+        let due = self
+            .last_save
+            .get()
+            .is_none_or(|last| last.elapsed() >= self.interval);
+        if !due {
+            *self.pending.borrow_mut() = Some(ram.to_vec());
+            return Ok(());
+        }
+
+        self.pending.borrow_mut().take();
+        self.write(ram)
+    }
+
+    fn load_rejected(&self, expected: usize, found: usize) {
+        self.inner.load_rejected(expected, found);
+    }
+}
+
+impl<S: GameSave> Drop for CoalescingSaver<S> {
+    fn drop(&mut self) {
+        _ = self.flush();
+    }
+}
+
+/// Corruption applied by `FaultInjectingSaver` to the loaded save
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SaveFault {
+    /// Keeps the first `len` bytes, as an interrupted write would
+    Truncate(usize),
+    /// Flips `bit` of the byte at `offset`, nothing past the end
+    BitFlip { offset: usize, bit: u8 },
+    /// Resizes to `len` bytes padded with 0xFF, as a save of another cartridge or emulator
+    WrongSize(usize),
+}
+
+impl SaveFault {
+    fn apply(&self, ram: &mut Vec<u8>) {
+        match *self {
+            Self::Truncate(len) => ram.truncate(len),
+            Self::BitFlip { offset, bit } => {
+                if let Some(byte) = ram.get_mut(offset) {
+                    *byte ^= 1 << (bit & 7);
+                }
+            }
+            Self::WrongSize(len) => ram.resize(len, 0xFF),
+        }
+    }
+}
+
+/// Adapter corrupting the save `inner` loads, in order, to test how games and the emulator
+/// cope with damaged battery ram. Saves go through untouched.
+pub struct FaultInjectingSaver<S: GameSave> {
+    inner: S,
+    faults: Vec<SaveFault>,
+}
+
+impl<S: GameSave> FaultInjectingSaver<S> {
+    pub fn new(inner: S, faults: Vec<SaveFault>) -> Self {
+        Self { inner, faults }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: GameSave> GameSave for FaultInjectingSaver<S> {
+    fn set_title(&mut self, title: String) {
+        self.inner.set_title(title);
+    }
+
+    fn load(&self) -> Result<Vec<u8>, Error> {
+        let mut ram = self.inner.load()?;
+        for fault in &self.faults {
+            fault.apply(&mut ram);
+        }
+        Ok(ram)
+    }
+
+    fn save(&self, ram: &[u8]) -> Result<(), Error> {
+        self.inner.save(ram)
+    }
+
+    fn load_rejected(&self, expected: usize, found: usize) {
+        self.inner.load_rejected(expected, found);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Recorder(Rc<RefCell<Vec<Vec<u8>>>>);
+
+    impl GameSave for Recorder {
+        fn save(&self, ram: &[u8]) -> Result<(), Error> {
+            self.0.borrow_mut().push(ram.to_vec());
+            Ok(())
+        }
+    }
+
+    fn temp_saver(name: &str) -> FileSaver {
+        let dir = std::env::temp_dir().join(format!("gamuboy-{name}-{}", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        let mut saver = FileSaver::in_dir(dir).unwrap();
+        saver.set_title("GAME".into());
+        saver
+    }
+
+    #[test]
+    fn test_file_saver() {
+        let saver = temp_saver("file-saver");
+        assert!(saver.load().is_err());
+
+        saver.save(&[1, 2, 3]).unwrap();
+        saver.save(&[4, 5, 6]).unwrap();
+        assert_eq!(vec![4, 5, 6], saver.load().unwrap());
+        assert!(!saver.temp_path().exists());
+
+        let file = fs::read(&saver.save_path).unwrap();
+        assert_eq!(3 + SAVE_FOOTER_SIZE, file.len());
+        assert_eq!(SAVE_FOOTER_MAGIC, file[3..7]);
+
+        // saves of other emulators have no footer
+        fs::write(&saver.save_path, [7; 16]).unwrap();
+        assert_eq!(vec![7; 16], saver.load().unwrap());
+
+        // corrupted saves still load, with a warning
+        let mut corrupted = file.clone();
+        corrupted[0] ^= 0xFF;
+        fs::write(&saver.save_path, corrupted).unwrap();
+        assert_eq!(vec![0xFB, 5, 6], saver.load().unwrap());
+
+        _ = fs::remove_dir_all(saver.save_path.parent().unwrap());
+    }
+
+    #[test]
+    fn test_coalescing_saver() {
+        let recorder = Recorder::default();
+        let saver = CoalescingSaver::new(recorder.clone(), Duration::from_secs(3600));
+
+        saver.save(&[1]).unwrap();
+        saver.save(&[2]).unwrap();
+        saver.save(&[3]).unwrap();
+        assert_eq!(vec![vec![1]], *recorder.0.borrow());
+        assert_eq!(vec![3], saver.load().unwrap());
+
+        saver.flush().unwrap();
+        saver.flush().unwrap();
+        assert_eq!(vec![vec![1], vec![3]], *recorder.0.borrow());
+
+        saver.save(&[4]).unwrap();
+        drop(saver);
+        assert_eq!(vec![vec![1], vec![3], vec![4]], *recorder.0.borrow());
+    }
+
+    #[test]
+    fn test_coalescing_saver_writes_once_the_interval_elapsed() {
+        let recorder = Recorder::default();
+        let saver = CoalescingSaver::new(recorder.clone(), Duration::ZERO);
+
+        saver.save(&[1]).unwrap();
+        saver.save(&[2]).unwrap();
+        drop(saver);
+        assert_eq!(vec![vec![1], vec![2]], *recorder.0.borrow());
+    }
+
+    struct Saved(Vec<u8>);
+
+    impl GameSave for Saved {
+        fn load(&self) -> Result<Vec<u8>, Error> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn test_fault_injecting_saver() {
+        let load = |faults| FaultInjectingSaver::new(Saved(vec![0x10; 4]), faults).load();
+
+        assert_eq!(vec![0x10; 4], load(vec![]).unwrap());
+        assert_eq!(vec![0x10; 2], load(vec![SaveFault::Truncate(2)]).unwrap());
+        assert_eq!(
+            vec![0x10, 0x10, 0x90, 0x10],
+            load(vec![SaveFault::BitFlip { offset: 2, bit: 7 }]).unwrap()
+        );
+        assert_eq!(
+            vec![0x10; 4],
+            load(vec![SaveFault::BitFlip { offset: 4, bit: 0 }]).unwrap()
+        );
+        assert_eq!(
+            vec![0x10, 0x10, 0x10, 0x10, 0xFF, 0xFF],
+            load(vec![SaveFault::WrongSize(6)]).unwrap()
+        );
+        assert_eq!(
+            vec![0x11, 0xFF],
+            load(vec![
+                SaveFault::Truncate(1),
+                SaveFault::WrongSize(2),
+                SaveFault::BitFlip { offset: 0, bit: 8 }
+            ])
+            .unwrap()
+        );
+
+        let recorder = Recorder::default();
+        let saver = FaultInjectingSaver::new(recorder.clone(), vec![SaveFault::Truncate(0)]);
+        saver.save(&[1, 2]).unwrap();
+        assert_eq!(vec![vec![1, 2]], *recorder.0.borrow());
     }
 }
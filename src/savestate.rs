@@ -0,0 +1,472 @@
+//! Savestate container format.
+//!
+//! A state is a header followed by one chunk per subsystem:
+//!
+//! ```text
+//! header: "GBST" | format version: u16
+//! chunk:  tag: [u8; 4] | chunk version: u16 | payload length: u32 | payload
+//! ```
+//!
+//! All integers are little endian. Chunks are length-prefixed so unknown ones can be skipped,
+//! and each carries its own version so a subsystem can append fields without breaking older
+//! states: reading past the end of a payload yields default values, and trailing fields written
+//! by a newer version are ignored.
+
+use std::{collections::HashMap, fmt};
+
+const MAGIC: [u8; 4] = *b"GBST";
+pub const FORMAT_VERSION: u16 = 1;
+
+#[derive(Debug, PartialEq)]
+pub enum SaveStateError {
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    /// A chunk header or payload is cut short
+    Truncated,
+    /// A chunk required by a subsystem is missing
+    MissingChunk([u8; 4]),
+    /// The state was made with another rom, (state, loaded) CRC32
+    RomMismatch(u32, u32),
+    /// A chunk holds values the subsystem can't be in
+    Corrupt([u8; 4]),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMagic => write!(f, "not a savestate"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported savestate format version {v}"),
+            Self::Truncated => write!(f, "truncated savestate"),
+            Self::MissingChunk(tag) => {
+                write!(f, "missing chunk {}", String::from_utf8_lossy(tag))
+            }
+            Self::RomMismatch(state, loaded) => write!(
+                f,
+                "savestate made with rom {state:08x}, loaded rom is {loaded:08x}"
+            ),
+            Self::Corrupt(tag) => {
+                write!(f, "corrupt chunk {}", String::from_utf8_lossy(tag))
+            }
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+/// A subsystem able to store its state in its own chunk
+pub trait Snapshot {
+    const TAG: [u8; 4];
+    /// Bumped whenever fields are appended to the chunk
+    const VERSION: u16;
+
+    fn save_state(&self, w: &mut ChunkWriter);
+    /// `version` is the one the chunk was written with, fields it doesn't hold read as defaults
+    fn load_state(&mut self, r: &mut ChunkReader, version: u16);
+}
+
+/// Payload of a single chunk
+#[derive(Default)]
+pub struct ChunkWriter {
+    payload: Vec<u8>,
+}
+
+impl ChunkWriter {
+    pub fn write_u8(&mut self, v: u8) {
+        self.payload.push(v);
+    }
+
+    pub fn write_bool(&mut self, v: bool) {
+        self.write_u8(v as u8);
+    }
+
+    pub fn write_u16(&mut self, v: u16) {
+        self.payload.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, v: u32) {
+        self.payload.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, v: u64) {
+        self.payload.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn write_f32(&mut self, v: f32) {
+        self.write_u32(v.to_bits());
+    }
+
+    /// Length-prefixed bytes
+    pub fn write_bytes(&mut self, v: &[u8]) {
+        self.write_u32(v.len() as u32);
+        self.payload.extend_from_slice(v);
+    }
+}
+
+/// Reads a chunk payload, every read past its end returns the type default
+pub struct ChunkReader<'a> {
+    payload: &'a [u8],
+    pos: usize,
+    /// a length-prefixed field runs past the payload
+    truncated: bool,
+    /// a length-prefixed field doesn't have the size of its destination
+    mismatched: bool,
+}
+
+impl<'a> ChunkReader<'a> {
+    pub fn new(payload: &'a [u8]) -> Self {
+        Self {
+            payload,
+            pos: 0,
+            truncated: false,
+            mismatched: false,
+        }
+    }
+
+    /// Whether every length-prefixed field read so far fit, subsystems load without failing so
+    /// this is checked once the chunk is read
+    pub fn check(&self, tag: [u8; 4]) -> Result<(), SaveStateError> {
+        match (self.truncated, self.mismatched) {
+            (true, _) => Err(SaveStateError::Truncated),
+            (_, true) => Err(SaveStateError::Corrupt(tag)),
+            _ => Ok(()),
+        }
+    }
+
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.payload.get(self.pos..self.pos + N)?;
+        self.pos += N;
+        bytes.try_into().ok()
+    }
+
+    pub fn read_u8(&mut self) -> u8 {
+        self.take::<1>().map_or(0, |b| b[0])
+    }
+
+    pub fn read_bool(&mut self) -> bool {
+        self.read_u8() != 0
+    }
+
+    pub fn read_u16(&mut self) -> u16 {
+        self.take().map_or(0, u16::from_le_bytes)
+    }
+
+    pub fn read_u32(&mut self) -> u32 {
+        self.take().map_or(0, u32::from_le_bytes)
+    }
+
+    pub fn read_u64(&mut self) -> u64 {
+        self.take().map_or(0, u64::from_le_bytes)
+    }
+
+    pub fn read_f32(&mut self) -> f32 {
+        f32::from_bits(self.read_u32())
+    }
+
+    pub fn read_bytes(&mut self) -> Vec<u8> {
+        let len = self.read_u32() as usize;
+        let end = self.pos.checked_add(len);
+        match end.and_then(|end| self.payload.get(self.pos..end)) {
+            Some(bytes) => {
+                self.pos += len;
+                bytes.to_vec()
+            }
+            None => {
+                self.truncated = true;
+                vec![]
+            }
+        }
+    }
+
+    /// Reads length-prefixed bytes into `dst`, leaving it untouched when the field is missing or
+    /// doesn't have its size
+    pub fn read_into(&mut self, dst: &mut [u8]) {
+        // not written by older chunk versions
+        if self.pos >= self.payload.len() {
+            return;
+        }
+        let bytes = self.read_bytes();
+        match bytes.len() == dst.len() {
+            true => dst.copy_from_slice(&bytes),
+            false => self.mismatched = true,
+        }
+    }
+}
+
+/// Builds a savestate out of subsystem chunks
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        Self { data }
+    }
+
+    pub fn write_chunk(&mut self, tag: [u8; 4], version: u16, chunk: ChunkWriter) {
+        self.data.extend_from_slice(&tag);
+        self.data.extend_from_slice(&version.to_le_bytes());
+        self.data
+            .extend_from_slice(&(chunk.payload.len() as u32).to_le_bytes());
+        self.data.extend_from_slice(&chunk.payload);
+    }
+
+    pub fn write<S: Snapshot>(&mut self, subsystem: &S) {
+        let mut chunk = ChunkWriter::default();
+        subsystem.save_state(&mut chunk);
+        self.write_chunk(S::TAG, S::VERSION, chunk);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// `len` bytes at `pos`, without overflowing on 32-bit targets
+fn slice(data: &[u8], pos: usize, len: usize) -> Result<&[u8], SaveStateError> {
+    pos.checked_add(len)
+        .filter(|&end| end <= data.len())
+        .map(|end| &data[pos..end])
+        .ok_or(SaveStateError::Truncated)
+}
+
+/// Parsed savestate, chunks are looked up by tag and unknown ones are ignored
+pub struct StateReader<'a> {
+    chunks: HashMap<[u8; 4], (u16, &'a [u8])>,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, SaveStateError> {
+        if data.len() < 6 || data[0..4] != MAGIC {
+            return Err(SaveStateError::InvalidMagic);
+        }
+        let format_version = u16::from_le_bytes([data[4], data[5]]);
+        if format_version > FORMAT_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(format_version));
+        }
+
+        let mut chunks = HashMap::new();
+        let mut pos = 6;
+        while pos < data.len() {
+            let header = slice(data, pos, 10)?;
+            let tag = [header[0], header[1], header[2], header[3]];
+            let version = u16::from_le_bytes([header[4], header[5]]);
+            let len = u32::from_le_bytes([header[6], header[7], header[8], header[9]]) as usize;
+            pos += 10;
+
+            chunks.insert(tag, (version, slice(data, pos, len)?));
+            pos += len;
+        }
+
+        Ok(Self { chunks })
+    }
+
+    /// Returns the chunk version and a reader over its payload
+    pub fn chunk(&self, tag: [u8; 4]) -> Option<(u16, ChunkReader<'a>)> {
+        self.chunks
+            .get(&tag)
+            .map(|&(version, payload)| (version, ChunkReader::new(payload)))
+    }
+
+    /// Checks every chunk in `tags` is there, so a load can fail before touching anything
+    pub fn require(&self, tags: &[[u8; 4]]) -> Result<(), SaveStateError> {
+        match tags.iter().find(|tag| !self.chunks.contains_key(*tag)) {
+            Some(&tag) => Err(SaveStateError::MissingChunk(tag)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn read<S: Snapshot>(&self, subsystem: &mut S) -> Result<(), SaveStateError> {
+        let (version, mut reader) = self
+            .chunk(S::TAG)
+            .ok_or(SaveStateError::MissingChunk(S::TAG))?;
+        subsystem.load_state(&mut reader, version);
+        reader.check(S::TAG)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq)]
+    struct V1 {
+        a: u8,
+        b: u16,
+    }
+
+    impl Snapshot for V1 {
+        const TAG: [u8; 4] = *b"TEST";
+        const VERSION: u16 = 1;
+
+        fn save_state(&self, w: &mut ChunkWriter) {
+            w.write_u8(self.a);
+            w.write_u16(self.b);
+        }
+
+        fn load_state(&mut self, r: &mut ChunkReader, _version: u16) {
+            self.a = r.read_u8();
+            self.b = r.read_u16();
+        }
+    }
+
+    /// Same subsystem after appending a field
+    #[derive(Debug, Default, PartialEq)]
+    struct V2 {
+        a: u8,
+        b: u16,
+        c: Vec<u8>,
+    }
+
+    impl Snapshot for V2 {
+        const TAG: [u8; 4] = *b"TEST";
+        const VERSION: u16 = 2;
+
+        fn save_state(&self, w: &mut ChunkWriter) {
+            w.write_u8(self.a);
+            w.write_u16(self.b);
+            w.write_bytes(&self.c);
+        }
+
+        fn load_state(&mut self, r: &mut ChunkReader, _version: u16) {
+            self.a = r.read_u8();
+            self.b = r.read_u16();
+            self.c = r.read_bytes();
+        }
+    }
+
+    fn save<S: Snapshot>(s: &S) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.write_chunk(*b"UNKN", 7, ChunkWriter::default());
+        w.write(s);
+        w.finish()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let state = save(&V2 {
+            a: 1,
+            b: 0x1234,
+            c: vec![1, 2, 3],
+        });
+
+        let mut loaded = V2::default();
+        StateReader::new(&state).unwrap().read(&mut loaded).unwrap();
+        assert_eq!(
+            V2 {
+                a: 1,
+                b: 0x1234,
+                c: vec![1, 2, 3]
+            },
+            loaded
+        );
+    }
+
+    #[test]
+    fn test_older_chunk_loads_with_defaults() {
+        let state = save(&V1 { a: 1, b: 2 });
+
+        let mut loaded = V2 {
+            a: 0,
+            b: 0,
+            c: vec![9],
+        };
+        let reader = StateReader::new(&state).unwrap();
+        assert_eq!(1, reader.chunk(*b"TEST").unwrap().0);
+        reader.read(&mut loaded).unwrap();
+        assert_eq!(
+            V2 {
+                a: 1,
+                b: 2,
+                c: vec![]
+            },
+            loaded
+        );
+    }
+
+    #[test]
+    fn test_newer_chunk_skips_trailing_fields() {
+        let state = save(&V2 {
+            a: 1,
+            b: 2,
+            c: vec![3],
+        });
+
+        let mut loaded = V1::default();
+        StateReader::new(&state).unwrap().read(&mut loaded).unwrap();
+        assert_eq!(V1 { a: 1, b: 2 }, loaded);
+    }
+
+    #[test]
+    fn test_invalid_states() {
+        assert_eq!(
+            Some(SaveStateError::InvalidMagic),
+            StateReader::new(b"nope").err()
+        );
+
+        let mut state = save(&V1::default());
+        state.pop();
+        assert_eq!(
+            Some(SaveStateError::Truncated),
+            StateReader::new(&state).err()
+        );
+
+        let state = StateWriter::new().finish();
+        assert_eq!(
+            Err(SaveStateError::MissingChunk(*b"TEST")),
+            StateReader::new(&state).unwrap().read(&mut V1::default())
+        );
+
+        let state = save(&V1::default());
+        let reader = StateReader::new(&state).unwrap();
+        assert_eq!(Ok(()), reader.require(&[*b"TEST", *b"UNKN"]));
+        assert_eq!(
+            Err(SaveStateError::MissingChunk(*b"NOPE")),
+            reader.require(&[*b"TEST", *b"NOPE"])
+        );
+    }
+
+    #[test]
+    fn test_length_prefixed_fields() {
+        let mut w = ChunkWriter::default();
+        w.write_bytes(&[1, 2, 3]);
+        w.write_bytes(&[4, 5]);
+        let payload = w.payload;
+
+        let mut r = ChunkReader::new(&payload);
+        let mut dst = [0; 3];
+        r.read_into(&mut dst);
+        assert_eq!([1, 2, 3], dst);
+        r.read_into(&mut dst);
+        assert_eq!([1, 2, 3], dst, "sizes differ, left untouched");
+        assert_eq!(Err(SaveStateError::Corrupt(*b"TEST")), r.check(*b"TEST"));
+
+        // missing from an older version
+        let mut r = ChunkReader::new(&[]);
+        r.read_into(&mut dst);
+        assert_eq!(Ok(()), r.check(*b"TEST"));
+
+        let mut r = ChunkReader::new(&[0xFF, 0xFF, 0xFF, 0xFF, 1]);
+        assert_eq!(Vec::<u8>::new(), r.read_bytes());
+        assert_eq!(Err(SaveStateError::Truncated), r.check(*b"TEST"));
+    }
+
+    #[test]
+    fn test_chunk_length_past_the_end() {
+        let mut state = StateWriter::new().finish();
+        state.extend_from_slice(b"TEST");
+        state.extend_from_slice(&1u16.to_le_bytes());
+        state.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            Some(SaveStateError::Truncated),
+            StateReader::new(&state).err()
+        );
+    }
+}
@@ -0,0 +1,743 @@
+//! Scripting hooks: per-frame and per-memory-write callbacks with access to the registers and the
+//! memory map, for trainers, auto-splitters and bots that don't need a recompiled crate. See
+//! `GameBoy::add_script`.
+//!
+//! Scripts implement `Script`. With the `rhai` feature, `RhaiScript` runs [Rhai](https://rhai.rs)
+//! scripts defining `on_frame(cpu)` and `on_write(cpu, address, value)`:
+//!
+//! ```text
+//! watch(0xD000, 0xD0FF);
+//!
+//! fn on_frame(cpu) {
+//!     write(0xC0A0, 9);
+//!     if read(0xC100) == 0 { write(0xC100, 3); }
+//! }
+//!
+//! fn on_write(cpu, address, value) {
+//!     if value > 3 { write(address, 3); }
+//! }
+//! ```
+//!
+//! The lighter `ExprScript` is always available, rules made of `expr` expressions, one per line,
+//! `#` starting comments:
+//!
+//! ```text
+//! # infinite lives
+//! frame: [0xC0A0] = 9
+//! # start on level 3, once
+//! frame if [0xC100] == 0: [0xC100] = 3; [0xC101] = 1
+//! # cap the damage taken
+//! write 0xD000..0xD0FF if VALUE > 3: [ADDR] = 3
+//! ```
+//!
+//! Write callbacks run after the instruction doing the write completes and writes made by scripts
+//! don't trigger them.
+
+use std::{cell::OnceCell, error, fmt, fs, io, ops::RangeInclusive, path::Path, rc::Rc};
+
+use crate::{
+    expr::{self, Context, Expr, ExprError},
+    registers::Registers,
+};
+
+/// State handed to the callbacks. Writes are applied to the memory map, as the cpu would do
+/// them, when the callback returns.
+pub struct ScriptApi<'a> {
+    /// Frames completed since power on
+    pub frame: u64,
+    pub registers: &'a Registers,
+    pub sp: u16,
+    pub pc: u16,
+    read: &'a dyn Fn(u16) -> u8,
+    writes: Vec<(u16, u8)>,
+    /// whole memory map, read once for the scripts that can't borrow `read`
+    snapshot: OnceCell<Rc<[u8]>>,
+}
+
+impl<'a> ScriptApi<'a> {
+    pub fn new(
+        frame: u64,
+        registers: &'a Registers,
+        sp: u16,
+        pc: u16,
+        read: &'a dyn Fn(u16) -> u8,
+    ) -> Self {
+        Self {
+            frame,
+            registers,
+            sp,
+            pc,
+            read,
+            writes: vec![],
+            snapshot: OnceCell::new(),
+        }
+    }
+
+    /// Reads the memory map without side effect, writes of this callback aren't visible yet
+    pub fn read(&self, address: u16) -> u8 {
+        (self.read)(address)
+    }
+
+    /// Copy of the memory map as `read` sees it, taken on the first call
+    pub fn snapshot(&self) -> Rc<[u8]> {
+        self.snapshot
+            .get_or_init(|| (0..=u16::MAX).map(|address| (self.read)(address)).collect())
+            .clone()
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        self.writes.push((address, value));
+    }
+
+    /// Writes of the callbacks, in order
+    pub fn into_writes(self) -> Vec<(u16, u8)> {
+        self.writes
+    }
+
+    /// Expression context of the current state, with the write being reported if any
+    pub fn context(&self, access: Option<(u16, u8)>) -> Context<'_> {
+        Context {
+            registers: self.registers,
+            sp: self.sp,
+            pc: self.pc,
+            read: self.read,
+            access,
+        }
+    }
+}
+
+pub trait Script {
+    /// Called after each completed frame
+    fn on_frame(&mut self, _api: &mut ScriptApi) {}
+
+    /// Addresses whose writes are reported to `on_write`, queried when the script is added
+    fn watched_writes(&self) -> Vec<RangeInclusive<u16>> {
+        vec![]
+    }
+
+    /// Called for each write to a watched address, with the value written
+    fn on_write(&mut self, _address: u16, _value: u8, _api: &mut ScriptApi) {}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ScriptId(u32);
+
+/// Scripts of a console, in the order they were added
+#[derive(Default)]
+pub struct Scripts {
+    scripts: Vec<(ScriptId, Box<dyn Script>)>,
+    next_id: u32,
+}
+
+impl Scripts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, script: Box<dyn Script>) -> ScriptId {
+        let id = ScriptId(self.next_id);
+        self.next_id += 1;
+        self.scripts.push((id, script));
+        id
+    }
+
+    pub fn remove(&mut self, id: ScriptId) -> Option<Box<dyn Script>> {
+        let i = self.scripts.iter().position(|(s, _)| *s == id)?;
+        Some(self.scripts.remove(i).1)
+    }
+
+    pub fn clear(&mut self) {
+        self.scripts.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.scripts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
+    /// Watched ranges of every script
+    pub fn watched_writes(&self) -> Vec<RangeInclusive<u16>> {
+        self.scripts
+            .iter()
+            .flat_map(|(_, script)| script.watched_writes())
+            .collect()
+    }
+
+    pub fn on_frame(&mut self, api: &mut ScriptApi) {
+        for (_, script) in &mut self.scripts {
+            script.on_frame(api);
+        }
+    }
+
+    /// Reports each write to the scripts watching its address
+    pub fn on_writes(&mut self, writes: &[(u16, u8)], api: &mut ScriptApi) {
+        for (_, script) in &mut self.scripts {
+            let watched = script.watched_writes();
+            for &(address, value) in writes {
+                if watched.iter().any(|range| range.contains(&address)) {
+                    script.on_write(address, value, api);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Io(io::Error),
+    /// 1-based line number and its content
+    InvalidLine(usize, String),
+    /// 1-based line number and the expression error
+    Expr(usize, ExprError),
+    /// Compilation or top level evaluation error of a Rhai script
+    #[cfg(feature = "rhai")]
+    Rhai(Box<rhai::EvalAltResult>),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to read script: {e}"),
+            Self::InvalidLine(number, line) => write!(f, "invalid rule on line {number}: {line}"),
+            Self::Expr(number, e) => write!(f, "invalid expression on line {number}: {e}"),
+            #[cfg(feature = "rhai")]
+            Self::Rhai(e) => write!(f, "rhai script error: {e}"),
+        }
+    }
+}
+
+impl error::Error for ScriptError {}
+
+impl From<io::Error> for ScriptError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(feature = "rhai")]
+impl From<Box<rhai::EvalAltResult>> for ScriptError {
+    fn from(e: Box<rhai::EvalAltResult>) -> Self {
+        Self::Rhai(e)
+    }
+}
+
+#[cfg(feature = "rhai")]
+impl From<rhai::ParseError> for ScriptError {
+    fn from(e: rhai::ParseError) -> Self {
+        Self::Rhai(e.into())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Trigger {
+    Frame,
+    Write(RangeInclusive<u16>),
+}
+
+/// `[address] = value`
+#[derive(Clone, Debug, PartialEq)]
+struct Assignment {
+    address: Expr,
+    value: Expr,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Rule {
+    trigger: Trigger,
+    condition: Option<Expr>,
+    assignments: Vec<Assignment>,
+}
+
+impl Rule {
+    fn run(&self, api: &mut ScriptApi, access: Option<(u16, u8)>) {
+        let writes: Vec<_> = {
+            let ctx = api.context(access);
+            if self.condition.as_ref().is_some_and(|c| !c.is_true(&ctx)) {
+                return;
+            }
+            self.assignments
+                .iter()
+                .map(|a| (a.address.eval(&ctx) as u16, a.value.eval(&ctx) as u8))
+                .collect()
+        };
+        for (address, value) in writes {
+            api.write(address, value);
+        }
+    }
+}
+
+/// Rules written with `expr` expressions, see the module documentation
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExprScript {
+    rules: Vec<Rule>,
+}
+
+impl ExprScript {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ScriptError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ScriptError> {
+        let mut rules = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let content = line.split('#').next().unwrap_or_default().trim();
+            if content.is_empty() {
+                continue;
+            }
+            rules.push(parse_rule(content).map_err(|e| match e {
+                Some(e) => ScriptError::Expr(i + 1, e),
+                None => ScriptError::InvalidLine(i + 1, line.to_string()),
+            })?);
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+impl Script for ExprScript {
+    fn on_frame(&mut self, api: &mut ScriptApi) {
+        for rule in self.rules.iter().filter(|r| r.trigger == Trigger::Frame) {
+            rule.run(api, None);
+        }
+    }
+
+    fn watched_writes(&self) -> Vec<RangeInclusive<u16>> {
+        self.rules
+            .iter()
+            .filter_map(|rule| match &rule.trigger {
+                Trigger::Write(range) => Some(range.clone()),
+                Trigger::Frame => None,
+            })
+            .collect()
+    }
+
+    fn on_write(&mut self, address: u16, value: u8, api: &mut ScriptApi) {
+        for rule in &self.rules {
+            if matches!(&rule.trigger, Trigger::Write(range) if range.contains(&address)) {
+                rule.run(api, Some((address, value)));
+            }
+        }
+    }
+}
+
+/// Operations a Rhai callback or the top level may run, a script looping forever fails
+/// instead of hanging the emulation
+#[cfg(feature = "rhai")]
+pub const MAX_RHAI_OPERATIONS: u64 = 1_000_000;
+
+/// Memory access of the callback being run, shared with the functions registered in the engine
+#[cfg(feature = "rhai")]
+#[derive(Default)]
+struct RhaiAccess {
+    /// `ScriptApi::snapshot` of the callback being run
+    memory: Option<Rc<[u8]>>,
+    writes: Vec<(u16, u8)>,
+}
+
+/// Rhai script, see the module documentation.
+///
+/// Scripts get `read(address)`, `write(address, value)` and, at their top level, `watch(start,
+/// end)` to report the writes of an address range to `on_write`. `cpu` is a map of the frame
+/// count, the 8-bit registers, `sp` and `pc`. Functions can't see the script variables, `this` is
+/// a map kept between calls for the state they need. Runtime errors are logged as warnings and
+/// kept in `error`, a callback running more than `MAX_RHAI_OPERATIONS` stops the script.
+#[cfg(feature = "rhai")]
+pub struct RhaiScript {
+    engine: rhai::Engine,
+    ast: rhai::AST,
+    access: Rc<std::cell::RefCell<RhaiAccess>>,
+    watched: Vec<RangeInclusive<u16>>,
+    state: rhai::Dynamic,
+    error: Option<ScriptError>,
+    stopped: bool,
+}
+
+#[cfg(feature = "rhai")]
+impl RhaiScript {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ScriptError> {
+        Self::new(&fs::read_to_string(path)?)
+    }
+
+    /// Compiles `source` and runs its top level
+    pub fn new(source: &str) -> Result<Self, ScriptError> {
+        use std::cell::RefCell;
+
+        let access = Rc::new(RefCell::new(RhaiAccess::default()));
+        let watched = Rc::new(RefCell::new(vec![]));
+        let mut engine = rhai::Engine::new();
+        engine.set_max_operations(MAX_RHAI_OPERATIONS);
+
+        let reader = access.clone();
+        engine.register_fn("read", move |address: i64| -> i64 {
+            reader
+                .borrow()
+                .memory
+                .as_ref()
+                .map_or(0xFF, |memory| memory[address as u16 as usize]) as i64
+        });
+        let writer = access.clone();
+        engine.register_fn("write", move |address: i64, value: i64| {
+            writer
+                .borrow_mut()
+                .writes
+                .push((address as u16, value as u8));
+        });
+        let watcher = watched.clone();
+        engine.register_fn("watch", move |start: i64, end: i64| {
+            watcher.borrow_mut().push(start as u16..=end as u16);
+        });
+
+        let ast = engine.compile(source)?;
+        engine.run_ast(&ast)?;
+        let watched = watched.take();
+
+        Ok(Self {
+            engine,
+            ast,
+            access,
+            watched,
+            state: rhai::Map::new().into(),
+            error: None,
+            stopped: false,
+        })
+    }
+
+    /// Last runtime error of the callbacks
+    pub fn error(&self) -> Option<&ScriptError> {
+        self.error.as_ref()
+    }
+
+    /// Whether the callbacks stopped being called after running too many operations
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    fn defines(&self, name: &str, params: usize) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == name && f.params.len() == params)
+    }
+
+    fn call(&mut self, api: &mut ScriptApi, name: &str, args: impl rhai::FuncArgs) {
+        if self.stopped {
+            return;
+        }
+        self.access.borrow_mut().memory = Some(api.snapshot());
+
+        let options = rhai::CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.state);
+        let result = self.engine.call_fn_with_options::<rhai::Dynamic>(
+            options,
+            &mut rhai::Scope::new(),
+            &self.ast,
+            name,
+            args,
+        );
+
+        let writes = {
+            let mut access = self.access.borrow_mut();
+            access.memory = None;
+            std::mem::take(&mut access.writes)
+        };
+        for (address, value) in writes {
+            api.write(address, value);
+        }
+        if let Err(e) = result {
+            self.stopped = matches!(*e, rhai::EvalAltResult::ErrorTooManyOperations(_));
+            warn!("Rhai script {name} failed: {e}");
+            self.error = Some(e.into());
+        }
+    }
+}
+
+#[cfg(feature = "rhai")]
+impl Script for RhaiScript {
+    fn on_frame(&mut self, api: &mut ScriptApi) {
+        if self.defines("on_frame", 1) {
+            let cpu = rhai_cpu(api);
+            self.call(api, "on_frame", (cpu,));
+        }
+    }
+
+    fn watched_writes(&self) -> Vec<RangeInclusive<u16>> {
+        self.watched.clone()
+    }
+
+    fn on_write(&mut self, address: u16, value: u8, api: &mut ScriptApi) {
+        if self.defines("on_write", 3) {
+            let cpu = rhai_cpu(api);
+            self.call(api, "on_write", (cpu, address as i64, value as i64));
+        }
+    }
+}
+
+/// `cpu` argument of the Rhai callbacks
+#[cfg(feature = "rhai")]
+fn rhai_cpu(api: &ScriptApi) -> rhai::Map {
+    let r = api.registers;
+    [
+        ("frame", api.frame as i64),
+        ("a", r.a() as i64),
+        ("f", r.f() as i64),
+        ("b", r.b() as i64),
+        ("c", r.c() as i64),
+        ("d", r.d() as i64),
+        ("e", r.e() as i64),
+        ("h", r.h() as i64),
+        ("l", r.l() as i64),
+        ("sp", api.sp as i64),
+        ("pc", api.pc as i64),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.into(), value.into()))
+    .collect()
+}
+
+/// `trigger [if condition]: assignments`, None for a malformed line
+fn parse_rule(content: &str) -> Result<Rule, Option<ExprError>> {
+    let (head, body) = content.split_once(':').ok_or(None)?;
+    let (trigger, condition) = match head.split_once(" if ") {
+        Some((trigger, condition)) => (trigger, Some(Expr::parse(condition)?)),
+        None => (head, None),
+    };
+
+    let mut words = trigger.split_whitespace();
+    let trigger = match (words.next(), words.next(), words.next()) {
+        (Some("frame"), None, _) => Trigger::Frame,
+        (Some("write"), Some(range), None) => {
+            let address = |word: &str| {
+                expr::parse_number(word)
+                    .and_then(|n| u16::try_from(n).ok())
+                    .ok_or(None)
+            };
+            match range.split_once("..") {
+                Some((start, end)) => Trigger::Write(address(start)?..=address(end)?),
+                None => Trigger::Write(address(range)?..=address(range)?),
+            }
+        }
+        _ => return Err(None),
+    };
+
+    let assignments = body
+        .split(';')
+        .map(parse_assignment)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Rule {
+        trigger,
+        condition,
+        assignments,
+    })
+}
+
+/// `[address] = value`
+fn parse_assignment(source: &str) -> Result<Assignment, Option<ExprError>> {
+    let source = source.trim().strip_prefix('[').ok_or(None)?;
+    let mut depth = 0;
+    let end = source
+        .char_indices()
+        .find(|&(_, c)| {
+            match c {
+                '[' => depth += 1,
+                ']' if depth == 0 => return true,
+                ']' => depth -= 1,
+                _ => {}
+            }
+            false
+        })
+        .ok_or(None)?
+        .0;
+    let value = source[end + 1..]
+        .trim_start()
+        .strip_prefix('=')
+        .ok_or(None)?;
+    if value.starts_with('=') {
+        return Err(None);
+    }
+    Ok(Assignment {
+        address: Expr::parse(&source[..end])?,
+        value: Expr::parse(value)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    const SCRIPT: &str = "# trainer
+frame: [0xC0A0] = 9
+frame if [0xC100] == 0: [0xC100] = 3; [$C101] = A + 1
+
+write 0xD000..0xD0FF if VALUE > 3: [ADDR] = 3 # cap
+write 0xD100: [0xD101] = [0xD101] + VALUE
+";
+
+    /// Runs `f` against a memory of `memory` writes applied at the end
+    fn with_api(memory: &RefCell<Vec<u8>>, f: impl FnOnce(&mut ScriptApi)) {
+        let mut registers = Registers::new();
+        registers.a = 0x41;
+        let writes = {
+            let read = |address: u16| memory.borrow()[address as usize];
+            let mut api = ScriptApi::new(7, &registers, 0xFFFE, 0x0150, &read);
+            f(&mut api);
+            api.into_writes()
+        };
+        for (address, value) in writes {
+            memory.borrow_mut()[address as usize] = value;
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let script = ExprScript::parse(SCRIPT).unwrap();
+        assert_eq!(4, script.len());
+        assert_eq!(
+            vec![0xD000..=0xD0FF, 0xD100..=0xD100],
+            script.watched_writes()
+        );
+
+        for (line, invalid) in [
+            ("frame [0xC000] = 1", true),
+            ("frame: 0xC000 = 1", true),
+            ("frame: [0xC000] == 1", true),
+            ("write: [0xC000] = 1", true),
+            ("write 0x1FFFF: [0xC000] = 1", true),
+            ("vblank: [0xC000] = 1", true),
+            ("frame if A ==: [0xC000] = 1", false),
+            ("frame: [0xC000] = Q", false),
+        ] {
+            let result = ExprScript::parse(&format!("# ok\n{line}"));
+            match invalid {
+                true => assert!(
+                    matches!(result, Err(ScriptError::InvalidLine(2, _))),
+                    "{line}: {result:?}"
+                ),
+                false => assert!(
+                    matches!(result, Err(ScriptError::Expr(2, _))),
+                    "{line}: {result:?}"
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_frame_rules() {
+        let mut script = ExprScript::parse(SCRIPT).unwrap();
+        let memory = RefCell::new(vec![0; 0x10000]);
+        with_api(&memory, |api| script.on_frame(api));
+        assert_eq!(9, memory.borrow()[0xC0A0]);
+        assert_eq!(3, memory.borrow()[0xC100]);
+        assert_eq!(0x42, memory.borrow()[0xC101]);
+
+        // the condition no longer holds
+        memory.borrow_mut()[0xC101] = 0;
+        with_api(&memory, |api| script.on_frame(api));
+        assert_eq!(0, memory.borrow()[0xC101]);
+    }
+
+    #[test]
+    fn test_write_rules() {
+        let mut scripts = Scripts::new();
+        let id = scripts.add(Box::new(ExprScript::parse(SCRIPT).unwrap()));
+        assert_eq!(2, scripts.watched_writes().len());
+        let memory = RefCell::new(vec![0; 0x10000]);
+        memory.borrow_mut()[0xD101] = 10;
+
+        let writes = [(0xD010, 5), (0xD011, 2), (0xD100, 4), (0xC000, 99)];
+        with_api(&memory, |api| scripts.on_writes(&writes, api));
+        assert_eq!(3, memory.borrow()[0xD010]);
+        assert_eq!(0, memory.borrow()[0xD011]);
+        assert_eq!(14, memory.borrow()[0xD101]);
+        assert_eq!(0, memory.borrow()[0xC000]);
+
+        assert!(scripts.remove(id).is_some());
+        assert!(scripts.is_empty());
+        assert!(scripts.watched_writes().is_empty());
+    }
+
+    #[cfg(feature = "rhai")]
+    #[test]
+    fn test_rhai_script() {
+        let source = "
+watch(0xD000, 0xD0FF);
+
+fn on_frame(cpu) {
+    this.frames = (this.frames ?? 0) + 1;
+    write(0xC0A0, this.frames);
+    if read(0xC100) == 0 { write(0xC100, cpu.a + 1); }
+}
+
+fn on_write(cpu, address, value) {
+    if value > 3 { write(address, 3); }
+}
+";
+        let mut scripts = Scripts::new();
+        scripts.add(Box::new(RhaiScript::new(source).unwrap()));
+        assert_eq!(vec![0xD000..=0xD0FF], scripts.watched_writes());
+
+        let memory = RefCell::new(vec![0; 0x10000]);
+        with_api(&memory, |api| scripts.on_frame(api));
+        assert_eq!(1, memory.borrow()[0xC0A0]);
+        assert_eq!(0x42, memory.borrow()[0xC100]);
+
+        // state kept in `this` between calls
+        with_api(&memory, |api| scripts.on_frame(api));
+        assert_eq!(2, memory.borrow()[0xC0A0]);
+
+        let writes = [(0xD010, 5), (0xD011, 2)];
+        with_api(&memory, |api| scripts.on_writes(&writes, api));
+        assert_eq!(3, memory.borrow()[0xD010]);
+        assert_eq!(0, memory.borrow()[0xD011]);
+
+        assert!(matches!(
+            RhaiScript::new("fn on_frame(cpu) {"),
+            Err(ScriptError::Rhai(_))
+        ));
+    }
+
+    #[cfg(feature = "rhai")]
+    #[test]
+    fn test_rhai_script_operation_limit() {
+        assert!(matches!(
+            RhaiScript::new("loop {}"),
+            Err(ScriptError::Rhai(_))
+        ));
+
+        let mut script = RhaiScript::new(
+            "
+fn on_frame(cpu) {
+    write(0xC000, read(0xC000) + 1);
+    if cpu.frame == 1 { loop {} }
+}
+",
+        )
+        .unwrap();
+        let memory = RefCell::new(vec![0; 0x10000]);
+        with_api(&memory, |api| script.on_frame(api));
+        assert_eq!(1, memory.borrow()[0xC000]);
+        assert!(script.error().is_none());
+
+        // writes made before the failure are applied, then the script stops
+        with_api(&memory, |api| {
+            api.frame = 1;
+            script.on_frame(api)
+        });
+        assert_eq!(2, memory.borrow()[0xC000]);
+        assert!(script.is_stopped());
+        assert!(matches!(script.error(), Some(ScriptError::Rhai(_))));
+        with_api(&memory, |api| script.on_frame(api));
+        assert_eq!(2, memory.borrow()[0xC000]);
+    }
+}
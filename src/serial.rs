@@ -1,26 +1,361 @@
-use crate::memory::MemReadWriter;
+use std::collections::VecDeque;
 
-/// Not implemented
-#[derive(Clone)]
-pub struct Serial {}
+use crate::{
+    clock::{ClockDomain, Clocked},
+    interrupts::InterruptRegisters,
+    link::LinkDevice,
+    memory::MemReadWriter,
+    mode::Mode,
+    savestate::{ChunkReader, ChunkWriter, Snapshot},
+};
+
+const TRANSFER_BIT: u8 = 1 << 7;
+const FAST_CLOCK_BIT: u8 = 1 << 1;
+const INTERNAL_CLOCK_BIT: u8 = 1;
+
+/// Cycles to shift a byte out with the internal clock, at 8192Hz or 262144Hz with the CGB fast clock.
+/// Counted in cpu cycles so the transfer rate doubles in double speed mode.
+const BYTE_CYCLES: u32 = 8 * 512;
+const FAST_BYTE_CYCLES: u32 = 8 * 16;
+
+/// Byte exchanged by a completed transfer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SerialTransfer {
+    pub sent: u8,
+    pub received: u8,
+    /// Whether the Game Boy clocked the transfer
+    pub internal_clock: bool,
+}
+
+/// Called on every completed transfer, see `Serial::set_transfer_callback`
+pub type TransferCallback = Box<dyn FnMut(SerialTransfer)>;
+
+/// https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html
+pub struct Serial {
+    mode: Mode,
+    sb: u8,
+    transfer: bool,
+    fast_clock: bool,
+    internal_clock: bool,
+    cycles: u32,
+    device: Option<Box<dyn LinkDevice>>,
+    /// bytes clocked in by an external program, see `inject_byte`
+    injected: VecDeque<u8>,
+    transfer_callback: Option<TransferCallback>,
+}
 
 impl Serial {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(mode: Mode) -> Self {
+        Self {
+            mode,
+            sb: 0,
+            transfer: false,
+            fast_clock: false,
+            internal_clock: false,
+            cycles: 0,
+            device: None,
+            injected: VecDeque::new(),
+            transfer_callback: None,
+        }
+    }
+
+    /// Queues a byte sent by a remote device clocking the transfer, e.g. a program bridging a
+    /// real link cable. It completes the next transfer the game starts with the external clock,
+    /// before any plugged device is asked.
+    pub fn inject_byte(&mut self, value: u8) {
+        self.injected.push_back(value);
+    }
+
+    /// Injected bytes the game hasn't received yet
+    pub fn pending_injected(&self) -> usize {
+        self.injected.len()
+    }
+
+    pub fn clear_injected(&mut self) {
+        self.injected.clear();
+    }
+
+    /// Tells the remote side what the game sent, returning the previous callback
+    pub fn set_transfer_callback(
+        &mut self,
+        callback: Option<TransferCallback>,
+    ) -> Option<TransferCallback> {
+        std::mem::replace(&mut self.transfer_callback, callback)
+    }
+
+    /// Plugs `device` into the link port, returning the previous one
+    pub fn set_device(
+        &mut self,
+        device: Option<Box<dyn LinkDevice>>,
+    ) -> Option<Box<dyn LinkDevice>> {
+        std::mem::replace(&mut self.device, device)
+    }
+
+    pub fn step(&mut self, int_reg: &mut InterruptRegisters, cycles: u8) {
+        if !self.transfer {
+            return;
+        }
+
+        let received = match self.internal_clock {
+            true => {
+                let byte_cycles = match self.fast_clock {
+                    true => FAST_BYTE_CYCLES,
+                    false => BYTE_CYCLES,
+                };
+                self.cycles = (self.cycles + cycles as u32).min(byte_cycles);
+                if self.cycles < byte_cycles {
+                    return;
+                }
+                // the byte is out, polled until the device answers
+                match &mut self.device {
+                    Some(device) => device.poll_transfer(self.sb),
+                    None => Some(0xFF),
+                }
+            }
+            false => match (self.injected.pop_front(), &mut self.device) {
+                (Some(injected), _) => Some(injected),
+                (None, Some(device)) => device.clock(cycles, self.sb),
+                (None, None) => None,
+            },
+        };
+
+        if let Some(received) = received {
+            let transfer = SerialTransfer {
+                sent: self.sb,
+                received,
+                internal_clock: self.internal_clock,
+            };
+            self.sb = received;
+            self.transfer = false;
+            int_reg.request_serial();
+            if let Some(callback) = &mut self.transfer_callback {
+                callback(transfer);
+            }
+        }
     }
 }
 
+/// The internal clock transfer rate doubles in double speed mode
+impl Clocked for Serial {
+    const DOMAIN: ClockDomain = ClockDomain::Cpu;
+}
+
 impl MemReadWriter for Serial {
     fn read_byte(&self, address: u16) -> u8 {
         match address {
-            0xFF01..=0xFF02 => 0xFF,
+            0xFF01 => self.sb,
+            0xFF02 => {
+                let sc = (self.transfer as u8) << 7 | self.internal_clock as u8;
+                match self.mode {
+                    Mode::DMG => sc | 0x7E,
+                    Mode::CGB => sc | (self.fast_clock as u8) << 1 | 0x7C,
+                }
+            }
             _ => unreachable!("Serial reading address {:#04x}", address),
         }
     }
-    fn write_byte(&mut self, address: u16, _value: u8) {
+    fn write_byte(&mut self, address: u16, value: u8) {
         match address {
-            0xFF01..=0xFF02 => {}
+            0xFF01 => self.sb = value,
+            0xFF02 => {
+                self.transfer = value & TRANSFER_BIT != 0;
+                self.fast_clock = matches!(self.mode, Mode::CGB) && value & FAST_CLOCK_BIT != 0;
+                self.internal_clock = value & INTERNAL_CLOCK_BIT != 0;
+                self.cycles = 0;
+            }
             _ => unreachable!("Serial writing address {:#04x}", address),
         }
     }
 }
+
+/// The link device, injected bytes and callback aren't part of the state, the transfer in
+/// progress carries on with the one attached when loading
+impl Snapshot for Serial {
+    const TAG: [u8; 4] = *b"SRAL";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, w: &mut ChunkWriter) {
+        w.write_u8(self.sb);
+        w.write_bool(self.transfer);
+        w.write_bool(self.fast_clock);
+        w.write_bool(self.internal_clock);
+        w.write_u32(self.cycles);
+    }
+
+    fn load_state(&mut self, r: &mut ChunkReader, _version: u16) {
+        self.sb = r.read_u8();
+        self.transfer = r.read_bool();
+        self.fast_clock = r.read_bool();
+        self.internal_clock = r.read_bool();
+        self.cycles = r.read_u32();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo;
+
+    impl LinkDevice for Echo {
+        fn transfer(&mut self, sent: u8) -> u8 {
+            !sent
+        }
+
+        fn clock(&mut self, _cycles: u8, sent: u8) -> Option<u8> {
+            Some(sent.rotate_left(4))
+        }
+    }
+
+    #[test]
+    fn test_internal_clock_transfer() {
+        let mut int_reg = InterruptRegisters::new();
+        let mut serial = Serial::new(Mode::DMG);
+        serial.write_byte(0xFF01, 0x5A);
+        serial.write_byte(0xFF02, 0x81);
+        assert_eq!(0xFF, serial.read_byte(0xFF02));
+
+        for _ in 0..BYTE_CYCLES / 4 - 1 {
+            serial.step(&mut int_reg, 4);
+        }
+        assert_eq!(0x5A, serial.read_byte(0xFF01));
+
+        serial.step(&mut int_reg, 4);
+        // disconnected
+        assert_eq!(0xFF, serial.read_byte(0xFF01));
+        assert_eq!(0x7F, serial.read_byte(0xFF02));
+        assert_eq!(0x08, int_reg.read_byte(0xFF0F) & 0x08);
+
+        serial.set_device(Some(Box::new(Echo)));
+        serial.write_byte(0xFF01, 0x5A);
+        serial.write_byte(0xFF02, 0x81);
+        for _ in 0..BYTE_CYCLES / 4 {
+            serial.step(&mut int_reg, 4);
+        }
+        assert_eq!(0xA5, serial.read_byte(0xFF01));
+    }
+
+    /// Answers the third time it's polled
+    struct Slow(u8);
+
+    impl LinkDevice for Slow {
+        fn poll_transfer(&mut self, sent: u8) -> Option<u8> {
+            self.0 += 1;
+            (self.0 == 3).then_some(!sent)
+        }
+    }
+
+    #[test]
+    fn test_internal_clock_transfer_waits_for_device() {
+        let mut int_reg = InterruptRegisters::new();
+        let mut serial = Serial::new(Mode::DMG);
+        serial.set_device(Some(Box::new(Slow(0))));
+        serial.write_byte(0xFF01, 0x5A);
+        serial.write_byte(0xFF02, 0x81);
+
+        for _ in 0..BYTE_CYCLES / 4 + 1 {
+            serial.step(&mut int_reg, 4);
+        }
+        assert_eq!(0xFF, serial.read_byte(0xFF02));
+        assert_eq!(0, int_reg.read_byte(0xFF0F) & 0x08);
+
+        serial.step(&mut int_reg, 4);
+        assert_eq!(0xA5, serial.read_byte(0xFF01));
+        assert_eq!(0x7F, serial.read_byte(0xFF02));
+        assert_eq!(0x08, int_reg.read_byte(0xFF0F) & 0x08);
+    }
+
+    #[test]
+    fn test_cgb_fast_clock() {
+        let mut int_reg = InterruptRegisters::new();
+        let mut serial = Serial::new(Mode::CGB);
+        serial.set_device(Some(Box::new(Echo)));
+        serial.write_byte(0xFF01, 0x0F);
+        serial.write_byte(0xFF02, 0x83);
+        assert_eq!(0xFF, serial.read_byte(0xFF02));
+
+        for _ in 0..FAST_BYTE_CYCLES / 4 {
+            serial.step(&mut int_reg, 4);
+        }
+        assert_eq!(0xF0, serial.read_byte(0xFF01));
+        assert_eq!(0x7F, serial.read_byte(0xFF02));
+    }
+
+    #[test]
+    fn test_external_clock_transfer() {
+        let mut int_reg = InterruptRegisters::new();
+        let mut serial = Serial::new(Mode::DMG);
+        serial.write_byte(0xFF01, 0x12);
+        serial.write_byte(0xFF02, 0x80);
+
+        // nothing plugged in, the transfer never completes
+        for _ in 0..BYTE_CYCLES {
+            serial.step(&mut int_reg, 4);
+        }
+        assert_eq!(0xFE, serial.read_byte(0xFF02));
+
+        serial.set_device(Some(Box::new(Echo)));
+        serial.step(&mut int_reg, 4);
+        assert_eq!(0x21, serial.read_byte(0xFF01));
+        assert_eq!(0x7E, serial.read_byte(0xFF02));
+    }
+
+    #[test]
+    fn test_injected_bytes() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let mut int_reg = InterruptRegisters::new();
+        let mut serial = Serial::new(Mode::DMG);
+        let transfers = Rc::new(RefCell::new(vec![]));
+        let log = transfers.clone();
+        serial.set_transfer_callback(Some(Box::new(move |t| log.borrow_mut().push(t))));
+        serial.set_device(Some(Box::new(Echo)));
+        serial.inject_byte(0x42);
+        serial.inject_byte(0x43);
+
+        // waits for the game to start an external clock transfer
+        serial.step(&mut int_reg, 4);
+        assert_eq!(2, serial.pending_injected());
+        serial.write_byte(0xFF01, 0x12);
+        serial.write_byte(0xFF02, 0x80);
+        serial.step(&mut int_reg, 4);
+        assert_eq!(0x42, serial.read_byte(0xFF01));
+        assert_eq!(0x08, int_reg.read_byte(0xFF0F) & 0x08);
+
+        // internal clock transfers go to the device
+        serial.write_byte(0xFF01, 0x5A);
+        serial.write_byte(0xFF02, 0x81);
+        for _ in 0..BYTE_CYCLES / 4 {
+            serial.step(&mut int_reg, 4);
+        }
+        assert_eq!(0xA5, serial.read_byte(0xFF01));
+        assert_eq!(1, serial.pending_injected());
+
+        serial.clear_injected();
+        serial.write_byte(0xFF02, 0x80);
+        serial.step(&mut int_reg, 4);
+        // back to the device
+        assert_eq!(0x5A, serial.read_byte(0xFF01));
+
+        assert_eq!(
+            vec![
+                SerialTransfer {
+                    sent: 0x12,
+                    received: 0x42,
+                    internal_clock: false
+                },
+                SerialTransfer {
+                    sent: 0x5A,
+                    received: 0xA5,
+                    internal_clock: true
+                },
+                SerialTransfer {
+                    sent: 0xA5,
+                    received: 0x5A,
+                    internal_clock: false
+                },
+            ],
+            *transfers.borrow()
+        );
+    }
+}
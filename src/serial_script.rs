@@ -0,0 +1,178 @@
+//! Scripted link port peer, to automate test and factory roms driven through the serial port.
+//!
+//! The script sends byte sequences at given frames, clocking them in as the master (external
+//! clock on the Game Boy side) so the rom receives them whenever it waits on a transfer. Bytes
+//! the rom shifts out with its own clock are captured as responses. See
+//! `testing::run_serial_script` to run a rom against a script.
+
+use std::{cell::RefCell, collections::VecDeque, fmt, rc::Rc};
+
+use crate::link::LinkDevice;
+
+/// Cycles between two scripted bytes, the pace of a transfer clocked at 8192Hz
+const BYTE_GAP_CYCLES: u32 = 8 * 512;
+
+/// Byte sequences to send, each at a given frame
+#[derive(Clone, Debug, Default)]
+pub struct SerialScript {
+    sends: Vec<(u64, Vec<u8>)>,
+}
+
+impl SerialScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues `bytes` at the start of `frame`, after the ones already queued
+    pub fn send_at(mut self, frame: u64, bytes: &[u8]) -> Self {
+        self.sends.push((frame, bytes.to_vec()));
+        self
+    }
+}
+
+/// Bytes shifted out by the rom with its internal clock, with the frame they completed on
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SerialTranscript {
+    pub responses: Vec<(u64, u8)>,
+}
+
+impl SerialTranscript {
+    pub fn bytes(&self) -> Vec<u8> {
+        self.responses.iter().map(|&(_, byte)| byte).collect()
+    }
+}
+
+/// Responses as text, non printable bytes escaped
+impl fmt::Display for SerialTranscript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.bytes() {
+            write!(f, "{}", std::ascii::escape_default(byte))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct State {
+    frame: u64,
+    pending: VecDeque<u8>,
+    gap: u32,
+    transcript: SerialTranscript,
+}
+
+/// Runs a `SerialScript`: call `advance_to` at the start of each frame and plug `device` into
+/// the link port
+pub struct SerialDriver {
+    script: SerialScript,
+    next: usize,
+    state: Rc<RefCell<State>>,
+}
+
+impl SerialDriver {
+    pub fn new(mut script: SerialScript) -> Self {
+        // stable, sends queued at the same frame keep their order
+        script.sends.sort_by_key(|&(frame, _)| frame);
+        Self {
+            script,
+            next: 0,
+            state: Rc::default(),
+        }
+    }
+
+    /// Link device sharing the driver state
+    pub fn device(&self) -> ScriptedLink {
+        ScriptedLink {
+            state: self.state.clone(),
+        }
+    }
+
+    /// Queues the sends scheduled up to `frame`
+    pub fn advance_to(&mut self, frame: u64) {
+        let mut state = self.state.borrow_mut();
+        state.frame = frame;
+        while let Some((at, bytes)) = self.script.sends.get(self.next) {
+            if *at > frame {
+                break;
+            }
+            state.pending.extend(bytes);
+            self.next += 1;
+        }
+    }
+
+    /// No scripted byte is left to send
+    pub fn is_done(&self) -> bool {
+        self.next == self.script.sends.len() && self.state.borrow().pending.is_empty()
+    }
+
+    pub fn transcript(&self) -> SerialTranscript {
+        self.state.borrow().transcript.clone()
+    }
+}
+
+pub struct ScriptedLink {
+    state: Rc<RefCell<State>>,
+}
+
+impl LinkDevice for ScriptedLink {
+    fn transfer(&mut self, sent: u8) -> u8 {
+        let mut state = self.state.borrow_mut();
+        let frame = state.frame;
+        state.transcript.responses.push((frame, sent));
+        0xFF
+    }
+
+    fn clock(&mut self, cycles: u8, _sent: u8) -> Option<u8> {
+        let mut state = self.state.borrow_mut();
+        if state.pending.is_empty() {
+            state.gap = 0;
+            return None;
+        }
+
+        state.gap += cycles as u32;
+        if state.gap < BYTE_GAP_CYCLES {
+            return None;
+        }
+        state.gap = 0;
+        state.pending.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_driver_schedules_sends() {
+        let mut driver = SerialDriver::new(
+            SerialScript::new()
+                .send_at(3, b"cd")
+                .send_at(1, b"ab")
+                .send_at(3, b"e"),
+        );
+        let mut link = driver.device();
+        let drain = |link: &mut ScriptedLink| {
+            let mut received = vec![];
+            for _ in 0..20_000 {
+                received.extend(link.clock(4, 0));
+            }
+            received
+        };
+
+        driver.advance_to(0);
+        assert!(drain(&mut link).is_empty());
+        driver.advance_to(1);
+        assert_eq!(b"ab".to_vec(), drain(&mut link));
+        assert!(!driver.is_done());
+        driver.advance_to(4);
+        assert_eq!(b"cde".to_vec(), drain(&mut link));
+        assert!(driver.is_done());
+
+        assert_eq!(0xFF, link.transfer(b'O'));
+        driver.advance_to(5);
+        link.transfer(b'K');
+        link.transfer(0x0A);
+        let transcript = driver.transcript();
+        assert_eq!(vec![(4, b'O'), (5, b'K'), (5, 0x0A)], transcript.responses);
+        assert_eq!("OK\\n", transcript.to_string());
+    }
+}
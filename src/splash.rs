@@ -0,0 +1,263 @@
+//! Boot animations shown when no bootrom is provided, purely frontend sugar: frames are drawn
+//! straight to the LCD and the emulated state is left untouched. See `Config::boot_splash` and
+//! `GameBoy::set_boot_animation`.
+
+use crate::lcd::{Frame, FrameBuffer, PIXELS_HEIGHT, PIXELS_WIDTH, RGB_DARK_GRAY, RGB_WHITE};
+
+pub trait BootAnimation {
+    /// Frames to show before the rom starts
+    fn frame_count(&self) -> usize;
+
+    /// Draws frame `frame` over `buffer`, which holds the previous frame
+    fn draw(&mut self, frame: usize, buffer: &mut FrameBuffer);
+}
+
+/// Animation being played, frame by frame
+pub(crate) struct Playback {
+    animation: Box<dyn BootAnimation>,
+    frame: usize,
+    buffer: Frame,
+}
+
+impl Playback {
+    pub(crate) fn new(animation: Box<dyn BootAnimation>) -> Self {
+        Self {
+            animation,
+            frame: 0,
+            buffer: Frame::new(vec![vec![RGB_WHITE; PIXELS_WIDTH]; PIXELS_HEIGHT]),
+        }
+    }
+
+    /// Draws the next frame, None once the animation is over
+    pub(crate) fn next_frame(&mut self) -> Option<&Frame> {
+        if self.frame >= self.animation.frame_count() {
+            return None;
+        }
+        self.animation.draw(self.frame, &mut self.buffer.pixels);
+        // animations draw in place, every frame counts as changed
+        self.buffer.changed = true;
+        self.buffer.complete();
+        self.frame += 1;
+        Some(&self.buffer)
+    }
+}
+
+/// 5x7 glyphs of the logo letters, one byte per row, most significant of the 5 bits on the left
+const LOGO: [[u8; 7]; 7] = [
+    // G
+    [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+    // A
+    [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+    // M
+    [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+    // U
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+    // B
+    [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+    // O
+    [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+    // Y
+    [0x11, 0x11, 0x0A, 0x04, 0x04, 0x04, 0x04],
+];
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+const LOGO_SCALE: usize = 2;
+const LOGO_SPACING: usize = 2;
+
+/// The logo scrolls down to the middle of the screen, then stays for a moment like the DMG boot
+pub struct LogoSplash {
+    scroll_frames: usize,
+    hold_frames: usize,
+}
+
+impl Default for LogoSplash {
+    fn default() -> Self {
+        Self {
+            scroll_frames: 60,
+            hold_frames: 30,
+        }
+    }
+}
+
+impl LogoSplash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn logo_width() -> usize {
+        LOGO.len() * (GLYPH_WIDTH * LOGO_SCALE + LOGO_SPACING) - LOGO_SPACING
+    }
+
+    /// Top of the logo at `frame`, negative while it's still above the screen
+    fn logo_top(&self, frame: usize, screen_height: usize) -> isize {
+        let height = (GLYPH_HEIGHT * LOGO_SCALE) as isize;
+        let (start, end) = (-height, (screen_height as isize - height) / 2);
+        let progress = frame.min(self.scroll_frames) as isize;
+        start + (end - start) * progress / self.scroll_frames as isize
+    }
+}
+
+impl BootAnimation for LogoSplash {
+    fn frame_count(&self) -> usize {
+        self.scroll_frames + self.hold_frames
+    }
+
+    fn draw(&mut self, frame: usize, buffer: &mut FrameBuffer) {
+        buffer.iter_mut().flatten().for_each(|p| *p = RGB_WHITE);
+
+        let width = buffer.first().map_or(0, Vec::len);
+        let left = width.saturating_sub(Self::logo_width()) / 2;
+        let top = self.logo_top(frame, buffer.len());
+
+        for (i, glyph) in LOGO.iter().enumerate() {
+            let glyph_left = left + i * (GLYPH_WIDTH * LOGO_SCALE + LOGO_SPACING);
+            for (row, bits) in glyph.iter().enumerate() {
+                for col in (0..GLYPH_WIDTH).filter(|col| bits >> (GLYPH_WIDTH - 1 - col) & 1 != 0) {
+                    for dy in 0..LOGO_SCALE {
+                        let y = top + (row * LOGO_SCALE + dy) as isize;
+                        let Some(line) = usize::try_from(y).ok().and_then(|y| buffer.get_mut(y))
+                        else {
+                            continue;
+                        };
+                        for dx in 0..LOGO_SCALE {
+                            if let Some(p) = line.get_mut(glyph_left + col * LOGO_SCALE + dx) {
+                                *p = RGB_DARK_GRAY;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc, sync::mpsc};
+
+    use crate::{
+        config::Config, gameboy::GameBoy, joypad_events_handler, lcd::LCD, pacing::Pacing, saver,
+        stereo, testing,
+    };
+
+    use super::*;
+
+    /// Keeps the drawn frames
+    #[derive(Clone, Default)]
+    struct RecordingLcd(Rc<RefCell<Vec<FrameBuffer>>>);
+
+    impl LCD for RecordingLcd {
+        fn draw_buffer(&mut self, frame: &Frame) {
+            self.0.borrow_mut().push(frame.pixels.clone());
+        }
+    }
+
+    struct Blink;
+
+    impl BootAnimation for Blink {
+        fn frame_count(&self) -> usize {
+            2
+        }
+
+        fn draw(&mut self, frame: usize, buffer: &mut FrameBuffer) {
+            let color = [RGB_DARK_GRAY, RGB_WHITE][frame];
+            buffer.iter_mut().flatten().for_each(|p| *p = color);
+        }
+    }
+
+    fn new_gameboy(
+        boot_splash: bool,
+        lcd: RecordingLcd,
+        rx: &mpsc::Receiver<()>,
+    ) -> GameBoy<'_, RecordingLcd, (), joypad_events_handler::Fake, stereo::Fake> {
+        let cfg = Config {
+            rom: testing::idle_rom(),
+            pacing: Pacing::Uncapped,
+            boot_splash,
+            ..Default::default()
+        };
+        GameBoy::new(
+            &cfg,
+            lcd,
+            stereo::Fake,
+            joypad_events_handler::Fake,
+            saver::Fake,
+            rx,
+        )
+    }
+
+    #[test]
+    fn test_splash_plays_before_the_rom() {
+        let (_, rx) = mpsc::channel();
+        let lcd = RecordingLcd::default();
+        let mut gb = new_gameboy(true, lcd.clone(), &rx);
+
+        let splash_frames = LogoSplash::new().frame_count();
+        for _ in 0..splash_frames {
+            gb.step_frame();
+        }
+        assert_eq!(splash_frames, lcd.0.borrow().len());
+        assert!(lcd
+            .0
+            .borrow()
+            .last()
+            .unwrap()
+            .iter()
+            .flatten()
+            .any(|&p| p == RGB_DARK_GRAY));
+        assert_eq!(0, gb.perf_stats().frames, "the rom hasn't started");
+
+        gb.step_frame();
+        assert_eq!(1, gb.perf_stats().frames);
+        assert_eq!(splash_frames + 1, lcd.0.borrow().len());
+    }
+
+    #[test]
+    fn test_custom_animation() {
+        let (_, rx) = mpsc::channel();
+        let lcd = RecordingLcd::default();
+        let mut gb = new_gameboy(false, lcd.clone(), &rx);
+        gb.set_boot_animation(Blink);
+
+        gb.step_frame();
+        gb.step_frame();
+        gb.step_frame();
+
+        let frames = lcd.0.borrow();
+        assert_eq!(3, frames.len());
+        assert!(frames[0].iter().flatten().all(|&p| p == RGB_DARK_GRAY));
+        assert!(frames[1].iter().flatten().all(|&p| p == RGB_WHITE));
+        assert_eq!(1, gb.perf_stats().frames);
+    }
+
+    #[test]
+    fn test_logo_scrolls_to_the_middle() {
+        let mut splash = LogoSplash::new();
+        let mut buffer = vec![vec![RGB_WHITE; PIXELS_WIDTH]; PIXELS_HEIGHT];
+        let logo_rows = |buffer: &FrameBuffer| -> Vec<usize> {
+            (0..buffer.len())
+                .filter(|&y| buffer[y].contains(&RGB_DARK_GRAY))
+                .collect()
+        };
+
+        splash.draw(0, &mut buffer);
+        assert!(logo_rows(&buffer).is_empty(), "above the screen");
+
+        splash.draw(30, &mut buffer);
+        let halfway = logo_rows(&buffer);
+        assert!(!halfway.is_empty() && halfway[0] < 65);
+
+        let last = splash.frame_count() - 1;
+        splash.draw(last, &mut buffer);
+        assert_eq!((65..79).collect::<Vec<_>>(), logo_rows(&buffer));
+        // centered horizontally
+        let columns: Vec<usize> = (0..PIXELS_WIDTH)
+            .filter(|&x| buffer.iter().any(|line| line[x] == RGB_DARK_GRAY))
+            .collect();
+        assert_eq!(
+            (39, 120),
+            (columns[0], *columns.last().unwrap()),
+            "{columns:?}"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+/// Runtime counters to diagnose performance issues, e.g. to report audio crackling with numbers
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PerfStats {
+    pub frames: u64,
+    /// Sample buffers handed to `StereoPlayer::play`
+    pub audio_buffers: u64,
+    /// Times the frontend audio queue ran dry, detected by the core or reported by the frontend
+    pub audio_underruns: u64,
+}
+
+/// Detects audio starvation around `StereoPlayer::play` calls
+#[derive(Clone, Debug, Default)]
+pub struct AudioMonitor {
+    buffers: u64,
+    underruns: u64,
+    pending_underruns: u64,
+    queued_after_play: usize,
+}
+
+impl AudioMonitor {
+    /// Called right before a buffer is played with the frontend queue size and the starvations
+    /// it reported. A queue that held samples after the previous buffer and is now empty drained
+    /// completely in between, that's an underrun.
+    pub fn before_play(&mut self, queued: usize, reported_underruns: u32) {
+        let mut underruns = reported_underruns as u64;
+        if self.queued_after_play > 0 && queued == 0 {
+            underruns += 1;
+        }
+
+        self.underruns += underruns;
+        self.pending_underruns += underruns;
+        self.buffers += 1;
+    }
+
+    pub fn after_play(&mut self, queued: usize) {
+        self.queued_after_play = queued;
+    }
+
+    pub fn buffers(&self) -> u64 {
+        self.buffers
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.underruns
+    }
+
+    /// Forgets the queue level of the last play, the frontend queue draining during a pause isn't
+    /// an underrun
+    pub fn resync(&mut self) {
+        self.queued_after_play = 0;
+    }
+
+    /// Underruns since the last call, surfaced through the event API
+    pub fn take_pending_underruns(&mut self) -> u64 {
+        std::mem::take(&mut self.pending_underruns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audio_monitor_underruns() {
+        let mut monitor = AudioMonitor::default();
+
+        // players not reporting a queue never underrun
+        monitor.before_play(0, 0);
+        monitor.after_play(0);
+        monitor.before_play(0, 0);
+        monitor.after_play(2048);
+        assert_eq!(0, monitor.underruns());
+
+        monitor.before_play(1024, 0);
+        monitor.after_play(2048);
+        monitor.before_play(0, 0); // drained
+        monitor.after_play(1024);
+        monitor.before_play(512, 2); // reported by the frontend
+        monitor.after_play(1536);
+
+        assert_eq!(5, monitor.buffers());
+        assert_eq!(3, monitor.underruns());
+        assert_eq!(3, monitor.take_pending_underruns());
+        assert_eq!(0, monitor.take_pending_underruns());
+
+        monitor.resync();
+        monitor.before_play(0, 0); // drained while paused
+        assert_eq!(3, monitor.underruns());
+    }
+}
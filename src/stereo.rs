@@ -1,7 +1,542 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use crate::apu::{MASTER_CLOCK_FREQ, SAMPLE_RATE};
+
 pub trait StereoPlayer {
     fn play(&self, _buffer: &[f32]) {}
+
+    /// Number of samples (interleaved left/right values) waiting to be played.
+    /// Used by audio-clock pacing, see `pacing::Pacing::Audio`.
+    fn queued_samples(&self) -> usize {
+        0
+    }
+
+    /// Starvations seen by the frontend since the last call (e.g. its audio callback found an
+    /// empty queue), counted in `stats::PerfStats::audio_underruns`
+    fn take_underruns(&self) -> u32 {
+        0
+    }
 }
 
 pub struct Fake;
 
 impl StereoPlayer for Fake {}
+
+/// Scales the samples into `out` by `gain`, clamped to [-1.0, 1.0] to avoid clipping
+pub fn apply_gain(samples: &[f32], gain: f32, out: &mut [f32]) {
+    for (o, s) in out.iter_mut().zip(samples) {
+        *o = (s * gain).clamp(-1., 1.);
+    }
+}
+
+/// What is heard while fast-forwarding, see `FastForward`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FastForwardAudio {
+    /// Keeps every n-th sample so the audio plays n times faster, pitch rises
+    #[default]
+    Resample,
+    /// Plays one buffer out of n untouched, pitch is preserved but sound gets choppy
+    Decimate,
+    /// Plays one silent buffer out of n, keeping audio pacing running
+    Mute,
+}
+
+/// Output stage bringing the samples of an emulation running `factor` times faster back to
+/// real time, as requested by the policy
+#[derive(Clone, Debug)]
+pub struct FastForward {
+    policy: FastForwardAudio,
+    factor: u32,
+    /// buffers received since the last one played, for decimation
+    received: u32,
+    /// stereo frames received since the last one kept, for resampling
+    frames: u32,
+    out: Vec<f32>,
+}
+
+impl FastForward {
+    pub fn new() -> Self {
+        Self {
+            policy: FastForwardAudio::default(),
+            factor: 1,
+            received: 0,
+            frames: 0,
+            out: vec![],
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: FastForwardAudio) {
+        self.policy = policy;
+        self.reset();
+    }
+
+    /// 1 is real time
+    pub fn set_factor(&mut self, factor: u32) {
+        self.factor = factor.max(1);
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.received = 0;
+        self.frames = 0;
+        self.out.clear();
+    }
+
+    /// Returns the buffer to play for the emulated `samples`, if any
+    pub fn process<'a>(&'a mut self, samples: &'a [f32]) -> Option<&'a [f32]> {
+        if self.factor == 1 {
+            return Some(samples);
+        }
+
+        match self.policy {
+            FastForwardAudio::Resample => {
+                if self.out.len() >= samples.len() {
+                    self.out.clear();
+                }
+                for frame in samples.chunks_exact(2) {
+                    if self.frames == 0 {
+                        self.out.extend_from_slice(frame);
+                    }
+                    self.frames = (self.frames + 1) % self.factor;
+                }
+                (self.out.len() >= samples.len()).then_some(&self.out[..])
+            }
+            FastForwardAudio::Decimate | FastForwardAudio::Mute => {
+                let play = self.received == 0;
+                self.received = (self.received + 1) % self.factor;
+                if !play {
+                    return None;
+                }
+                match self.policy {
+                    FastForwardAudio::Mute => {
+                        self.out.clear();
+                        self.out.resize(samples.len(), 0.);
+                        Some(&self.out)
+                    }
+                    _ => Some(samples),
+                }
+            }
+        }
+    }
+}
+
+impl Default for FastForward {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Highest boost applied to quiet games by `Normalizer`, +12 dB
+pub const MAX_NORMALIZER_GAIN: f32 = 4.;
+/// Time for `Normalizer` to halve its peak estimate once the output gets quieter
+const NORMALIZER_RELEASE_SECONDS: f32 = 0.5;
+
+/// Output stage bringing the peaks of the mixed output to `target`: games driving every channel
+/// near max are limited before they clip, quiet ones are boosted up to `MAX_NORMALIZER_GAIN`.
+/// Peaks are followed instantly and released slowly, so the output never exceeds `target`.
+#[derive(Clone, Debug)]
+pub struct Normalizer {
+    target: f32,
+    /// peak estimate, decaying by `release` each stereo frame
+    envelope: f32,
+    release: f32,
+    out: Vec<f32>,
+}
+
+impl Normalizer {
+    /// `target` is the output peak, in (0.0, 1.0]
+    pub fn new(target: f32) -> Self {
+        let target = target.clamp(f32::EPSILON, 1.);
+        Self {
+            target,
+            envelope: target,
+            release: 0.5f32.powf(1. / (NORMALIZER_RELEASE_SECONDS * SAMPLE_RATE as f32)),
+            out: vec![],
+        }
+    }
+
+    /// Gain currently applied
+    pub fn gain(&self) -> f32 {
+        self.target / self.envelope
+    }
+
+    pub fn process(&mut self, samples: &[f32]) -> &[f32] {
+        self.out.clear();
+        let floor = self.target / MAX_NORMALIZER_GAIN;
+        for frame in samples.chunks_exact(2) {
+            let peak = frame[0].abs().max(frame[1].abs());
+            self.envelope = (self.envelope * self.release).max(peak).max(floor);
+            let gain = self.gain();
+            self.out.extend(frame.iter().map(|s| s * gain));
+        }
+        &self.out
+    }
+}
+
+/// High-pass filter of the console audio output, removing the DC offset of the DAC
+/// https://gbdev.io/pandocs/Audio_details.html#obscure-behavior
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum AudioFilter {
+    /// Raw mixer output
+    #[default]
+    None,
+    Dmg,
+    /// Charges faster than the DMG one
+    Cgb,
+}
+
+impl AudioFilter {
+    /// Capacitor charge kept per master clock cycle
+    fn charge_factor(self) -> Option<f32> {
+        match self {
+            Self::None => None,
+            Self::Dmg => Some(0.999958),
+            Self::Cgb => Some(0.998943),
+        }
+    }
+}
+
+/// Output stage applying an `AudioFilter`
+#[derive(Clone, Debug)]
+pub struct HighPass {
+    /// charge kept per stereo frame
+    charge: f32,
+    capacitors: [f32; 2],
+    out: Vec<f32>,
+}
+
+impl HighPass {
+    /// None for `AudioFilter::None`
+    pub fn new(filter: AudioFilter) -> Option<Self> {
+        let charge = filter.charge_factor()?;
+        Some(Self {
+            charge: charge.powf((MASTER_CLOCK_FREQ / SAMPLE_RATE) as f32),
+            capacitors: [0.; 2],
+            out: vec![],
+        })
+    }
+
+    pub fn process(&mut self, samples: &[f32]) -> &[f32] {
+        self.out.clear();
+        for frame in samples.chunks_exact(2) {
+            for (s, capacitor) in frame.iter().zip(&mut self.capacitors) {
+                let out = s - *capacitor;
+                *capacitor = s - out * self.charge;
+                self.out.push(out);
+            }
+        }
+        &self.out
+    }
+}
+
+/// Per-output gain adapter, to be combined with `Tee` (e.g. a quieter monitor while recording at full volume)
+pub struct Gain<P: StereoPlayer> {
+    player: P,
+    gain: f32,
+}
+
+impl<P: StereoPlayer> Gain<P> {
+    pub fn new(player: P, gain: f32) -> Self {
+        Self {
+            player,
+            gain: gain.max(0.),
+        }
+    }
+
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain.max(0.);
+    }
+
+    pub fn player(&self) -> &P {
+        &self.player
+    }
+
+    pub fn into_inner(self) -> P {
+        self.player
+    }
+}
+
+impl<P: StereoPlayer> StereoPlayer for Gain<P> {
+    fn play(&self, buffer: &[f32]) {
+        let mut scaled = vec![0.; buffer.len()];
+        apply_gain(buffer, self.gain, &mut scaled);
+        self.player.play(&scaled);
+    }
+
+    fn queued_samples(&self) -> usize {
+        self.player.queued_samples()
+    }
+
+    fn take_underruns(&self) -> u32 {
+        self.player.take_underruns()
+    }
+}
+
+/// Fan-out adapter playing every buffer on two players, e.g. speakers and a recorder.
+/// Nest them to attach more outputs: `Tee::new(speakers, Tee::new(recorder, visualizer))`.
+/// The primary player is the one reporting queued samples for audio pacing.
+pub struct Tee<P: StereoPlayer, S: StereoPlayer> {
+    primary: P,
+    secondary: S,
+}
+
+impl<P: StereoPlayer, S: StereoPlayer> Tee<P, S> {
+    pub fn new(primary: P, secondary: S) -> Self {
+        Self { primary, secondary }
+    }
+
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    pub fn secondary(&self) -> &S {
+        &self.secondary
+    }
+
+    pub fn into_inner(self) -> (P, S) {
+        (self.primary, self.secondary)
+    }
+}
+
+impl<P: StereoPlayer, S: StereoPlayer> StereoPlayer for Tee<P, S> {
+    fn play(&self, buffer: &[f32]) {
+        self.primary.play(buffer);
+        self.secondary.play(buffer);
+    }
+
+    fn queued_samples(&self) -> usize {
+        self.primary.queued_samples()
+    }
+
+    fn take_underruns(&self) -> u32 {
+        self.primary.take_underruns()
+    }
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    samples: VecDeque<f32>,
+    underruns: u32,
+}
+
+/// Player for hosts pulling audio from a callback, like a Web Audio worklet or a native audio
+/// callback: `play` only queues the samples and the callback takes them with `pull`.
+/// Clones share the queue, keep one for the callback and hand the other to the `GameBoy`.
+#[derive(Clone, Debug)]
+pub struct SampleQueue {
+    state: Arc<Mutex<QueueState>>,
+    /// samples kept at most, the oldest are dropped past it
+    capacity: usize,
+}
+
+impl SampleQueue {
+    /// `capacity` in samples (interleaved left/right values)
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Arc::default(),
+            capacity,
+        }
+    }
+
+    /// Fills `out` with the oldest queued samples and returns how many there were. The rest is
+    /// silence, counted as an underrun reported through `take_underruns`.
+    pub fn pull(&self, out: &mut [f32]) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let count = out.len().min(state.samples.len());
+        for (o, s) in out.iter_mut().zip(state.samples.drain(..count)) {
+            *o = s;
+        }
+        out[count..].fill(0.);
+        if count < out.len() {
+            state.underruns += 1;
+        }
+        count
+    }
+
+    /// Drops the queued samples, e.g. to cut the latency piled up by a stalled callback
+    pub fn clear(&self) {
+        self.state.lock().unwrap().samples.clear();
+    }
+}
+
+impl StereoPlayer for SampleQueue {
+    fn play(&self, buffer: &[f32]) {
+        let mut state = self.state.lock().unwrap();
+        state.samples.extend(buffer);
+        let excess = state.samples.len().saturating_sub(self.capacity);
+        state.samples.drain(..excess);
+    }
+
+    fn queued_samples(&self) -> usize {
+        self.state.lock().unwrap().samples.len()
+    }
+
+    fn take_underruns(&self) -> u32 {
+        std::mem::take(&mut self.state.lock().unwrap().underruns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    struct Recorder {
+        samples: RefCell<Vec<f32>>,
+        queued: usize,
+    }
+
+    impl Recorder {
+        fn new(queued: usize) -> Self {
+            Self {
+                samples: RefCell::new(vec![]),
+                queued,
+            }
+        }
+    }
+
+    impl StereoPlayer for Recorder {
+        fn play(&self, buffer: &[f32]) {
+            self.samples.borrow_mut().extend_from_slice(buffer);
+        }
+
+        fn queued_samples(&self) -> usize {
+            self.queued
+        }
+    }
+
+    #[test]
+    fn test_tee_plays_on_every_output() {
+        let tee = Tee::new(
+            Recorder::new(3),
+            Tee::new(Recorder::new(5), Recorder::new(7)),
+        );
+
+        tee.play(&[0.5, -0.5]);
+        tee.play(&[0.25, -0.25]);
+
+        let expected = vec![0.5, -0.5, 0.25, -0.25];
+        assert_eq!(expected, *tee.primary().samples.borrow());
+        assert_eq!(expected, *tee.secondary().primary().samples.borrow());
+        assert_eq!(expected, *tee.secondary().secondary().samples.borrow());
+        assert_eq!(3, tee.queued_samples());
+    }
+
+    #[test]
+    fn test_fast_forward_real_time_passthrough() {
+        let mut ff = FastForward::new();
+        assert_eq!(Some(&[0.5, -0.5][..]), ff.process(&[0.5, -0.5]));
+    }
+
+    #[test]
+    fn test_fast_forward_resample() {
+        let mut ff = FastForward::new();
+        ff.set_factor(2);
+
+        assert_eq!(None, ff.process(&[1., 1., 2., 2., 3., 3., 4., 4.]));
+        assert_eq!(
+            Some(&[1., 1., 3., 3., 5., 5., 7., 7.][..]),
+            ff.process(&[5., 5., 6., 6., 7., 7., 8., 8.])
+        );
+        assert_eq!(None, ff.process(&[9., 9., 10., 10., 11., 11., 12., 12.]));
+    }
+
+    #[test]
+    fn test_fast_forward_decimate_and_mute() {
+        let mut ff = FastForward::new();
+        ff.set_factor(3);
+        ff.set_policy(FastForwardAudio::Decimate);
+
+        let played: Vec<bool> = (0..6).map(|_| ff.process(&[0.5, 0.5]).is_some()).collect();
+        assert_eq!(vec![true, false, false, true, false, false], played);
+
+        ff.set_policy(FastForwardAudio::Mute);
+        assert_eq!(Some(&[0., 0.][..]), ff.process(&[0.5, 0.5]));
+        assert_eq!(None, ff.process(&[0.5, 0.5]));
+    }
+
+    #[test]
+    fn test_gain_scales_and_clamps() {
+        let gain = Gain::new(Recorder::new(0), 2.);
+
+        gain.play(&[0.25, -0.25, 0.75, -0.75]);
+
+        assert_eq!(vec![0.5, -0.5, 1., -1.], *gain.player().samples.borrow());
+    }
+
+    #[test]
+    fn test_normalizer_limits_loud_output() {
+        let mut normalizer = Normalizer::new(0.8);
+
+        let out = normalizer.process(&[0.4, -0.4, 1., -1., 0.9, 0.5]).to_vec();
+        for (expected, s) in [0.4, -0.4, 0.8, -0.8].iter().zip(&out) {
+            assert!((expected - s).abs() < 1e-3, "{out:?}");
+        }
+        assert!(out.iter().all(|s| s.abs() <= 0.8));
+        assert!((normalizer.gain() - 0.8).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_high_pass_removes_dc_offset() {
+        assert!(HighPass::new(AudioFilter::None).is_none());
+
+        for filter in [AudioFilter::Dmg, AudioFilter::Cgb] {
+            let mut high_pass = HighPass::new(filter).unwrap();
+            let dc = [0.5, -0.5].repeat(SAMPLE_RATE as usize);
+
+            let out = high_pass.process(&dc);
+            assert_eq!(out[..2], [0.5, -0.5]);
+            assert!(
+                out[out.len() - 2..].iter().all(|s| s.abs() < 1e-3),
+                "{filter:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_normalizer_boosts_quiet_output() {
+        let mut normalizer = Normalizer::new(0.8);
+        let quiet = [0.1, -0.1].repeat(SAMPLE_RATE as usize * 4);
+
+        let out = normalizer.process(&quiet);
+        // boosted to the target over a few release periods, capped by the max gain
+        assert!(
+            (out.last().unwrap() + 0.4).abs() < 1e-3,
+            "{}",
+            out.last().unwrap()
+        );
+        assert_eq!(MAX_NORMALIZER_GAIN, normalizer.gain());
+
+        let out = normalizer.process(&[0.5, 0.5]);
+        assert_eq!(vec![0.8, 0.8], out);
+    }
+
+    #[test]
+    fn test_sample_queue() {
+        let queue = SampleQueue::new(6);
+        let player = queue.clone();
+        player.play(&[0.1, 0.2, 0.3, 0.4]);
+        assert_eq!(4, queue.queued_samples());
+
+        let mut out = [1.; 2];
+        assert_eq!(2, queue.pull(&mut out));
+        assert_eq!([0.1, 0.2], out);
+
+        // the oldest samples are dropped past the capacity
+        player.play(&[0.5, 0.6, 0.7, 0.8, 0.9, 1.]);
+        assert_eq!(6, player.queued_samples());
+        let mut out = [1.; 8];
+        assert_eq!(6, queue.pull(&mut out));
+        assert_eq!([0.5, 0.6, 0.7, 0.8, 0.9, 1., 0., 0.], out);
+        assert_eq!(1, player.take_underruns());
+        assert_eq!(0, player.take_underruns());
+
+        player.play(&[0.1]);
+        queue.clear();
+        assert_eq!(0, player.queued_samples());
+    }
+}
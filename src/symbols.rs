@@ -0,0 +1,217 @@
+//! RGBDS `.sym` symbol files, to show `Main_Loop` instead of `0150` in debugger output and cpu
+//! traces. One `BANK:ADDR Name` line per symbol, in hex, `;` starting comments:
+//!
+//! ```text
+//! ; File generated by rgblink
+//! 00:0150 Main
+//! 00:0158 Main.loop
+//! 01:4000 Level_Init
+//! ```
+//!
+//! Banks are only told apart in switchable rom (4000-7FFF) and cartridge ram (A000-BFFF), labels
+//! elsewhere match whatever bank is mapped.
+
+use std::{collections::BTreeMap, error, fmt, fs, io, path::Path};
+
+#[derive(Debug)]
+pub enum SymbolError {
+    Io(io::Error),
+    /// 1-based line number and its content
+    InvalidLine(usize, String),
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to read symbol file: {e}"),
+            Self::InvalidLine(number, line) => {
+                write!(f, "invalid symbol on line {number}: {line}")
+            }
+        }
+    }
+}
+
+impl error::Error for SymbolError {}
+
+impl From<io::Error> for SymbolError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Labels of a rom, see the module documentation
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SymbolTable {
+    /// (address, bank) -> first label declared there
+    labels: BTreeMap<(u16, u16), String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, SymbolError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, SymbolError> {
+        let mut table = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            let content = line.split(';').next().unwrap_or_default().trim();
+            if content.is_empty() {
+                continue;
+            }
+            let invalid = || SymbolError::InvalidLine(i + 1, line.to_string());
+
+            let (location, name) = content
+                .split_once(char::is_whitespace)
+                .ok_or_else(invalid)?;
+            let (bank, address) = location.split_once(':').ok_or_else(invalid)?;
+            let bank = u16::from_str_radix(bank, 16).map_err(|_| invalid())?;
+            let address = u16::from_str_radix(address, 16).map_err(|_| invalid())?;
+            table.insert(bank, address, name.trim());
+        }
+        Ok(table)
+    }
+
+    /// Keeps the first label of an address, later ones being aliases
+    pub fn insert(&mut self, bank: u16, address: u16, name: &str) {
+        self.labels
+            .entry((address, bank))
+            .or_insert_with(|| name.to_string());
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Label declared at `address` with `bank` mapped
+    pub fn label(&self, bank: u16, address: u16) -> Option<&str> {
+        let (_, name) = self
+            .labels_at(address)
+            .find(|&(b, _)| b == bank || !banked(address))?;
+        Some(name)
+    }
+
+    /// Label or label plus offset of the closest label before `address` in the same memory
+    /// region, e.g. `Main.loop+3`
+    pub fn describe(&self, bank: u16, address: u16) -> Option<String> {
+        let (label_address, name) = self.nearest(bank, address)?;
+        Some(match address - label_address {
+            0 => name.to_string(),
+            offset => format!("{name}+{offset}"),
+        })
+    }
+
+    /// Address and name of the closest label at or before `address` in the same memory region,
+    /// e.g. the function containing it
+    pub fn nearest(&self, bank: u16, address: u16) -> Option<(u16, &str)> {
+        let area = region(address);
+        let ((label_address, _), name) = self
+            .labels
+            .range(..=(address, u16::MAX))
+            .rev()
+            .take_while(|((a, _), _)| region(*a) == area)
+            .find(|((_, b), _)| *b == bank || !banked(address))?;
+        Some((*label_address, name))
+    }
+
+    /// (bank, address) of `name`, e.g. to set a breakpoint on it
+    pub fn address_of(&self, name: &str) -> Option<(u16, u16)> {
+        self.labels
+            .iter()
+            .find(|(_, label)| *label == name)
+            .map(|(&(address, bank), _)| (bank, address))
+    }
+
+    fn labels_at(&self, address: u16) -> impl Iterator<Item = (u16, &str)> {
+        self.labels
+            .range((address, 0)..=(address, u16::MAX))
+            .map(|(&(_, bank), name)| (bank, name.as_str()))
+    }
+}
+
+/// Regions where the same address maps different banks over time
+fn banked(address: u16) -> bool {
+    matches!(address, 0x4000..=0x7FFF | 0xA000..=0xBFFF)
+}
+
+/// Memory map area, labels don't extend across them
+fn region(address: u16) -> u8 {
+    match address {
+        0x0000..=0x3FFF => 0,
+        0x4000..=0x7FFF => 1,
+        0x8000..=0x9FFF => 2,
+        0xA000..=0xBFFF => 3,
+        0xC000..=0xDFFF => 4,
+        0xE000..=0xFDFF => 5,
+        0xFE00..=0xFEFF => 6,
+        0xFF00..=0xFF7F => 7,
+        _ => 8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYM: &str = "; File generated by rgblink
+00:0150 Main
+00:0150 Main_Alias
+00:0158 Main.loop ; inner loop
+01:4000 Level1_Init
+02:4000 Level2_Init
+00:C000 wFrameCounter
+";
+
+    #[test]
+    fn test_parse() {
+        let table = SymbolTable::parse(SYM).unwrap();
+        assert_eq!(5, table.len());
+        assert_eq!(Some("Main"), table.label(0, 0x0150));
+        assert_eq!(Some("Main.loop"), table.label(0, 0x0158));
+        assert_eq!(None, table.label(0, 0x0151));
+        assert_eq!(Some((0, 0x0158)), table.address_of("Main.loop"));
+        assert_eq!(Some((2, 0x4000)), table.address_of("Level2_Init"));
+        assert_eq!(None, table.address_of("Missing"));
+
+        assert!(matches!(
+            SymbolTable::parse("00:0150 Main\n0150 Broken"),
+            Err(SymbolError::InvalidLine(2, _))
+        ));
+        assert!(SymbolTable::parse("zz:0150 Main").is_err());
+    }
+
+    #[test]
+    fn test_banks() {
+        let table = SymbolTable::parse(SYM).unwrap();
+        assert_eq!(Some("Level1_Init"), table.label(1, 0x4000));
+        assert_eq!(Some("Level2_Init"), table.label(2, 0x4000));
+        assert_eq!(None, table.label(3, 0x4000));
+        // work ram labels match any bank
+        assert_eq!(Some("wFrameCounter"), table.label(1, 0xC000));
+    }
+
+    #[test]
+    fn test_describe() {
+        let table = SymbolTable::parse(SYM).unwrap();
+        assert_eq!(Some("Main".to_string()), table.describe(0, 0x0150));
+        assert_eq!(Some("Main+3".to_string()), table.describe(0, 0x0153));
+        assert_eq!(Some("Main.loop+16".to_string()), table.describe(0, 0x0168));
+        assert_eq!(Some("Level2_Init+5".to_string()), table.describe(2, 0x4005));
+        assert_eq!(
+            Some("wFrameCounter+1".to_string()),
+            table.describe(0, 0xC001)
+        );
+        assert_eq!(Some((0x0158, "Main.loop")), table.nearest(0, 0x0168));
+        // no label before it in its region
+        assert_eq!(None, table.describe(0, 0x0100));
+        assert_eq!(None, table.describe(3, 0x4005));
+        assert_eq!(None, table.describe(0, 0x8000));
+    }
+}
@@ -0,0 +1,256 @@
+//! Link cable over TCP, to connect two emulator instances running on different machines.
+//! Native targets only, browsers have no TCP sockets.
+//!
+//! Each side plugs a `TcpLink` into its link port. The Game Boy using the internal clock is the
+//! master: its transfer sends the byte to the peer and completes with the byte the peer had ready
+//! in SB, answered as soon as the peer waits on a transfer with the external clock. Emulation
+//! keeps running while the answer travels, the game sees a slower transfer. When both sides use
+//! the internal clock at the same time they simply swap their bytes, which is what games
+//! negotiating who is the master (e.g. Pokémon) expect from a real cable.
+//!
+//! Messages are 2 bytes: a kind and a byte. A master waiting longer than the timeout reads 0xFF,
+//! like nothing plugged in, and the late answer is dropped.
+
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::link::LinkDevice;
+
+/// Byte shifted out by a master
+const TRANSFER: u8 = 0x01;
+/// Byte shifted back by the peer, in answer to a `TRANSFER`
+const REPLY: u8 = 0x02;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct TcpLink {
+    stream: TcpStream,
+    /// (kind, byte) messages read by a background thread, disconnected with the peer
+    incoming: Receiver<(u8, u8)>,
+    timeout: Duration,
+    /// replies to transfers that timed out, dropped when they arrive
+    late_replies: usize,
+    /// when the transfer waiting on the peer was sent
+    pending: Option<Instant>,
+    connected: bool,
+}
+
+impl TcpLink {
+    /// Waits for the peer to connect on `addr`
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        Self::from_stream(stream)
+    }
+
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Self::from_stream(TcpStream::connect(addr)?)
+    }
+
+    pub fn from_stream(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        let mut reader = stream.try_clone()?;
+        let (tx, incoming) = mpsc::channel();
+        thread::spawn(move || {
+            let mut message = [0; 2];
+            while reader.read_exact(&mut message).is_ok() {
+                if tx.send((message[0], message[1])).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            stream,
+            incoming,
+            timeout: DEFAULT_TIMEOUT,
+            late_replies: 0,
+            pending: None,
+            connected: true,
+        })
+    }
+
+    /// How long a master waits for the peer to answer, 1s by default
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// False once the peer is gone, the link then behaves as if nothing was plugged in
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    fn send(&mut self, kind: u8, byte: u8) {
+        if self.stream.write_all(&[kind, byte]).is_err() {
+            self.connected = false;
+        }
+    }
+}
+
+/// Stops the background reader, the peer sees the link disconnected
+impl Drop for TcpLink {
+    fn drop(&mut self) {
+        _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+impl LinkDevice for TcpLink {
+    /// Blocks until the peer answers or the timeout, for use outside of the emulation loop
+    fn transfer(&mut self, sent: u8) -> u8 {
+        loop {
+            match self.poll_transfer(sent) {
+                Some(received) => return received,
+                None => thread::sleep(Duration::from_micros(100)),
+            }
+        }
+    }
+
+    fn poll_transfer(&mut self, sent: u8) -> Option<u8> {
+        if !self.connected {
+            return Some(0xFF);
+        }
+
+        let started = match self.pending {
+            Some(started) => started,
+            None => {
+                self.send(TRANSFER, sent);
+                *self.pending.insert(Instant::now())
+            }
+        };
+        loop {
+            match self.incoming.try_recv() {
+                Ok((REPLY, _)) if self.late_replies > 0 => self.late_replies -= 1,
+                // a transfer from the peer means it's a master too, both shift their byte out
+                Ok((REPLY | TRANSFER, received)) => {
+                    self.pending = None;
+                    return Some(received);
+                }
+                Ok(_) => {}
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.connected = false;
+                    self.pending = None;
+                    return Some(0xFF);
+                }
+            }
+        }
+
+        if started.elapsed() < self.timeout {
+            return None;
+        }
+        self.late_replies += 1;
+        self.pending = None;
+        Some(0xFF)
+    }
+
+    fn clock(&mut self, _cycles: u8, sent: u8) -> Option<u8> {
+        loop {
+            match self.incoming.try_recv() {
+                Ok((TRANSFER, received)) => {
+                    self.send(REPLY, sent);
+                    return Some(received);
+                }
+                Ok((REPLY, _)) if self.late_replies > 0 => self.late_replies -= 1,
+                Ok(_) => {}
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => {
+                    self.connected = false;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn linked_pair() -> (TcpLink, TcpLink) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || TcpLink::connect(addr).unwrap());
+        let (stream, _) = listener.accept().unwrap();
+        (
+            TcpLink::from_stream(stream).unwrap(),
+            client.join().unwrap(),
+        )
+    }
+
+    /// Polls `link` as a Game Boy waiting with the external clock
+    fn wait_external(mut link: TcpLink, sent: u8) -> (TcpLink, u8) {
+        loop {
+            if let Some(received) = link.clock(4, sent) {
+                return (link, received);
+            }
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn test_master_and_slave_exchange() {
+        let (mut master, slave) = linked_pair();
+        let slave = thread::spawn(move || wait_external(slave, 0x42));
+
+        assert_eq!(0x42, master.transfer(0x99));
+        let (_, received) = slave.join().unwrap();
+        assert_eq!(0x99, received);
+    }
+
+    #[test]
+    fn test_two_masters_swap_bytes() {
+        let (mut a, mut b) = linked_pair();
+        let b = thread::spawn(move || b.transfer(0x02));
+
+        assert_eq!(0x02, a.transfer(0x01));
+        assert_eq!(0x01, b.join().unwrap());
+    }
+
+    #[test]
+    fn test_unanswered_transfer_times_out() {
+        let (mut master, slave) = linked_pair();
+        master.set_timeout(Duration::from_millis(20));
+
+        assert_eq!(0xFF, master.transfer(0x11));
+
+        // the late answer isn't mistaken for the next one
+        let (slave, received) = wait_external(slave, 0xAA);
+        assert_eq!(0x11, received);
+        let slave = thread::spawn(move || wait_external(slave, 0xBB));
+        master.set_timeout(DEFAULT_TIMEOUT);
+        assert_eq!(0xBB, master.transfer(0x22));
+        assert_eq!(0x22, slave.join().unwrap().1);
+    }
+
+    #[test]
+    fn test_poll_transfer_doesnt_block() {
+        let (mut master, slave) = linked_pair();
+
+        assert_eq!(None, master.poll_transfer(0x99));
+        assert_eq!(None, master.poll_transfer(0x99));
+        let (_, received) = wait_external(slave, 0x42);
+        assert_eq!(0x99, received);
+
+        let received = loop {
+            if let Some(received) = master.poll_transfer(0x99) {
+                break received;
+            }
+            thread::yield_now();
+        };
+        assert_eq!(0x42, received);
+    }
+
+    #[test]
+    fn test_disconnected_peer_reads_as_unplugged() {
+        let (mut link, peer) = linked_pair();
+        drop(peer);
+
+        assert_eq!(0xFF, link.transfer(0x11));
+        assert!(!link.is_connected());
+        assert_eq!(None, link.clock(4, 0x00));
+    }
+}
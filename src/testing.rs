@@ -0,0 +1,953 @@
+//! Helpers to test the core itself or games running on it.
+
+use std::{cell::Cell, fmt, fs, io, path::Path, rc::Rc, sync::mpsc};
+
+use crate::{
+    cartridge::NINTENDO_LOGO,
+    config::Config,
+    gameboy::GameBoy,
+    joypad::Button,
+    joypad_events_handler::{self, EventsHandler},
+    lcd::{self, FrameBuffer, LCD},
+    mode::Mode,
+    pacing::Pacing,
+    saver,
+    serial_script::{SerialDriver, SerialScript, SerialTranscript},
+    stereo::StereoPlayer,
+};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// FNV-1a, stable across runs and platforms unlike std hashers
+fn fnv1a(hash: u64, bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes
+        .into_iter()
+        .fold(hash, |h, b| (h ^ b as u64).wrapping_mul(FNV_PRIME))
+}
+
+pub fn frame_hash(frame: &FrameBuffer) -> u64 {
+    fnv1a(
+        FNV_OFFSET_BASIS,
+        frame.iter().flatten().flat_map(|&(r, g, b)| [r, g, b]),
+    )
+}
+
+/// Audio output folding every played sample into a running hash
+#[derive(Clone)]
+pub struct HashingPlayer {
+    hash: Rc<Cell<u64>>,
+}
+
+impl Default for HashingPlayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HashingPlayer {
+    pub fn new() -> Self {
+        Self {
+            hash: Rc::new(Cell::new(FNV_OFFSET_BASIS)),
+        }
+    }
+
+    /// Hash of every sample played so far, shared between clones
+    pub fn hash(&self) -> u64 {
+        self.hash.get()
+    }
+
+    /// Starts hashing from scratch, as if nothing had been played
+    pub fn reset(&self) {
+        self.hash.set(FNV_OFFSET_BASIS);
+    }
+}
+
+impl StereoPlayer for HashingPlayer {
+    fn play(&self, buffer: &[f32]) {
+        let bytes = buffer.iter().flat_map(|s| s.to_bits().to_le_bytes());
+        self.hash.set(fnv1a(self.hash.get(), bytes));
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DivergenceKind {
+    Frame,
+    Audio,
+}
+
+/// First frame where two runs of the same rom and inputs stopped matching
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    pub frame: usize,
+    pub kind: DivergenceKind,
+    pub hashes: (u64, u64),
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} diverged at frame {}: {:#018x} != {:#018x}",
+            self.kind, self.frame, self.hashes.0, self.hashes.1
+        )
+    }
+}
+
+/// Address the assembled code is placed at, right after the header
+pub const CODE_START: u16 = 0x0150;
+
+/// 8 bits operands, in opcode encoding order
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum R8 {
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    /// (HL)
+    HLInd,
+    A,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum R16 {
+    BC,
+    DE,
+    HL,
+    SP,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cond {
+    NZ,
+    Z,
+    NC,
+    C,
+}
+
+/// Tiny SM83 assembler covering what smoke tests need, `db` emits anything else.
+/// Jumps take absolute targets, use `here` to get the address of a backward label.
+#[derive(Clone, Debug)]
+pub struct Asm {
+    code: Vec<u8>,
+}
+
+impl Default for Asm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Asm {
+    pub fn new() -> Self {
+        Self { code: vec![] }
+    }
+
+    /// Address of the next emitted instruction
+    pub fn here(&self) -> u16 {
+        CODE_START + self.code.len() as u16
+    }
+
+    pub fn db(&mut self, bytes: &[u8]) -> &mut Self {
+        self.code.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn nop(&mut self) -> &mut Self {
+        self.db(&[0x00])
+    }
+
+    pub fn halt(&mut self) -> &mut Self {
+        self.db(&[0x76])
+    }
+
+    pub fn di(&mut self) -> &mut Self {
+        self.db(&[0xF3])
+    }
+
+    pub fn ei(&mut self) -> &mut Self {
+        self.db(&[0xFB])
+    }
+
+    pub fn ld_r_n(&mut self, r: R8, n: u8) -> &mut Self {
+        self.db(&[0x06 | (r as u8) << 3, n])
+    }
+
+    pub fn ld_r_r(&mut self, dst: R8, src: R8) -> &mut Self {
+        assert!(
+            !(dst == R8::HLInd && src == R8::HLInd),
+            "ld (hl),(hl) is halt"
+        );
+        self.db(&[0x40 | (dst as u8) << 3 | src as u8])
+    }
+
+    pub fn ld_rr_nn(&mut self, rr: R16, nn: u16) -> &mut Self {
+        let [lo, hi] = nn.to_le_bytes();
+        self.db(&[0x01 | (rr as u8) << 4, lo, hi])
+    }
+
+    /// ld (nn),a
+    pub fn ld_nn_a(&mut self, nn: u16) -> &mut Self {
+        let [lo, hi] = nn.to_le_bytes();
+        self.db(&[0xEA, lo, hi])
+    }
+
+    /// ld a,(nn)
+    pub fn ld_a_nn(&mut self, nn: u16) -> &mut Self {
+        let [lo, hi] = nn.to_le_bytes();
+        self.db(&[0xFA, lo, hi])
+    }
+
+    /// ldh (0xFF00+n),a
+    pub fn ldh_n_a(&mut self, n: u8) -> &mut Self {
+        self.db(&[0xE0, n])
+    }
+
+    /// ldh a,(0xFF00+n)
+    pub fn ldh_a_n(&mut self, n: u8) -> &mut Self {
+        self.db(&[0xF0, n])
+    }
+
+    /// ld (hl+),a
+    pub fn ld_hli_a(&mut self) -> &mut Self {
+        self.db(&[0x22])
+    }
+
+    pub fn inc(&mut self, r: R8) -> &mut Self {
+        self.db(&[0x04 | (r as u8) << 3])
+    }
+
+    pub fn dec(&mut self, r: R8) -> &mut Self {
+        self.db(&[0x05 | (r as u8) << 3])
+    }
+
+    pub fn inc_rr(&mut self, rr: R16) -> &mut Self {
+        self.db(&[0x03 | (rr as u8) << 4])
+    }
+
+    pub fn dec_rr(&mut self, rr: R16) -> &mut Self {
+        self.db(&[0x0B | (rr as u8) << 4])
+    }
+
+    pub fn add_n(&mut self, n: u8) -> &mut Self {
+        self.db(&[0xC6, n])
+    }
+
+    pub fn sub_n(&mut self, n: u8) -> &mut Self {
+        self.db(&[0xD6, n])
+    }
+
+    pub fn and_n(&mut self, n: u8) -> &mut Self {
+        self.db(&[0xE6, n])
+    }
+
+    pub fn xor_n(&mut self, n: u8) -> &mut Self {
+        self.db(&[0xEE, n])
+    }
+
+    pub fn or_n(&mut self, n: u8) -> &mut Self {
+        self.db(&[0xF6, n])
+    }
+
+    pub fn cp_n(&mut self, n: u8) -> &mut Self {
+        self.db(&[0xFE, n])
+    }
+
+    pub fn jp(&mut self, nn: u16) -> &mut Self {
+        let [lo, hi] = nn.to_le_bytes();
+        self.db(&[0xC3, lo, hi])
+    }
+
+    /// Relative jump to `target`, unconditional when `cond` is None
+    pub fn jr(&mut self, cond: Option<Cond>, target: u16) -> &mut Self {
+        let opcode = match cond {
+            None => 0x18,
+            Some(cond) => 0x20 | (cond as u8) << 3,
+        };
+        let offset = target as i32 - (self.here() as i32 + 2);
+        let offset = i8::try_from(offset).expect("jr target out of range");
+        self.db(&[opcode, offset as u8])
+    }
+
+    pub fn call(&mut self, nn: u16) -> &mut Self {
+        let [lo, hi] = nn.to_le_bytes();
+        self.db(&[0xCD, lo, hi])
+    }
+
+    pub fn ret(&mut self) -> &mut Self {
+        self.db(&[0xC9])
+    }
+
+    /// Loops forever on a `jr -2`, without touching IO
+    pub fn spin(&mut self) -> &mut Self {
+        let here = self.here();
+        self.jr(None, here)
+    }
+}
+
+/// Builds minimal roms with a valid header and checksums, so tests don't depend on binary files.
+/// The entry point jumps to the assembled code at `CODE_START`.
+#[derive(Clone, Debug)]
+pub struct RomBuilder {
+    title: String,
+    cgb_flag: u8,
+    cartridge_type: u8,
+    rom_size: u8,
+    ram_size: u8,
+    code: Vec<u8>,
+    patches: Vec<(usize, Vec<u8>)>,
+}
+
+impl Default for RomBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RomBuilder {
+    pub fn new() -> Self {
+        Self {
+            title: "TEST".to_string(),
+            cgb_flag: 0x00,
+            cartridge_type: 0x00,
+            rom_size: 0x00,
+            ram_size: 0x00,
+            code: vec![],
+            patches: vec![],
+        }
+    }
+
+    /// Up to 16 ascii characters (11 when a CGB flag is set, in practice)
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = title.to_string();
+        self
+    }
+
+    pub fn cgb_flag(mut self, flag: u8) -> Self {
+        self.cgb_flag = flag;
+        self
+    }
+
+    /// Mapper byte (0x0147)
+    pub fn cartridge_type(mut self, cartridge_type: u8) -> Self {
+        self.cartridge_type = cartridge_type;
+        self
+    }
+
+    /// Rom size byte (0x0148), the rom holds `2 << rom_size` banks of 16 KiB
+    pub fn rom_size(mut self, rom_size: u8) -> Self {
+        self.rom_size = rom_size;
+        self
+    }
+
+    /// Ram size byte (0x0149)
+    pub fn ram_size(mut self, ram_size: u8) -> Self {
+        self.ram_size = ram_size;
+        self
+    }
+
+    pub fn code(mut self, asm: &Asm) -> Self {
+        self.code = asm.code.clone();
+        self
+    }
+
+    /// Raw bytes at a rom offset, e.g. data in a switchable bank
+    pub fn patch(mut self, offset: usize, bytes: &[u8]) -> Self {
+        self.patches.push((offset, bytes.to_vec()));
+        self
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000 << self.rom_size];
+
+        // entry point: nop; jp CODE_START
+        let [lo, hi] = CODE_START.to_le_bytes();
+        rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, lo, hi]);
+        rom[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+
+        let title = self.title.as_bytes();
+        let title_len = title.len().min(16);
+        rom[0x0134..0x0134 + title_len].copy_from_slice(&title[..title_len]);
+        rom[0x0143] = self.cgb_flag;
+        rom[0x0147] = self.cartridge_type;
+        rom[0x0148] = self.rom_size;
+        rom[0x0149] = self.ram_size;
+        rom[0x014A] = 0x01; // overseas
+
+        let code_start = CODE_START as usize;
+        assert!(
+            code_start + self.code.len() <= 0x4000,
+            "code overflows bank 0"
+        );
+        rom[code_start..code_start + self.code.len()].copy_from_slice(&self.code);
+
+        for (offset, bytes) in &self.patches {
+            rom[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+
+        // https://gbdev.io/pandocs/The_Cartridge_Header.html#014d--header-checksum
+        rom[0x014D] = rom[0x0134..=0x014C]
+            .iter()
+            .fold(0u8, |c, &b| c.wrapping_sub(b).wrapping_sub(1));
+
+        let global = rom
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != 0x014E && i != 0x014F)
+            .fold(0u16, |c, (_, &b)| c.wrapping_add(b as u16));
+        rom[0x014E..0x0150].copy_from_slice(&global.to_be_bytes());
+
+        rom
+    }
+}
+
+/// Runs `gb` unthrottled and without enhancements (`GameBoy::disable_enhancements`) for at most
+/// `max_frames` emulated frames, checking `done` after every frame. Returns the number of frames
+/// it took for `done` to hold, `None` when the budget ran out. Unlike a wall-clock timeout, the
+/// outcome doesn't depend on how fast the host is.
+pub fn run_frames<L: LCD, E: Send + 'static, H: EventsHandler<E>, S: StereoPlayer>(
+    gb: &mut GameBoy<'_, L, E, H, S>,
+    max_frames: u64,
+    mut done: impl FnMut(&GameBoy<'_, L, E, H, S>) -> bool,
+) -> Option<u64> {
+    gb.set_pacing(Pacing::Uncapped);
+    gb.disable_enhancements();
+    for frame in 1..=max_frames {
+        gb.step_frame();
+        if done(gb) {
+            return Some(frame);
+        }
+    }
+    None
+}
+
+/// Smallest valid rom: no mbc, its code spins forever so the cpu never touches IO
+pub fn idle_rom() -> Vec<u8> {
+    RomBuilder::new().code(Asm::new().spin()).build()
+}
+
+/// Fuzzing entry point: the first byte selects the mode, every following 3 bytes write a
+/// random value to an APU, PPU or timer register then run up to 63 cpu steps.
+/// Panics on emulator panics or broken invariants.
+pub fn fuzz_io_registers(data: &[u8]) {
+    let Some((&mode, ops)) = data.split_first() else {
+        return;
+    };
+
+    let cfg = Config {
+        mode: if mode & 1 == 1 { Mode::CGB } else { Mode::DMG },
+        rom: idle_rom(),
+        headless_mode: true,
+        pacing: Pacing::Uncapped,
+        ..Default::default()
+    };
+
+    let (_, rx) = mpsc::channel::<()>();
+    let mut gb = GameBoy::new(
+        &cfg,
+        lcd::Fake,
+        HashingPlayer::new(),
+        joypad_events_handler::Fake,
+        saver::Fake,
+        &rx,
+    );
+
+    for op in ops.chunks_exact(3) {
+        let (selector, offset, value) = (op[0], op[1], op[2]);
+        let address = match selector & 3 {
+            0 | 1 => 0xFF10 + offset as u16 % 0x30,
+            2 => 0xFF40 + offset as u16 % 0x0C,
+            _ => 0xFF04 + offset as u16 % 4,
+        };
+        gb.write_memory(address, value);
+
+        for _ in 0..selector >> 2 {
+            gb.step();
+        }
+
+        let ly = gb.read_memory(0xFF44);
+        assert!(ly <= 153, "LY out of range: {ly}");
+
+        let lcd_on = gb.read_memory(0xFF40) & 0x80 != 0;
+        let mode = gb.read_memory(0xFF41) & 0b11;
+        if lcd_on && ly >= 144 {
+            assert_eq!(1, mode, "not in VBlank on line {ly}");
+        }
+    }
+}
+
+/// gbmicrotest result protocol, the rom writes its outcome to high ram
+/// https://github.com/aappleby/gbmicrotest
+pub const MICROTEST_ACTUAL_ADDR: u16 = 0xFF80;
+pub const MICROTEST_EXPECTED_ADDR: u16 = 0xFF81;
+pub const MICROTEST_RESULT_ADDR: u16 = 0xFF82;
+const MICROTEST_PASS: u8 = 0x01;
+const MICROTEST_FAIL: u8 = 0xFF;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MicrotestResult {
+    Pass,
+    Fail {
+        actual: u8,
+        expected: u8,
+    },
+    /// No result was written in the allotted frames
+    Timeout,
+}
+
+/// Runs a single gbmicrotest rom, polling the result address after every frame
+pub fn run_microtest(rom: &[u8], mode: Mode, max_frames: usize) -> MicrotestResult {
+    let cfg = Config {
+        mode,
+        rom: rom.to_vec(),
+        headless_mode: true,
+        pacing: Pacing::Uncapped,
+        ..Default::default()
+    };
+
+    let (_, rx) = mpsc::channel::<()>();
+    let mut gb = GameBoy::new(
+        &cfg,
+        lcd::Fake,
+        HashingPlayer::new(),
+        joypad_events_handler::Fake,
+        saver::Fake,
+        &rx,
+    );
+
+    for _ in 0..max_frames {
+        gb.step_frame();
+        match gb.read_memory(MICROTEST_RESULT_ADDR) {
+            MICROTEST_PASS => return MicrotestResult::Pass,
+            MICROTEST_FAIL => {
+                return MicrotestResult::Fail {
+                    actual: gb.read_memory(MICROTEST_ACTUAL_ADDR),
+                    expected: gb.read_memory(MICROTEST_EXPECTED_ADDR),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    MicrotestResult::Timeout
+}
+
+/// Per-rom results of a microtest suite run, its `Display` lists failures and the pass rate
+#[derive(Clone, Debug, Default)]
+pub struct MicrotestReport {
+    pub results: Vec<(String, MicrotestResult)>,
+}
+
+impl MicrotestReport {
+    pub fn passed(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|(_, r)| *r == MicrotestResult::Pass)
+            .count()
+    }
+}
+
+impl fmt::Display for MicrotestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, result) in &self.results {
+            match result {
+                MicrotestResult::Pass => {}
+                MicrotestResult::Fail { actual, expected } => writeln!(
+                    f,
+                    "FAIL {name}: got {actual:#04x}, expected {expected:#04x}"
+                )?,
+                MicrotestResult::Timeout => writeln!(f, "TIMEOUT {name}")?,
+            }
+        }
+        write!(f, "{}/{} passed", self.passed(), self.results.len())
+    }
+}
+
+/// Runs every `.gb` rom of `dir` in name order
+pub fn run_microtest_suite(
+    dir: &Path,
+    mode: Mode,
+    max_frames: usize,
+) -> io::Result<MicrotestReport> {
+    let mut paths: Vec<_> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    paths.retain(|p| p.extension().is_some_and(|ext| ext == "gb"));
+    paths.sort();
+
+    let mut report = MicrotestReport::default();
+    for path in paths {
+        let rom = fs::read(&path)?;
+        let name = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        report
+            .results
+            .push((name, run_microtest(&rom, mode.clone(), max_frames)));
+    }
+
+    Ok(report)
+}
+
+/// Runs the same rom and input script twice in-process, in lockstep, and compares the frame and
+/// audio hashes after every frame. Catches nondeterminism (host time, iteration order,
+/// uninitialized buffers...) that would break TAS and netplay.
+/// `inputs[i]` holds the buttons pressed during frame `i`, nothing is pressed past the script.
+pub fn check_determinism(
+    rom: &[u8],
+    mode: Mode,
+    inputs: &[Vec<Button>],
+    frames: usize,
+) -> Result<(), Divergence> {
+    let cfg = Config {
+        mode,
+        rom: rom.to_vec(),
+        headless_mode: true,
+        pacing: Pacing::Uncapped,
+        ..Default::default()
+    };
+
+    let (_, rx) = mpsc::channel::<()>();
+    let make_run = || {
+        let audio = HashingPlayer::new();
+        let gb = GameBoy::new(
+            &cfg,
+            lcd::Fake,
+            audio.clone(),
+            joypad_events_handler::Fake,
+            saver::Fake,
+            &rx,
+        );
+        (gb, audio)
+    };
+
+    let (mut first, first_audio) = make_run();
+    let (mut second, second_audio) = make_run();
+
+    for frame in 0..frames {
+        let buttons = inputs.get(frame).map(Vec::as_slice).unwrap_or(&[]);
+        first.frame_advance(buttons);
+        second.frame_advance(buttons);
+
+        let hashes = (
+            frame_hash(first.frame_buffer()),
+            frame_hash(second.frame_buffer()),
+        );
+        if hashes.0 != hashes.1 {
+            return Err(Divergence {
+                frame,
+                kind: DivergenceKind::Frame,
+                hashes,
+            });
+        }
+
+        let hashes = (first_audio.hash(), second_audio.hash());
+        if hashes.0 != hashes.1 {
+            return Err(Divergence {
+                frame,
+                kind: DivergenceKind::Audio,
+                hashes,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs `rom` for `frames` frames with `script` driving the link port, returns what the rom sent
+/// back. Frame `n` of the script starts after `n` completed frames.
+pub fn run_serial_script(
+    rom: &[u8],
+    mode: Mode,
+    script: SerialScript,
+    frames: u64,
+) -> SerialTranscript {
+    let cfg = Config {
+        mode,
+        rom: rom.to_vec(),
+        headless_mode: true,
+        pacing: Pacing::Uncapped,
+        ..Default::default()
+    };
+
+    let (_, rx) = mpsc::channel::<()>();
+    let mut gb = GameBoy::new(
+        &cfg,
+        lcd::Fake,
+        crate::stereo::Fake,
+        joypad_events_handler::Fake,
+        saver::Fake,
+        &rx,
+    );
+
+    let mut driver = SerialDriver::new(script);
+    gb.attach_link_device(driver.device());
+    for frame in 0..frames {
+        driver.advance_to(frame);
+        gb.step_frame();
+    }
+    driver.transcript()
+}
+
+/// Runs `rom` for `save_at` frames, saves a state, records the next `frames` frame and audio
+/// hashes, then reloads the state, replays the same inputs and compares. Catches state the
+/// savestates miss. `inputs[i]` holds the buttons pressed during frame `save_at + i`.
+pub fn check_savestate_divergence(
+    rom: &[u8],
+    mode: Mode,
+    save_at: usize,
+    inputs: &[Vec<Button>],
+    frames: usize,
+) -> Result<(), Divergence> {
+    let cfg = Config {
+        mode,
+        rom: rom.to_vec(),
+        headless_mode: true,
+        pacing: Pacing::Uncapped,
+        ..Default::default()
+    };
+
+    let (_, rx) = mpsc::channel::<()>();
+    let audio = HashingPlayer::new();
+    let mut gb = GameBoy::new(
+        &cfg,
+        lcd::Fake,
+        audio.clone(),
+        joypad_events_handler::Fake,
+        saver::Fake,
+        &rx,
+    );
+
+    for _ in 0..save_at {
+        gb.frame_advance(&[]);
+    }
+    let state = gb.save_state();
+
+    let run = |gb: &mut GameBoy<_, _, _, _>| {
+        audio.reset();
+        (0..frames)
+            .map(|frame| {
+                let buttons = inputs.get(frame).map(Vec::as_slice).unwrap_or(&[]);
+                gb.frame_advance(buttons);
+                (frame_hash(gb.frame_buffer()), audio.hash())
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let recorded = run(&mut gb);
+    gb.load_state(&state)
+        .expect("state made by the same emulator loads");
+    let replayed = run(&mut gb);
+
+    for (frame, (first, second)) in recorded.into_iter().zip(replayed).enumerate() {
+        if first.0 != second.0 {
+            return Err(Divergence {
+                frame,
+                kind: DivergenceKind::Frame,
+                hashes: (first.0, second.0),
+            });
+        }
+        if first.1 != second.1 {
+            return Err(Divergence {
+                frame,
+                kind: DivergenceKind::Audio,
+                hashes: (first.1, second.1),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_hash() {
+        let mut frame = vec![vec![lcd::RGB_WHITE; lcd::PIXELS_WIDTH]; lcd::PIXELS_HEIGHT];
+        let white = frame_hash(&frame);
+        assert_eq!(white, frame_hash(&frame.clone()));
+
+        frame[143][159] = lcd::RGB_BLACK;
+        assert_ne!(white, frame_hash(&frame));
+    }
+
+    #[test]
+    fn test_fuzz_io_registers_random_sequences() {
+        let mut seed = 0x2545_f491_u32;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as u8
+        };
+
+        for _ in 0..16 {
+            let data: Vec<u8> = (0..1 + 3 * 64).map(|_| next()).collect();
+            fuzz_io_registers(&data);
+        }
+    }
+
+    /// Runs `rom` for a few frames and returns the work ram byte at each address
+    fn run_rom(rom: Vec<u8>, addresses: &[u16]) -> Vec<u8> {
+        let cfg = Config {
+            rom,
+            headless_mode: true,
+            pacing: Pacing::Uncapped,
+            ..Default::default()
+        };
+        let (_, rx) = mpsc::channel::<()>();
+        let mut gb = GameBoy::new(
+            &cfg,
+            lcd::Fake,
+            crate::stereo::Fake,
+            joypad_events_handler::Fake,
+            saver::Fake,
+            &rx,
+        );
+        for _ in 0..3 {
+            gb.step_frame();
+        }
+        addresses.iter().map(|&a| gb.read_memory(a)).collect()
+    }
+
+    #[test]
+    fn test_run_frames() {
+        let cfg = Config {
+            rom: idle_rom(),
+            headless_mode: true,
+            pacing: Pacing::Video,
+            ..Default::default()
+        };
+        let (_, rx) = mpsc::channel::<()>();
+        let mut gb = GameBoy::new(
+            &cfg,
+            lcd::Fake,
+            crate::stereo::Fake,
+            joypad_events_handler::Fake,
+            saver::Fake,
+            &rx,
+        );
+
+        let mut checks = 0;
+        let done = run_frames(&mut gb, 600, |_| {
+            checks += 1;
+            checks == 300
+        });
+        assert_eq!(Some(300), done);
+
+        assert_eq!(None, run_frames(&mut gb, 10, |_| false));
+    }
+
+    #[test]
+    fn test_rom_builder_header() {
+        let rom = RomBuilder::new()
+            .title("SMOKE")
+            .cartridge_type(0x01)
+            .rom_size(0x01)
+            .build();
+
+        assert_eq!(0x10000, rom.len());
+        assert_eq!(b"SMOKE", &rom[0x0134..0x0139]);
+        assert_eq!(0x01, rom[0x0147]);
+
+        let header_checksum = rom[0x0134..=0x014C]
+            .iter()
+            .fold(0u8, |c, &b| c.wrapping_sub(b).wrapping_sub(1));
+        assert_eq!(header_checksum, rom[0x014D]);
+    }
+
+    #[test]
+    fn test_rom_builder_opcodes() {
+        let mut asm = Asm::new();
+        asm.ld_r_n(R8::B, 5).ld_r_n(R8::A, 0);
+        let multiply = asm.here();
+        asm.add_n(3)
+            .dec(R8::B)
+            .jr(Some(Cond::NZ), multiply)
+            .ld_nn_a(0xC000)
+            .ld_rr_nn(R16::HL, 0xC001)
+            .xor_n(0xFF)
+            .ld_hli_a()
+            .inc(R8::B)
+            .ld_r_r(R8::HLInd, R8::B)
+            .spin();
+
+        let rom = RomBuilder::new().code(&asm).build();
+        assert_eq!(vec![15, !15, 1], run_rom(rom, &[0xC000, 0xC001, 0xC002]));
+    }
+
+    #[test]
+    fn test_rom_builder_mbc1_banks() {
+        let mut asm = Asm::new();
+        for (bank, dst) in [(2, 0xC000), (3, 0xC001)] {
+            asm.ld_r_n(R8::A, bank)
+                .ld_nn_a(0x2000)
+                .ld_a_nn(0x4000)
+                .ld_nn_a(dst);
+        }
+        asm.spin();
+
+        let rom = RomBuilder::new()
+            .cartridge_type(0x01)
+            .rom_size(0x01)
+            .code(&asm)
+            .patch(0x8000, &[0x22])
+            .patch(0xC000, &[0x33])
+            .build();
+        assert_eq!(vec![0x22, 0x33], run_rom(rom, &[0xC000, 0xC001]));
+    }
+
+    #[test]
+    fn test_run_microtest() {
+        let microtest = |result: u8| {
+            let mut asm = Asm::new();
+            asm.ld_r_n(R8::A, 0x12)
+                .ldh_n_a(0x80)
+                .ld_r_n(R8::A, 0x34)
+                .ldh_n_a(0x81)
+                .ld_r_n(R8::A, result)
+                .ldh_n_a(0x82)
+                .spin();
+            RomBuilder::new().code(&asm).build()
+        };
+
+        assert_eq!(
+            MicrotestResult::Pass,
+            run_microtest(&microtest(0x01), Mode::DMG, 5)
+        );
+        assert_eq!(
+            MicrotestResult::Fail {
+                actual: 0x12,
+                expected: 0x34
+            },
+            run_microtest(&microtest(0xFF), Mode::DMG, 5)
+        );
+        assert_eq!(
+            MicrotestResult::Timeout,
+            run_microtest(&idle_rom(), Mode::DMG, 5)
+        );
+
+        let report = MicrotestReport {
+            results: vec![
+                ("a".to_string(), MicrotestResult::Pass),
+                ("b".to_string(), MicrotestResult::Timeout),
+            ],
+        };
+        assert_eq!("TIMEOUT b\n1/2 passed", report.to_string());
+    }
+
+    #[test]
+    fn test_hashing_player() {
+        let player = HashingPlayer::new();
+        let clone = player.clone();
+
+        player.play(&[0.5, -0.5]);
+        assert_eq!(player.hash(), clone.hash());
+        assert_ne!(HashingPlayer::new().hash(), player.hash());
+    }
+}
@@ -1,4 +1,9 @@
-use crate::{interrupts::InterruptRegisters, memory::MemReadWriter};
+use crate::{
+    clock::{ClockDomain, Clocked},
+    interrupts::InterruptRegisters,
+    memory::MemReadWriter,
+    savestate::{ChunkReader, ChunkWriter, Snapshot},
+};
 
 const BIT_4: u8 = 1 << 4;
 const BIT_5: u8 = 1 << 5;
@@ -135,15 +140,41 @@ impl Timer {
         }
 
         if self.system_counter.has_ticked() {
-            let (new_tima, overflowed) = self.tima.overflowing_add(1);
-            self.tima = new_tima;
-            if overflowed {
-                self.delayed_timer = true;
-            }
+            self.increment_tima();
+        }
+    }
+
+    fn increment_tima(&mut self) {
+        let (new_tima, overflowed) = self.tima.overflowing_add(1);
+        self.tima = new_tima;
+        if overflowed {
+            self.delayed_timer = true;
+        }
+    }
+
+    /// Input of the falling edge detector: the selected system counter bit ANDed with the enable
+    /// bit
+    fn timer_input(&self) -> bool {
+        self.tac.enabled && (self.system_counter.counter >> self.tac.falling_edge_bit()) & 1 == 1
+    }
+
+    /// The edge detector sees TAC changes too: switching to a frequency whose bit is low, or
+    /// disabling the timer, while the selected bit is high increments TIMA.
+    /// https://gbdev.io/pandocs/Timer_Obscure_Behaviour.html#relation-between-timer-and-divider-register
+    fn write_tac(&mut self, value: u8) {
+        let was_high = self.timer_input();
+        self.tac.write(value);
+        if was_high && !self.timer_input() {
+            self.increment_tima();
         }
     }
 }
 
+/// DIV counts cpu cycles, twice as fast in double speed mode
+impl Clocked for Timer {
+    const DOMAIN: ClockDomain = ClockDomain::Cpu;
+}
+
 impl MemReadWriter for Timer {
     fn read_byte(&self, address: u16) -> u8 {
         match address {
@@ -159,8 +190,98 @@ impl MemReadWriter for Timer {
             0xFF04 => self.system_counter.reset(),
             0xFF05 => self.tima = value,
             0xFF06 => self.tma = value,
-            0xFF07 => self.tac.write(value),
+            0xFF07 => self.write_tac(value),
             _ => unreachable!("Timer writing address {:#04x}", address),
         }
     }
 }
+
+impl Snapshot for Timer {
+    const TAG: [u8; 4] = *b"TIMR";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, w: &mut ChunkWriter) {
+        w.write_u16(self.system_counter.counter);
+        w.write_u16(self.system_counter.prev);
+        w.write_bool(self.system_counter.ticked);
+        w.write_bool(self.system_counter.div_apu_event);
+        w.write_bool(self.delayed_timer);
+        w.write_u8(self.tima);
+        w.write_u8(self.tma);
+        w.write_u8(self.tac.read());
+    }
+
+    fn load_state(&mut self, r: &mut ChunkReader, _version: u16) {
+        self.system_counter.counter = r.read_u16();
+        self.system_counter.prev = r.read_u16();
+        self.system_counter.ticked = r.read_bool();
+        self.system_counter.div_apu_event = r.read_bool();
+        self.delayed_timer = r.read_bool();
+        self.tima = r.read_u8();
+        self.tma = r.read_u8();
+        self.tac.write(r.read_u8());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Enabled timer at 262144 Hz (bit 3) with TIMA at 0, and a system counter of `counter`
+    fn timer_at(counter: u16) -> Timer {
+        let mut timer = Timer::new();
+        timer.delayed_timer = false;
+        timer.write_byte(0xFF07, 0b101);
+        timer.system_counter.counter = counter;
+        timer.system_counter.prev = counter;
+        timer
+    }
+
+    #[test]
+    fn test_tac_frequency_change_glitch() {
+        // bit 3 high, bit 5 low: the detector input falls
+        let mut timer = timer_at(0b0000_1000);
+        timer.write_byte(0xFF07, 0b110);
+        assert_eq!(1, timer.read_byte(0xFF05));
+
+        // bit 3 high, bit 5 high: no edge
+        let mut timer = timer_at(0b0010_1000);
+        timer.write_byte(0xFF07, 0b110);
+        assert_eq!(0, timer.read_byte(0xFF05));
+
+        // bit 3 low: nothing to fall
+        let mut timer = timer_at(0b0000_0000);
+        timer.write_byte(0xFF07, 0b110);
+        assert_eq!(0, timer.read_byte(0xFF05));
+    }
+
+    #[test]
+    fn test_tac_disable_glitch() {
+        let mut timer = timer_at(0b0000_1000);
+        timer.write_byte(0xFF07, 0b001);
+        assert_eq!(1, timer.read_byte(0xFF05));
+
+        let mut timer = timer_at(0b0000_0000);
+        timer.write_byte(0xFF07, 0b001);
+        assert_eq!(0, timer.read_byte(0xFF05));
+
+        // rewriting the same value is no change
+        let mut timer = timer_at(0b0000_1000);
+        timer.write_byte(0xFF07, 0b101);
+        assert_eq!(0, timer.read_byte(0xFF05));
+    }
+
+    #[test]
+    fn test_tac_glitch_overflow_reloads() {
+        let mut timer = timer_at(0b0000_1000);
+        timer.write_byte(0xFF05, 0xFF);
+        timer.write_byte(0xFF06, 0x42);
+        timer.write_byte(0xFF07, 0b110);
+        assert_eq!(0, timer.read_byte(0xFF05));
+
+        let mut int_reg = InterruptRegisters::new();
+        timer.step(&mut int_reg, 4, false);
+        assert_eq!(0x42, timer.read_byte(0xFF05));
+        assert_eq!(0x04, int_reg.read_byte(0xFF0F) & 0x04);
+    }
+}
@@ -0,0 +1,82 @@
+//! Per-instruction cpu traces in the Gameboy Doctor format
+//! (https://github.com/robert/gameboy-doctor), to diff against reference emulators. One line per
+//! instruction, the state before it executes:
+//! `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+//!
+//! The reference logs are taken with LY stuck at 0x90, see `Config::stub_ly`.
+
+use std::io::{self, BufWriter, Write};
+
+use crate::registers::Registers;
+
+/// LY value the reference logs are taken with
+pub const DOCTOR_LY: u8 = 0x90;
+
+/// Trace line of the state before executing the instruction at `pc`
+pub fn doctor_line<M: Fn(u16) -> u8>(registers: &Registers, sp: u16, pc: u16, read: M) -> String {
+    let [m0, m1, m2, m3] = [0, 1, 2, 3].map(|i| read(pc.wrapping_add(i)));
+    format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{sp:04X} PC:{pc:04X} PCMEM:{m0:02X},{m1:02X},{m2:02X},{m3:02X}",
+        registers.a(),
+        registers.f(),
+        registers.b(),
+        registers.c(),
+        registers.d(),
+        registers.e(),
+        registers.h(),
+        registers.l(),
+    )
+}
+
+/// Buffered trace output, owned by `GameBoy`
+pub(crate) struct CpuTrace {
+    out: BufWriter<Box<dyn Write>>,
+}
+
+impl CpuTrace {
+    pub(crate) fn new(out: Box<dyn Write>) -> Self {
+        Self {
+            out: BufWriter::new(out),
+        }
+    }
+
+    pub(crate) fn record<M: Fn(u16) -> u8>(
+        &mut self,
+        registers: &Registers,
+        sp: u16,
+        pc: u16,
+        read: M,
+    ) -> io::Result<()> {
+        writeln!(self.out, "{}", doctor_line(registers, sp, pc, read))
+    }
+
+    /// `name:` line before the instructions of a label, see `GameBoy::set_cpu_trace_labels`
+    pub(crate) fn record_label(&mut self, name: &str) -> io::Result<()> {
+        writeln!(self.out, "{name}:")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mode::Model;
+
+    use super::*;
+
+    #[test]
+    fn test_doctor_line() {
+        let registers = Registers::new_post_boot(Model::DMG);
+        let rom = [0x00, 0xC3, 0x13, 0x02];
+        assert_eq!(
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02",
+            doctor_line(&registers, 0xFFFE, 0x0100, |address| rom
+                [address as usize - 0x0100])
+        );
+
+        // PCMEM wraps around the address space
+        let line = doctor_line(&registers, 0xDFF0, 0xFFFE, |address| address as u8);
+        assert!(
+            line.ends_with("SP:DFF0 PC:FFFE PCMEM:FE,FF,00,01"),
+            "{line}"
+        );
+    }
+}
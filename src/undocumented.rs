@@ -0,0 +1,107 @@
+use crate::{
+    memory::MemReadWriter,
+    mode::Mode,
+    savestate::{ChunkReader, ChunkWriter, Snapshot},
+};
+
+/// CGB-only undocumented registers, unmapped on DMG.
+/// PCM12/PCM34 (FF76/FF77) expose the APU channels outputs and are read by the bus.
+/// https://gbdev.io/pandocs/Hardware_Reg_List.html#undocumented-registers
+#[derive(Clone)]
+pub struct UndocumentedRegisters {
+    mode: Mode,
+    /// FF72-FF74, fully read/write
+    scratch: [u8; 3],
+    /// FF75, only bits 4-6 are read/write
+    ff75: u8,
+}
+
+impl UndocumentedRegisters {
+    pub fn new(mode: Mode) -> Self {
+        Self {
+            mode,
+            scratch: [0; 3],
+            ff75: 0,
+        }
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.mode, Mode::CGB)
+    }
+}
+
+impl MemReadWriter for UndocumentedRegisters {
+    fn read_byte(&self, address: u16) -> u8 {
+        if !self.is_mapped() {
+            return 0xFF;
+        }
+
+        match address {
+            0xFF72..=0xFF74 => self.scratch[(address - 0xFF72) as usize],
+            0xFF75 => self.ff75 | 0b1000_1111,
+            // PCM12/PCM34 are served by the bus
+            0xFF76..=0xFF77 => 0x00,
+            _ => unreachable!("Undocumented registers reading address {:#04x}", address),
+        }
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) {
+        if !self.is_mapped() {
+            return;
+        }
+
+        match address {
+            0xFF72..=0xFF74 => self.scratch[(address - 0xFF72) as usize] = value,
+            0xFF75 => self.ff75 = value & 0b0111_0000,
+            // read only
+            0xFF76..=0xFF77 => {}
+            _ => unreachable!("Undocumented registers writing address {:#04x}", address),
+        }
+    }
+}
+
+impl Snapshot for UndocumentedRegisters {
+    const TAG: [u8; 4] = *b"UNDC";
+    const VERSION: u16 = 1;
+
+    fn save_state(&self, w: &mut ChunkWriter) {
+        w.write_bytes(&self.scratch);
+        w.write_u8(self.ff75);
+    }
+
+    fn load_state(&mut self, r: &mut ChunkReader, _version: u16) {
+        r.read_into(&mut self.scratch);
+        self.ff75 = r.read_u8();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cgb_undocumented_registers() {
+        let mut regs = UndocumentedRegisters::new(Mode::CGB);
+
+        for address in 0xFF72..=0xFF74 {
+            regs.write_byte(address, 0x5A);
+            assert_eq!(0x5A, regs.read_byte(address));
+        }
+
+        assert_eq!(0x8F, regs.read_byte(0xFF75));
+        regs.write_byte(0xFF75, 0xFF);
+        assert_eq!(0xFF, regs.read_byte(0xFF75));
+        regs.write_byte(0xFF75, 0x00);
+        assert_eq!(0x8F, regs.read_byte(0xFF75));
+    }
+
+    #[test]
+    fn test_dmg_undocumented_registers_unmapped() {
+        let mut regs = UndocumentedRegisters::new(Mode::DMG);
+
+        for address in 0xFF72..=0xFF77 {
+            regs.write_byte(address, 0x00);
+            assert_eq!(0xFF, regs.read_byte(address));
+        }
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{memory::MemReadWriter, mode::Mode};
+use crate::{
+    memory::MemReadWriter,
+    mode::Mode,
+    savestate::{ChunkReader, ChunkWriter},
+};
 
 pub const BANK_REGISTER: u16 = 0xFF4F;
 
@@ -33,6 +37,17 @@ impl VRAM {
     pub fn read_at_bank(&self, address: u16, bank: u8) -> u8 {
         self.mem[compute_address_from_bank(address, bank)]
     }
+
+    /// Stored in the PPU savestate chunk
+    pub(crate) fn save(&self, w: &mut ChunkWriter) {
+        w.write_bytes(&self.mem);
+        w.write_u8(self.bank);
+    }
+
+    pub(crate) fn load(&mut self, r: &mut ChunkReader) {
+        r.read_into(&mut self.mem);
+        self.bank = r.read_u8() & 1;
+    }
 }
 
 fn compute_address_from_bank(address: u16, bank: u8) -> usize {
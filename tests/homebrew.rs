@@ -0,0 +1,1284 @@
+//! Smoke tests on roms assembled at test time with `gamuboy::testing`, so `cargo test` passes
+//! without any third party rom. The blargg/mooneye/... suites in `test.rs` need the `rom-tests`
+//! feature.
+
+use std::{
+    cell::{Cell, RefCell},
+    io::{self, Write},
+    rc::Rc,
+    sync::mpsc::channel,
+};
+
+use gamuboy::{
+    cheats::Cheat,
+    config::Config,
+    debug::RomCoverage,
+    debugger::{StopReason, WatchAction, WatchHit, WatchKind},
+    events::Event,
+    expr::Expr,
+    gameboy::{GameBoy, HeadlessGameBoy},
+    joypad_events_handler,
+    lcd::{self, DmgPalette, RGB_BLACK, RGB_WHITE},
+    link,
+    mode::{Mode, Model},
+    pacing::{BackgroundPolicy, Pacing},
+    saver::{self, FaultInjectingSaver, GameSave, SaveFault},
+    savestate::SaveStateError,
+    scripting::{ExprScript, Script, ScriptApi},
+    serial_script::SerialScript,
+    stereo,
+    symbols::SymbolTable,
+    testing::{self, Asm, Cond, RomBuilder, R16, R8},
+    trace,
+};
+
+/// Runs `rom` for `frames` frames then hands the console to `check`
+fn run(rom: Vec<u8>, frames: usize, check: impl FnOnce(&mut HeadlessGameBoy)) {
+    let (_, rx) = channel();
+    let cfg = Config {
+        rom,
+        ..Default::default()
+    };
+    let mut gb = HeadlessGameBoy::headless(&cfg, &rx);
+    for _ in 0..frames {
+        gb.step_frame();
+    }
+    check(&mut gb);
+}
+
+/// push af; pop bc; ld a,c: moves the flags to A
+fn flags_to_a(asm: &mut Asm) -> &mut Asm {
+    asm.db(&[0xF5, 0xC1]).ld_r_r(R8::A, R8::C)
+}
+
+#[test]
+fn test_cpu_flags() {
+    const SCF: u8 = 0x37;
+
+    // (setup, expected A, expected F)
+    let cases: [(fn(&mut Asm), u8, u8); 9] = [
+        (|a| _ = a.ld_r_n(R8::A, 0x0F).add_n(0x01), 0x10, 0x20),
+        (|a| _ = a.ld_r_n(R8::A, 0xFF).add_n(0x01), 0x00, 0xB0),
+        (|a| _ = a.ld_r_n(R8::A, 0x00).sub_n(0x01), 0xFF, 0x70),
+        (|a| _ = a.ld_r_n(R8::A, 0x42).cp_n(0x42), 0x42, 0xC0),
+        (|a| _ = a.ld_r_n(R8::A, 0xF0).and_n(0x0F), 0x00, 0xA0),
+        (|a| _ = a.ld_r_n(R8::A, 0x55).xor_n(0x55), 0x00, 0x80),
+        (|a| _ = a.ld_r_n(R8::A, 0x00).or_n(0x01), 0x01, 0x00),
+        // inc/dec leave the carry alone
+        (
+            |a| _ = a.ld_r_n(R8::A, 0xFF).db(&[SCF]).inc(R8::A),
+            0x00,
+            0xB0,
+        ),
+        (|a| _ = a.ld_r_n(R8::A, 0x10).or_n(0).dec(R8::A), 0x0F, 0x60),
+    ];
+
+    let mut asm = Asm::new();
+    for (i, (setup, _, _)) in cases.iter().enumerate() {
+        let address = 0xC000 + 2 * i as u16;
+        setup(&mut asm);
+        asm.ld_nn_a(address);
+        flags_to_a(&mut asm).ld_nn_a(address + 1);
+    }
+    asm.spin();
+
+    run(RomBuilder::new().code(&asm).build(), 1, |gb| {
+        for (i, &(_, a, f)) in cases.iter().enumerate() {
+            let address = 0xC000 + 2 * i as u16;
+            assert_eq!(
+                (a, f),
+                (gb.read_memory(address), gb.read_memory(address + 1)),
+                "case {i}"
+            );
+        }
+    });
+}
+
+#[test]
+fn test_mbc1_banking() {
+    // 1 MiB rom, every bank starts with its number
+    let mut rom = RomBuilder::new()
+        .cartridge_type(0x02)
+        .rom_size(0x05)
+        .ram_size(0x02);
+    for bank in 1..64u8 {
+        rom = rom.patch(bank as usize * 0x4000, &[bank]);
+    }
+
+    // (upper bits, lower bits) written before reading the bank marker at 0x4000
+    let selections = [(0, 1), (0, 2), (0, 0x1F), (0, 0), (1, 1), (1, 0), (3, 0x1F)];
+    let expected = [1, 2, 0x1F, 1, 0x21, 0x21, 0x3F];
+
+    let mut asm = Asm::new();
+    for (i, &(upper, lower)) in selections.iter().enumerate() {
+        asm.ld_r_n(R8::A, upper)
+            .ld_nn_a(0x4000)
+            .ld_r_n(R8::A, lower)
+            .ld_nn_a(0x2000)
+            .ld_a_nn(0x4000)
+            .ld_nn_a(0xC000 + i as u16);
+    }
+    // external ram only answers once enabled
+    asm.ld_r_n(R8::A, 0x99)
+        .ld_nn_a(0xA000)
+        .ld_a_nn(0xA000)
+        .ld_nn_a(0xC020)
+        .ld_r_n(R8::A, 0x0A)
+        .ld_nn_a(0x0000)
+        .ld_r_n(R8::A, 0x99)
+        .ld_nn_a(0xA000)
+        .ld_a_nn(0xA000)
+        .ld_nn_a(0xC021)
+        .spin();
+
+    run(rom.code(&asm).build(), 1, |gb| {
+        for (i, &bank) in expected.iter().enumerate() {
+            assert_eq!(
+                bank,
+                gb.read_memory(0xC000 + i as u16),
+                "{:?}",
+                selections[i]
+            );
+        }
+        assert_eq!(0xFF, gb.read_memory(0xC020));
+        assert_eq!(0x99, gb.read_memory(0xC021));
+
+        // advanced banking mode maps the upper bits to 0x0000-0x3FFF too, switched from here
+        // since the code runs from there
+        gb.write_memory(0x4000, 1);
+        gb.write_memory(0x6000, 1);
+        assert_eq!(0x20, gb.read_memory(0x0000));
+    });
+}
+
+/// Draws a black first map row over a white background, then turns the lcd on
+fn background(asm: &mut Asm) -> &mut Asm {
+    // wait for vblank before turning the lcd off
+    let wait = asm.here();
+    asm.ldh_a_n(0x44).cp_n(144).jr(Some(Cond::NZ), wait);
+    asm.ld_r_n(R8::A, 0x00).ldh_n_a(0x40);
+
+    // tile 0 is all color 0 and tile 1 all color 3
+    asm.ld_rr_nn(R16::HL, 0x8000).ld_r_n(R8::B, 16);
+    let clear_tile = asm.here();
+    asm.ld_r_n(R8::A, 0x00)
+        .ld_hli_a()
+        .dec(R8::B)
+        .jr(Some(Cond::NZ), clear_tile);
+    asm.ld_r_n(R8::B, 16);
+    let fill_tile = asm.here();
+    asm.ld_r_n(R8::A, 0xFF)
+        .ld_hli_a()
+        .dec(R8::B)
+        .jr(Some(Cond::NZ), fill_tile);
+
+    // the first map row uses tile 1, the rest of the 32x32 map tile 0
+    asm.ld_rr_nn(R16::HL, 0x9800)
+        .ld_r_n(R8::C, 4)
+        .ld_r_n(R8::B, 0);
+    let clear_map = asm.here();
+    asm.ld_r_n(R8::A, 0x00)
+        .ld_hli_a()
+        .dec(R8::B)
+        .jr(Some(Cond::NZ), clear_map)
+        .dec(R8::C)
+        .jr(Some(Cond::NZ), clear_map);
+    asm.ld_rr_nn(R16::HL, 0x9800).ld_r_n(R8::B, 32);
+    let fill_row = asm.here();
+    asm.ld_r_n(R8::A, 0x01)
+        .ld_hli_a()
+        .dec(R8::B)
+        .jr(Some(Cond::NZ), fill_row);
+
+    asm.ld_r_n(R8::A, 0xE4)
+        .ldh_n_a(0x47)
+        .ld_r_n(R8::A, 0x00)
+        .ldh_n_a(0x42)
+        .ldh_n_a(0x43);
+    // lcd on, bg tiles at 0x8000, map at 0x9800
+    asm.ld_r_n(R8::A, 0x91).ldh_n_a(0x40)
+}
+
+#[test]
+fn test_ppu_background() {
+    let mut asm = Asm::new();
+    background(&mut asm).spin();
+
+    run(RomBuilder::new().code(&asm).build(), 3, |gb| {
+        let frame = gb.frame_buffer();
+        for (y, line) in frame.iter().enumerate() {
+            let expected = if y < 8 { RGB_BLACK } else { RGB_WHITE };
+            assert!(line.iter().all(|&pixel| pixel == expected), "line {y}");
+        }
+    });
+}
+
+#[test]
+fn test_run_frame() {
+    let mut asm = Asm::new();
+    background(&mut asm).spin();
+
+    run(RomBuilder::new().code(&asm).build(), 0, |gb| {
+        // the lcd is turned on during the first frame
+        gb.run_frame();
+        gb.run_frame();
+        let frame = gb.run_frame().clone();
+        assert_eq!(RGB_BLACK, frame[0][0]);
+        assert_eq!(
+            RGB_WHITE,
+            frame[lcd::PIXELS_HEIGHT - 1][lcd::PIXELS_WIDTH - 1]
+        );
+        assert_eq!(&frame, gb.frame_buffer());
+    });
+}
+
+#[test]
+fn test_frame_metadata() {
+    let mut asm = Asm::new();
+    background(&mut asm).spin();
+    run(RomBuilder::new().code(&asm).build(), 3, |gb| {
+        let frame = gb.frame_buffer();
+        assert_eq!(3, frame.index);
+        assert_eq!(gamuboy::pacing::frames_duration(3), frame.timestamp);
+        assert_eq!(
+            (lcd::PIXELS_WIDTH, lcd::PIXELS_HEIGHT),
+            (frame.width(), frame.height())
+        );
+        // the background was drawn once, the screen no longer changes
+        assert!(!frame.dirty);
+    });
+
+    run(animated_rom(), 10, |gb| {
+        assert_eq!(10, gb.frame_buffer().index);
+        assert!(gb.frame_buffer().dirty);
+    });
+}
+
+/// Scrolls the background, counts frames in work ram and retriggers a tone every frame
+fn animated_rom() -> Vec<u8> {
+    let mut asm = Asm::new();
+    background(&mut asm);
+
+    // sound on, square channel 1 on both sides at full volume
+    asm.ld_r_n(R8::A, 0x80)
+        .ldh_n_a(0x26)
+        .ld_r_n(R8::A, 0x77)
+        .ldh_n_a(0x24)
+        .ld_r_n(R8::A, 0xFF)
+        .ldh_n_a(0x25)
+        .ld_r_n(R8::A, 0xF3)
+        .ldh_n_a(0x12);
+
+    let frame = asm.here();
+    let wait_vblank = asm.here();
+    asm.ldh_a_n(0x44).cp_n(144).jr(Some(Cond::NZ), wait_vblank);
+    asm.ldh_a_n(0x42).inc(R8::A).ldh_n_a(0x42);
+    asm.ld_a_nn(0xC000)
+        .inc(R8::A)
+        .ld_nn_a(0xC000)
+        .ldh_n_a(0x13)
+        .ld_r_n(R8::A, 0x87)
+        .ldh_n_a(0x14);
+    let wait_next_line = asm.here();
+    asm.ldh_a_n(0x44)
+        .cp_n(144)
+        .jr(Some(Cond::Z), wait_next_line);
+    asm.jr(None, frame);
+
+    RomBuilder::new().code(&asm).build()
+}
+
+#[test]
+fn test_savestate_replays_identically() {
+    if let Err(divergence) =
+        testing::check_savestate_divergence(&animated_rom(), Mode::DMG, 30, &[], 60)
+    {
+        panic!("{divergence}");
+    }
+}
+
+#[test]
+fn test_savestate_restores_machine() {
+    let rom = animated_rom();
+    run(rom.clone(), 10, |gb| {
+        let state = gb.save_state();
+        let frame = gb.frame_buffer().clone();
+        let counter = gb.read_memory(0xC000);
+        assert_ne!(0, counter);
+
+        for _ in 0..5 {
+            gb.step_frame();
+        }
+        assert_ne!(counter, gb.read_memory(0xC000));
+
+        gb.load_state(&state).unwrap();
+        assert_eq!(counter, gb.read_memory(0xC000));
+        assert_eq!(&frame, gb.frame_buffer());
+
+        // a failed load leaves the machine as is
+        gb.step_frame();
+        let counter = gb.read_memory(0xC000);
+        assert_eq!(
+            Err(SaveStateError::Truncated),
+            gb.load_state(&state[..state.len() - 1])
+        );
+        assert_eq!(counter, gb.read_memory(0xC000));
+
+        // nor does a field of the wrong size, found once the subsystems before it are loaded
+        // ppu payload: dots, frame, frame ready, vram...
+        let ppu = state.windows(4).position(|tag| tag == b"PPU ").unwrap() + 10;
+        let frame_len = u32::from_le_bytes(state[ppu + 4..ppu + 8].try_into().unwrap());
+        let vram_len = ppu + 8 + frame_len as usize + 1;
+        let mut corrupt = state.clone();
+        corrupt[vram_len] ^= 1;
+        let before = gb.save_state();
+        assert_eq!(
+            Err(SaveStateError::Corrupt(*b"PPU ")),
+            gb.load_state(&corrupt)
+        );
+        assert!(before == gb.save_state());
+    });
+
+    // states only load on the rom they were made with
+    let mut other_state = vec![];
+    run(testing::idle_rom(), 1, |gb| other_state = gb.save_state());
+    run(rom, 1, |gb| {
+        assert!(matches!(
+            gb.load_state(&other_state),
+            Err(SaveStateError::RomMismatch(..))
+        ));
+    });
+}
+
+#[test]
+fn test_savestate_resets_blank_screen_diagnostic() {
+    run(animated_rom(), 10, |gb| {
+        let state = gb.save_state();
+        for _ in 0..5 {
+            gb.step_frame();
+        }
+        assert!(gb.diagnose_blank_screen().frames_since_change.is_some());
+
+        gb.load_state(&state).unwrap();
+        let report = gb.diagnose_blank_screen();
+        assert_eq!(None, report.frames_since_change);
+        assert_eq!(10, report.frame);
+    });
+}
+
+/// Waits on a byte clocked by the peer, then sends it back incremented with its own clock
+fn serial_echo_rom() -> Vec<u8> {
+    let mut asm = Asm::new();
+    let receive = asm.here();
+    asm.ld_r_n(R8::A, 0x80).ldh_n_a(0x02);
+    let wait_receive = asm.here();
+    asm.ldh_a_n(0x02)
+        .and_n(0x80)
+        .jr(Some(Cond::NZ), wait_receive);
+    asm.ldh_a_n(0x01)
+        .inc(R8::A)
+        .ldh_n_a(0x01)
+        .ld_r_n(R8::A, 0x81)
+        .ldh_n_a(0x02);
+    let wait_send = asm.here();
+    asm.ldh_a_n(0x02)
+        .and_n(0x80)
+        .jr(Some(Cond::NZ), wait_send)
+        .jr(None, receive);
+    RomBuilder::new().code(&asm).build()
+}
+
+#[test]
+fn test_models() {
+    // inc hl / dec hl pointing into OAM all along
+    let mut asm = Asm::new();
+    asm.ld_rr_nn(R16::HL, 0xFE10);
+    let spin = asm.here();
+    asm.inc_rr(R16::HL).dec_rr(R16::HL).jr(None, spin);
+    let rom = RomBuilder::new().code(&asm).build();
+
+    for (mode, model, a, corrupts) in [
+        (Mode::DMG, None, 0x01, true),
+        (Mode::DMG, Some(Model::DMG0), 0x01, true),
+        (Mode::DMG, Some(Model::MGB), 0xFF, true),
+        (Mode::CGB, Some(Model::CGB0), 0x11, false),
+        // mismatched, runs as the usual CGB
+        (Mode::CGB, Some(Model::DMG), 0x11, false),
+    ] {
+        let (_, rx) = channel();
+        let cfg = Config {
+            rom: rom.clone(),
+            mode,
+            model,
+            ..Default::default()
+        };
+        let mut gb: HeadlessGameBoy = GameBoy::new(
+            &cfg,
+            lcd::Fake,
+            stereo::Fake,
+            joypad_events_handler::Fake,
+            saver::Fake,
+            &rx,
+        );
+        assert_eq!(a, gb.registers().a(), "{model:?}");
+        assert_eq!(0xFFFE, gb.sp(), "{model:?}");
+        assert_eq!(corrupts, gb.model().is_monochrome(), "{model:?}");
+
+        for i in 0..0xA0 {
+            gb.write_memory(0xFE00 + i, i as u8);
+        }
+        gb.step_frame();
+        let intact = (0..0xA0).all(|i| gb.read_memory(0xFE00 + i) == i as u8);
+        assert_eq!(corrupts, !intact, "{model:?}");
+    }
+}
+
+#[test]
+fn test_serial_script_echo() {
+    let script = SerialScript::new().send_at(2, &[1, 2, 3]).send_at(10, b"A");
+    let transcript = testing::run_serial_script(&serial_echo_rom(), Mode::DMG, script, 20);
+
+    assert_eq!(vec![2, 3, 4, b'B'], transcript.bytes());
+    assert!(transcript.responses[..3]
+        .iter()
+        .all(|&(frame, _)| (2..10).contains(&frame)));
+    assert!(transcript.responses[3].0 >= 10);
+}
+
+#[test]
+fn test_serial_injection() {
+    run(serial_echo_rom(), 1, |gb| {
+        let transfers = Rc::new(RefCell::new(vec![]));
+        let log = transfers.clone();
+        gb.set_serial_transfer_callback(Some(Box::new(move |t| log.borrow_mut().push(t))));
+        for byte in [1, 2, 3] {
+            gb.inject_serial_byte(byte);
+        }
+        assert_eq!(3, gb.pending_serial_bytes());
+        for _ in 0..2 {
+            gb.step_frame();
+        }
+        assert_eq!(0, gb.pending_serial_bytes());
+
+        let transfers = transfers.borrow();
+        let received: Vec<_> = transfers
+            .iter()
+            .filter(|t| !t.internal_clock)
+            .map(|t| t.received)
+            .collect();
+        let sent: Vec<_> = transfers
+            .iter()
+            .filter(|t| t.internal_clock)
+            .map(|t| t.sent)
+            .collect();
+        assert_eq!(vec![1, 2, 3], received);
+        assert_eq!(vec![2, 3, 4], sent);
+    });
+}
+
+#[test]
+fn test_link_cable_between_instances() {
+    // master: sends 0x42 until the slave answers, keeps the answer at C000
+    let mut master = Asm::new();
+    let send = master.here();
+    master
+        .ld_r_n(R8::A, 0x42)
+        .ldh_n_a(0x01)
+        .ld_r_n(R8::A, 0x81)
+        .ldh_n_a(0x02);
+    let wait_send = master.here();
+    master
+        .ldh_a_n(0x02)
+        .and_n(0x80)
+        .jr(Some(Cond::NZ), wait_send)
+        .ldh_a_n(0x01)
+        .cp_n(0xFF)
+        .jr(Some(Cond::Z), send)
+        .ld_nn_a(0xC000)
+        .spin();
+
+    // slave: waits on the external clock with 0x24, keeps the byte received at C000
+    let mut slave = Asm::new();
+    slave
+        .ld_r_n(R8::A, 0x24)
+        .ldh_n_a(0x01)
+        .ld_r_n(R8::A, 0x80)
+        .ldh_n_a(0x02);
+    let wait_receive = slave.here();
+    slave
+        .ldh_a_n(0x02)
+        .and_n(0x80)
+        .jr(Some(Cond::NZ), wait_receive)
+        .ldh_a_n(0x01)
+        .ld_nn_a(0xC000)
+        .spin();
+
+    let (_, rx) = channel();
+    let new_gameboy = |asm: &Asm| {
+        let cfg = Config {
+            rom: RomBuilder::new().code(asm).build(),
+            ..Default::default()
+        };
+        GameBoy::new(
+            &cfg,
+            lcd::Fake,
+            stereo::Fake,
+            joypad_events_handler::Fake,
+            saver::Fake,
+            &rx,
+        )
+    };
+    let mut master: HeadlessGameBoy = new_gameboy(&master);
+    let mut slave: HeadlessGameBoy = new_gameboy(&slave);
+    let (master_end, slave_end) = link::cable();
+    master.attach_link_device(master_end);
+    slave.attach_link_device(slave_end);
+
+    for _ in 0..5 {
+        master.step_frame();
+        slave.step_frame();
+    }
+
+    assert_eq!(0x24, master.read_memory(0xC000));
+    assert_eq!(0x42, slave.read_memory(0xC000));
+}
+
+#[test]
+fn test_pause_and_frame_advance() {
+    // turns the LCD off, then keeps incrementing C000
+    let mut asm = Asm::new();
+    asm.ld_r_n(R8::A, 0x00).ldh_n_a(0x40);
+    let count = asm.here();
+    asm.ld_a_nn(0xC000)
+        .inc(R8::A)
+        .ld_nn_a(0xC000)
+        .jr(None, count);
+
+    run(RomBuilder::new().code(&asm).build(), 1, |gb| {
+        gb.pause();
+        let counter = gb.read_memory(0xC000);
+        for _ in 0..10_000 {
+            gb.step();
+        }
+        assert_eq!(counter, gb.read_memory(0xC000));
+
+        // frames keep coming with the LCD off
+        let frames = gb.perf_stats().frames;
+        gb.step_frame();
+        assert!(gb.is_paused());
+        assert_eq!(frames + 1, gb.perf_stats().frames);
+        assert_ne!(counter, gb.read_memory(0xC000));
+
+        gb.resume();
+        let counter = gb.read_memory(0xC000);
+        for _ in 0..100 {
+            gb.step();
+        }
+        assert_ne!(counter, gb.read_memory(0xC000));
+    });
+}
+
+#[test]
+fn test_debugger_breakpoints_and_steps() {
+    // ld a,1; call sub; ld a,3; spin; sub: inc a; ret
+    let build = |sub: u16| {
+        let mut asm = Asm::new();
+        asm.ld_r_n(R8::A, 1);
+        let call = asm.here();
+        asm.call(sub);
+        let after = asm.here();
+        asm.ld_r_n(R8::A, 3).spin();
+        let sub = asm.here();
+        asm.inc(R8::A).ret();
+        (RomBuilder::new().code(&asm).build(), call, after, sub)
+    };
+    let (_, _, _, sub) = build(0);
+    let (rom, call, after, sub) = build(sub);
+
+    let (_, rx) = channel();
+    let cfg = Config {
+        rom,
+        ..Default::default()
+    };
+    let new_gameboy = || -> HeadlessGameBoy {
+        let mut gb = GameBoy::new(
+            &cfg,
+            lcd::Fake,
+            stereo::Fake,
+            joypad_events_handler::Fake,
+            saver::Fake,
+            &rx,
+        );
+        gb.debugger_mut().add_breakpoint(call);
+        assert_eq!(StopReason::Breakpoint { pc: call }, gb.run_until_break(10));
+        gb
+    };
+
+    let mut gb = new_gameboy();
+    assert_eq!(StopReason::Step, gb.step_over());
+    assert_eq!(after, gb.pc());
+    assert_eq!(2, gb.registers().a());
+
+    let mut gb = new_gameboy();
+    assert_eq!(StopReason::Step, gb.step_into());
+    assert_eq!(sub, gb.pc());
+    // resuming from a breakpoint doesn't hit it again
+    gb.debugger_mut().add_breakpoint(sub);
+    assert_eq!(StopReason::FrameLimit, gb.run_until_break(2));
+
+    // breakpoints inside the stepped over subroutine stop it
+    let mut gb = new_gameboy();
+    gb.debugger_mut().add_breakpoint(sub);
+    assert_eq!(StopReason::Breakpoint { pc: sub }, gb.step_over());
+}
+
+#[test]
+fn test_watchpoints() {
+    run(animated_rom(), 1, |gb| {
+        let counter = gb.read_memory(0xC000);
+        let id = gb.add_watchpoint(0xC000..=0xC000, WatchKind::Write, WatchAction::Break);
+        let StopReason::Watchpoint(hit) = gb.run_until_break(2) else {
+            panic!("the frame counter wasn't written");
+        };
+        assert_eq!(
+            WatchHit {
+                id,
+                address: 0xC000,
+                value: counter.wrapping_add(1),
+                write: true
+            },
+            hit
+        );
+        assert_eq!(hit.value, gb.read_memory(0xC000));
+
+        // a free running loop pauses on the next hit
+        for _ in 0..2 * 70224 {
+            if gb.is_paused() {
+                break;
+            }
+            gb.step();
+        }
+        assert!(gb.is_paused());
+        let hit = gb.take_watch_break().unwrap();
+        assert_eq!(counter.wrapping_add(2), hit.value);
+        gb.resume();
+
+        let reads = Rc::new(Cell::new(0));
+        let count = reads.clone();
+        gb.remove_watchpoint(id);
+        gb.add_watchpoint(
+            0xC000..=0xC0FF,
+            WatchKind::Read,
+            WatchAction::Callback(Box::new(move |_| count.set(count.get() + 1))),
+        );
+        for _ in 0..5 {
+            gb.step_frame();
+        }
+        assert!(!gb.is_paused());
+        assert_eq!(None, gb.take_watch_break());
+        // one read of the counter per frame
+        assert!((4..=6).contains(&reads.get()), "{} reads", reads.get());
+    });
+}
+
+#[test]
+fn test_ram_cheats_skip_watchpoints() {
+    run(animated_rom(), 1, |gb| {
+        gb.add_cheat(Cheat::Ram {
+            address: 0xC100,
+            value: 0x42,
+        });
+        gb.add_watchpoint(0xC100..=0xC100, WatchKind::Write, WatchAction::Break);
+        assert_eq!(StopReason::FrameLimit, gb.run_until_break(2));
+        assert_eq!(0x42, gb.read_memory(0xC100));
+    });
+}
+
+#[test]
+fn test_debug_messages_skip_watchpoints() {
+    let mut asm = Asm::new();
+    let message = b"hi";
+    asm.db(&[0x52, 0x18, 4 + message.len() as u8, 0x64, 0x64, 0x00, 0x00]);
+    let start = asm.here();
+    asm.db(message).spin();
+    let (_, rx) = channel();
+    let cfg = Config {
+        rom: RomBuilder::new().code(&asm).build(),
+        debug_messages: true,
+        ..Default::default()
+    };
+    let mut gb = HeadlessGameBoy::headless(&cfg, &rx);
+
+    let reads = Rc::new(Cell::new(0));
+    let count = reads.clone();
+    gb.add_watchpoint(
+        start..=start + message.len() as u16 - 1,
+        WatchKind::Read,
+        WatchAction::Callback(Box::new(move |_| count.set(count.get() + 1))),
+    );
+    gb.step_frame();
+    assert_eq!(vec![Event::DebugMessage("hi".into())], gb.drain_events());
+    assert_eq!(0, reads.get());
+}
+
+#[test]
+fn test_conditional_debugging() {
+    run(animated_rom(), 1, |gb| {
+        let counter = gb.read_memory(0xC000);
+        let target = counter.wrapping_add(3);
+        let condition = Expr::parse(&format!("VALUE == {target}")).unwrap();
+        let id = gb.add_conditional_watchpoint(
+            0xC000..=0xC000,
+            WatchKind::Write,
+            WatchAction::Break,
+            condition,
+        );
+        let StopReason::Watchpoint(hit) = gb.run_until_break(5) else {
+            panic!("the counter didn't reach {target}");
+        };
+        assert_eq!(id, hit.id);
+        assert_eq!(target, hit.value);
+        gb.remove_watchpoint(id);
+
+        // break on the instruction after the write once the counter went 2 further
+        let pc = gb.pc();
+        let condition = Expr::parse(&format!("[0xC000] == {}", target.wrapping_add(2))).unwrap();
+        gb.debugger_mut().add_conditional_breakpoint(pc, condition);
+        assert_eq!(StopReason::Breakpoint { pc }, gb.run_until_break(5));
+        assert_eq!(target.wrapping_add(2), gb.read_memory(0xC000));
+    });
+}
+
+/// Keeps what is written in a buffer shared with the test
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_gameboy_doctor_trace() {
+    let mut asm = Asm::new();
+    asm.ldh_a_n(0x44).ld_nn_a(0xC000).spin();
+    let (_, rx) = channel();
+    let cfg = Config {
+        rom: RomBuilder::new().code(&asm).build(),
+        stub_ly: true,
+        ..Default::default()
+    };
+    let mut gb: HeadlessGameBoy = GameBoy::new(
+        &cfg,
+        lcd::Fake,
+        stereo::Fake,
+        joypad_events_handler::Fake,
+        saver::Fake,
+        &rx,
+    );
+    let buffer = SharedBuffer::default();
+    gb.set_cpu_trace(Some(Box::new(buffer.clone())));
+    for _ in 0..5 {
+        gb.step_into();
+    }
+    gb.set_cpu_trace(None);
+
+    let trace = String::from_utf8(buffer.0.take()).unwrap();
+    assert_eq!(
+        vec![
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,50,01",
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:C3,50,01,CE",
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0150 PCMEM:F0,44,EA,00",
+            // LY stubbed
+            "A:90 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0152 PCMEM:EA,00,C0,18",
+            "A:90 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0155 PCMEM:18,FE,00,00",
+        ],
+        trace.lines().collect::<Vec<_>>()
+    );
+    assert_eq!(trace::DOCTOR_LY, gb.read_memory(0xC000));
+}
+
+/// Battery save loaded as is
+struct SavedRam(Vec<u8>);
+
+impl GameSave for SavedRam {
+    fn load(&self) -> io::Result<Vec<u8>> {
+        Ok(self.0.clone())
+    }
+}
+
+#[test]
+fn test_corrupted_saves() {
+    // MBC1+RAM+BATTERY with 8 KiB, copies the first saved byte to C000
+    let mut asm = Asm::new();
+    asm.ld_r_n(R8::A, 0x0A)
+        .ld_nn_a(0x0000)
+        .ld_a_nn(0xA000)
+        .ld_nn_a(0xC000)
+        .spin();
+    let rom = RomBuilder::new()
+        .cartridge_type(0x03)
+        .ram_size(0x02)
+        .code(&asm)
+        .build();
+
+    let boot = |faults: Vec<SaveFault>| {
+        let (_, rx) = channel();
+        let cfg = Config {
+            rom: rom.clone(),
+            ..Default::default()
+        };
+        let saver = FaultInjectingSaver::new(SavedRam(vec![0x42; 0x2000]), faults);
+        let mut gb: HeadlessGameBoy = GameBoy::new(
+            &cfg,
+            lcd::Fake,
+            stereo::Fake,
+            joypad_events_handler::Fake,
+            saver,
+            &rx,
+        );
+        gb.step_frame();
+        (gb.read_memory(0xC000), gb.drain_events())
+    };
+
+    assert_eq!((0x42, vec![]), boot(vec![]));
+    // a flipped bit goes unnoticed, the game gets to check its own data
+    assert_eq!(
+        (0x43, vec![]),
+        boot(vec![SaveFault::BitFlip { offset: 0, bit: 0 }])
+    );
+    assert_eq!(
+        (
+            0x00,
+            vec![Event::SaveRejected {
+                expected: 0x2000,
+                found: 0x100
+            }]
+        ),
+        boot(vec![SaveFault::Truncate(0x100)])
+    );
+    assert_eq!(
+        (
+            0x00,
+            vec![Event::SaveRejected {
+                expected: 0x2000,
+                found: 0x2030
+            }]
+        ),
+        boot(vec![SaveFault::WrongSize(0x2030)])
+    );
+}
+
+#[test]
+fn test_pc_history() {
+    // ld a,1; call sub; illegal 0xDD; spin; sub: inc a; ret
+    let mut asm = Asm::new();
+    let sub = testing::CODE_START + 8;
+    asm.ld_r_n(R8::A, 1).call(sub).db(&[0xDD]).spin();
+    assert_eq!(sub, asm.here());
+    asm.inc(R8::A).ret();
+
+    run(RomBuilder::new().code(&asm).build(), 1, |gb| {
+        let events = gb.drain_events();
+        let Some(Event::IllegalOpcode { pc, history, .. }) = events.first() else {
+            panic!("no illegal opcode in {events:?}");
+        };
+        assert_eq!(0x0155, *pc);
+        assert_eq!(
+            vec![
+                (0x0150, 0x3E),
+                (0x0152, 0xCD),
+                (0x0158, 0x3C),
+                (0x0159, 0xC9),
+                (0x0155, 0xDD)
+            ],
+            history[history.len() - 5..]
+        );
+
+        // the spin loop fills the history afterwards
+        gb.set_pc_history_len(4);
+        gb.step_frame();
+        let history = gb.pc_history();
+        assert_eq!(4, history.len());
+        assert!(history.iter().all(|&(pc, _)| pc == 0x0156), "{history:?}");
+    });
+}
+
+#[test]
+fn test_instant_replay() {
+    run(animated_rom(), 0, |gb| {
+        assert!(gb.replay().is_none());
+        gb.set_replay_seconds(1);
+        for _ in 0..120 {
+            gb.step_frame();
+        }
+        let state = gb.save_state();
+        let replay = gb.replay().unwrap();
+        // one second at every other frame
+        assert_eq!(30, replay.len());
+        let indexes: Vec<_> = replay.frames().map(|f| f.index).collect();
+        assert_eq!((62..=120).step_by(2).collect::<Vec<_>>(), indexes);
+        let frames: Vec<_> = replay.frames().collect();
+        assert!(frames[0].timestamp < frames[1].timestamp);
+        assert_eq!(
+            lcd::PIXELS_WIDTH * lcd::PIXELS_HEIGHT,
+            frames[0].pixels.len()
+        );
+
+        #[cfg(feature = "replay-gif")]
+        {
+            let gif = gb
+                .export_replay_gif(std::time::Duration::from_millis(500))
+                .unwrap();
+            assert!(gif.starts_with(b"GIF89a"));
+            assert_eq!(Some(&0x3B), gif.last());
+        }
+
+        // frames before a loaded state belong to another timeline
+        gb.load_state(&state).unwrap();
+        assert!(gb.replay().unwrap().is_empty());
+        gb.step_frame();
+        assert_eq!(0, gb.replay().unwrap().len());
+        gb.step_frame();
+        assert_eq!(1, gb.replay().unwrap().len());
+    });
+}
+
+/// Frame and value of each counter write
+type SplitLog = Vec<(u64, u8)>;
+
+/// Auto-splitter like script recording the frames at which the counter is written
+struct Splits(Rc<RefCell<SplitLog>>);
+
+impl Script for Splits {
+    fn watched_writes(&self) -> Vec<std::ops::RangeInclusive<u16>> {
+        vec![0xC000..=0xC000]
+    }
+
+    fn on_write(&mut self, _address: u16, value: u8, api: &mut ScriptApi) {
+        self.0.borrow_mut().push((api.frame, value));
+    }
+}
+
+#[test]
+fn test_scripts() {
+    run(animated_rom(), 0, |gb| {
+        // the frame counter wraps at 5
+        let wrap = ExprScript::parse("write 0xC000 if VALUE >= 5: [ADDR] = 0").unwrap();
+        let wrap = gb.add_script(Box::new(wrap));
+        let splits = Rc::new(RefCell::new(vec![]));
+        gb.add_script(Box::new(Splits(splits.clone())));
+        for _ in 0..16 {
+            gb.step_frame();
+        }
+        assert!(gb.read_memory(0xC000) < 5);
+        // writes of the scripts aren't reported
+        let values: Vec<_> = splits.borrow().iter().map(|&(_, v)| v).collect();
+        assert_eq!(vec![1, 2, 3, 4, 5, 1, 2, 3, 4, 5, 1], values[..11]);
+        let frames: Vec<_> = splits.borrow().iter().map(|&(f, _)| f).collect();
+        assert!(frames.windows(2).all(|w| w[1] == w[0] + 1), "{frames:?}");
+
+        assert!(gb.remove_script(wrap).is_some());
+        for _ in 0..10 {
+            gb.step_frame();
+        }
+        assert!(gb.read_memory(0xC000) > 5);
+
+        gb.clear_scripts();
+        splits.borrow_mut().clear();
+        gb.step_frame();
+        assert!(splits.borrow().is_empty());
+    });
+}
+
+#[test]
+fn test_background() {
+    // loop: inc b; jr loop
+    let mut asm = Asm::new();
+    let main_loop = asm.here();
+    asm.inc(R8::B).jr(None, main_loop);
+
+    run(RomBuilder::new().code(&asm).build(), 1, |gb| {
+        let steps_counted = |gb: &mut HeadlessGameBoy| {
+            let b = gb.registers().b();
+            for _ in 0..4 {
+                gb.step();
+            }
+            gb.registers().b() != b
+        };
+        assert!(steps_counted(gb));
+
+        gb.set_background(true);
+        assert!(gb.is_background());
+        assert!(!gb.is_paused(), "not a player pause");
+        assert!(!steps_counted(gb));
+        gb.set_background(false);
+        assert!(steps_counted(gb));
+
+        // regaining focus doesn't resume the player pause
+        gb.pause();
+        gb.set_background(true);
+        gb.set_background(false);
+        assert!(gb.is_paused());
+        gb.resume();
+        assert!(steps_counted(gb));
+
+        gb.set_background_policy(BackgroundPolicy::Mute);
+        gb.set_background(true);
+        assert!(steps_counted(gb));
+        gb.set_background_policy(BackgroundPolicy::Pause);
+        assert!(!steps_counted(gb));
+        gb.set_background_policy(BackgroundPolicy::Run);
+        assert!(steps_counted(gb));
+    });
+}
+
+#[test]
+fn test_rom_coverage() {
+    // ld a,(data); ld (0x2100),a; spin; data: db 0x42, 0x43
+    let mut asm = Asm::new();
+    let data = testing::CODE_START + 8;
+    asm.ld_a_nn(data).ld_nn_a(0x2100).spin().db(&[0x42, 0x43]);
+    assert_eq!(data + 2, asm.here());
+
+    run(RomBuilder::new().code(&asm).build(), 0, |gb| {
+        assert!(gb.rom_coverage().is_none());
+        gb.set_rom_coverage(true);
+        gb.step_frame();
+        let coverage = gb.rom_coverage().unwrap();
+        assert_eq!(0x8000, coverage.rom_size());
+
+        let code = testing::CODE_START as usize;
+        for offset in code..code + 8 {
+            assert_ne!(
+                0,
+                coverage.flags(offset) & RomCoverage::EXECUTED,
+                "{offset:#x}"
+            );
+        }
+        assert_eq!(RomCoverage::READ, coverage.flags(data as usize));
+        assert_eq!(0, coverage.flags(data as usize + 1));
+        assert_eq!(RomCoverage::WRITTEN, coverage.flags(0x2100));
+        assert_eq!(
+            coverage.count(RomCoverage::EXECUTED),
+            coverage.count(RomCoverage::EXECUTED | RomCoverage::READ)
+        );
+
+        gb.set_rom_coverage(false);
+        assert!(gb.rom_coverage().is_none());
+    });
+}
+
+#[test]
+fn test_profiler() {
+    // Main: ld b,0; Main.loop: call Count; jr Main.loop; Count: inc b; ret
+    let mut asm = Asm::new();
+    asm.ld_r_n(R8::B, 0);
+    let main_loop = asm.here();
+    let count = main_loop + 5;
+    asm.call(count).jr(None, main_loop);
+    assert_eq!(count, asm.here());
+    asm.inc(R8::B).ret();
+    let symbols = SymbolTable::parse(&format!(
+        "00:{:04X} Main\n00:{main_loop:04X} Main.loop\n00:{count:04X} Count\n",
+        testing::CODE_START
+    ))
+    .unwrap();
+
+    run(RomBuilder::new().code(&asm).build(), 0, |gb| {
+        assert!(gb.profile_report(5).is_none());
+        gb.set_profiler(true);
+        gb.step_frame();
+        let profiler = gb.profiler().unwrap();
+        // about a frame of cpu cycles
+        assert!(profiler.total_cycles().abs_diff(70224) < 64);
+
+        // call 24 + jr 12 against inc 4 + ret 16
+        let functions = profiler.functions(None);
+        assert_eq!(format!("00:{main_loop:04X}"), functions[0].name);
+        gb.set_symbols(Some(symbols));
+        let functions = gb.profiler().unwrap().functions(gb.symbols());
+        let names: Vec<_> = functions.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(vec!["Main.loop", "Count"], names[..2]);
+        // two instructions each per iteration
+        assert!(
+            functions[0]
+                .instructions
+                .abs_diff(functions[1].instructions)
+                <= 2
+        );
+        assert!(gb.profile_report(1).unwrap().contains("Main.loop"));
+        assert!(!gb.profile_report(1).unwrap().contains("Count"));
+    });
+}
+
+#[test]
+fn test_hot_spots() {
+    // a loop in bank 0 calling a routine copied to wram
+    let mut asm = Asm::new();
+    asm.ld_r_n(R8::A, 0xC9).ld_nn_a(0xC000); // ret
+    let main_loop = asm.here();
+    asm.call(0xC000).jr(None, main_loop);
+
+    run(RomBuilder::new().code(&asm).build(), 0, |gb| {
+        assert!(gb.hot_spot_report(5).is_none());
+        gb.set_hot_spots(true);
+        gb.step_frame();
+        let hot_spots = gb.hot_spots().unwrap();
+        assert!(hot_spots.total_cycles().abs_diff(70224) < 64);
+        // call 24 + jr 12 in rom against ret 16 in wram
+        let hottest = hot_spots.hottest(5);
+        assert_eq!(1, hottest.len());
+        assert_eq!(
+            testing::CODE_START as usize / 0x100 * 0x100,
+            hottest[0].rom_offset
+        );
+        assert!(hot_spots.outside_cycles() > 0);
+        assert!(hottest[0].cycles > hot_spots.outside_cycles());
+        assert!(gb.hot_spot_report(5).unwrap().contains("(halted or ram)"));
+
+        gb.set_hot_spots(false);
+        assert!(gb.hot_spots().is_none());
+    });
+}
+
+#[test]
+fn test_symbols() {
+    // Main: ld a,1; Loop: inc a; jr Loop
+    let mut asm = Asm::new();
+    asm.ld_r_n(R8::A, 1);
+    let main_loop = asm.here();
+    asm.inc(R8::A).jr(None, main_loop);
+    let symbols = SymbolTable::parse(&format!(
+        "; rgblink\n00:{:04X} Main\n00:{main_loop:04X} Main.loop\n",
+        testing::CODE_START
+    ))
+    .unwrap();
+
+    let (_, rx) = channel();
+    let cfg = Config {
+        rom: RomBuilder::new().code(&asm).build(),
+        ..Default::default()
+    };
+    let mut gb: HeadlessGameBoy = GameBoy::new(
+        &cfg,
+        lcd::Fake,
+        stereo::Fake,
+        joypad_events_handler::Fake,
+        saver::Fake,
+        &rx,
+    );
+    assert_eq!("0100", gb.describe_address(0x0100));
+    gb.set_symbols(Some(symbols));
+    assert_eq!("Main", gb.describe_address(testing::CODE_START));
+    assert_eq!("Main.loop+1", gb.describe_address(main_loop + 1));
+
+    let buffer = SharedBuffer::default();
+    gb.set_cpu_trace(Some(Box::new(buffer.clone())));
+    gb.set_cpu_trace_labels(true);
+    for _ in 0..5 {
+        gb.step_into();
+    }
+    gb.set_cpu_trace(None);
+    let trace = String::from_utf8(buffer.0.take()).unwrap();
+    let lines: Vec<_> = trace
+        .lines()
+        .map(|line| line.split(' ').nth(9).unwrap_or(line))
+        .collect();
+    assert_eq!(
+        vec![
+            "PC:0100",
+            "PC:0101",
+            "Main:",
+            "PC:0150",
+            "Main.loop:",
+            "PC:0152",
+            "PC:0153"
+        ],
+        lines
+    );
+
+    assert!(
+        gb.format_pc_history()
+            .ends_with("0150: 3E Main\n0152: 3C Main.loop\n0153: 18 Main.loop+1\n"),
+        "{}",
+        gb.format_pc_history()
+    );
+}
+
+#[test]
+fn test_run_frames_disables_enhancements() {
+    let mut asm = Asm::new();
+    let count = asm.here();
+    asm.ld_a_nn(0xC000)
+        .inc(R8::A)
+        .ld_nn_a(0xC000)
+        .jr(None, count);
+    let rom = RomBuilder::new().code(&asm).build();
+
+    // iterations of the counting loop in a frame, mod 256
+    let count = |overclock| {
+        let (_, rx) = channel();
+        let cfg = Config {
+            rom: rom.clone(),
+            overclock,
+            ..Default::default()
+        };
+        let mut gb = HeadlessGameBoy::headless(&cfg, &rx);
+        testing::run_frames(&mut gb, 1, |_| false);
+        gb.read_memory(0xC000)
+    };
+    assert_eq!(count(0), count(200));
+}
+
+/// Index and top left pixel of each drawn frame
+type DrawnFrameLog = Vec<(u64, lcd::RGB)>;
+
+#[derive(Clone, Default)]
+struct DrawnFrames(Rc<RefCell<DrawnFrameLog>>);
+
+impl lcd::LCD for DrawnFrames {
+    fn draw_buffer(&mut self, frame: &lcd::Frame) {
+        self.0.borrow_mut().push((frame.index, frame.pixels[0][0]));
+    }
+}
+
+#[test]
+fn test_runtime_options() {
+    let (_, rx) = channel::<()>();
+    let cfg = Config {
+        rom: testing::idle_rom(),
+        pacing: Pacing::Uncapped,
+        frameskip: 2,
+        ..Default::default()
+    };
+    let drawn = DrawnFrames::default();
+    let mut gb = GameBoy::new(
+        &cfg,
+        drawn.clone(),
+        stereo::Fake,
+        joypad_events_handler::Fake,
+        saver::Fake,
+        &rx,
+    );
+    for _ in 0..7 {
+        gb.step_frame();
+    }
+    assert_eq!(
+        vec![(1, RGB_WHITE), (4, RGB_WHITE), (7, RGB_WHITE)],
+        drawn.0.take()
+    );
+
+    gb.set_frameskip(0);
+    gb.set_palette(DmgPalette::GREEN);
+    gb.step_frame();
+    gb.step_frame();
+    let green = DmgPalette::GREEN.0[0];
+    assert_eq!(vec![(8, green), (9, green)], drawn.0.take());
+}
@@ -1,22 +1,15 @@
 #[cfg(test)]
 mod tests {
-    use std::{
-        cell::RefCell,
-        fs,
-        path::Path,
-        rc::Rc,
-        sync::mpsc::channel,
-        time::{Duration, SystemTime},
-        vec,
-    };
+    use std::{cell::RefCell, fs, path::Path, rc::Rc, sync::mpsc::channel, vec};
 
     use gamuboy::{
         config::Config,
         gameboy::GameBoy,
+        joypad::Button,
         joypad_events_handler,
         lcd::{self, LCD},
         mode::Mode,
-        saver, stereo,
+        saver, stereo, testing,
     };
 
     const ROMS_PATH: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/roms");
@@ -40,9 +33,9 @@ mod tests {
     }
 
     impl LCD for TextLCD {
-        fn draw_buffer(&mut self, matrix: &lcd::FrameBuffer) {
+        fn draw_buffer(&mut self, frame: &lcd::Frame) {
             let mut output = "".to_owned();
-            for line in matrix {
+            for line in frame.iter() {
                 for pixel in line {
                     match pixel {
                         &lcd::RGB_WHITE => output.push(' '),
@@ -82,10 +75,10 @@ mod tests {
     }
 
     impl LCD for ImageLCD {
-        fn draw_buffer(&mut self, matrix: &lcd::FrameBuffer) {
+        fn draw_buffer(&mut self, frame: &lcd::Frame) {
             let mut output = vec![];
             output.append(&mut "P6\n160 144\n255\n".as_bytes().to_vec());
-            for line in matrix {
+            for line in frame.iter() {
                 for &(r, g, b) in line {
                     output.push(r);
                     output.push(g);
@@ -106,33 +99,44 @@ mod tests {
         }
     }
 
-    fn test_rom_dmg(component_name: &str, rom_name: &str, timeout: Duration) {
+    fn test_rom_dmg(component_name: &str, rom_name: &str, max_frames: u64) {
         test_rom(
             component_name,
             rom_name,
-            timeout,
+            max_frames,
             Mode::DMG,
             &mut TextLCD::new(),
             "txt",
         );
     }
 
-    fn test_rom_cgb(component_name: &str, rom_name: &str, timeout: Duration) {
+    fn test_rom_cgb(component_name: &str, rom_name: &str, max_frames: u64) {
         test_rom(
             component_name,
             rom_name,
-            timeout,
+            max_frames,
             Mode::CGB,
             &mut TextLCD::new(),
             "txt",
         );
     }
 
-    fn test_rom_cgb_with_image(component_name: &str, rom_name: &str, timeout: Duration) {
+    fn test_rom_dmg_with_image(component_name: &str, rom_name: &str, max_frames: u64) {
+        test_rom(
+            component_name,
+            rom_name,
+            max_frames,
+            Mode::DMG,
+            &mut ImageLCD::new(),
+            "ppm",
+        );
+    }
+
+    fn test_rom_cgb_with_image(component_name: &str, rom_name: &str, max_frames: u64) {
         test_rom(
             component_name,
             rom_name,
-            timeout,
+            max_frames,
             Mode::CGB,
             &mut ImageLCD::new(),
             "ppm",
@@ -142,7 +146,7 @@ mod tests {
     fn test_rom<L: LCD + Display + Clone + 'static>(
         component_name: &str,
         rom_name: &str,
-        timeout: Duration,
+        max_frames: u64,
         mode: Mode,
         lcd: &mut L,
         output_extension: &str,
@@ -175,6 +179,7 @@ mod tests {
             headless_mode: false,
             bootrom: None,
             log_file_path: None,
+            ..Default::default()
         };
 
         let output = Rc::new(RefCell::new(vec![]));
@@ -189,16 +194,11 @@ mod tests {
             &rx,
         );
 
-        let start = SystemTime::now();
-        while SystemTime::now()
-            .duration_since(start)
-            .expect("oopsy computing duration")
-            <= timeout
-        {
-            test_gb.step();
-            if !is_update && lcd.get_displayed() == expected {
-                return;
-            }
+        let done = testing::run_frames(&mut test_gb, max_frames, |_| {
+            !is_update && lcd.get_displayed() == expected
+        });
+        if done.is_some() {
+            return;
         }
 
         if is_update {
@@ -212,415 +212,364 @@ mod tests {
 
     #[test]
     fn test_blargg_roms_cpu_instrs_01_special() {
-        test_rom_dmg(
-            "blargg/cpu_instrs",
-            "01-special.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/cpu_instrs", "01-special.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_cpu_instrs_02_interrupts() {
-        test_rom_dmg(
-            "blargg/cpu_instrs",
-            "02-interrupts.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/cpu_instrs", "02-interrupts.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_cpu_instrs_03_op_sp_hl() {
-        test_rom_dmg(
-            "blargg/cpu_instrs",
-            "03-op sp,hl.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/cpu_instrs", "03-op sp,hl.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_cpu_instrs_04_op_r_imm() {
-        test_rom_dmg(
-            "blargg/cpu_instrs",
-            "04-op r,imm.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/cpu_instrs", "04-op r,imm.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_cpu_instrs_05_op_rp() {
-        test_rom_dmg("blargg/cpu_instrs", "05-op rp.gb", Duration::from_secs(60));
+        test_rom_dmg("blargg/cpu_instrs", "05-op rp.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_cpu_instrs_06_ld_r_r() {
-        test_rom_dmg("blargg/cpu_instrs", "06-ld r,r.gb", Duration::from_secs(60));
+        test_rom_dmg("blargg/cpu_instrs", "06-ld r,r.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_cpu_instrs_07_jr_jp_call_ret_rst() {
-        test_rom_dmg(
-            "blargg/cpu_instrs",
-            "07-jr,jp,call,ret,rst.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/cpu_instrs", "07-jr,jp,call,ret,rst.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_cpu_instrs_08_misc_instsr() {
-        test_rom_dmg(
-            "blargg/cpu_instrs",
-            "08-misc instrs.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/cpu_instrs", "08-misc instrs.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_cpu_instrs_09_op_r_r() {
-        test_rom_dmg("blargg/cpu_instrs", "09-op r,r.gb", Duration::from_secs(60));
+        test_rom_dmg("blargg/cpu_instrs", "09-op r,r.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_cpu_instrs_10_bit_ops() {
-        test_rom_dmg(
-            "blargg/cpu_instrs",
-            "10-bit ops.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/cpu_instrs", "10-bit ops.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_cpu_instrs_11_op_a_hl() {
-        test_rom_dmg(
-            "blargg/cpu_instrs",
-            "11-op a,(hl).gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/cpu_instrs", "11-op a,(hl).gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_instr_timing() {
-        test_rom_dmg(
-            "blargg/instr_timing",
-            "instr_timing.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/instr_timing", "instr_timing.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_mem_timing_01_read_timing() {
-        test_rom_dmg(
-            "blargg/mem_timing",
-            "01-read_timing.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/mem_timing", "01-read_timing.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_mem_timing_02_write_timing() {
-        test_rom_dmg(
-            "blargg/mem_timing",
-            "02-write_timing.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/mem_timing", "02-write_timing.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_mem_timing_03_modify_timing() {
-        test_rom_dmg(
-            "blargg/mem_timing",
-            "03-modify_timing.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/mem_timing", "03-modify_timing.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_mem_timing_2_01_read_timing() {
-        test_rom_dmg(
-            "blargg/mem_timing-2",
-            "01-read_timing.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/mem_timing-2", "01-read_timing.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_mem_timing_2_02_write_timing() {
-        test_rom_dmg(
-            "blargg/mem_timing-2",
-            "02-write_timing.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/mem_timing-2", "02-write_timing.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_mem_timing_2_03_modify_timing() {
-        test_rom_dmg(
-            "blargg/mem_timing-2",
-            "03-modify_timing.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/mem_timing-2", "03-modify_timing.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_dmg_sound_01_registers() {
-        test_rom_dmg(
-            "blargg/dmg_sound",
-            "01-registers.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/dmg_sound", "01-registers.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_dmg_sound_02_len_ctr() {
-        test_rom_dmg("blargg/dmg_sound", "02-len ctr.gb", Duration::from_secs(60));
+        test_rom_dmg("blargg/dmg_sound", "02-len ctr.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_dmg_sound_03_trigger() {
-        test_rom_dmg("blargg/dmg_sound", "03-trigger.gb", Duration::from_secs(60));
+        test_rom_dmg("blargg/dmg_sound", "03-trigger.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_dmg_sound_04_sweep() {
-        test_rom_dmg("blargg/dmg_sound", "04-sweep.gb", Duration::from_secs(60));
+        test_rom_dmg("blargg/dmg_sound", "04-sweep.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_dmg_sound_05_sweep_details() {
-        test_rom_dmg(
-            "blargg/dmg_sound",
-            "05-sweep details.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/dmg_sound", "05-sweep details.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_dmg_sound_06_overflow_on_trigger() {
-        test_rom_dmg(
-            "blargg/dmg_sound",
-            "06-overflow on trigger.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/dmg_sound", "06-overflow on trigger.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_dmg_sound_07_len_sweep_period_sync() {
-        test_rom_dmg(
-            "blargg/dmg_sound",
-            "07-len sweep period sync.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/dmg_sound", "07-len sweep period sync.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_dmg_sound_08_len_str_during_power() {
-        test_rom_dmg(
-            "blargg/dmg_sound",
-            "08-len ctr during power.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/dmg_sound", "08-len ctr during power.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_dmg_sound_09_wave_read_while_on() {
-        test_rom_dmg(
-            "blargg/dmg_sound",
-            "09-wave read while on.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/dmg_sound", "09-wave read while on.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_dmg_sound_10_wave_trigger_while_on() {
-        test_rom_dmg(
-            "blargg/dmg_sound",
-            "10-wave trigger while on.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/dmg_sound", "10-wave trigger while on.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_dmg_sound_11_regs_after_power() {
-        test_rom_dmg(
-            "blargg/dmg_sound",
-            "11-regs after power.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/dmg_sound", "11-regs after power.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_dmg_sound_12_wave_write_while_on() {
-        test_rom_dmg(
-            "blargg/dmg_sound",
-            "12-wave write while on.gb",
-            Duration::from_secs(60),
-        );
+        test_rom_dmg("blargg/dmg_sound", "12-wave write while on.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc1_bits_bank1() {
-        test_rom_dmg("mooneye/mbc1", "bits_bank1.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc1", "bits_bank1.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc1_bits_bank2() {
-        test_rom_dmg("mooneye/mbc1", "bits_bank2.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc1", "bits_bank2.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc1_bits_mode() {
-        test_rom_dmg("mooneye/mbc1", "bits_mode.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc1", "bits_mode.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc1_bits_ramg() {
-        test_rom_dmg("mooneye/mbc1", "bits_ramg.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc1", "bits_ramg.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc1_ram_64kb() {
-        test_rom_dmg("mooneye/mbc1", "ram_64kb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc1", "ram_64kb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc1_ram_256kb() {
-        test_rom_dmg("mooneye/mbc1", "ram_256kb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc1", "ram_256kb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc1_rom_1mb() {
-        test_rom_dmg("mooneye/mbc1", "rom_1Mb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc1", "rom_1Mb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc1_rom_2mb() {
-        test_rom_dmg("mooneye/mbc1", "rom_2Mb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc1", "rom_2Mb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc1_rom_4mb() {
-        test_rom_dmg("mooneye/mbc1", "rom_4Mb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc1", "rom_4Mb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc1_rom_8mb() {
-        test_rom_dmg("mooneye/mbc1", "rom_8Mb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc1", "rom_8Mb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc1_rom_16mb() {
-        test_rom_dmg("mooneye/mbc1", "rom_16Mb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc1", "rom_16Mb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc1_rom_512kb() {
-        test_rom_dmg("mooneye/mbc1", "rom_512kb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc1", "rom_512kb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc2_bits_ramg() {
-        test_rom_dmg("mooneye/mbc2", "bits_ramg.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc2", "bits_ramg.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc2_bits_romb() {
-        test_rom_dmg("mooneye/mbc2", "bits_romb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc2", "bits_romb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc2_bits_unused() {
-        test_rom_dmg("mooneye/mbc2", "bits_unused.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc2", "bits_unused.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc2_ram() {
-        test_rom_dmg("mooneye/mbc2", "ram.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc2", "ram.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc2_rom_1mb() {
-        test_rom_dmg("mooneye/mbc2", "rom_1Mb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc2", "rom_1Mb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc2_rom_2mb() {
-        test_rom_dmg("mooneye/mbc2", "rom_2Mb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc2", "rom_2Mb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc2_rom_512kb() {
-        test_rom_dmg("mooneye/mbc2", "rom_512kb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc2", "rom_512kb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc5_rom_512kb() {
-        test_rom_dmg("mooneye/mbc5", "rom_512kb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc5", "rom_512kb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc5_rom_1mb() {
-        test_rom_dmg("mooneye/mbc5", "rom_1Mb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc5", "rom_1Mb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc5_rom_2mb() {
-        test_rom_dmg("mooneye/mbc5", "rom_2Mb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc5", "rom_2Mb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc5_rom_4mb() {
-        test_rom_dmg("mooneye/mbc5", "rom_4Mb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc5", "rom_4Mb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc5_rom_8mb() {
-        test_rom_dmg("mooneye/mbc5", "rom_8Mb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc5", "rom_8Mb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc5_rom_16mb() {
-        test_rom_dmg("mooneye/mbc5", "rom_16Mb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc5", "rom_16Mb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc5_rom_32mb() {
-        test_rom_dmg("mooneye/mbc5", "rom_32Mb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc5", "rom_32Mb.gb", 3600);
     }
 
     #[test]
     fn test_mooneye_roms_mbc5_rom_64mb() {
-        test_rom_dmg("mooneye/mbc5", "rom_64Mb.gb", Duration::from_secs(60));
+        test_rom_dmg("mooneye/mbc5", "rom_64Mb.gb", 3600);
     }
 
     #[test]
     fn test_blargg_roms_interrupt_time() {
-        test_rom_cgb(
-            "blargg/interrupt_time",
-            "interrupt_time.gb",
-            Duration::from_secs(30),
-        );
+        test_rom_cgb("blargg/interrupt_time", "interrupt_time.gb", 1800);
     }
 
     #[test]
     fn test_cgb_acid2() {
-        test_rom_cgb_with_image("cgb-acid2", "cgb-acid2.gbc", Duration::from_secs(15));
+        test_rom_cgb_with_image("cgb-acid2", "cgb-acid2.gbc", 900);
     }
 
     #[test]
     fn test_cgb_magen_bg_oam_priority() {
-        test_rom_cgb_with_image("magen", "bg_oam_priority.gbc", Duration::from_secs(15));
+        test_rom_cgb_with_image("magen", "bg_oam_priority.gbc", 900);
     }
 
     #[test]
     fn test_cgb_magen_hblank_vram_dma() {
-        test_rom_cgb_with_image("magen", "hblank_vram_dma.gbc", Duration::from_secs(15));
+        test_rom_cgb_with_image("magen", "hblank_vram_dma.gbc", 900);
     }
 
     #[test]
     fn test_cgb_magen_oam_internal_priority() {
-        test_rom_cgb_with_image(
-            "magen",
-            "oam_internal_priority.gbc",
-            Duration::from_secs(15),
-        );
+        test_rom_cgb_with_image("magen", "oam_internal_priority.gbc", 900);
+    }
+
+    // mealybug-tearoom-tests roms aren't vendored yet, drop the built roms and their expected
+    // screenshots converted to ppm in tests/roms/mealybug to run these
+    #[test]
+    #[ignore = "requires mealybug-tearoom-tests roms in tests/roms/mealybug"]
+    fn test_dmg_mealybug_m3_bgp_change() {
+        test_rom_dmg_with_image("mealybug", "m3_bgp_change.gb", 900);
+    }
+
+    #[test]
+    #[ignore = "requires mealybug-tearoom-tests roms in tests/roms/mealybug"]
+    fn test_dmg_mealybug_m3_scx_high_5_bits() {
+        test_rom_dmg_with_image("mealybug", "m3_scx_high_5_bits.gb", 900);
+    }
+
+    #[test]
+    #[ignore = "requires mealybug-tearoom-tests roms in tests/roms/mealybug"]
+    fn test_dmg_mealybug_m3_wx_4_change() {
+        test_rom_dmg_with_image("mealybug", "m3_wx_4_change.gb", 900);
+    }
+
+    // gbmicrotest roms aren't vendored, drop the built roms in tests/roms/gbmicrotest to run this.
+    // Reports the pass rate rather than failing, most timing tests don't pass yet.
+    #[test]
+    #[ignore = "requires gbmicrotest roms in tests/roms/gbmicrotest"]
+    fn test_gbmicrotest_suite() {
+        let dir = Path::new(ROMS_PATH).join("gbmicrotest");
+        let report = testing::run_microtest_suite(&dir, Mode::DMG, 60).unwrap();
+        println!("{report}");
+        assert!(!report.results.is_empty(), "no roms in {}", dir.display());
+    }
+
+    #[test]
+    fn test_determinism_double_run() {
+        let rom = fs::read(Path::new(ROMS_PATH).join("blargg/cpu_instrs/01-special.gb")).unwrap();
+        let inputs = vec![vec![], vec![Button::Start], vec![Button::A, Button::Right]];
+
+        if let Err(divergence) = testing::check_determinism(&rom, Mode::DMG, &inputs, 120) {
+            panic!("{divergence}");
+        }
+    }
+
+    #[test]
+    fn test_savestate_divergence() {
+        let rom = fs::read(Path::new(ROMS_PATH).join("blargg/dmg_sound/01-registers.gb")).unwrap();
+        let inputs = vec![vec![], vec![Button::Start], vec![Button::A, Button::Right]];
+
+        if let Err(divergence) =
+            testing::check_savestate_divergence(&rom, Mode::DMG, 30, &inputs, 120)
+        {
+            panic!("{divergence}");
+        }
     }
 }
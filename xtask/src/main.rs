@@ -0,0 +1,55 @@
+//! Repository chores, `cargo run --manifest-path xtask/Cargo.toml -- <task>`:
+//!
+//! - `header` regenerates `include/gamuboy.h` from `src/ffi.rs` with cbindgen, configured by
+//!   `cbindgen.toml`
+//! - `header --check` fails when the committed header differs from the generated one
+
+use std::{env, fs, path::Path, process::ExitCode};
+
+const HEADER: &str = "include/gamuboy.h";
+
+fn generate_header(root: &Path) -> Result<String, String> {
+    let config = cbindgen::Config::from_file(root.join("cbindgen.toml"))?;
+    let mut header = vec![];
+    // only the bindings, parsing the whole crate would export every public constant
+    cbindgen::Builder::new()
+        .with_src(root.join("src/ffi.rs"))
+        .with_config(config)
+        .generate()
+        .map_err(|e| e.to_string())?
+        .write(&mut header);
+    Ok(String::from_utf8(header).expect("cbindgen writes utf-8"))
+}
+
+fn main() -> ExitCode {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
+    let args: Vec<_> = env::args().skip(1).collect();
+    let args: Vec<_> = args.iter().map(String::as_str).collect();
+
+    match args[..] {
+        ["header", ..] => {
+            let header = match generate_header(root) {
+                Ok(header) => header,
+                Err(e) => {
+                    eprintln!("cbindgen failed: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            let path = root.join(HEADER);
+            if args.contains(&"--check") {
+                if fs::read_to_string(&path).ok().as_deref() != Some(header.as_str()) {
+                    eprintln!("{HEADER} is out of date, run the header task");
+                    return ExitCode::FAILURE;
+                }
+            } else if let Err(e) = fs::write(&path, header) {
+                eprintln!("cannot write {HEADER}: {e}");
+                return ExitCode::FAILURE;
+            }
+            ExitCode::SUCCESS
+        }
+        _ => {
+            eprintln!("usage: xtask header [--check]");
+            ExitCode::FAILURE
+        }
+    }
+}